@@ -0,0 +1,156 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::cache::HashCache;
+use crate::hasher::calculate_file_sha256;
+use crate::paths::{extended_length_path, file_id};
+use crate::resolver::{select_keeper, FileCandidate, KeepStrategy};
+use crate::store::{rename_over, sibling};
+
+/// What `scan --action` does with the duplicates it found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupAction {
+    /// Replace files with the same contents (sha256) by hard links to the one kept
+    Hardlink,
+}
+
+/// A file to be replaced by a hard link to the kept copy of its contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedLink {
+    pub path: PathBuf,
+    pub keeper: PathBuf,
+    /// Bytes freed by replacing it, 0 when another name of it is already counted
+    pub bytes_saved: u64,
+}
+
+/// What `plan_hardlinks` decided, nothing has been touched yet
+#[derive(Debug, Default)]
+pub struct HardlinkPlan {
+    pub links: Vec<PlannedLink>,
+    /// Files that are already hard links to their keeper
+    pub already_linked: usize,
+    /// Other files tagged Keep, which are left as they are
+    pub protected: Vec<PathBuf>,
+    pub bytes_saved: u64,
+}
+
+/// What `apply_hardlinks` did
+#[derive(Debug, Default)]
+pub struct HardlinkReport {
+    pub linked: Vec<PathBuf>,
+    pub bytes_saved: u64,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Work out which files of each group to replace with hard links: files are split by their
+/// cached sha256 and by filesystem, since hard links can't cross one, and `strategy` picks the
+/// file each set is linked to. Symbolic links, files without a cached sha256 and sets of one
+/// are left alone
+pub fn plan_hardlinks(
+    groups: &[Vec<PathBuf>],
+    strategy: KeepStrategy,
+    cache: &HashCache,
+) -> HardlinkPlan {
+    let mut plan = HardlinkPlan::default();
+    for group in groups {
+        let mut identical: BTreeMap<(String, Option<u64>), Vec<FileCandidate>> = BTreeMap::new();
+        for path in group {
+            let is_symlink = fs::symlink_metadata(extended_length_path(path))
+                .is_ok_and(|metadata| metadata.file_type().is_symlink());
+            let Some((_, sha256)) = cache.get_cached_hash_details(path).ok().flatten() else {
+                continue;
+            };
+            let Some(candidate) = FileCandidate::from_path(path, cache).filter(|_| !is_symlink)
+            else {
+                continue;
+            };
+            let device = candidate.file_id.map(|(device, _)| device);
+            identical
+                .entry((sha256, device))
+                .or_default()
+                .push(candidate);
+        }
+        for candidates in identical.into_values().filter(|set| set.len() > 1) {
+            plan_set(candidates, strategy, &mut plan);
+        }
+    }
+    plan
+}
+
+fn plan_set(candidates: Vec<FileCandidate>, strategy: KeepStrategy, plan: &mut HardlinkPlan) {
+    let Some(keep_index) = select_keeper(&candidates, strategy) else {
+        return;
+    };
+    let keeper = candidates[keep_index].path.clone();
+    let kept_id = candidates[keep_index].file_id;
+    let mut replaced_ids = BTreeSet::new();
+    for (index, candidate) in candidates.into_iter().enumerate() {
+        if index == keep_index {
+            continue;
+        }
+        if candidate.file_id.is_some() && candidate.file_id == kept_id {
+            plan.already_linked += 1;
+        } else if candidate.keep_tagged {
+            plan.protected.push(candidate.path);
+        } else {
+            let bytes_saved = match candidate.file_id {
+                Some(id) if !replaced_ids.insert(id) => 0,
+                _ => candidate.size,
+            };
+            plan.bytes_saved += bytes_saved;
+            plan.links.push(PlannedLink {
+                path: candidate.path,
+                keeper: keeper.clone(),
+                bytes_saved,
+            });
+        }
+    }
+}
+
+/// Carry out a plan, checking each file again first: it has to be on the keeper's filesystem
+/// and still have exactly the keeper's contents. A file is only replaced once its link exists
+pub fn apply_hardlinks(plan: &HardlinkPlan) -> HardlinkReport {
+    let mut report = HardlinkReport::default();
+    for link in &plan.links {
+        match link_to_keeper(&link.path, &link.keeper) {
+            Ok(()) => {
+                debug!(
+                    "Linked {} to {}",
+                    link.path.display(),
+                    link.keeper.display()
+                );
+                report.linked.push(link.path.clone());
+                report.bytes_saved += link.bytes_saved;
+            }
+            Err(e) => report.failed.push((link.path.clone(), format!("{e:#}"))),
+        }
+    }
+    report
+}
+
+fn link_to_keeper(path: &Path, keeper: &Path) -> Result<()> {
+    // Without device ids, making the link fails across filesystems instead
+    if let (Some((device, _)), Some((keeper_device, _))) = (file_id(path), file_id(keeper)) {
+        if device != keeper_device {
+            bail!("{} is on another filesystem", keeper.display());
+        }
+    }
+    let size = fs::metadata(extended_length_path(path))?.len();
+    if size != fs::metadata(extended_length_path(keeper))?.len()
+        || calculate_file_sha256(path)? != calculate_file_sha256(keeper)?
+    {
+        bail!(
+            "Its contents no longer match {}, scan again first",
+            keeper.display()
+        );
+    }
+
+    let partial = sibling(path, "link");
+    fs::hard_link(extended_length_path(keeper), extended_length_path(&partial))
+        .with_context(|| format!("Could not link {}", keeper.display()))?;
+    rename_over(&partial, path)
+}
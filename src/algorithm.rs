@@ -0,0 +1,189 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use imghash::average::AverageHasher;
+use imghash::difference::DifferenceHasher;
+use imghash::perceptual::PerceptualHasher;
+use imghash::{ColorSpace, ImageHash, ImageHasher};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Side in pixels of one blockhash block after the image is scaled down
+const BLOCK_PIXELS: u32 = 4;
+/// Blockhash compares each block with the median of its band of rows
+const BLOCKHASH_BANDS: usize = 4;
+/// pHash scales the image to this many times the grid before its cosine transform, as imghash's
+/// default hasher does
+const PERCEPTUAL_FACTOR: u8 = 4;
+
+/// How images are reduced to a hash. Hashes of different algorithms are never compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    /// pHash: the low frequencies of the image's discrete cosine transform
+    #[default]
+    Perceptual,
+    /// dHash: whether each pixel is brighter than its neighbour
+    Difference,
+    /// aHash: whether each pixel is brighter than the image's mean
+    Average,
+    /// Whether each block is brighter than the median of its band of rows
+    Blockhash,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgorithm::Perceptual => "perceptual",
+            HashAlgorithm::Difference => "difference",
+            HashAlgorithm::Average => "average",
+            HashAlgorithm::Blockhash => "blockhash",
+        }
+    }
+
+    /// The name cached hashes and groups are stored with. Perceptual keeps the name hashes had
+    /// before the algorithm could be picked, so they stay valid
+    pub fn stored_name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Perceptual => "perceptual-rotation-invariant",
+            HashAlgorithm::Difference => "difference-rotation-invariant",
+            HashAlgorithm::Average => "average-rotation-invariant",
+            HashAlgorithm::Blockhash => "blockhash-rotation-invariant",
+        }
+    }
+
+    /// The hasher for a `grid_size` grid, giving hashes of `grid_size` squared bits. Fails for
+    /// an empty grid
+    pub fn hasher(self, grid_size: u8) -> Result<GridHasher> {
+        Ok(match self {
+            HashAlgorithm::Perceptual => GridHasher::Perceptual(PerceptualHasher::new(
+                grid_size,
+                grid_size,
+                PERCEPTUAL_FACTOR,
+                ColorSpace::REC601,
+            )?),
+            HashAlgorithm::Difference => GridHasher::Difference(DifferenceHasher::new(
+                grid_size,
+                grid_size,
+                ColorSpace::REC601,
+            )?),
+            HashAlgorithm::Average => GridHasher::Average(AverageHasher::new(
+                grid_size,
+                grid_size,
+                ColorSpace::REC601,
+            )?),
+            HashAlgorithm::Blockhash => {
+                if grid_size == 0 {
+                    bail!("The hash grid can't be empty");
+                }
+                GridHasher::Blockhash(grid_size)
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Set from `--algorithm` or the config file's `algorithm` at startup
+static HASH_ALGORITHM: OnceLock<HashAlgorithm> = OnceLock::new();
+
+/// Hash with `algorithm` for the rest of the process
+pub fn set_hash_algorithm(algorithm: HashAlgorithm) {
+    if HASH_ALGORITHM.set(algorithm).is_err() {
+        warn!("Hash algorithm was already set");
+    }
+}
+
+pub fn hash_algorithm() -> HashAlgorithm {
+    HASH_ALGORITHM.get().copied().unwrap_or_default()
+}
+
+/// A hasher of one of the algorithms, see `HashAlgorithm::hasher`
+#[derive(Debug, Clone)]
+pub enum GridHasher {
+    Perceptual(PerceptualHasher),
+    Difference(DifferenceHasher),
+    Average(AverageHasher),
+    /// Side of the grid of blocks
+    Blockhash(u8),
+}
+
+impl GridHasher {
+    pub fn hash_from_img(&self, image: &DynamicImage) -> Result<ImageHash> {
+        Ok(match self {
+            GridHasher::Perceptual(hasher) => hasher.hash_from_img(image)?,
+            GridHasher::Difference(hasher) => hasher.hash_from_img(image)?,
+            GridHasher::Average(hasher) => hasher.hash_from_img(image)?,
+            GridHasher::Blockhash(grid_size) => blockhash(image, *grid_size)?,
+        })
+    }
+}
+
+/// Blockhash: the image's brightness summed over a grid of blocks, each bit set when its block
+/// is brighter than the median of its band of rows. The image is scaled down first so every
+/// block has the same number of pixels
+fn blockhash(image: &DynamicImage, grid_size: u8) -> Result<ImageHash> {
+    let blocks_across = u32::from(grid_size);
+    let side = blocks_across * BLOCK_PIXELS;
+    let pixels = image
+        .resize_exact(side, side, FilterType::Triangle)
+        .to_luma8();
+    let mut blocks = vec![0u32; (blocks_across * blocks_across) as usize];
+    for (x, y, pixel) in pixels.enumerate_pixels() {
+        blocks[((y / BLOCK_PIXELS) * blocks_across + x / BLOCK_PIXELS) as usize] +=
+            u32::from(pixel[0]);
+    }
+
+    let band_rows = usize::from(grid_size).div_ceil(BLOCKHASH_BANDS);
+    let mut bits = Vec::with_capacity(blocks.len());
+    for band in blocks.chunks(band_rows * usize::from(grid_size)) {
+        let mut sorted = band.to_vec();
+        sorted.sort_unstable();
+        // Twice the median, which falls between two blocks for an even number of them
+        let middle = sorted.len() / 2;
+        let median = match sorted.len() % 2 {
+            0 => sorted[middle - 1] + sorted[middle],
+            _ => sorted[middle] * 2,
+        };
+        bits.extend(band.iter().map(|&block| block * 2 > median));
+    }
+
+    Ok(ImageHash::from_bool_iter(bits, grid_size, grid_size)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn blockhash_sets_the_bits_of_bright_blocks() {
+        // Bright left half, dark right half
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, _| {
+            if x < 32 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        }));
+        let hash = HashAlgorithm::Blockhash
+            .hasher(8)
+            .expect("hasher")
+            .hash_from_img(&image)
+            .expect("hash");
+        assert_eq!(hash.encode().expect("encode"), "f0".repeat(8));
+
+        let mirrored = HashAlgorithm::Blockhash
+            .hasher(8)
+            .expect("hasher")
+            .hash_from_img(&image.fliph())
+            .expect("hash");
+        assert_eq!(hash.distance(&mirrored).expect("distance"), 64);
+    }
+}
@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Pin and note stored for one file. Both are set on every file of a group, so they stay with
+/// the group's files when groups are recomputed, merged or split
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileAnnotation {
+    pub pinned: bool,
+    pub note: Option<String>,
+    /// Seconds since the Unix epoch
+    pub updated_at: i64,
+}
+
+/// What a group's files say about the group
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct GroupAnnotation {
+    /// Whether any file of the group is pinned
+    pub pinned: bool,
+    /// The most recently written note of any file in the group
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+pub fn group_annotation(
+    group: &[PathBuf],
+    annotations: &BTreeMap<PathBuf, FileAnnotation>,
+) -> GroupAnnotation {
+    let stored: Vec<&FileAnnotation> = group
+        .iter()
+        .filter_map(|path| annotations.get(path))
+        .collect();
+    GroupAnnotation {
+        pinned: stored.iter().any(|annotation| annotation.pinned),
+        note: stored
+            .iter()
+            .filter(|annotation| annotation.note.is_some())
+            .max_by_key(|annotation| annotation.updated_at)
+            .and_then(|annotation| annotation.note.clone()),
+    }
+}
+
+/// Move pinned groups to the top, keeping the order within pinned and unpinned groups
+pub fn pin_to_top(
+    groups: Vec<Vec<PathBuf>>,
+    annotations: &BTreeMap<PathBuf, FileAnnotation>,
+) -> Vec<Vec<PathBuf>> {
+    let (mut pinned, unpinned): (Vec<_>, Vec<_>) = groups
+        .into_iter()
+        .partition(|group| group_annotation(group, annotations).pinned);
+    pinned.extend(unpinned);
+    pinned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn annotation(pinned: bool, note: Option<&str>, updated_at: i64) -> FileAnnotation {
+        FileAnnotation {
+            pinned,
+            note: note.map(str::to_string),
+            updated_at,
+        }
+    }
+
+    #[test]
+    fn pinned_groups_move_up_in_order() {
+        let groups = vec![
+            paths(&["/a1", "/a2"]),
+            paths(&["/b1", "/b2"]),
+            paths(&["/c1", "/c2"]),
+            paths(&["/d1", "/d2"]),
+        ];
+        let annotations = BTreeMap::from([
+            (PathBuf::from("/c2"), annotation(true, None, 1)),
+            (PathBuf::from("/b1"), annotation(true, None, 2)),
+            (PathBuf::from("/d1"), annotation(false, Some("later"), 3)),
+        ]);
+
+        assert_eq!(
+            pin_to_top(groups, &annotations),
+            vec![
+                paths(&["/b1", "/b2"]),
+                paths(&["/c1", "/c2"]),
+                paths(&["/a1", "/a2"]),
+                paths(&["/d1", "/d2"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn merged_groups_show_the_latest_note() {
+        let annotations = BTreeMap::from([
+            (PathBuf::from("/a1"), annotation(false, Some("older"), 1)),
+            (PathBuf::from("/b1"), annotation(true, Some("newer"), 5)),
+        ]);
+
+        assert_eq!(
+            group_annotation(&paths(&["/a1", "/b1"]), &annotations),
+            GroupAnnotation {
+                pinned: true,
+                note: Some("newer".to_string()),
+            }
+        );
+        assert_eq!(
+            group_annotation(&paths(&["/c1", "/c2"]), &annotations),
+            GroupAnnotation::default()
+        );
+    }
+}
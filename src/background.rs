@@ -0,0 +1,285 @@
+use anyhow::{bail, Result};
+use std::sync::{Condvar, Mutex, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// I/O rate used by `--background` when no limit is given, in MB/s
+pub const DEFAULT_BACKGROUND_IO_LIMIT_MB: f64 = 20.0;
+
+const BYTES_PER_MB: f64 = 1_000_000.0;
+
+/// Set once background mode is entered, file reads are throttled against it from then on
+static IO_THROTTLE: OnceLock<IoThrottle> = OnceLock::new();
+
+/// Held by `/api/worker/pause`, hashing workers check it before each file
+static PAUSED: PauseGate = PauseGate::new();
+
+/// Whether work is paused, and the condition paused workers wait on
+struct PauseGate {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl PauseGate {
+    const fn new() -> Self {
+        Self {
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+        }
+    }
+
+    /// Set whether work is paused, returning whether that changed anything
+    fn set(&self, pause: bool) -> bool {
+        let mut paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+        let changed = *paused != pause;
+        *paused = pause;
+        if !pause {
+            self.resumed.notify_all();
+        }
+        changed
+    }
+
+    fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait(&self) {
+        let paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+        drop(
+            self.resumed
+                .wait_while(paused, |paused| *paused)
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+}
+
+/// Spreads reads out so their average rate stays under a limit
+struct IoThrottle {
+    bytes_per_sec: f64,
+    /// (when throttling started, bytes read since)
+    state: Mutex<(Instant, u64)>,
+}
+
+impl IoThrottle {
+    fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            bytes_per_sec,
+            state: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Record a read and return how long to wait so the average rate stays within the limit
+    fn delay_for(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.1 += bytes;
+        let due = Duration::from_secs_f64(state.1 as f64 / self.bytes_per_sec);
+        due.saturating_sub(state.0.elapsed())
+    }
+}
+
+/// Run the rest of the process at low priority: lowest CPU priority, idle I/O class where the
+/// platform has one, and file reads limited to `io_limit_mb` MB/s.
+///
+/// Threads already running are lowered too, and threads created afterwards inherit it.
+pub fn enter_background_mode(io_limit_mb: f64) -> Result<()> {
+    if io_limit_mb.is_nan() || io_limit_mb <= 0.0 {
+        bail!("The I/O limit must be a positive number of MB/s, got {io_limit_mb}");
+    }
+    lower_priority();
+    if IO_THROTTLE
+        .set(IoThrottle::new(io_limit_mb * BYTES_PER_MB))
+        .is_err()
+    {
+        warn!("Background mode was already enabled");
+    }
+    info!("Running in the background: low priority, reads limited to {io_limit_mb} MB/s");
+    Ok(())
+}
+
+/// Account for `bytes` read from disk, sleeping if background mode's rate limit is exceeded.
+/// Always yields so foreground work gets a turn between files. Does nothing otherwise.
+pub fn throttle_io(bytes: u64) {
+    let Some(throttle) = IO_THROTTLE.get() else {
+        return;
+    };
+    let delay = throttle.delay_for(bytes);
+    if !delay.is_zero() {
+        thread::sleep(delay);
+    }
+    thread::yield_now();
+}
+
+/// Hold hashing before its next file until `resume_hashing`, files being hashed are finished
+pub fn pause_hashing() {
+    if PAUSED.set(true) {
+        info!("Hashing paused");
+    }
+}
+
+/// Let paused hashing carry on where it stopped
+pub fn resume_hashing() {
+    if PAUSED.set(false) {
+        info!("Hashing resumed");
+    }
+}
+
+pub fn hashing_paused() -> bool {
+    PAUSED.is_paused()
+}
+
+/// Block while hashing is paused. Called before each file is read for hashing, never for
+/// requests that read a single file
+pub fn wait_while_paused() {
+    PAUSED.wait();
+}
+
+#[cfg(unix)]
+fn lower_priority() {
+    let (mut cpu_error, mut io_error) = (None, None);
+    for thread in priority_targets() {
+        // SAFETY: setpriority only changes the scheduling priority of this process's threads
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, thread, 19) } != 0 {
+            cpu_error = cpu_error.or(unless_exited(std::io::Error::last_os_error()));
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+            const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+            const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+            // SAFETY: ioprio_set only changes the I/O scheduling class of this process's threads
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    libc::c_long::from(thread),
+                    IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+                )
+            };
+            if result != 0 {
+                io_error = io_error.or(unless_exited(std::io::Error::last_os_error()));
+            }
+        }
+    }
+    if let Some(e) = cpu_error {
+        warn!("Could not lower CPU priority: {e}");
+    }
+    if let Some(e) = io_error {
+        warn!("Could not lower I/O priority: {e}");
+    }
+}
+
+/// What to lower the priority of. On Linux `setpriority` and `ioprio_set` only change the thread
+/// they're given, and the async runtime's threads already run, so every thread is listed. Threads
+/// started later inherit the priority of the one starting them
+#[cfg(target_os = "linux")]
+fn priority_targets() -> Vec<libc::id_t> {
+    match std::fs::read_dir("/proc/self/task") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(e) => {
+            warn!("Could not list this process's threads, only lowering this one's priority: {e}");
+            vec![0]
+        }
+    }
+}
+
+/// Elsewhere 0 stands for the whole process
+#[cfg(all(unix, not(target_os = "linux")))]
+fn priority_targets() -> Vec<libc::id_t> {
+    vec![0]
+}
+
+/// A thread that exited since it was listed doesn't need a lower priority
+#[cfg(unix)]
+fn unless_exited(error: std::io::Error) -> Option<std::io::Error> {
+    (error.raw_os_error() != Some(libc::ESRCH)).then_some(error)
+}
+
+/// Background processing mode lowers the CPU, I/O and memory priority of every thread of the
+/// process, those started later included
+#[cfg(windows)]
+fn lower_priority() {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess, SetPriorityClass, PROCESS_MODE_BACKGROUND_BEGIN,
+    };
+
+    // SAFETY: GetCurrentProcess returns a pseudo handle that needs no closing, and
+    // SetPriorityClass only changes this process's scheduling priority
+    if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+        warn!(
+            "Could not lower process priority: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lower_priority() {
+    warn!("Lowering process priority isn't supported on this platform, only reads are throttled");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle_delays_reads_past_the_limit() {
+        let throttle = IoThrottle::new(BYTES_PER_MB);
+
+        let first = throttle.delay_for(500_000);
+        assert!(first <= Duration::from_millis(500));
+        assert!(first > Duration::from_millis(400));
+
+        // Two seconds' worth of reads in total
+        let second = throttle.delay_for(1_500_000);
+        assert!(second <= Duration::from_secs(2));
+        assert!(second > Duration::from_millis(1900));
+    }
+
+    #[test]
+    fn paused_work_waits_for_resume() {
+        // A gate of its own, pausing the global one would hold up hashing tests running alongside
+        let gate = std::sync::Arc::new(PauseGate::new());
+        assert!(gate.set(true));
+        assert!(gate.is_paused());
+        let worker = thread::spawn({
+            let gate = std::sync::Arc::clone(&gate);
+            move || {
+                gate.wait();
+                Instant::now()
+            }
+        });
+        thread::sleep(Duration::from_millis(100));
+        let resumed_at = Instant::now();
+        assert!(gate.set(false));
+        let finished_at = worker.join().expect("worker should finish");
+        assert!(finished_at >= resumed_at);
+        assert!(!gate.is_paused());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn priority_targets_include_running_threads() {
+        let (send_tid, receive_tid) = std::sync::mpsc::channel();
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let worker = thread::spawn(move || {
+            // SAFETY: gettid only returns the calling thread's ID
+            send_tid
+                .send(unsafe { libc::gettid() })
+                .expect("send thread ID");
+            stopped.recv().ok();
+        });
+        let tid = receive_tid.recv().expect("thread ID");
+
+        let targets = priority_targets();
+        stop.send(()).expect("stop worker");
+        worker.join().expect("worker should finish");
+        assert!(
+            targets.contains(&(tid as libc::id_t)),
+            "{targets:?} lacks {tid}"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use rusqlite::backup::Backup;
+use rusqlite::Connection;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Pages copied per backup step, small enough that other processes can get the lock in between
+const PAGES_PER_STEP: i32 = 4096;
+pub(crate) const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Folder next to the database that backups are written to
+pub fn backup_dir(db_path: &Path) -> PathBuf {
+    db_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("backups")
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC, which sorts in time order
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Gregorian (year, month, day) for a number of days since 1970-01-01
+pub(crate) fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Shift the epoch to 0000-03-01 so leap days fall at the end of each 400 year era
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Copy the live database to a timestamped file in the backup folder with SQLite's online
+/// backup API, then delete all but the newest `retention` backups of this database.
+/// `reason` ends up in the file name, e.g. `hashes-20260101-120000-clean-missing.db`.
+pub fn snapshot(
+    conn: &Connection,
+    db_path: &Path,
+    reason: &str,
+    retention: usize,
+) -> Result<PathBuf> {
+    let dir = backup_dir(db_path);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Could not create backup folder {}", dir.display()))?;
+    let stem = db_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "hashes".to_string());
+    let backup_path = dir.join(format!(
+        "{stem}-{}-{reason}.db",
+        timestamp(SystemTime::now())
+    ));
+
+    info!("Backing up database to {}...", backup_path.display());
+    let mut destination = Connection::open(&backup_path)
+        .with_context(|| format!("Could not create backup {}", backup_path.display()))?;
+    Backup::new(conn, &mut destination)?.run_to_completion(PAGES_PER_STEP, Duration::ZERO, None)?;
+    drop(destination);
+
+    prune_backups(&dir, &stem, retention)?;
+    Ok(backup_path)
+}
+
+/// Delete the oldest backups of the database named `stem` so at most `retention` are left
+pub fn prune_backups(dir: &Path, stem: &str, retention: usize) -> Result<Vec<PathBuf>> {
+    let prefix = format!("{stem}-");
+    let mut backups: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "db")
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .collect();
+    // Names start with the timestamp after the stem, so newest sorts last
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(retention);
+    let removed: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+    for old in &removed {
+        if let Err(e) = fs::remove_file(old) {
+            warn!("Could not remove old backup {}: {}", old.display(), e);
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn timestamps_are_utc_calendar_dates() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        // 2024-02-29 23:59:58, a leap day
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(1_709_251_198)),
+            "20240229-235958"
+        );
+    }
+
+    #[test]
+    fn snapshots_copy_the_database_and_keep_the_newest() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let db_path = temp_dir.path().join("hashes.db");
+        let conn = Connection::open(&db_path).expect("open");
+        conn.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (42);")
+            .expect("populate");
+
+        let dir = backup_dir(&db_path);
+        fs::create_dir_all(&dir).expect("backup dir");
+        for old in [
+            "hashes-20200101-000000-migration.db",
+            "hashes-20210101-000000-clean-missing.db",
+        ] {
+            fs::write(dir.join(old), b"").expect("write");
+        }
+        fs::write(dir.join("other-20200101-000000-migration.db"), b"").expect("write");
+
+        let backup = snapshot(&conn, &db_path, "clean-missing", 2).expect("snapshot");
+
+        let copy = Connection::open(&backup).expect("open backup");
+        let value: i64 = copy
+            .query_row("SELECT x FROM t", [], |row| row.get(0))
+            .expect("backed up row");
+        assert_eq!(value, 42);
+
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .expect("read backups")
+            .map(|entry| {
+                entry
+                    .expect("entry")
+                    .file_name()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        left.sort();
+        assert_eq!(left.len(), 3);
+        assert_eq!(left[0], "hashes-20210101-000000-clean-missing.db");
+        assert!(left[1].starts_with("hashes-") && left[1].ends_with("-clean-missing.db"));
+        assert_eq!(left[2], "other-20200101-000000-migration.db");
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::cache::HashCache;
+use crate::hasher::decode_hashes;
+use crate::paths::extended_length_path;
+
+/// Format version written by `--output json`. Version 1 only listed the paths of each group
+pub const RESULTS_JSON_VERSION: u32 = 2;
+
+/// Duplicate groups as printed by `--output json`, and read back as a baseline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultsJson {
+    pub version: u32,
+    pub threshold: u32,
+    pub groups: Vec<ResultGroup>,
+}
+
+/// One duplicate group of `--output json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "StoredGroup")]
+pub struct ResultGroup {
+    /// Number of the group as listed, from 1
+    pub id: usize,
+    pub files: Vec<ResultFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultFile {
+    pub path: PathBuf,
+    /// Size in bytes, None when the file can't be read
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Hamming distance from the group's first file, None when a hash isn't cached
+    #[serde(default)]
+    pub distance: Option<u32>,
+}
+
+/// A group as written by any version, version 1 wrote its paths
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredGroup {
+    Paths(Vec<PathBuf>),
+    Group { id: usize, files: Vec<ResultFile> },
+}
+
+impl From<StoredGroup> for ResultGroup {
+    fn from(group: StoredGroup) -> Self {
+        match group {
+            // Numbered by `ResultsJson::load`
+            StoredGroup::Paths(paths) => Self {
+                id: 0,
+                files: paths
+                    .into_iter()
+                    .map(|path| ResultFile {
+                        path,
+                        size: None,
+                        distance: None,
+                    })
+                    .collect(),
+            },
+            StoredGroup::Group { id, files } => Self { id, files },
+        }
+    }
+}
+
+impl ResultGroup {
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.iter().map(|file| file.path.clone()).collect()
+    }
+}
+
+impl ResultsJson {
+    /// The groups with their files' sizes, and distances from the cached hashes
+    pub fn new(groups: &[Vec<PathBuf>], threshold: u32, cache: &HashCache) -> Self {
+        let groups = groups
+            .iter()
+            .enumerate()
+            .map(|(index, group)| {
+                let hashes = group
+                    .iter()
+                    .map(|path| {
+                        let (perceptual_hash, _) = cache.get_cached_hash_details(path).ok()??;
+                        decode_hashes(vec![(path.clone(), perceptual_hash)])
+                            .pop()
+                            .map(|(_, hash)| hash)
+                    })
+                    .collect::<Vec<_>>();
+                let first = hashes.first().cloned().flatten();
+                let files = group
+                    .iter()
+                    .zip(&hashes)
+                    .map(|(path, hash)| ResultFile {
+                        path: path.clone(),
+                        size: fs::metadata(extended_length_path(path))
+                            .map(|metadata| metadata.len())
+                            .ok(),
+                        distance: first
+                            .as_ref()
+                            .zip(hash.as_ref())
+                            .and_then(|(first, hash)| first.distance(hash).ok())
+                            .map(|distance| distance as u32),
+                    })
+                    .collect();
+                ResultGroup {
+                    id: index + 1,
+                    files,
+                }
+            })
+            .collect();
+        Self {
+            version: RESULTS_JSON_VERSION,
+            threshold,
+            groups,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Could not read results {}", path.display()))?;
+        let mut results: ResultsJson = serde_json::from_str(&contents)
+            .with_context(|| format!("Could not parse results {}", path.display()))?;
+        if results.version > RESULTS_JSON_VERSION {
+            bail!(
+                "Results {} have version {}, this build supports up to {}",
+                path.display(),
+                results.version,
+                RESULTS_JSON_VERSION
+            );
+        }
+        if results.version < 2 {
+            for (index, group) in results.groups.iter_mut().enumerate() {
+                group.id = index + 1;
+            }
+        }
+        Ok(results)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The groups of a reviewed earlier run. Findings it already had are left out, so only what's
+/// new gets reported
+#[derive(Debug, Clone, Default)]
+pub struct Baseline {
+    /// Which baseline group each file was in
+    group_of: BTreeMap<PathBuf, usize>,
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> Result<Self> {
+        let results = ResultsJson::load(path)?;
+        let groups: Vec<Vec<PathBuf>> = results.groups.iter().map(ResultGroup::paths).collect();
+        Ok(Self::from_groups(&groups))
+    }
+
+    pub fn from_groups(groups: &[Vec<PathBuf>]) -> Self {
+        let group_of = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(id, group)| group.iter().map(move |path| (path.clone(), id)))
+            .collect();
+        Self { group_of }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.group_of.is_empty()
+    }
+
+    /// Whether a file was in any of the baseline's groups
+    pub fn contains(&self, path: &Path) -> bool {
+        self.group_of.contains_key(path)
+    }
+
+    /// Groups with something the baseline didn't have: a file it didn't have, or files it had
+    /// in different groups. Groups whose files were all in one baseline group are left out
+    pub fn new_findings(&self, groups: Vec<Vec<PathBuf>>) -> Vec<Vec<PathBuf>> {
+        if self.is_empty() {
+            return groups;
+        }
+        groups
+            .into_iter()
+            .filter(|group| {
+                let mut ids = group.iter().map(|path| self.group_of.get(path));
+                let first = ids.next().flatten();
+                first.is_none() || ids.any(|id| id != first)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::FileMetadata;
+    use tempfile::TempDir;
+
+    fn group(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn only_new_findings_are_kept() {
+        let baseline =
+            Baseline::from_groups(&[group(&["/a1", "/a2", "/a3"]), group(&["/b1", "/b2"])]);
+        let groups = vec![
+            // Reviewed, and a file of it got cleaned up since
+            group(&["/a1", "/a2"]),
+            // A new copy of a reviewed image
+            group(&["/b1", "/b2", "/b3"]),
+            // Files that were in different groups
+            group(&["/a3", "/b1"]),
+            group(&["/c1", "/c2"]),
+        ];
+
+        assert_eq!(
+            baseline.new_findings(groups),
+            vec![
+                group(&["/b1", "/b2", "/b3"]),
+                group(&["/a3", "/b1"]),
+                group(&["/c1", "/c2"]),
+            ]
+        );
+        assert!(baseline.contains(Path::new("/b2")));
+        assert!(!baseline.contains(Path::new("/b3")));
+    }
+
+    #[test]
+    fn groups_are_written_with_sizes_and_distances() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let cache = HashCache::new_in_memory().expect("cache");
+        let files = ["a.jpg", "b.jpg"].map(|name| temp_dir.path().join(name));
+        for (path, (contents, hash)) in files
+            .iter()
+            .zip([("aaaa", "0000000000000000"), ("bb", "0000000000000007")])
+        {
+            fs::write(path, contents).expect("write file");
+            cache
+                .store_hash(&FileMetadata {
+                    path: path.clone(),
+                    size: contents.len() as u64,
+                    sha256: contents.to_string(),
+                    perceptual_hash: hash.to_string(),
+                    width: None,
+                    height: None,
+                    dominant_color: None,
+                    modified: None,
+                })
+                .expect("store hash");
+        }
+        let missing = temp_dir.path().join("gone.jpg");
+        let groups = vec![vec![files[0].clone(), files[1].clone(), missing.clone()]];
+
+        let mut written = Vec::new();
+        ResultsJson::new(&groups, 15, &cache)
+            .write(&mut written)
+            .expect("write");
+        let parsed: ResultsJson = serde_json::from_slice(&written).expect("parse");
+        assert_eq!(parsed.version, RESULTS_JSON_VERSION);
+        assert_eq!(parsed.groups[0].id, 1);
+        let files: Vec<_> = parsed.groups[0]
+            .files
+            .iter()
+            .map(|file| (file.path.clone(), file.size, file.distance))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (groups[0][0].clone(), Some(4), Some(0)),
+                (groups[0][1].clone(), Some(2), Some(3)),
+                (missing, None, None),
+            ]
+        );
+
+        // Version 1 only had the paths
+        let results_path = temp_dir.path().join("v1.json");
+        fs::write(
+            &results_path,
+            r#"{"version": 1, "threshold": 15, "groups": [["/x1", "/x2"], ["/y1", "/y2"]]}"#,
+        )
+        .expect("write results");
+        let old = ResultsJson::load(&results_path).expect("load version 1");
+        assert_eq!(old.groups[1].id, 2);
+        assert_eq!(old.groups[1].paths(), group(&["/y1", "/y2"]));
+        assert!(Baseline::load(&results_path)
+            .expect("baseline")
+            .contains(Path::new("/x2")));
+    }
+}
@@ -0,0 +1,165 @@
+use imghash::ImageHash;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::borrow::Borrow;
+
+/// Distance used for hashes that can't be compared (mismatched sizes), never within a threshold
+const INCOMPARABLE: u32 = u32::MAX;
+
+fn hash_distance(a: &ImageHash, b: &ImageHash) -> u32 {
+    a.distance(b)
+        .map(|distance| distance as u32)
+        .unwrap_or(INCOMPARABLE)
+}
+
+struct Node<H> {
+    hash: H,
+    /// (distance to this node, index of the child node)
+    children: Vec<(u32, usize)>,
+}
+
+/// BK-tree over perceptual hashes. Finding every hash within a Hamming distance only visits
+/// the branches that can contain matches, instead of comparing against every stored hash.
+///
+/// Hashes are identified by insertion order. `H` is either an owned `ImageHash` or a reference,
+/// so an index can be built over an existing slice without copying it.
+pub struct HashIndex<H> {
+    nodes: Vec<Node<H>>,
+}
+
+impl<H> Default for HashIndex<H> {
+    fn default() -> Self {
+        Self { nodes: Vec::new() }
+    }
+}
+
+impl<H: Borrow<ImageHash> + Sync> HashIndex<H> {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add a hash, returning its id
+    pub fn insert(&mut self, hash: H) -> usize {
+        let id = self.nodes.len();
+        if id > 0 {
+            let mut current = 0;
+            loop {
+                let distance = hash_distance(self.nodes[current].hash.borrow(), hash.borrow());
+                match self.nodes[current]
+                    .children
+                    .iter()
+                    .find(|(child_distance, _)| *child_distance == distance)
+                {
+                    Some(&(_, child)) => current = child,
+                    None => {
+                        self.nodes[current].children.push((distance, id));
+                        break;
+                    }
+                }
+            }
+        }
+        self.nodes.push(Node {
+            hash,
+            children: Vec::new(),
+        });
+        id
+    }
+
+    /// Every stored hash within `max_distance` of `hash`, as (id, distance) in no particular order
+    pub fn find_within(&self, hash: &ImageHash, max_distance: u32) -> Vec<(usize, u32)> {
+        let mut matches = Vec::new();
+        if self.nodes.is_empty() {
+            return matches;
+        }
+
+        let mut pending = vec![0];
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = hash_distance(node.hash.borrow(), hash);
+            if distance <= max_distance {
+                matches.push((current, distance));
+            }
+
+            // Triangle inequality: only children at a distance within range of ours can match
+            let low = distance.saturating_sub(max_distance);
+            let high = distance.saturating_add(max_distance);
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(child_distance, _)| (low..=high).contains(child_distance))
+                    .map(|&(_, child)| child),
+            );
+        }
+
+        matches
+    }
+
+    /// For every hash, the later hashes (by id) within `max_distance`, sorted by id. `progress`
+    /// counts the hashes searched
+    pub fn neighbours(&self, max_distance: u32, progress: &ProgressBar) -> Vec<Vec<(usize, u32)>> {
+        self.nodes
+            .par_iter()
+            .enumerate()
+            .map(|(id, node)| {
+                let mut found: Vec<(usize, u32)> = self
+                    .find_within(node.hash.borrow(), max_distance)
+                    .into_iter()
+                    .filter(|&(other, _)| other > id)
+                    .collect();
+                found.sort_unstable();
+                progress.inc(1);
+                found
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgb, RgbImage};
+    use imghash::{perceptual::PerceptualHasher, ImageHasher};
+
+    /// Hash a generated image so the test doesn't depend on the hash string format
+    fn hash(seed: u64) -> ImageHash {
+        let img = RgbImage::from_fn(32, 32, |x, y| {
+            let value = (x as u64 * 31 + y as u64 * 17).wrapping_mul(seed + 1) % 251;
+            Rgb([
+                value as u8,
+                (value * 3 % 256) as u8,
+                (value * 7 % 256) as u8,
+            ])
+        });
+        PerceptualHasher::default()
+            .hash_from_img(&DynamicImage::ImageRgb8(img))
+            .expect("test image should hash")
+    }
+
+    #[test]
+    fn matches_brute_force_search() {
+        let hashes: Vec<ImageHash> = (0..200).map(hash).collect();
+
+        let mut index = HashIndex::default();
+        for hash in &hashes {
+            index.insert(hash);
+        }
+
+        for threshold in [0, 5, 20, 40] {
+            let neighbours = index.neighbours(threshold, &ProgressBar::hidden());
+            for (i, a) in hashes.iter().enumerate() {
+                let expected: Vec<(usize, u32)> = hashes
+                    .iter()
+                    .enumerate()
+                    .skip(i + 1)
+                    .map(|(j, b)| (j, hash_distance(a, b)))
+                    .filter(|&(_, distance)| distance <= threshold)
+                    .collect();
+                assert_eq!(neighbours[i], expected, "hash {i} at threshold {threshold}");
+            }
+        }
+    }
+}
@@ -1,39 +1,296 @@
-use anyhow::Result;
-use rusqlite::{params, Connection};
+use anyhow::{bail, Result};
+use rusqlite::{params, Connection, ErrorCode, ToSql, Transaction, TransactionBehavior};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use tracing::{debug, info};
-
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, instrument, warn};
+
+use crate::algorithm::{hash_algorithm, HashAlgorithm};
+use crate::annotations::FileAnnotation;
+use crate::backup::snapshot;
+use crate::estimate::{parse_size, DEFAULT_CONFIRM_ABOVE};
+use crate::feed::GroupDiscovery;
+use crate::groupcap::{max_group_size, DEFAULT_MAX_GROUP_SIZE};
 use crate::hex::encode_lower_hex;
+use crate::ingest::download_dir;
+use crate::jobs::{JobKind, JobRun, JobTrigger, LogLine, RunState, DEFAULT_JOB_HISTORY};
+use crate::messages::Lang;
+use crate::overrides::GroupOverride;
+use crate::partial::default_temporary_patterns;
+use crate::paths::{extended_length_path, is_case_insensitive, on_disk_case, path_key};
+use crate::settings::{read_settings, write_settings, Settings};
+use crate::sidecar::SidecarMode;
+use crate::store::{LinkKind, StoreLink};
+use crate::strategy::HashStrategy;
+use crate::thumbnail::thumbnail_dir;
+use crate::usage::{ServeKind, ServedFile, SessionUsage, DEFAULT_SERVE_LOG_SESSIONS};
+
+/// Port the web server listens on when none is configured
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Algorithm of the hashes cached before it was recorded with them, see
+/// `HashAlgorithm::stored_name`
+const UNRECORDED_ALGORITHM: &str = "perceptual-rotation-invariant";
+/// Version of this tool, recorded with every cached hash and set of groups
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Side of the grid perceptual hashes are computed on when `grid_size` isn't set. Hashes
+/// cached before their size was recorded were all made on it
+pub const DEFAULT_GRID_SIZE: u8 = 8;
+/// Changes to an image its hash doesn't see, see `canonical::canonical_hash`
+pub const HASH_INVARIANCE: &str = "rotation";
+
+/// Which tool version and hash settings made a number of cached hashes. Hashes cached before
+/// this was recorded have none of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HashProvenance {
+    pub tool_version: Option<String>,
+    pub algorithm: Option<String>,
+    pub hash_size: Option<u32>,
+    pub invariance: Option<String>,
+    pub hashes: usize,
+}
+
+impl HashProvenance {
+    /// What this version records for the hashes it makes on a `grid_size` grid
+    pub fn current(grid_size: u8) -> Self {
+        Self {
+            tool_version: Some(TOOL_VERSION.to_string()),
+            algorithm: Some(hash_algorithm().stored_name().to_string()),
+            hash_size: Some(u32::from(grid_size)),
+            invariance: Some(HASH_INVARIANCE.to_string()),
+            hashes: 0,
+        }
+    }
+}
+
+/// A set of cached duplicate groups and what made them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupSetProvenance {
+    pub threshold: u32,
+    /// Hash algorithm, grid size and maximum group size, see `GroupingKey`
+    pub comparison: String,
+    pub tool_version: Option<String>,
+    pub groups: usize,
+}
+
+/// Everything in the cache by the version and settings that made it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CacheProvenance {
+    pub hashes: Vec<HashProvenance>,
+    pub group_sets: Vec<GroupSetProvenance>,
+}
+
+/// Everything that decides which groups a set of hashes makes. Cached groups are only used when
+/// all of it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupingKey {
+    pub threshold: u32,
+    pub grid_size: u8,
+    pub algorithm: HashAlgorithm,
+    /// None when groups aren't split
+    pub max_group_size: Option<usize>,
+}
+
+impl GroupingKey {
+    /// The key for this process's hash algorithm and maximum group size
+    pub fn new(threshold: u32, grid_size: u8) -> Self {
+        Self {
+            threshold,
+            grid_size,
+            algorithm: hash_algorithm(),
+            max_group_size: max_group_size(),
+        }
+    }
+
+    /// Everything but the threshold, as stored next to it
+    fn comparison(&self) -> String {
+        format!(
+            "{};grid={};max_group={}",
+            self.algorithm.stored_name(),
+            self.grid_size,
+            self.max_group_size.unwrap_or(0)
+        )
+    }
+}
+
+/// How fast earlier scans got through their files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HashingSpeed {
+    pub files_per_second: f64,
+    pub bytes_per_second: f64,
+}
+
+/// Where the database lives when no `database_path` is configured
+pub fn default_database_path() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("vibe-image-comparator")
+        .join("hashes.db")
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub grid_size: Option<u32>,
+    pub grid_size: Option<u8>,
     pub threshold: Option<u32>,
+    /// How images are hashed, hashes of another algorithm are made again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<HashAlgorithm>,
     pub database_path: Option<String>,
     #[serde(default)]
     pub ignore_paths: Option<Vec<String>>,
+    /// Directories scanned when none are given on the command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_paths: Option<Vec<String>>,
+    /// Directories the web server may scan and serve files from, `scan_paths` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_roots: Option<Vec<String>>,
+    /// Serve results for browsing only: the web server has no scan, delete, resolve, group edit
+    /// or settings routes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    /// How long SQLite waits for another process to release a lock, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub busy_timeout_ms: Option<u64>,
+    /// Use write-ahead logging so readers don't block the writer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wal_mode: Option<bool>,
+    /// How many times a write is retried after the busy timeout expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_retries: Option<u32>,
+    /// How many database backups to keep, taken before migrations, `clean` and
+    /// `clean --all`. 0 turns backups off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_retention: Option<usize>,
+    /// Content types served for file extensions whose contents can't be recognised,
+    /// e.g. `{"heic": "image/heic"}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_types: Option<BTreeMap<String, String>>,
+    /// How files are hashed per extension, e.g. `{"gif": "all-frames", "svg": "skip"}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_strategies: Option<BTreeMap<String, HashStrategy>>,
+    /// Scans reading more than this much, e.g. `"100GB"`, ask before hashing when run in a
+    /// terminal. `"0"` never asks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm_scan_above: Option<String>,
+    /// Names of files that are still being written and left out of scans, e.g. `["*.part"]`.
+    /// Replaces the default list of partial download and temp file patterns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temporary_file_patterns: Option<Vec<String>>,
+    /// Seconds files in a folder are left alone after changing, e.g. `{"~/Downloads": 30}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debounce_seconds: Option<BTreeMap<String, u64>>,
+    /// Groups with more files than this are split by regrouping them more strictly, 0 never
+    /// splits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_group_size: Option<usize>,
+    /// Whether XMP/JSON sidecar files are deleted or moved along with their image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidecars: Option<SidecarMode>,
+    /// Language for result summaries and web API messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<Lang>,
+    /// Port the web server listens on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Shell command run after each scan with a JSON summary on stdin, see `hooks::ScanSummary`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_scan_hook: Option<String>,
+    /// Seconds between runs of each web server job, e.g. `{"scan": 86400, "compaction": 604800}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_schedule: Option<BTreeMap<JobKind, u64>>,
+    /// How many job runs are kept with their logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_history: Option<usize>,
+    /// How many web server runs' served files are kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serve_log_sessions: Option<usize>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            grid_size: Some(128),
+            grid_size: Some(DEFAULT_GRID_SIZE),
             threshold: Some(15),
+            algorithm: None,
             database_path: None,
             ignore_paths: Some(Vec::new()),
+            scan_paths: None,
+            server_roots: None,
+            read_only: None,
+            busy_timeout_ms: None,
+            wal_mode: None,
+            write_retries: None,
+            backup_retention: None,
+            content_types: None,
+            hash_strategies: None,
+            confirm_scan_above: None,
+            temporary_file_patterns: None,
+            debounce_seconds: None,
+            max_group_size: None,
+            sidecars: None,
+            lang: None,
+            port: None,
+            post_scan_hook: None,
+            job_schedule: None,
+            job_history: None,
+            serve_log_sessions: None,
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ResolvedConfig {
-    pub grid_size: u32,
+    pub grid_size: u8,
     pub threshold: u32,
+    pub algorithm: HashAlgorithm,
     pub database_path: Option<String>,
     pub ignore_paths: Vec<String>,
+    pub scan_paths: Vec<String>,
+    /// Empty when the web server may access any path
+    pub server_roots: Vec<String>,
+    pub read_only: bool,
+    pub content_types: BTreeMap<String, String>,
+    pub hash_strategies: BTreeMap<String, HashStrategy>,
+    /// Size in bytes above which scans ask for confirmation, None to never ask
+    pub confirm_scan_above: Option<u64>,
+    pub temporary_file_patterns: Vec<String>,
+    pub debounce_seconds: BTreeMap<String, u64>,
+    /// 0 when groups are never split
+    pub max_group_size: usize,
+    pub sidecars: SidecarMode,
+    pub lang: Lang,
+    pub port: u16,
+    pub post_scan_hook: Option<String>,
+    pub job_schedule: BTreeMap<JobKind, u64>,
+    pub job_history: usize,
+    pub serve_log_sessions: usize,
+    pub connection: ConnectionOptions,
+}
+
+/// How database connections behave when several processes share one database, and how the
+/// database is protected from destructive maintenance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    pub busy_timeout_ms: u64,
+    pub wal_mode: bool,
+    pub write_retries: u32,
+    /// Backups kept in the `backups` folder next to the database, 0 for none
+    pub backup_retention: usize,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            busy_timeout_ms: 5000,
+            wal_mode: true,
+            write_retries: 5,
+            backup_retention: 3,
+        }
+    }
 }
 
 impl Config {
@@ -42,15 +299,68 @@ impl Config {
     /// Always returns concrete values (no None values)
     pub fn with_overrides(
         &self,
-        cli_grid_size: Option<u32>,
+        cli_grid_size: Option<u8>,
         cli_threshold: Option<u32>,
         cli_database_path: Option<String>,
     ) -> ResolvedConfig {
         ResolvedConfig {
-            grid_size: cli_grid_size.or(self.grid_size).unwrap_or(128),
+            grid_size: cli_grid_size
+                .or(self.grid_size)
+                .unwrap_or(DEFAULT_GRID_SIZE),
             threshold: cli_threshold.or(self.threshold).unwrap_or(15),
+            algorithm: self.algorithm.unwrap_or_default(),
             database_path: cli_database_path.or_else(|| self.database_path.clone()),
             ignore_paths: self.ignore_paths.clone().unwrap_or_default(),
+            scan_paths: self.scan_paths.clone().unwrap_or_default(),
+            server_roots: self
+                .server_roots
+                .clone()
+                .or_else(|| self.scan_paths.clone())
+                .unwrap_or_default(),
+            read_only: self.read_only.unwrap_or(false),
+            content_types: self.content_types.clone().unwrap_or_default(),
+            hash_strategies: self.hash_strategies.clone().unwrap_or_default(),
+            confirm_scan_above: self.confirm_scan_limit(),
+            temporary_file_patterns: self
+                .temporary_file_patterns
+                .clone()
+                .unwrap_or_else(default_temporary_patterns),
+            debounce_seconds: self.debounce_seconds.clone().unwrap_or_default(),
+            max_group_size: self.max_group_size.unwrap_or(DEFAULT_MAX_GROUP_SIZE),
+            sidecars: self.sidecars.unwrap_or_default(),
+            lang: self.lang.unwrap_or_default(),
+            port: self.port.unwrap_or(DEFAULT_PORT),
+            post_scan_hook: self.post_scan_hook.clone(),
+            job_schedule: self.job_schedule.clone().unwrap_or_default(),
+            job_history: self.job_history.unwrap_or(DEFAULT_JOB_HISTORY),
+            serve_log_sessions: self
+                .serve_log_sessions
+                .unwrap_or(DEFAULT_SERVE_LOG_SESSIONS),
+            connection: self.connection_options(),
+        }
+    }
+
+    fn confirm_scan_limit(&self) -> Option<u64> {
+        let Some(text) = &self.confirm_scan_above else {
+            return Some(DEFAULT_CONFIRM_ABOVE);
+        };
+        match parse_size(text) {
+            Ok(0) => None,
+            Ok(limit) => Some(limit),
+            Err(e) => {
+                warn!("Ignoring confirm_scan_above: {e}");
+                Some(DEFAULT_CONFIRM_ABOVE)
+            }
+        }
+    }
+
+    fn connection_options(&self) -> ConnectionOptions {
+        let defaults = ConnectionOptions::default();
+        ConnectionOptions {
+            busy_timeout_ms: self.busy_timeout_ms.unwrap_or(defaults.busy_timeout_ms),
+            wal_mode: self.wal_mode.unwrap_or(defaults.wal_mode),
+            write_retries: self.write_retries.unwrap_or(defaults.write_retries),
+            backup_retention: self.backup_retention.unwrap_or(defaults.backup_retention),
         }
     }
 }
@@ -61,39 +371,196 @@ pub struct FileMetadata {
     pub size: u64,
     pub sha256: String,
     pub perceptual_hash: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub dominant_color: Option<String>,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: Option<i64>,
+}
+
+/// Everything the cache knows about a single file
+#[derive(Debug, Clone)]
+pub struct CachedImageDetails {
+    pub perceptual_hash: String,
+    pub sha256: String,
+    pub dimensions: Option<(u32, u32)>,
+    pub dominant_color: Option<String>,
+    pub modified: Option<i64>,
+    pub photo: PhotoMetadata,
+}
+
+/// Cached files with exactly the same perceptual hash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SharedHash {
+    pub perceptual_hash: String,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Hex digits of the perceptual hash that near matches across caches must share, 16 bits
+const CROSS_CACHE_PREFIX_LEN: usize = 4;
+/// Bits of the perceptual hash stored in the indexed `hash_prefix` column
+const HASH_PREFIX_BITS: u32 = 16;
+/// Prefixes this many bits away from a query's are looked up too. Each bit more multiplies the
+/// lookups (137 at 2, 697 at 3), so near matches differing in more of the first 16 bits are missed
+const HASH_PREFIX_SEARCH_RADIUS: u32 = 2;
+
+/// The first 16 bits of an encoded perceptual hash, None for hashes too short to have them
+fn hash_prefix(perceptual_hash: &str) -> Option<i64> {
+    let digits = perceptual_hash.get(..(HASH_PREFIX_BITS / 4) as usize)?;
+    u16::from_str_radix(digits, 16).ok().map(i64::from)
+}
+
+/// Side of the square grid an encoded perceptual hash was computed on, four bits per hex digit
+pub fn hash_side(perceptual_hash: &str) -> u8 {
+    let side = ((perceptual_hash.len() * 4) as f64).sqrt();
+    // Longer than any grid this makes, decoding then fails on its length
+    u8::try_from(side as u32).unwrap_or(u8::MAX)
+}
+
+/// Number of differing bits between two encoded perceptual hashes, None when they aren't hex
+/// or weren't computed on the same grid
+fn hash_distance(a: &str, b: &str) -> Option<u32> {
+    if a.len() != b.len() {
+        return None;
+    }
+    a.chars().zip(b.chars()).try_fold(0, |distance, (a, b)| {
+        Some(distance + (a.to_digit(16)? ^ b.to_digit(16)?).count_ones())
+    })
+}
+
+/// Every prefix within `radius` bits of `prefix`, itself first
+fn prefixes_within(prefix: i64, radius: u32) -> Vec<i64> {
+    let mut prefixes = vec![prefix];
+    let mut frontier = vec![(prefix, 0)];
+    for _ in 0..radius {
+        let mut next = Vec::new();
+        for (value, lowest) in frontier {
+            // Flipping bits in increasing order reaches each combination once
+            for bit in lowest..HASH_PREFIX_BITS {
+                let flipped = value ^ (1 << bit);
+                prefixes.push(flipped);
+                next.push((flipped, bit + 1));
+            }
+        }
+        frontier = next;
+    }
+    prefixes
+}
+
+/// A file in this cache matching a file in another one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrossCacheMatch {
+    pub local: PathBuf,
+    pub other: PathBuf,
+    /// Hamming distance of the perceptual hashes, None for identical contents
+    pub distance: Option<u32>,
 }
 
+/// What the cache recorded about a file's contents when it was hashed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedContent {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: Option<i64>,
+}
+
+/// Details about a photo imported from elsewhere, e.g. Google Takeout sidecars
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhotoMetadata {
+    /// Original capture time in seconds since the Unix epoch
+    pub taken_at: Option<i64>,
+    pub description: Option<String>,
+}
+
+/// Pause before the first write retry, doubled on each further attempt
+const WRITE_RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
 pub struct HashCache {
     conn: Connection,
+    write_retries: u32,
+    /// None for in-memory caches, which have nothing to back up
+    path: Option<PathBuf>,
+    backup_retention: usize,
 }
 
 impl HashCache {
     pub fn new(database_path: Option<&str>) -> Result<Self> {
-        let conn = if let Some(path) = database_path {
-            Connection::open(path)?
-        } else {
-            let cache_dir = dirs::cache_dir()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join("vibe-image-comparator");
+        Self::with_options(database_path, &ConnectionOptions::default())
+    }
+
+    /// Open the database configured in `config`, using its connection settings
+    pub fn open(config: &ResolvedConfig) -> Result<Self> {
+        Self::with_options(config.database_path.as_deref(), &config.connection)
+    }
 
-            fs::create_dir_all(&cache_dir)?;
-            let db_path = cache_dir.join("hashes.db");
-            Connection::open(db_path)?
+    pub fn with_options(database_path: Option<&str>, options: &ConnectionOptions) -> Result<Self> {
+        let db_path = if let Some(path) = database_path {
+            PathBuf::from(path)
+        } else {
+            let db_path = default_database_path();
+            if let Some(cache_dir) = db_path.parent() {
+                fs::create_dir_all(cache_dir)?;
+            }
+            db_path
         };
+        let conn = Connection::open(&db_path)?;
+
+        conn.busy_timeout(Duration::from_millis(options.busy_timeout_ms))?;
+        if options.wal_mode {
+            // Switching journal mode needs a brief exclusive lock, so retry it like a write
+            retry_on_busy(options.write_retries, || {
+                let mode: String =
+                    conn.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+                Ok(mode)
+            })?;
+        }
 
-        Self::create_tables(&conn)?;
-        Self::migrate_old_schema(&conn)?;
-        Self::migrate_blob_to_text(&conn)?;
+        if options.backup_retention > 0 && Self::needs_migration(&conn)? {
+            snapshot(&conn, &db_path, "migration", options.backup_retention)?;
+        }
+
+        // Take the write lock before touching the schema so that processes starting at the
+        // same time run migrations one after another instead of racing each other
+        retry_on_busy(options.write_retries, || {
+            let tx = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?;
+            Self::create_tables(&tx)?;
+            Self::migrate_old_schema(&tx)?;
+            Self::migrate_blob_to_text(&tx)?;
+            Self::migrate_add_dimensions(&tx)?;
+            Self::migrate_add_image_details(&tx)?;
+            Self::migrate_add_hash_prefix(&tx)?;
+            Self::migrate_add_group_comparison(&tx)?;
+            Self::migrate_add_scan_run_root(&tx)?;
+            Self::migrate_add_provenance(&tx)?;
+            tx.commit()?;
+            Ok(())
+        })?;
+
+        // Foreign keys can't be toggled inside a transaction
+        conn.execute("PRAGMA foreign_keys = ON", [])?;
 
-        Ok(HashCache { conn })
+        Ok(HashCache {
+            conn,
+            write_retries: options.write_retries,
+            path: Some(db_path),
+            backup_retention: options.backup_retention,
+        })
     }
 
-    #[cfg(test)]
-    #[allow(dead_code)]
+    /// Create a temporary cache that lives only as long as this value, nothing is written to disk
     pub fn new_in_memory() -> Result<Self> {
         let conn = Connection::open(":memory:")?;
         Self::create_tables(&conn)?;
-        Ok(HashCache { conn })
+        Self::migrate_add_hash_prefix(&conn)?;
+        conn.execute("PRAGMA foreign_keys = ON", [])?;
+        Ok(HashCache {
+            conn,
+            write_retries: 0,
+            path: None,
+            backup_retention: 0,
+        })
     }
 
     fn create_tables(conn: &Connection) -> Result<()> {
@@ -103,6 +570,14 @@ impl HashCache {
                 id INTEGER PRIMARY KEY,
                 sha256 TEXT UNIQUE NOT NULL,
                 perceptual_hash TEXT NOT NULL,
+                width INTEGER,
+                height INTEGER,
+                dominant_color TEXT,
+                hash_prefix INTEGER,
+                tool_version TEXT,
+                algorithm TEXT,
+                hash_size INTEGER,
+                invariance TEXT,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP
             )",
             [],
@@ -115,6 +590,7 @@ impl HashCache {
                 path TEXT UNIQUE NOT NULL,
                 size INTEGER NOT NULL,
                 perceptual_hash_id INTEGER NOT NULL,
+                modified INTEGER,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
                 FOREIGN KEY (perceptual_hash_id) REFERENCES perceptual_hashes(id)
             )",
@@ -126,7 +602,9 @@ impl HashCache {
             "CREATE TABLE IF NOT EXISTS duplicate_groups (
                 id INTEGER PRIMARY KEY,
                 threshold INTEGER NOT NULL,
+                comparison TEXT NOT NULL DEFAULT '',
                 group_hash TEXT NOT NULL,
+                tool_version TEXT,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP
             )",
             [],
@@ -143,12 +621,276 @@ impl HashCache {
             [],
         )?;
 
-        // Enable foreign key constraints
-        conn.execute("PRAGMA foreign_keys = ON", [])?;
+        // Imported photo details, kept per path so rehashing a file doesn't lose them
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS photo_metadata (
+                path TEXT PRIMARY KEY,
+                taken_at INTEGER,
+                description TEXT,
+                imported_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+
+        // Manual merges and splits, replayed on top of computed duplicate groups
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS group_overrides (
+                id INTEGER PRIMARY KEY,
+                kind TEXT NOT NULL,
+                path TEXT NOT NULL,
+                other_path TEXT,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+
+        // Pins and notes from triage in the web interface, kept on each file of a group
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS group_annotations (
+                path TEXT PRIMARY KEY,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                note TEXT,
+                updated_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // Tags from extended attributes, read on every scan since setting one doesn't change
+        // the file's contents
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS file_tags (
+                path TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (path, tag)
+            )",
+            [],
+        )?;
+
+        // How long hashing took on earlier scans, to estimate how long the next one takes
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS scan_runs (
+                id INTEGER PRIMARY KEY,
+                finished_at INTEGER NOT NULL,
+                files INTEGER NOT NULL,
+                bytes INTEGER NOT NULL,
+                seconds REAL NOT NULL,
+                root TEXT
+            )",
+            [],
+        )?;
+
+        // When each file's contents were last read back and compared with its cached sha256,
+        // so sampled checks get round to every file
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS content_checks (
+                path TEXT PRIMARY KEY,
+                checked_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // 16x16 hashes confirming coarse matches with --multi-resolution, by file contents
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS fine_hashes (
+                sha256 TEXT PRIMARY KEY,
+                fine_hash TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Hashes of the frames sampled from each video, comma separated, confirming matches of
+        // their blended signatures, by file contents
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS video_signatures (
+                sha256 TEXT PRIMARY KEY,
+                frame_hashes TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // When each current duplicate group was first stored, for the feed of new groups.
+        // `generation` is bumped on every store, groups not stored again are gone
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS group_discoveries (
+                id INTEGER PRIMARY KEY,
+                threshold INTEGER NOT NULL,
+                group_key TEXT NOT NULL,
+                discovered_at INTEGER NOT NULL,
+                generation INTEGER NOT NULL,
+                paths TEXT NOT NULL,
+                UNIQUE(threshold, group_key)
+            )",
+            [],
+        )?;
+
+        // Pairs of files whose hashes are within `max_distance` of each other, as found the
+        // last time groups were computed from the hashes. Only valid while the hashes match
+        // `group_hash`
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS pair_distances (
+                path_a TEXT NOT NULL,
+                path_b TEXT NOT NULL,
+                distance INTEGER NOT NULL,
+                PRIMARY KEY (path_a, path_b)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS pair_distance_state (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                algorithm TEXT NOT NULL,
+                group_hash TEXT NOT NULL,
+                max_distance INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // Runs of the web server's jobs, with the lines they logged as JSON
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS job_runs (
+                id INTEGER PRIMARY KEY,
+                kind TEXT NOT NULL,
+                triggered_by TEXT NOT NULL,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER,
+                state TEXT NOT NULL,
+                message TEXT,
+                log TEXT NOT NULL DEFAULT '[]'
+            )",
+            [],
+        )?;
+
+        // Files the web server sent, for bandwidth accounting per server run
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS serve_log (
+                id INTEGER PRIMARY KEY,
+                session_started INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                bytes INTEGER NOT NULL,
+                served_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_serve_log_session ON serve_log(session_started)",
+            [],
+        )?;
+
+        // Where downloaded images came from, one row per URL
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS url_sources (
+                url TEXT PRIMARY KEY,
+                path TEXT NOT NULL,
+                fetched_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_url_sources_path ON url_sources(path)",
+            [],
+        )?;
+
+        // Originals replaced by links into the content-addressed store, so it can be undone
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS store_links (
+                path TEXT PRIMARY KEY,
+                sha256 TEXT NOT NULL,
+                store_path TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                modified INTEGER
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_store_links_store_path ON store_links(store_path)",
+            [],
+        )?;
+
+        // Options changed at runtime, layered between the config file and environment
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
 
         Ok(())
     }
 
+    /// Whether an existing database has an older schema that opening it will migrate
+    fn needs_migration(conn: &Connection) -> Result<bool> {
+        let table_exists = |name: &str| -> Result<bool> {
+            let mut stmt =
+                conn.prepare("SELECT name FROM sqlite_master WHERE type='table' AND name=?1")?;
+            Ok(stmt.exists(params![name])?)
+        };
+        if table_exists("file_hashes")? {
+            return Ok(true);
+        }
+        // A new database has nothing worth backing up
+        if !table_exists("perceptual_hashes")? {
+            return Ok(false);
+        }
+
+        let columns = |table: &str| -> Result<Vec<(String, String)>> {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+            let columns = stmt
+                .query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(columns)
+        };
+        let hash_columns = columns("perceptual_hashes")?;
+        let has_hash_column = |wanted: &str| hash_columns.iter().any(|(name, _)| name == wanted);
+        let blob_hashes = hash_columns.iter().any(|(name, col_type)| {
+            name == "perceptual_hash" && col_type.eq_ignore_ascii_case("BLOB")
+        });
+        let file_columns = columns("files")?;
+        let group_columns = columns("duplicate_groups")?;
+        let run_columns = columns("scan_runs")?;
+
+        Ok(blob_hashes
+            || !has_hash_column("width")
+            || !has_hash_column("dominant_color")
+            || !has_hash_column("hash_prefix")
+            || !has_hash_column("tool_version")
+            || !file_columns.iter().any(|(name, _)| name == "modified")
+            || !group_columns.iter().any(|(name, _)| name == "comparison")
+            || !group_columns.iter().any(|(name, _)| name == "tool_version")
+            || !run_columns.iter().any(|(name, _)| name == "root"))
+    }
+
+    /// Folder thumbnails are stored in, None for in-memory caches
+    pub fn thumbnail_dir(&self) -> Option<PathBuf> {
+        self.path.as_deref().map(thumbnail_dir)
+    }
+
+    /// Folder images downloaded with `ingest-urls` are stored in, None for in-memory caches
+    pub fn download_dir(&self) -> Option<PathBuf> {
+        self.path.as_deref().map(download_dir)
+    }
+
+    /// Snapshot the database before a destructive operation, as configured by
+    /// `backup_retention`. Returns the backup's path, None when backups are off or in memory
+    pub fn backup(&self, reason: &str) -> Result<Option<PathBuf>> {
+        match &self.path {
+            Some(path) if self.backup_retention > 0 => Ok(Some(snapshot(
+                &self.conn,
+                path,
+                reason,
+                self.backup_retention,
+            )?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Run a write, retrying with backoff if another process still holds the database lock
+    /// once the busy timeout has expired
+    fn write<T>(&self, mut op: impl FnMut(&Connection) -> Result<T>) -> Result<T> {
+        retry_on_busy(self.write_retries, || op(&self.conn))
+    }
+
     fn migrate_old_schema(conn: &Connection) -> Result<()> {
         // Check if old table exists
         let mut stmt = conn
@@ -201,17 +943,38 @@ impl HashCache {
         Ok(())
     }
 
-    pub fn get_cached_hash(&self, path: &Path, size: u64, sha256: &str) -> Result<Option<String>> {
+    /// The cached hash of a file, when it was computed on a `grid_size` grid with this process's
+    /// algorithm. Hashes of another size or algorithm don't count, the next `store_hash`
+    /// replaces them
+    #[instrument(level = "trace", skip_all)]
+    pub fn get_cached_hash(
+        &self,
+        path: &Path,
+        size: u64,
+        sha256: &str,
+        grid_size: u8,
+    ) -> Result<Option<String>> {
         let mut stmt = self.conn.prepare(
             "SELECT ph.perceptual_hash 
              FROM files f 
              JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id 
-             WHERE f.path = ?1 AND f.size = ?2 AND ph.sha256 = ?3",
+             WHERE f.path = ?1 AND f.size = ?2 AND ph.sha256 = ?3
+               AND COALESCE(ph.hash_size, ?5) = ?4
+               AND COALESCE(ph.algorithm, ?7) = ?6",
         )?;
 
-        let mut rows = stmt.query_map(params![path.to_string_lossy(), size, sha256], |row| {
-            row.get::<_, String>(0)
-        })?;
+        let mut rows = stmt.query_map(
+            params![
+                path_key(path),
+                size,
+                sha256,
+                grid_size,
+                DEFAULT_GRID_SIZE,
+                hash_algorithm().stored_name(),
+                UNRECORDED_ALGORITHM
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
 
         if let Some(row) = rows.next() {
             Ok(Some(row?))
@@ -220,33 +983,155 @@ impl HashCache {
         }
     }
 
+    #[instrument(level = "trace", skip_all)]
     pub fn store_hash(&self, metadata: &FileMetadata) -> Result<()> {
-        // Insert or get perceptual hash ID
-        self.conn.execute(
-            "INSERT OR IGNORE INTO perceptual_hashes (sha256, perceptual_hash) VALUES (?1, ?2)",
-            params![metadata.sha256, metadata.perceptual_hash],
-        )?;
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
 
-        let perceptual_hash_id: i64 = self.conn.query_row(
-            "SELECT id FROM perceptual_hashes WHERE sha256 = ?1",
-            params![metadata.sha256],
-            |row| row.get(0),
-        )?;
+            // Insert or get perceptual hash ID, replacing a hash of another size or algorithm
+            tx.execute(
+                "INSERT INTO perceptual_hashes
+                 (sha256, perceptual_hash, width, height, dominant_color, hash_prefix,
+                  tool_version, algorithm, hash_size, invariance)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT(sha256) DO UPDATE SET
+                     perceptual_hash = excluded.perceptual_hash,
+                     hash_prefix = excluded.hash_prefix,
+                     tool_version = excluded.tool_version,
+                     algorithm = excluded.algorithm,
+                     hash_size = excluded.hash_size,
+                     invariance = excluded.invariance
+                 WHERE COALESCE(hash_size, ?11) != excluded.hash_size
+                    OR COALESCE(algorithm, ?12) != excluded.algorithm",
+                params![
+                    metadata.sha256,
+                    metadata.perceptual_hash,
+                    metadata.width,
+                    metadata.height,
+                    metadata.dominant_color,
+                    hash_prefix(&metadata.perceptual_hash),
+                    TOOL_VERSION,
+                    hash_algorithm().stored_name(),
+                    hash_side(&metadata.perceptual_hash),
+                    HASH_INVARIANCE,
+                    DEFAULT_GRID_SIZE,
+                    UNRECORDED_ALGORITHM
+                ],
+            )?;
 
-        // Insert or replace file record
-        self.conn.execute(
-            "INSERT OR REPLACE INTO files (path, size, perceptual_hash_id) VALUES (?1, ?2, ?3)",
-            params![
-                metadata.path.to_string_lossy(),
-                metadata.size,
-                perceptual_hash_id
-            ],
-        )?;
+            // Backfill details for hashes cached before they were recorded
+            tx.execute(
+                "UPDATE perceptual_hashes SET
+                     width = COALESCE(width, ?2),
+                     height = COALESCE(height, ?3),
+                     dominant_color = COALESCE(dominant_color, ?4)
+                 WHERE sha256 = ?1",
+                params![
+                    metadata.sha256,
+                    metadata.width,
+                    metadata.height,
+                    metadata.dominant_color
+                ],
+            )?;
 
-        Ok(())
+            let perceptual_hash_id: i64 = tx.query_row(
+                "SELECT id FROM perceptual_hashes WHERE sha256 = ?1",
+                params![metadata.sha256],
+                |row| row.get(0),
+            )?;
+
+            // Insert or replace file record
+            tx.execute(
+                "INSERT OR REPLACE INTO files (path, size, perceptual_hash_id, modified)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    path_key(&metadata.path),
+                    metadata.size,
+                    perceptual_hash_id,
+                    metadata.modified
+                ],
+            )?;
+
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Remove entries for files that no longer exist, returning (files, hashes) removed.
+    ///
+    /// A file only counts as missing when its folder is still there, so entries on an unmounted
+    /// share or drive are kept. With `only_under`, only entries under that folder are checked;
+    /// it must exist and not be empty, and then every missing file under it is removed.
+    #[instrument(level = "debug", skip_all)]
+    pub fn cleanup_missing_files_and_hashes(
+        &self,
+        only_under: Option<&Path>,
+    ) -> Result<(usize, usize)> {
+        let missing_paths = self.find_missing_files(only_under)?;
+        self.backup("clean-missing")?;
+        if missing_paths.is_empty() {
+            return Ok((0, 0));
+        }
+
+        // Remove missing files from database
+        info!("Removing missing files from database...");
+        let (files_removed, hashes_removed) = self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+
+            let mut files_removed = 0;
+            for path in &missing_paths {
+                let path_str = path_key(path);
+                tx.execute("DELETE FROM files WHERE path = ?1", params![path_str])?;
+                tx.execute("DELETE FROM url_sources WHERE path = ?1", params![path_str])?;
+                files_removed += 1;
+            }
+
+            // Clean up orphaned perceptual hashes
+            info!("Cleaning up orphaned hashes...");
+            let hashes_removed = tx.execute(
+                "DELETE FROM perceptual_hashes 
+                 WHERE id NOT IN (SELECT DISTINCT perceptual_hash_id FROM files)",
+                [],
+            )?;
+            tx.execute(
+                "DELETE FROM fine_hashes WHERE sha256 NOT IN (SELECT sha256 FROM perceptual_hashes)",
+                [],
+            )?;
+            tx.execute(
+                "DELETE FROM video_signatures WHERE sha256 NOT IN (SELECT sha256 FROM perceptual_hashes)",
+                [],
+            )?;
+            tx.execute(
+                "DELETE FROM content_checks WHERE path NOT IN (SELECT path FROM files)",
+                [],
+            )?;
+
+            tx.commit()?;
+            Ok((files_removed, hashes_removed))
+        })?;
+
+        // Clear cached duplicate groups since file cache has changed
+        self.clear_duplicate_groups_cache()?;
+
+        info!("Database cleanup completed successfully");
+        Ok((files_removed, hashes_removed))
     }
 
-    pub fn cleanup_missing_files_and_hashes(&self) -> Result<(usize, usize)> {
+    /// The cached files `cleanup_missing_files_and_hashes` would remove, nothing is changed
+    pub fn find_missing_files(&self, only_under: Option<&Path>) -> Result<Vec<PathBuf>> {
+        if let Some(prefix) = only_under {
+            let fs_prefix = extended_length_path(prefix);
+            let has_entries = fs::read_dir(&fs_prefix)
+                .map(|mut entries| entries.next().is_some())
+                .unwrap_or(false);
+            if !has_entries {
+                bail!(
+                    "{} is missing or empty, is it mounted? Refusing to treat its files as missing",
+                    prefix.display()
+                );
+            }
+        }
+
         info!("Scanning database for missing files...");
 
         // Get all file paths from database
@@ -256,19 +1141,37 @@ impl HashCache {
             .collect::<Result<Vec<_>, _>>()?;
 
         let total_files = paths.len();
-        let mut files_removed = 0;
 
         // Check each file and collect missing ones
         let mut missing_paths = Vec::new();
+        let mut unavailable = 0;
         for (i, path_str) in paths.iter().enumerate() {
             if i % 100 == 0 {
                 debug!("Checked {i}/{total_files} files...");
             }
 
-            let path = PathBuf::from(path_str);
-            if !path.exists() {
-                missing_paths.push(path_str);
+            let path = Path::new(path_str);
+            if only_under.is_some_and(|prefix| !path.starts_with(prefix))
+                || extended_length_path(path).exists()
+            {
+                continue;
             }
+
+            let folder_available = only_under.is_some()
+                || path
+                    .parent()
+                    .is_some_and(|parent| extended_length_path(parent).is_dir());
+            if folder_available {
+                missing_paths.push(path.to_path_buf());
+            } else {
+                unavailable += 1;
+            }
+        }
+
+        if unavailable > 0 {
+            warn!(
+                "Kept {unavailable} entries whose folders are unavailable, they may be on an offline drive or share. Use --only-under <folder> to remove them once it is mounted"
+            );
         }
 
         info!(
@@ -276,49 +1179,87 @@ impl HashCache {
             missing_paths.len(),
             total_files
         );
+        Ok(missing_paths)
+    }
 
-        if missing_paths.is_empty() {
-            return Ok((0, 0));
-        }
-
-        // Remove missing files from database
-        info!("Removing missing files from database...");
-        let tx = self.conn.unchecked_transaction()?;
-
-        for path_str in missing_paths {
-            tx.execute("DELETE FROM files WHERE path = ?1", params![path_str])?;
-            files_removed += 1;
-        }
-
-        // Clean up orphaned perceptual hashes
-        info!("Cleaning up orphaned hashes...");
-        let hashes_removed = tx.execute(
-            "DELETE FROM perceptual_hashes 
-             WHERE id NOT IN (SELECT DISTINCT perceptual_hash_id FROM files)",
-            [],
+    /// Remove entries that are another spelling of a cached file on a case-insensitive volume
+    /// (`Photo.JPG` and `photo.jpg`), keeping the on-disk spelling, and return the removed paths.
+    /// Paths differing in more than ASCII case aren't noticed
+    pub fn fold_case_aliases(&self) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path FROM files
+             WHERE lower(path) IN (
+                 SELECT lower(path) FROM files GROUP BY lower(path) HAVING COUNT(*) > 1
+             )
+             ORDER BY lower(path), path",
         )?;
+        let paths: Vec<String> = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
 
-        tx.commit()?;
-
-        // Clear cached duplicate groups since file cache has changed
-        self.clear_duplicate_groups_cache()?;
-
-        info!("Database cleanup completed successfully");
-        Ok((files_removed, hashes_removed))
+        let mut spellings: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for path in paths {
+            spellings
+                .entry(path.to_ascii_lowercase())
+                .or_default()
+                .push(PathBuf::from(path));
+        }
+        let mut aliases = Vec::new();
+        for paths in spellings.into_values() {
+            if !is_case_insensitive(&paths[0]) {
+                continue;
+            }
+            let on_disk = on_disk_case(&paths[0]);
+            let keep = paths.iter().position(|path| *path == on_disk).unwrap_or(0);
+            aliases.extend(
+                paths
+                    .into_iter()
+                    .enumerate()
+                    .filter(|&(index, _)| index != keep)
+                    .map(|(_, path)| path),
+            );
+        }
+        for alias in &aliases {
+            info!(
+                "Removing {} from the cache, it's another spelling of a cached file",
+                alias.display()
+            );
+            self.remove_file_entry(alias)?;
+        }
+        Ok(aliases)
     }
 
     pub fn remove_file_entry(&self, path: &Path) -> Result<()> {
-        self.conn.execute(
-            "DELETE FROM files WHERE path = ?1",
-            params![path.to_string_lossy()],
-        )?;
+        let orphaned = self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            tx.execute("DELETE FROM files WHERE path = ?1", params![path_key(path)])?;
+            tx.execute(
+                "DELETE FROM url_sources WHERE path = ?1",
+                params![path_key(path)],
+            )?;
+            tx.execute(
+                "DELETE FROM file_tags WHERE path = ?1",
+                params![path_key(path)],
+            )?;
 
-        // Clean up orphaned perceptual hashes after removing the file
-        let orphaned = self.conn.execute(
-            "DELETE FROM perceptual_hashes 
-             WHERE id NOT IN (SELECT DISTINCT perceptual_hash_id FROM files)",
-            [],
-        )?;
+            // Clean up orphaned perceptual hashes after removing the file
+            let orphaned = tx.execute(
+                "DELETE FROM perceptual_hashes 
+                 WHERE id NOT IN (SELECT DISTINCT perceptual_hash_id FROM files)",
+                [],
+            )?;
+            tx.execute(
+                "DELETE FROM fine_hashes WHERE sha256 NOT IN (SELECT sha256 FROM perceptual_hashes)",
+                [],
+            )?;
+            tx.execute(
+                "DELETE FROM video_signatures WHERE sha256 NOT IN (SELECT sha256 FROM perceptual_hashes)",
+                [],
+            )?;
+
+            tx.commit()?;
+            Ok(orphaned)
+        })?;
 
         if orphaned > 0 {
             info!("Cleaned up {orphaned} orphaned perceptual hashes after removing broken file");
@@ -330,6 +1271,33 @@ impl HashCache {
         Ok(())
     }
 
+    /// Keep what's known about a file that was moved, under its new path
+    pub fn move_file_entry(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (path_key(from), path_key(to));
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            // A file that was at the destination before has been replaced
+            for table in ["files", "photo_metadata", "group_annotations", "file_tags"] {
+                tx.execute(&format!("DELETE FROM {table} WHERE path = ?1"), params![to])?;
+            }
+            for table in [
+                "files",
+                "photo_metadata",
+                "url_sources",
+                "group_annotations",
+                "file_tags",
+            ] {
+                tx.execute(
+                    &format!("UPDATE {table} SET path = ?2 WHERE path = ?1"),
+                    params![from, to],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })?;
+        self.clear_duplicate_groups_cache()
+    }
+
     fn migrate_blob_to_text(conn: &Connection) -> Result<()> {
         // Check if perceptual_hashes table has BLOB column
         let mut stmt = conn.prepare("PRAGMA table_info(perceptual_hashes)")?;
@@ -363,21 +1331,352 @@ impl HashCache {
         Ok(())
     }
 
-    pub fn get_all_cached_hashes(&self) -> Result<Vec<(PathBuf, String)>> {
+    fn migrate_add_dimensions(conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare("PRAGMA table_info(perceptual_hashes)")?;
+        let column_names: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !column_names.iter().any(|name| name == "width") {
+            info!("Adding image dimension columns to cache schema...");
+            conn.execute("ALTER TABLE perceptual_hashes ADD COLUMN width INTEGER", [])?;
+            conn.execute(
+                "ALTER TABLE perceptual_hashes ADD COLUMN height INTEGER",
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn migrate_add_image_details(conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare("PRAGMA table_info(perceptual_hashes)")?;
+        let hash_columns: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if !hash_columns.iter().any(|name| name == "dominant_color") {
+            info!("Adding dominant colour column to cache schema...");
+            conn.execute(
+                "ALTER TABLE perceptual_hashes ADD COLUMN dominant_color TEXT",
+                [],
+            )?;
+        }
+
+        let mut stmt = conn.prepare("PRAGMA table_info(files)")?;
+        let file_columns: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if !file_columns.iter().any(|name| name == "modified") {
+            info!("Adding modification time column to cache schema...");
+            conn.execute("ALTER TABLE files ADD COLUMN modified INTEGER", [])?;
+        }
+
+        Ok(())
+    }
+
+    fn migrate_add_hash_prefix(conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare("PRAGMA table_info(perceptual_hashes)")?;
+        let hash_columns: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if !hash_columns.iter().any(|name| name == "hash_prefix") {
+            info!("Adding hash prefix column to cache schema...");
+            conn.execute(
+                "ALTER TABLE perceptual_hashes ADD COLUMN hash_prefix INTEGER",
+                [],
+            )?;
+
+            // SQLite can't parse hex, so existing hashes get their prefix from here
+            let mut stmt = conn.prepare("SELECT id, perceptual_hash FROM perceptual_hashes")?;
+            let hashes: Vec<(i64, String)> = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut update =
+                conn.prepare("UPDATE perceptual_hashes SET hash_prefix = ?2 WHERE id = ?1")?;
+            for (id, perceptual_hash) in hashes {
+                update.execute(params![id, hash_prefix(&perceptual_hash)])?;
+            }
+        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_perceptual_hashes_prefix ON perceptual_hashes(hash_prefix)",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    fn migrate_add_group_comparison(conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare("PRAGMA table_info(duplicate_groups)")?;
+        let group_columns: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if !group_columns.iter().any(|name| name == "comparison") {
+            info!("Adding comparison settings column to cached duplicate groups...");
+            // Nothing says which settings the cached groups came from, they're computed again
+            conn.execute("DELETE FROM duplicate_group_files", [])?;
+            conn.execute("DELETE FROM duplicate_groups", [])?;
+            conn.execute(
+                "ALTER TABLE duplicate_groups ADD COLUMN comparison TEXT NOT NULL DEFAULT ''",
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn migrate_add_scan_run_root(conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare("PRAGMA table_info(scan_runs)")?;
+        let run_columns: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if !run_columns.iter().any(|name| name == "root") {
+            info!("Adding scan root column to recorded scan runs...");
+            // Earlier runs stay as they are and count towards the overall speed only
+            conn.execute("ALTER TABLE scan_runs ADD COLUMN root TEXT", [])?;
+        }
+
+        Ok(())
+    }
+
+    fn migrate_add_provenance(conn: &Connection) -> Result<()> {
+        let columns = |table: &str| -> Result<Vec<String>> {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+            let columns = stmt
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(columns)
+        };
+        // Rows cached before stay without, nothing says which version made them
+        if !columns("perceptual_hashes")?
+            .iter()
+            .any(|name| name == "tool_version")
+        {
+            info!("Adding version and hash settings columns to cached hashes...");
+            for column in [
+                "tool_version TEXT",
+                "algorithm TEXT",
+                "hash_size INTEGER",
+                "invariance TEXT",
+            ] {
+                conn.execute(
+                    &format!("ALTER TABLE perceptual_hashes ADD COLUMN {column}"),
+                    [],
+                )?;
+            }
+        }
+        if !columns("duplicate_groups")?
+            .iter()
+            .any(|name| name == "tool_version")
+        {
+            info!("Adding version column to cached duplicate groups...");
+            conn.execute(
+                "ALTER TABLE duplicate_groups ADD COLUMN tool_version TEXT",
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the cached image dimensions (width, height) for a file, if they were recorded
+    pub fn get_cached_dimensions(&self, path: &Path) -> Result<Option<(u32, u32)>> {
         let mut stmt = self.conn.prepare(
-            "SELECT f.path, ph.perceptual_hash 
-             FROM files f 
+            "SELECT ph.width, ph.height
+             FROM files f
+             JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+             WHERE f.path = ?1",
+        )?;
+
+        let mut rows = stmt.query_map(params![path_key(path)], |row| {
+            Ok((row.get::<_, Option<u32>>(0)?, row.get::<_, Option<u32>>(1)?))
+        })?;
+
+        match rows.next() {
+            Some(row) => match row? {
+                (Some(width), Some(height)) => Ok(Some((width, height))),
+                _ => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Get the cached (perceptual hash, sha256) pair for a file
+    pub fn get_cached_hash_details(&self, path: &Path) -> Result<Option<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT ph.perceptual_hash, ph.sha256
+             FROM files f
+             JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+             WHERE f.path = ?1",
+        )?;
+
+        let mut rows = stmt.query_map(params![path_key(path)], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        if let Some(row) = rows.next() {
+            Ok(Some(row?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get the hashes, dimensions, dominant colour and modification time cached for a file
+    pub fn get_cached_image_details(&self, path: &Path) -> Result<Option<CachedImageDetails>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT ph.perceptual_hash, ph.sha256, ph.width, ph.height, ph.dominant_color,
+                    f.modified, pm.taken_at, pm.description
+             FROM files f
+             JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+             LEFT JOIN photo_metadata pm ON pm.path = f.path
+             WHERE f.path = ?1",
+        )?;
+
+        let mut rows = stmt.query_map(params![path_key(path)], |row| {
+            let width: Option<u32> = row.get(2)?;
+            let height: Option<u32> = row.get(3)?;
+            Ok(CachedImageDetails {
+                perceptual_hash: row.get(0)?,
+                sha256: row.get(1)?,
+                dimensions: width.zip(height),
+                dominant_color: row.get(4)?,
+                modified: row.get(5)?,
+                photo: PhotoMetadata {
+                    taken_at: row.get(6)?,
+                    description: row.get(7)?,
+                },
+            })
+        })?;
+
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every cached file with its perceptual hash and sha256
+    pub fn get_all_cached_hash_details(&self) -> Result<Vec<(PathBuf, String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.path, ph.perceptual_hash, ph.sha256
+             FROM files f
              JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
-             WHERE EXISTS (SELECT 1 FROM files WHERE path = f.path)",
+             ORDER BY f.path",
         )?;
 
         let rows = stmt.query_map([], |row| {
             Ok((
                 PathBuf::from(row.get::<_, String>(0)?),
                 row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Every perceptual hash that more than one cached file has, with its files. Read straight
+    /// from the database without grouping, so it's much quicker than finding duplicates. The
+    /// hashes with the most files come first
+    pub fn get_shared_hashes(&self) -> Result<Vec<SharedHash>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT ph.perceptual_hash, f.path
+             FROM files f
+             JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+             WHERE ph.perceptual_hash IN (
+                 SELECT ph.perceptual_hash
+                 FROM files f
+                 JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+                 GROUP BY ph.perceptual_hash
+                 HAVING COUNT(*) > 1
+             )
+             ORDER BY ph.perceptual_hash, f.path",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                PathBuf::from(row.get::<_, String>(1)?),
             ))
         })?;
 
+        let mut by_hash: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for row in rows {
+            let (perceptual_hash, path) = row?;
+            by_hash.entry(perceptual_hash).or_default().push(path);
+        }
+        let mut shared: Vec<SharedHash> = by_hash
+            .into_iter()
+            .map(|(perceptual_hash, paths)| SharedHash {
+                perceptual_hash,
+                paths,
+            })
+            .collect();
+        // Stable, so hashes with as many files stay in hash order
+        shared.sort_by_key(|hash| std::cmp::Reverse(hash.paths.len()));
+        Ok(shared)
+    }
+
+    /// Visit every cached (path, perceptual hash) pair of this process's algorithm in blocks
+    /// of at most `block_size` rows, so callers never need the whole table in memory at once
+    pub fn for_each_cached_hash_block(
+        &self,
+        block_size: usize,
+        mut visit: impl FnMut(Vec<(PathBuf, String)>),
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.path, ph.perceptual_hash
+             FROM files f
+             JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+             WHERE COALESCE(ph.algorithm, ?2) = ?1",
+        )?;
+
+        let rows = stmt.query_map(
+            params![hash_algorithm().stored_name(), UNRECORDED_ALGORITHM],
+            |row| {
+                Ok((
+                    PathBuf::from(row.get::<_, String>(0)?),
+                    row.get::<_, String>(1)?,
+                ))
+            },
+        )?;
+
+        let block_size = block_size.max(1);
+        let mut block = Vec::with_capacity(block_size);
+        for row in rows {
+            block.push(row?);
+            if block.len() == block_size {
+                visit(std::mem::replace(
+                    &mut block,
+                    Vec::with_capacity(block_size),
+                ));
+            }
+        }
+        if !block.is_empty() {
+            visit(block);
+        }
+
+        Ok(())
+    }
+
+    /// Every cached file with its hash, for the hashes of this process's algorithm
+    #[instrument(level = "debug", skip_all)]
+    pub fn get_all_cached_hashes(&self) -> Result<Vec<(PathBuf, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.path, ph.perceptual_hash 
+             FROM files f 
+             JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+             WHERE EXISTS (SELECT 1 FROM files WHERE path = f.path)
+               AND COALESCE(ph.algorithm, ?2) = ?1",
+        )?;
+
+        let rows = stmt.query_map(
+            params![hash_algorithm().stored_name(), UNRECORDED_ALGORITHM],
+            |row| {
+                Ok((
+                    PathBuf::from(row.get::<_, String>(0)?),
+                    row.get::<_, String>(1)?,
+                ))
+            },
+        )?;
+
         let mut results = Vec::new();
         for row in rows {
             results.push(row?);
@@ -415,6 +1714,7 @@ impl HashCache {
     }
 
     /// Generate a hash for the current set of cached files to detect changes
+    #[instrument(level = "debug", skip_all)]
     fn generate_cache_state_hash(&self) -> Result<String> {
         let mut stmt = self.conn.prepare(
             "SELECT f.path, ph.perceptual_hash 
@@ -437,106 +1737,280 @@ impl HashCache {
         Ok(encode_lower_hex(hasher.finalize()))
     }
 
-    /// Store duplicate groups for a given threshold
+    /// Store duplicate groups for a given threshold and comparison settings
+    #[instrument(level = "debug", skip_all, fields(threshold = key.threshold, groups = duplicates.len()))]
     pub fn store_duplicate_groups(
         &self,
-        threshold: u32,
+        key: GroupingKey,
         duplicates: &[Vec<PathBuf>],
     ) -> Result<()> {
-        if duplicates.is_empty() {
-            return Ok(());
-        }
-
+        let threshold = key.threshold;
+        let comparison = key.comparison();
         let cache_hash = self.generate_cache_state_hash()?;
+        let discovered_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
 
-        // Clear any existing duplicate groups for this threshold
-        self.conn.execute(
-            "DELETE FROM duplicate_groups WHERE threshold = ?1",
-            params![threshold],
-        )?;
-
-        let tx = self.conn.unchecked_transaction()?;
-
-        for group in duplicates {
-            if group.len() < 2 {
-                continue; // Skip non-duplicate groups
-            }
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
 
-            // Insert the group
+            // Clear any existing duplicate groups for these settings, and groups of any settings
+            // that were computed from hashes that have changed since
             tx.execute(
-                "INSERT INTO duplicate_groups (threshold, group_hash) VALUES (?1, ?2)",
-                params![threshold, cache_hash],
+                "DELETE FROM duplicate_groups
+                 WHERE (threshold = ?1 AND comparison = ?2) OR group_hash != ?3",
+                params![threshold, comparison, cache_hash],
+            )?;
+            let generation: i64 = tx.query_row(
+                "SELECT COALESCE(MAX(generation), 0) + 1 FROM group_discoveries WHERE threshold = ?1",
+                params![threshold],
+                |row| row.get(0),
             )?;
 
-            let group_id: i64 = tx.last_insert_rowid();
+            for group in duplicates {
+                if group.len() < 2 {
+                    continue; // Skip non-duplicate groups
+                }
+
+                // Insert the group
+                tx.execute(
+                    "INSERT INTO duplicate_groups (threshold, comparison, group_hash, tool_version)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![threshold, comparison, cache_hash, TOOL_VERSION],
+                )?;
+
+                let group_id: i64 = tx.last_insert_rowid();
 
-            // Insert the file paths for this group
-            for path in group {
+                // Insert the file paths for this group
+                for path in group {
+                    tx.execute(
+                        "INSERT INTO duplicate_group_files (group_id, file_path) VALUES (?1, ?2)",
+                        params![group_id, path_key(path)],
+                    )?;
+                }
+
+                let (group_key, paths) = discovery_key(group);
                 tx.execute(
-                    "INSERT INTO duplicate_group_files (group_id, file_path) VALUES (?1, ?2)",
-                    params![group_id, path.to_string_lossy()],
+                    "INSERT INTO group_discoveries
+                         (threshold, group_key, discovered_at, generation, paths)
+                     VALUES (?1, ?2, ?3, ?4, ?5)
+                     ON CONFLICT(threshold, group_key) DO UPDATE SET generation = excluded.generation",
+                    params![
+                        threshold,
+                        group_key,
+                        discovered_at,
+                        generation,
+                        serde_json::to_string(&paths)?
+                    ],
                 )?;
             }
-        }
+            tx.execute(
+                "DELETE FROM group_discoveries WHERE threshold = ?1 AND generation < ?2",
+                params![threshold, generation],
+            )?;
 
-        tx.commit()?;
+            tx.commit()?;
+            Ok(())
+        })?;
         info!(
-            "Cached {} duplicate groups for threshold {}",
+            "Cached {} duplicate groups for threshold {} ({})",
             duplicates.len(),
-            threshold
+            threshold,
+            comparison
         );
         Ok(())
     }
 
-    /// Get cached duplicate groups for a given threshold
-    pub fn get_cached_duplicate_groups(
+    /// Replace the stored pair distances with `pairs`, which must be every pair of cached files
+    /// within `max_distance` of each other
+    #[instrument(level = "debug", skip_all, fields(max_distance, pairs = pairs.len()))]
+    pub fn store_pair_distances(
         &self,
-        threshold: u32,
-        count: Option<usize>,
-        offset: Option<usize>,
-    ) -> Result<Option<Vec<Vec<PathBuf>>>> {
-        let current_cache_hash = self.generate_cache_state_hash()?;
+        max_distance: u32,
+        pairs: &[(&Path, &Path, u32)],
+    ) -> Result<()> {
+        let cache_hash = self.generate_cache_state_hash()?;
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            tx.execute("DELETE FROM pair_distances", [])?;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT OR REPLACE INTO pair_distances (path_a, path_b, distance)
+                     VALUES (?1, ?2, ?3)",
+                )?;
+                for (a, b, distance) in pairs {
+                    insert.execute(params![path_key(a), path_key(b), distance])?;
+                }
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO pair_distance_state (id, algorithm, group_hash, max_distance)
+                 VALUES (1, ?1, ?2, ?3)",
+                params![hash_algorithm().stored_name(), cache_hash, max_distance],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })?;
+        debug!("Stored {} pair distances up to {max_distance}", pairs.len());
+        Ok(())
+    }
 
-        // Check if we have cached groups for this threshold with matching cache state
+    /// The stored pairs within `threshold` of each other, or None when the stored distances
+    /// don't reach `threshold` or the hashes changed since they were stored
+    #[instrument(level = "debug", skip(self))]
+    pub fn get_pair_distances(
+        &self,
+        threshold: u32,
+    ) -> Result<Option<Vec<(PathBuf, PathBuf, u32)>>> {
         let mut stmt = self.conn.prepare(
-            "SELECT COUNT(*) FROM duplicate_groups WHERE threshold = ?1 AND group_hash = ?2",
+            "SELECT algorithm, group_hash, max_distance FROM pair_distance_state WHERE id = 1",
         )?;
-
-        let num_rows: i64 =
-            stmt.query_row(params![threshold, current_cache_hash], |row| row.get(0))?;
-
-        if num_rows == 0 {
-            info!(
-                "No valid cached duplicate groups found for threshold {}",
-                threshold
-            );
+        let mut rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u32>(2)?,
+            ))
+        })?;
+        let Some(state) = rows.next() else {
+            return Ok(None);
+        };
+        let (algorithm, group_hash, max_distance) = state?;
+        if algorithm != hash_algorithm().stored_name()
+            || max_distance < threshold
+            || group_hash != self.generate_cache_state_hash()?
+        {
             return Ok(None);
         }
 
-        let mut query =
-            "SELECT dg.id FROM duplicate_groups dg WHERE dg.threshold = ?1 AND dg.group_hash = ?2"
-                .to_string();
+        let mut pairs_stmt = self
+            .conn
+            .prepare("SELECT path_a, path_b, distance FROM pair_distances WHERE distance <= ?1")?;
+        let pairs = pairs_stmt
+            .query_map(params![threshold], |row| {
+                Ok((
+                    PathBuf::from(row.get::<_, String>(0)?),
+                    PathBuf::from(row.get::<_, String>(1)?),
+                    row.get(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(pairs))
+    }
 
-        let params = match (count, offset) {
-            (Some(count), Some(offset)) => {
-                query = format!("{query} LIMIT ?3 OFFSET ?4");
-                params![
-                    threshold,
-                    current_cache_hash,
-                    count.to_owned(),
+    /// The most recently discovered of the current groups at a threshold, newest first
+    pub fn get_group_discoveries(
+        &self,
+        threshold: u32,
+        limit: usize,
+    ) -> Result<Vec<GroupDiscovery>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT group_key, discovered_at, paths FROM group_discoveries
+             WHERE threshold = ?1 ORDER BY discovered_at DESC, id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![threshold, limit], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut discoveries = Vec::new();
+        for row in rows {
+            let (key, discovered_at, paths) = row?;
+            let paths: Vec<String> = serde_json::from_str(&paths)?;
+            discoveries.push(GroupDiscovery {
+                key,
+                discovered_at,
+                paths: paths.into_iter().map(PathBuf::from).collect(),
+            });
+        }
+        Ok(discoveries)
+    }
+
+    /// The groups last stored for these settings, even when hashes have changed since, for
+    /// updating them in place after a few files changed. Empty when none were stored
+    pub fn get_last_duplicate_groups(&self, key: GroupingKey) -> Result<Vec<Vec<PathBuf>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT dgf.group_id, dgf.file_path FROM duplicate_group_files dgf
+             JOIN duplicate_groups dg ON dg.id = dgf.group_id
+             WHERE dg.threshold = ?1 AND dg.comparison = ?2
+             ORDER BY dg.id, dgf.rowid",
+        )?;
+        let rows = stmt.query_map(params![key.threshold, key.comparison()], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                PathBuf::from(row.get::<_, String>(1)?),
+            ))
+        })?;
+
+        let mut groups: Vec<(i64, Vec<PathBuf>)> = Vec::new();
+        for row in rows {
+            let (group_id, path) = row?;
+            match groups.last_mut() {
+                Some((last_id, files)) if *last_id == group_id => files.push(path),
+                _ => groups.push((group_id, vec![path])),
+            }
+        }
+        Ok(groups.into_iter().map(|(_, files)| files).collect())
+    }
+
+    /// Get cached duplicate groups for a given threshold and comparison settings
+    #[instrument(level = "debug", skip_all, fields(threshold = key.threshold))]
+    pub fn get_cached_duplicate_groups(
+        &self,
+        key: GroupingKey,
+        count: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Option<Vec<Vec<PathBuf>>>> {
+        let threshold = key.threshold;
+        let comparison = key.comparison();
+        let current_cache_hash = self.generate_cache_state_hash()?;
+
+        // Check if we have cached groups for these settings with matching cache state
+        let mut stmt = self.conn.prepare(
+            "SELECT COUNT(*) FROM duplicate_groups
+             WHERE threshold = ?1 AND comparison = ?2 AND group_hash = ?3",
+        )?;
+
+        let num_rows: i64 = stmt
+            .query_row(params![threshold, comparison, current_cache_hash], |row| {
+                row.get(0)
+            })?;
+
+        if num_rows == 0 {
+            info!(
+                "No valid cached duplicate groups found for threshold {} ({})",
+                threshold, comparison
+            );
+            return Ok(None);
+        }
+
+        let mut query =
+            "SELECT dg.id FROM duplicate_groups dg WHERE dg.threshold = ?1 AND dg.comparison = ?2 AND dg.group_hash = ?3 ORDER BY dg.id"
+                .to_string();
+
+        let params = match (count, offset) {
+            (Some(count), Some(offset)) => {
+                query = format!("{query} LIMIT ?4 OFFSET ?5");
+                params![
+                    threshold,
+                    comparison,
+                    current_cache_hash,
+                    count.to_owned(),
                     offset.to_owned()
                 ]
             }
             (None, Some(offset)) => {
-                query = format!("{query} OFFSET ?3");
-                params![threshold, current_cache_hash, offset.to_owned()]
+                query = format!("{query} OFFSET ?4");
+                params![threshold, comparison, current_cache_hash, offset.to_owned()]
             }
             (Some(count), None) => {
-                query = format!("{query} LIMIT ?3");
-                params![threshold, current_cache_hash, count.to_owned()]
+                query = format!("{query} LIMIT ?4");
+                params![threshold, comparison, current_cache_hash, count.to_owned()]
             }
             (None, None) => {
-                params![threshold, current_cache_hash]
+                params![threshold, comparison, current_cache_hash]
             }
         };
         // Retrieve the cached groups
@@ -549,9 +2023,10 @@ impl HashCache {
         let mut duplicates = Vec::new();
 
         for group_id in group_ids {
-            let mut files_stmt = self
-                .conn
-                .prepare("SELECT file_path FROM duplicate_group_files WHERE group_id = ?1")?;
+            let mut files_stmt = self.conn.prepare(
+                // Insertion order, which is the order the group was stored in
+                "SELECT file_path FROM duplicate_group_files WHERE group_id = ?1 ORDER BY rowid",
+            )?;
 
             let file_paths: Vec<PathBuf> = files_stmt
                 .query_map(params![group_id], |row| {
@@ -573,27 +2048,963 @@ impl HashCache {
     }
 
     /// Clear all cached duplicate groups (e.g., when file cache changes)
+    /// Count cached hashes and groups by the version and settings that made them
+    pub fn provenance(&self) -> Result<CacheProvenance> {
+        let mut stmt = self.conn.prepare(
+            "SELECT tool_version, algorithm, hash_size, invariance, COUNT(*)
+             FROM perceptual_hashes
+             GROUP BY tool_version, algorithm, hash_size, invariance
+             ORDER BY tool_version, algorithm, hash_size, invariance",
+        )?;
+        let hashes = stmt
+            .query_map([], |row| {
+                Ok(HashProvenance {
+                    tool_version: row.get(0)?,
+                    algorithm: row.get(1)?,
+                    hash_size: row.get(2)?,
+                    invariance: row.get(3)?,
+                    hashes: row.get::<_, i64>(4)? as usize,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT threshold, comparison, tool_version, COUNT(*)
+             FROM duplicate_groups
+             GROUP BY threshold, comparison, tool_version
+             ORDER BY threshold, comparison, tool_version",
+        )?;
+        let group_sets = stmt
+            .query_map([], |row| {
+                Ok(GroupSetProvenance {
+                    threshold: row.get(0)?,
+                    comparison: row.get(1)?,
+                    tool_version: row.get(2)?,
+                    groups: row.get::<_, i64>(3)? as usize,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(CacheProvenance { hashes, group_sets })
+    }
+
+    /// Forget the hashes made by a tool version, or those cached before versions were recorded
+    /// when None, so the files are hashed again on the next scan. Returns the number of hashes
+    /// forgotten
+    pub fn forget_hashes_made_by(&self, tool_version: Option<&str>) -> Result<usize> {
+        let forgotten = self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            tx.execute(
+                "DELETE FROM files WHERE perceptual_hash_id IN
+                 (SELECT id FROM perceptual_hashes WHERE tool_version IS ?1)",
+                params![tool_version],
+            )?;
+            let forgotten = tx.execute(
+                "DELETE FROM perceptual_hashes WHERE tool_version IS ?1",
+                params![tool_version],
+            )?;
+            tx.commit()?;
+            Ok(forgotten)
+        })?;
+        if forgotten > 0 {
+            self.clear_duplicate_groups_cache()?;
+        }
+        Ok(forgotten)
+    }
+
     pub fn clear_duplicate_groups_cache(&self) -> Result<()> {
-        let deleted = self.conn.execute("DELETE FROM duplicate_groups", [])?;
+        let deleted = self.write(|conn| Ok(conn.execute("DELETE FROM duplicate_groups", [])?))?;
         if deleted > 0 {
             info!("Cleared {} cached duplicate groups", deleted);
         }
         Ok(())
     }
 
+    /// Record a manual merge or split
+    pub fn add_group_override(&self, group_override: &GroupOverride) -> Result<()> {
+        let (kind, path, other_path) = match group_override {
+            GroupOverride::Merge { a, b } => ("merge", path_key(a), Some(path_key(b))),
+            GroupOverride::Split { path } => ("split", path_key(path), None),
+        };
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO group_overrides (kind, path, other_path) VALUES (?1, ?2, ?3)",
+                params![kind, path, other_path],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Store imported details for a photo, replacing earlier imports
+    pub fn store_photo_metadata(&self, path: &Path, metadata: &PhotoMetadata) -> Result<()> {
+        let path = path_key(path);
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO photo_metadata (path, taken_at, description)
+                 VALUES (?1, ?2, ?3)",
+                params![path, metadata.taken_at, metadata.description],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Imported details for a photo, if any were imported
+    pub fn get_photo_metadata(&self, path: &Path) -> Result<Option<PhotoMetadata>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT taken_at, description FROM photo_metadata WHERE path = ?1")?;
+        let mut rows = stmt.query_map(params![path_key(path)], |row| {
+            Ok(PhotoMetadata {
+                taken_at: row.get(0)?,
+                description: row.get(1)?,
+            })
+        })?;
+
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the tags recorded for a file, only writing when they changed
+    pub fn store_file_tags(&self, path: &Path, tags: &[String]) -> Result<()> {
+        if self.get_file_tags(path)? == tags {
+            return Ok(());
+        }
+        let path = path_key(path);
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            tx.execute("DELETE FROM file_tags WHERE path = ?1", params![path])?;
+            for tag in tags {
+                tx.execute(
+                    "INSERT OR IGNORE INTO file_tags (path, tag) VALUES (?1, ?2)",
+                    params![path, tag],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Tags recorded for a file on its last scan, sorted
+    pub fn get_file_tags(&self, path: &Path) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT tag FROM file_tags WHERE path = ?1 ORDER BY tag")?;
+        let tags = stmt
+            .query_map(params![path_key(path)], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tags)
+    }
+
+    /// The cached 16x16 hash of a file's contents
+    pub fn get_fine_hash(&self, sha256: &str) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT fine_hash FROM fine_hashes WHERE sha256 = ?1")?;
+        let mut rows = stmt.query_map(params![sha256], |row| row.get(0))?;
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn store_fine_hash(&self, sha256: &str, fine_hash: &str) -> Result<()> {
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO fine_hashes (sha256, fine_hash) VALUES (?1, ?2)",
+                params![sha256, fine_hash],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The cached frame hashes of a video's contents, comma separated
+    pub fn get_video_signature(&self, sha256: &str) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT frame_hashes FROM video_signatures WHERE sha256 = ?1")?;
+        let mut rows = stmt.query_map(params![sha256], |row| row.get(0))?;
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn store_video_signature(&self, sha256: &str, frame_hashes: &str) -> Result<()> {
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO video_signatures (sha256, frame_hashes) VALUES (?1, ?2)",
+                params![sha256, frame_hashes],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn cached_file_count(&self) -> Result<usize> {
+        Ok(self
+            .conn
+            .query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0))?)
+    }
+
+    /// Up to `limit` cached files whose contents were checked longest ago, never checked first
+    pub fn files_to_verify(&self, limit: usize) -> Result<Vec<CachedContent>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.path, f.size, ph.sha256, f.modified
+             FROM files f
+             JOIN perceptual_hashes ph ON f.perceptual_hash_id = ph.id
+             LEFT JOIN content_checks cc ON cc.path = f.path
+             ORDER BY cc.checked_at IS NOT NULL, cc.checked_at, f.path
+             LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            Ok(CachedContent {
+                path: PathBuf::from(row.get::<_, String>(0)?),
+                size: row.get(1)?,
+                sha256: row.get(2)?,
+                modified: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Remember that these files' contents were just checked
+    pub fn record_content_checks(&self, paths: &[PathBuf]) -> Result<()> {
+        let checked_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            for path in paths {
+                tx.execute(
+                    "INSERT OR REPLACE INTO content_checks (path, checked_at) VALUES (?1, ?2)",
+                    params![path_key(path), checked_at],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Visit the files matching a file in another cache database, which is attached read-only
+    /// for the duration. Both sides are joined in SQLite and streamed, neither is loaded into
+    /// memory: identical contents by sha256, then near matches within `threshold` among the
+    /// files whose perceptual hashes share a prefix. Near matches that differ in the first 16
+    /// bits aren't found. Only hashes made on a `grid_size` grid with this process's algorithm
+    /// are compared, on both sides, since others aren't comparable
+    #[instrument(level = "debug", skip(self, visit))]
+    pub fn for_each_cross_cache_match(
+        &self,
+        other: &Path,
+        threshold: u32,
+        grid_size: u8,
+        mut visit: impl FnMut(CrossCacheMatch),
+    ) -> Result<()> {
+        if !extended_length_path(other).is_file() {
+            bail!("No cache database at {}", other.display());
+        }
+        // A URI so it's opened read-only, `?` and `#` would end the path
+        let uri = format!(
+            "file:{}?mode=ro",
+            path_key(other)
+                .replace('%', "%25")
+                .replace('?', "%3f")
+                .replace('#', "%23")
+        );
+        self.conn
+            .execute("ATTACH DATABASE ?1 AS other", params![uri])?;
+        let result = self.visit_cross_cache_matches(threshold, grid_size, &mut visit);
+        let detached = self.conn.execute("DETACH DATABASE other", []);
+        result?;
+        detached?;
+        Ok(())
+    }
+
+    fn visit_cross_cache_matches(
+        &self,
+        threshold: u32,
+        grid_size: u8,
+        visit: &mut impl FnMut(CrossCacheMatch),
+    ) -> Result<()> {
+        // Databases from before hash sizes and algorithms were recorded lack the columns, their
+        // hashes were all made the default way
+        let other_column = |column: &str| -> Result<String> {
+            let recorded = self.conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('perceptual_hashes', 'other') WHERE name = ?1",
+                params![column],
+                |row| row.get::<_, i64>(0),
+            )? > 0;
+            Ok(if recorded {
+                format!("oph.{column}")
+            } else {
+                "NULL".to_string()
+            })
+        };
+        let (other_size, other_algorithm) =
+            (other_column("hash_size")?, other_column("algorithm")?);
+        let comparable = |other_size: &str, other_algorithm: &str| {
+            format!(
+                "COALESCE(ph.hash_size, ?4) = ?3 AND COALESCE(ph.algorithm, ?2) = ?1
+                 AND COALESCE({other_size}, ?4) = ?3 AND COALESCE({other_algorithm}, ?2) = ?1"
+            )
+        };
+        let settings = params![
+            hash_algorithm().stored_name(),
+            UNRECORDED_ALGORITHM,
+            grid_size,
+            DEFAULT_GRID_SIZE
+        ];
+
+        let mut identical = self.conn.prepare(&format!(
+            "SELECT f.path, o.path
+             FROM main.files f
+             JOIN main.perceptual_hashes ph ON ph.id = f.perceptual_hash_id
+             JOIN other.perceptual_hashes oph ON oph.sha256 = ph.sha256
+             JOIN other.files o ON o.perceptual_hash_id = oph.id
+             WHERE {}
+             ORDER BY f.path, o.path",
+            comparable(&other_size, &other_algorithm)
+        ))?;
+        let rows = identical.query_map(settings, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (local, other) = row?;
+            visit(CrossCacheMatch {
+                local: PathBuf::from(local),
+                other: PathBuf::from(other),
+                distance: None,
+            });
+        }
+
+        // The other cache is attached read-only, so its prefixes are indexed in a temp table,
+        // which SQLite keeps in a temporary file rather than in memory
+        self.conn
+            .execute("DROP TABLE IF EXISTS temp.other_prefixes", [])?;
+        self.conn.execute(
+            &format!(
+                "CREATE TEMP TABLE other_prefixes AS
+                     SELECT substr(oph.perceptual_hash, 1, {CROSS_CACHE_PREFIX_LEN}) AS prefix,
+                            o.path, oph.sha256, oph.perceptual_hash,
+                            {other_size} AS hash_size, {other_algorithm} AS algorithm
+                     FROM other.files o
+                     JOIN other.perceptual_hashes oph ON oph.id = o.perceptual_hash_id"
+            ),
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX temp.idx_other_prefixes ON other_prefixes(prefix)",
+            [],
+        )?;
+        let mut similar = self.conn.prepare(&format!(
+            "SELECT f.path, ph.perceptual_hash, op.path, op.perceptual_hash
+             FROM main.files f
+             JOIN main.perceptual_hashes ph ON ph.id = f.perceptual_hash_id
+             JOIN temp.other_prefixes op
+                 ON op.prefix = substr(ph.perceptual_hash, 1, {CROSS_CACHE_PREFIX_LEN})
+             WHERE op.sha256 != ph.sha256 AND {}
+             ORDER BY f.path, op.path",
+            comparable("op.hash_size", "op.algorithm")
+        ))?;
+        let rows = similar.query_map(settings, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        for row in rows {
+            let (local, local_hash, other, other_hash) = row?;
+            let Some(distance) = hash_distance(&local_hash, &other_hash) else {
+                continue;
+            };
+            if distance <= threshold {
+                visit(CrossCacheMatch {
+                    local: PathBuf::from(local),
+                    other: PathBuf::from(other),
+                    distance: Some(distance),
+                });
+            }
+        }
+        drop(similar);
+        self.conn.execute("DROP TABLE temp.other_prefixes", [])?;
+        Ok(())
+    }
+
+    /// Cached files whose perceptual hash is within `threshold` of this one, closest first.
+    /// Candidates come from the indexed hash prefixes within a couple of bits of its own and only
+    /// they are compared in full, so a lookup stays fast however large the cache is. Near matches
+    /// differing in more than 2 of the first 16 bits aren't found
+    #[instrument(level = "debug", skip(self))]
+    pub fn find_similar(
+        &self,
+        perceptual_hash: &str,
+        threshold: u32,
+    ) -> Result<Vec<(PathBuf, u32)>> {
+        let Some(prefix) = hash_prefix(perceptual_hash)
+            .filter(|_| perceptual_hash.chars().all(|c| c.is_ascii_hexdigit()))
+        else {
+            bail!("Not a perceptual hash: {perceptual_hash}");
+        };
+        let mut candidates = self.conn.prepare(
+            "SELECT f.path, ph.perceptual_hash
+             FROM perceptual_hashes ph
+             JOIN files f ON f.perceptual_hash_id = ph.id
+             WHERE ph.hash_prefix = ?1 AND COALESCE(ph.algorithm, ?3) = ?2",
+        )?;
+        let mut similar = Vec::new();
+        for candidate_prefix in prefixes_within(prefix, threshold.min(HASH_PREFIX_SEARCH_RADIUS)) {
+            let rows = candidates.query_map(
+                params![
+                    candidate_prefix,
+                    hash_algorithm().stored_name(),
+                    UNRECORDED_ALGORITHM
+                ],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )?;
+            for row in rows {
+                let (path, hash) = row?;
+                let Some(distance) = hash_distance(perceptual_hash, &hash) else {
+                    continue;
+                };
+                if distance <= threshold {
+                    similar.push((PathBuf::from(path), distance));
+                }
+            }
+        }
+        similar.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(similar)
+    }
+
+    /// Whether the database has an entry for this path, whatever its contents
+    pub fn is_path_cached(&self, path: &Path) -> Result<bool> {
+        let mut stmt = self.conn.prepare("SELECT 1 FROM files WHERE path = ?1")?;
+        Ok(stmt.exists(params![path_key(path)])?)
+    }
+
+    /// Cached files that are `path` or below it, for when a file or a whole folder is gone
+    pub fn get_cached_paths_under(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let prefix = PathBuf::from(path_key(path));
+        let mut stmt = self.conn.prepare("SELECT path FROM files ORDER BY path")?;
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(paths
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|cached| cached.starts_with(&prefix))
+            .collect())
+    }
+
+    /// Remember how long hashing a scan's files took, with the folder it scanned when it was
+    /// only one
+    pub fn record_hashing_run(
+        &self,
+        root: Option<&Path>,
+        files: usize,
+        bytes: u64,
+        elapsed: Duration,
+    ) -> Result<()> {
+        let finished_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        let root = root.map(path_key);
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO scan_runs (finished_at, files, bytes, seconds, root)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![finished_at, files, bytes, elapsed.as_secs_f64(), root],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Hashing speed over the most recent runs of one scan folder, or of all runs without
+    /// `root`. None before the first one
+    pub fn recent_hashing_speed(
+        &self,
+        root: Option<&Path>,
+        runs: usize,
+    ) -> Result<Option<HashingSpeed>> {
+        let (files, bytes, seconds): (Option<f64>, Option<f64>, Option<f64>) =
+            self.conn.query_row(
+                "SELECT SUM(files), SUM(bytes), SUM(seconds) FROM (
+                     SELECT files, bytes, seconds FROM scan_runs
+                     WHERE seconds > 0 AND (?1 IS NULL OR root = ?1)
+                     ORDER BY id DESC LIMIT ?2
+                 )",
+                params![root.map(path_key), runs],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+        Ok(match (files, bytes, seconds) {
+            (Some(files), Some(bytes), Some(seconds)) if bytes > 0.0 && seconds > 0.0 => {
+                Some(HashingSpeed {
+                    files_per_second: files / seconds,
+                    bytes_per_second: bytes / seconds,
+                })
+            }
+            _ => None,
+        })
+    }
+
+    /// Pin or unpin the group made of these files
+    pub fn set_group_pinned(&self, paths: &[PathBuf], pinned: bool) -> Result<()> {
+        self.update_annotations(paths, "pinned", &pinned)
+    }
+
+    /// Attach a note to the group made of these files, or remove it with `None`
+    pub fn set_group_note(&self, paths: &[PathBuf], note: Option<&str>) -> Result<()> {
+        self.update_annotations(paths, "note", &note)
+    }
+
+    fn update_annotations(
+        &self,
+        paths: &[PathBuf],
+        column: &'static str,
+        value: &dyn ToSql,
+    ) -> Result<()> {
+        let updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        let update = format!(
+            "INSERT INTO group_annotations (path, {column}, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(path) DO UPDATE SET {column} = excluded.{column},
+                                             updated_at = excluded.updated_at"
+        );
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            for path in paths {
+                tx.execute(&update, params![path_key(path), value, updated_at])?;
+            }
+            // Files with neither a pin nor a note have nothing left to remember
+            tx.execute(
+                "DELETE FROM group_annotations WHERE pinned = 0 AND note IS NULL",
+                [],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Pins and notes of every annotated file
+    pub fn get_group_annotations(&self) -> Result<BTreeMap<PathBuf, FileAnnotation>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, pinned, note, updated_at FROM group_annotations")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                PathBuf::from(row.get::<_, String>(0)?),
+                FileAnnotation {
+                    pinned: row.get(1)?,
+                    note: row.get(2)?,
+                    updated_at: row.get(3)?,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// All manual merges and splits in the order they were made
+    pub fn get_group_overrides(&self) -> Result<Vec<GroupOverride>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT kind, path, other_path FROM group_overrides ORDER BY id")?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                PathBuf::from(row.get::<_, String>(1)?),
+                row.get::<_, Option<String>>(2)?.map(PathBuf::from),
+            ))
+        })?;
+
+        let mut overrides = Vec::new();
+        for row in rows {
+            match row? {
+                (kind, a, Some(b)) if kind == "merge" => {
+                    overrides.push(GroupOverride::Merge { a, b })
+                }
+                (kind, path, _) if kind == "split" => overrides.push(GroupOverride::Split { path }),
+                (kind, path, _) => warn!(
+                    "Ignoring unknown group override {kind} for {}",
+                    path.display()
+                ),
+            }
+        }
+        Ok(overrides)
+    }
+
+    /// Record a file sent by the web server run that started at `session_started`
+    pub fn log_served(
+        &self,
+        session_started: i64,
+        path: &Path,
+        kind: ServeKind,
+        bytes: u64,
+    ) -> Result<()> {
+        let served_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO serve_log (session_started, path, kind, bytes, served_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    session_started,
+                    path_key(path),
+                    kind.as_str(),
+                    bytes,
+                    served_at
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Drop the served files of all but the newest `keep` web server runs
+    pub fn prune_serve_log(&self, keep: usize) -> Result<usize> {
+        self.write(|conn| {
+            Ok(conn.execute(
+                "DELETE FROM serve_log WHERE session_started NOT IN (
+                     SELECT DISTINCT session_started FROM serve_log
+                     ORDER BY session_started DESC LIMIT ?1
+                 )",
+                params![keep],
+            )?)
+        })
+    }
+
+    /// Totals per web server run, newest first
+    pub fn get_session_usage(&self) -> Result<Vec<SessionUsage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT session_started, COUNT(*), COUNT(DISTINCT path), SUM(bytes),
+                    SUM(CASE WHEN kind = 'image' THEN bytes ELSE 0 END),
+                    SUM(CASE WHEN kind = 'thumbnail' THEN bytes ELSE 0 END)
+             FROM serve_log
+             GROUP BY session_started
+             ORDER BY session_started DESC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SessionUsage {
+                started_at: row.get(0)?,
+                requests: row.get(1)?,
+                files: row.get(2)?,
+                bytes: row.get(3)?,
+                image_bytes: row.get(4)?,
+                thumbnail_bytes: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The last `limit` files sent during the web server run that started at `session_started`,
+    /// newest first
+    pub fn get_served_files(&self, session_started: i64, limit: usize) -> Result<Vec<ServedFile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, kind, bytes, served_at FROM serve_log
+             WHERE session_started = ?1
+             ORDER BY id DESC
+             LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![session_started, limit], |row| {
+            Ok((
+                PathBuf::from(row.get::<_, String>(0)?),
+                row.get::<_, String>(1)?,
+                row.get(2)?,
+                row.get(3)?,
+            ))
+        })?;
+
+        let mut served = Vec::new();
+        for row in rows {
+            let (path, kind, bytes, served_at) = row?;
+            match ServeKind::from_name(&kind) {
+                Some(kind) => served.push(ServedFile {
+                    path,
+                    kind,
+                    bytes,
+                    served_at,
+                }),
+                None => warn!("Ignoring unknown served kind {kind} for {}", path.display()),
+            }
+        }
+        Ok(served)
+    }
+
+    /// Remember that the image at `path` was downloaded from `url`
+    pub fn store_url_source(&self, url: &str, path: &Path) -> Result<()> {
+        let fetched_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO url_sources (url, path, fetched_at) VALUES (?1, ?2, ?3)",
+                params![url, path_key(path), fetched_at],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// URLs the image at `path` was downloaded from
+    pub fn get_source_urls(&self, path: &Path) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT url FROM url_sources WHERE path = ?1 ORDER BY url")?;
+        let urls = stmt.query_map(params![path_key(path)], |row| row.get(0))?;
+        Ok(urls.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Record that `link.path` now links into the store
+    pub fn add_store_link(&self, link: &StoreLink) -> Result<()> {
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO store_links (path, sha256, store_path, kind, modified)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    path_key(&link.path),
+                    link.sha256,
+                    path_key(&link.store_path),
+                    link.kind.as_str(),
+                    link.modified
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn get_store_link(&self, path: &Path) -> Result<Option<StoreLink>> {
+        Ok(self
+            .query_store_links("WHERE path = ?1", params![path_key(path)])?
+            .pop())
+    }
+
+    /// Every recorded link, by path
+    pub fn get_store_links(&self) -> Result<Vec<StoreLink>> {
+        self.query_store_links("ORDER BY path", params![])
+    }
+
+    fn query_store_links(
+        &self,
+        clause: &str,
+        values: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<StoreLink>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT path, sha256, store_path, kind, modified FROM store_links {clause}"
+        ))?;
+        let rows = stmt.query_map(values, |row| {
+            Ok((
+                PathBuf::from(row.get::<_, String>(0)?),
+                row.get::<_, String>(1)?,
+                PathBuf::from(row.get::<_, String>(2)?),
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+            ))
+        })?;
+
+        let mut links = Vec::new();
+        for row in rows {
+            let (path, sha256, store_path, kind, modified) = row?;
+            match LinkKind::from_name(&kind) {
+                Some(kind) => links.push(StoreLink {
+                    path,
+                    sha256,
+                    store_path,
+                    kind,
+                    modified,
+                }),
+                None => warn!("Ignoring unknown link kind {kind} for {}", path.display()),
+            }
+        }
+        Ok(links)
+    }
+
+    pub fn remove_store_link(&self, path: &Path) -> Result<()> {
+        self.write(|conn| {
+            conn.execute(
+                "DELETE FROM store_links WHERE path = ?1",
+                params![path_key(path)],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// How many recorded links share this store file
+    pub fn count_store_links_to(&self, store_path: &Path) -> Result<usize> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM store_links WHERE store_path = ?1",
+            params![path_key(store_path)],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Options stored with `config set` or through the web API
+    pub fn get_settings(&self) -> Result<Settings> {
+        read_settings(&self.conn)
+    }
+
+    /// Replace the stored options
+    pub fn store_settings(&self, settings: &Settings) -> Result<()> {
+        self.write(|conn| {
+            let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            write_settings(&tx, settings)?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Forget all manual merges and splits, returns how many were removed
+    pub fn clear_group_overrides(&self) -> Result<usize> {
+        self.write(|conn| Ok(conn.execute("DELETE FROM group_overrides", [])?))
+    }
+
+    /// Record that a job started, returning the run's id
+    pub fn start_job_run(&self, kind: JobKind, trigger: JobTrigger) -> Result<i64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO job_runs (kind, triggered_by, started_at, state) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    kind.as_str(),
+                    trigger.as_str(),
+                    now,
+                    RunState::Running.as_str()
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    /// Record how a job run ended and what it logged, then drop all but the newest `keep` runs
+    pub fn finish_job_run(
+        &self,
+        id: i64,
+        state: RunState,
+        message: &str,
+        log: &[LogLine],
+        keep: usize,
+    ) -> Result<()> {
+        let log = serde_json::to_string(log)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        self.write(|conn| {
+            conn.execute(
+                "UPDATE job_runs SET finished_at = ?2, state = ?3, message = ?4, log = ?5
+                 WHERE id = ?1",
+                params![id, now, state.as_str(), message, log],
+            )?;
+            conn.execute(
+                "DELETE FROM job_runs WHERE id NOT IN (
+                     SELECT id FROM job_runs ORDER BY id DESC LIMIT ?1
+                 )",
+                params![keep],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Mark runs that are still running as failed, for a server starting after one that stopped
+    /// in the middle of a job
+    pub fn fail_interrupted_job_runs(&self, message: &str) -> Result<usize> {
+        self.write(|conn| {
+            Ok(conn.execute(
+                "UPDATE job_runs SET state = ?1, message = ?2 WHERE state = ?3",
+                params![
+                    RunState::Failed.as_str(),
+                    message,
+                    RunState::Running.as_str()
+                ],
+            )?)
+        })
+    }
+
+    /// The newest `limit` job runs, newest first
+    pub fn get_job_runs(&self, limit: usize) -> Result<Vec<JobRun>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, kind, triggered_by, started_at, finished_at, state, message, log
+             FROM job_runs ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, String>(7)?,
+            ))
+        })?;
+        let mut runs = Vec::new();
+        for row in rows {
+            let (id, kind, trigger, started_at, finished_at, state, message, log) = row?;
+            runs.push(JobRun {
+                id,
+                kind: JobKind::parse(&kind)?,
+                trigger: JobTrigger::parse(&trigger)?,
+                started_at,
+                finished_at,
+                state: RunState::parse(&state)?,
+                message,
+                log: serde_json::from_str(&log)?,
+            });
+        }
+        Ok(runs)
+    }
+
+    /// Rewrite the database without its free pages and refresh the query planner's statistics,
+    /// after taking a backup
+    pub fn compact(&self) -> Result<()> {
+        self.backup("compaction")?;
+        self.write(|conn| {
+            conn.execute_batch("PRAGMA optimize; VACUUM;")?;
+            Ok(())
+        })
+    }
+
     /// Completely clear all cache data (files, hashes, duplicate groups)
     pub fn clear_all_cache(&self) -> Result<()> {
+        self.backup("clear-cache")?;
         info!("Clearing all cache data...");
 
-        let tx = self.conn.unchecked_transaction()?;
-
-        // Clear all tables in reverse dependency order
-        let duplicate_groups_deleted = tx.execute("DELETE FROM duplicate_group_files", [])?;
-        let files_deleted = tx.execute("DELETE FROM duplicate_groups", [])?;
-        let perceptual_hashes_deleted = tx.execute("DELETE FROM files", [])?;
-        let _final_deleted = tx.execute("DELETE FROM perceptual_hashes", [])?;
-
-        tx.commit()?;
+        let (duplicate_groups_deleted, files_deleted, perceptual_hashes_deleted) =
+            self.write(|conn| {
+                let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+
+                // Clear all tables in reverse dependency order
+                let duplicate_groups_deleted =
+                    tx.execute("DELETE FROM duplicate_group_files", [])?;
+                let files_deleted = tx.execute("DELETE FROM duplicate_groups", [])?;
+                let perceptual_hashes_deleted = tx.execute("DELETE FROM files", [])?;
+                let _final_deleted = tx.execute("DELETE FROM perceptual_hashes", [])?;
+                tx.execute("DELETE FROM photo_metadata", [])?;
+                tx.execute("DELETE FROM url_sources", [])?;
+                tx.execute("DELETE FROM file_tags", [])?;
+                tx.execute("DELETE FROM group_discoveries", [])?;
+                tx.execute("DELETE FROM pair_distances", [])?;
+                tx.execute("DELETE FROM pair_distance_state", [])?;
+                tx.execute("DELETE FROM fine_hashes", [])?;
+                tx.execute("DELETE FROM video_signatures", [])?;
+                tx.execute("DELETE FROM content_checks", [])?;
+
+                tx.commit()?;
+                Ok((
+                    duplicate_groups_deleted,
+                    files_deleted,
+                    perceptual_hashes_deleted,
+                ))
+            })?;
 
         info!("Cleared all cache data:");
         info!("  - {} duplicate group files", duplicate_groups_deleted);
@@ -603,9 +3014,41 @@ impl HashCache {
 
         // Vacuum the database to reclaim disk space
         info!("Reclaiming disk space...");
-        self.conn.execute("VACUUM", [])?;
+        self.write(|conn| Ok(conn.execute("VACUUM", [])?))?;
         info!("Database optimization complete");
 
         Ok(())
     }
 }
+
+/// A group's files sorted by path, and a key that's the same whenever the group has these files
+fn discovery_key(group: &[PathBuf]) -> (String, Vec<String>) {
+    let mut paths: Vec<String> = group.iter().map(|path| path_key(path)).collect();
+    paths.sort();
+    let key = encode_lower_hex(Sha256::digest(paths.join("\n").as_bytes()));
+    (key, paths)
+}
+
+fn is_busy_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error
+            .downcast_ref::<rusqlite::Error>()
+            .and_then(rusqlite::Error::sqlite_error_code),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+fn retry_on_busy<T>(retries: u32, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && is_busy_error(&e) => {
+                let delay = WRITE_RETRY_BASE_DELAY * 2u32.pow(attempt.min(6));
+                warn!("Database is busy, retrying in {}ms: {e}", delay.as_millis());
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use imghash::ImageHash;
+
+use crate::algorithm::{hash_algorithm, GridHasher};
+use crate::cache::DEFAULT_GRID_SIZE;
+
+/// How an image is turned, clockwise, to reach its canonical orientation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    Original,
+    Rotated90,
+    Rotated180,
+    Rotated270,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 4] = [
+        Orientation::Original,
+        Orientation::Rotated90,
+        Orientation::Rotated180,
+        Orientation::Rotated270,
+    ];
+
+    /// Turn an image this way
+    pub fn apply(self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            Orientation::Original => image.clone(),
+            Orientation::Rotated90 => image.rotate90(),
+            Orientation::Rotated180 => image.rotate180(),
+            Orientation::Rotated270 => image.rotate270(),
+        }
+    }
+
+    /// The turn that undoes this one
+    pub fn inverse(self) -> Orientation {
+        match self {
+            Orientation::Original => Orientation::Original,
+            Orientation::Rotated90 => Orientation::Rotated270,
+            Orientation::Rotated180 => Orientation::Rotated180,
+            Orientation::Rotated270 => Orientation::Rotated90,
+        }
+    }
+}
+
+/// The hash scans store for an image, with the turn that gives it: the image is hashed in all
+/// four orientations and the hash that sorts first is kept. Rotated copies of an image get the
+/// same hash, and turning each by its orientation lines them up. Mirrored copies aren't
+/// canonicalized and generally hash differently. When several orientations hash the same,
+/// e.g. for symmetric images, the first in `Orientation::ALL` wins
+pub fn canonical_hash(image: &DynamicImage) -> Result<(ImageHash, Orientation)> {
+    canonical_hash_with(&hash_algorithm().hasher(DEFAULT_GRID_SIZE)?, image)
+}
+
+/// `canonical_hash` with a hasher of another size or algorithm
+pub fn canonical_hash_with(
+    hasher: &GridHasher,
+    image: &DynamicImage,
+) -> Result<(ImageHash, Orientation)> {
+    let mut candidates = Vec::with_capacity(Orientation::ALL.len());
+    for orientation in Orientation::ALL {
+        let hash = hasher.hash_from_img(&orientation.apply(image))?;
+        candidates.push((hash.encode()?, hash, orientation));
+    }
+    // Stable, so ties keep the order of `Orientation::ALL`
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+    candidates
+        .into_iter()
+        .next()
+        .map(|(_, hash, orientation)| (hash, orientation))
+        .ok_or_else(|| anyhow!("No rotation candidate hashes generated"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{pattern, FIXTURE_SIZE};
+
+    #[test]
+    fn rotations_share_a_hash_and_line_up() {
+        let image = pattern(FIXTURE_SIZE, FIXTURE_SIZE, 7);
+        let (hash, orientation) = canonical_hash(&image).expect("hash");
+        let canonical = orientation.apply(&image);
+
+        for turn in Orientation::ALL {
+            let rotated = turn.apply(&image);
+            let (rotated_hash, rotated_orientation) = canonical_hash(&rotated).expect("hash");
+            assert_eq!(rotated_hash, hash, "{turn:?} changed the hash");
+            assert_eq!(
+                rotated_orientation.apply(&rotated).to_rgb8(),
+                canonical.to_rgb8(),
+                "{turn:?} didn't line up"
+            );
+            assert_eq!(turn.inverse().apply(&rotated).to_rgb8(), image.to_rgb8());
+        }
+    }
+
+    #[test]
+    fn mirrored_rotations_share_the_mirror_image_hash() {
+        let image = pattern(FIXTURE_SIZE, FIXTURE_SIZE, 11);
+        let (mirrored_hash, _) = canonical_hash(&image.fliph()).expect("hash");
+        for turn in Orientation::ALL {
+            let (hash, _) = canonical_hash(&turn.apply(&image).fliph()).expect("hash");
+            assert_eq!(
+                hash, mirrored_hash,
+                "{turn:?} then flipped changed the hash"
+            );
+            let (hash, _) = canonical_hash(&turn.apply(&image).flipv()).expect("hash");
+            assert_eq!(
+                hash, mirrored_hash,
+                "{turn:?} then flipped changed the hash"
+            );
+        }
+    }
+}
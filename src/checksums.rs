@@ -0,0 +1,76 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::cache::HashCache;
+use crate::hasher::calculate_file_sha256;
+
+/// Machine readable listing printed to stdout, with logs moved to stderr to keep it clean
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// `<sha256>  <path>` lines, as written by `sha256sum` and checked by `sha256sum -c`
+    Sha256sum,
+    /// Duplicate groups as JSON with each file's size and distance, which `--baseline` reads back
+    Json,
+}
+
+/// Which files a `sha256sum` listing covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputScope {
+    /// Every file in a duplicate group, group after group
+    #[default]
+    Duplicates,
+    /// Every hashed file
+    All,
+}
+
+/// Write a `sha256sum` line for each path, using the sha256 cached while hashing. Returns how
+/// many lines were written
+pub fn write_sha256sums(
+    paths: &[PathBuf],
+    cache: &HashCache,
+    writer: &mut impl Write,
+) -> Result<usize> {
+    for path in paths {
+        let sha256 = match cache.get_cached_image_details(path)? {
+            Some(details) => details.sha256,
+            None => calculate_file_sha256(path)?,
+        };
+        writeln!(writer, "{}", sha256sum_line(&sha256, path))?;
+    }
+    writer.flush()?;
+    Ok(paths.len())
+}
+
+/// A line in `sha256sum`'s text mode format. Like GNU coreutils, a path containing a backslash
+/// or newline is escaped and the line starts with a backslash, so the list stays one file per line
+pub fn sha256sum_line(sha256: &str, path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if !path.contains(['\\', '\n', '\r']) {
+        return format!("{sha256}  {path}");
+    }
+    let escaped = path
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\\{sha256}  {escaped}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_match_sha256sum_output() {
+        let sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(
+            sha256sum_line(sha256, Path::new("photos/a b.jpg")),
+            format!("{sha256}  photos/a b.jpg")
+        );
+        assert_eq!(
+            sha256sum_line(sha256, Path::new("odd\nname\\.jpg")),
+            format!("\\{sha256}  odd\\nname\\\\.jpg")
+        );
+    }
+}
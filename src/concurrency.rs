@@ -0,0 +1,48 @@
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Files read and checksummed at once unless configured otherwise. Reading is mostly waiting,
+/// on network shares especially, so this is more than most machines have cores
+pub const DEFAULT_IO_THREADS: usize = 8;
+
+/// Set from `--io-threads` at startup
+static IO_THREADS: OnceLock<usize> = OnceLock::new();
+/// Set from `--cpu-threads` at startup, the global rayon pool is used when unset
+static CPU_POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// Limit how many files are read for their checksum at once, and how many are decoded and
+/// hashed at once, for the rest of the process
+pub fn set_concurrency(io_threads: Option<usize>, cpu_threads: Option<usize>) {
+    if let Some(threads) = io_threads {
+        if IO_THREADS.set(threads.max(1)).is_err() {
+            warn!("IO threads were already set");
+        }
+    }
+    if let Some(threads) = cpu_threads {
+        match ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .thread_name(|index| format!("hash-{index}"))
+            .build()
+        {
+            Ok(pool) => {
+                if CPU_POOL.set(pool).is_err() {
+                    warn!("CPU threads were already set");
+                }
+            }
+            Err(e) => warn!("Could not start {threads} hashing threads: {e}"),
+        }
+    }
+}
+
+pub fn io_threads() -> usize {
+    IO_THREADS.get().copied().unwrap_or(DEFAULT_IO_THREADS)
+}
+
+/// Run parallel decoding work within the CPU limit
+pub fn on_cpu_threads<T: Send>(work: impl FnOnce() -> T + Send) -> T {
+    match CPU_POOL.get() {
+        Some(pool) => pool.install(work),
+        None => work(),
+    }
+}
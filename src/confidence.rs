@@ -0,0 +1,122 @@
+use clap::ValueEnum;
+use imghash::ImageHash;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::cache::HashCache;
+use crate::hasher::decode_hashes;
+
+/// How sure we are that a group's files are the same picture. Ordered from least to most
+/// certain, so `tier >= Confidence::NearCertain` picks the safer groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Confidence {
+    /// Every pair of files is within the threshold, or linked through files that are
+    Probable,
+    /// Every pair of files is within a third of the threshold
+    NearCertain,
+    /// Byte-for-byte identical files
+    Exact,
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Confidence::Probable => "probable",
+            Confidence::NearCertain => "near-certain",
+            Confidence::Exact => "exact",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Confidence tier of a duplicate group found with `threshold`, from the cached hashes.
+/// Files that aren't cached can't be compared, which leaves the group probable at best
+pub fn group_confidence(group: &[PathBuf], threshold: u32, cache: &HashCache) -> Confidence {
+    let mut sha256s = Vec::new();
+    let mut encoded = Vec::new();
+    for path in group {
+        let Ok(Some((perceptual_hash, sha256))) = cache.get_cached_hash_details(path) else {
+            return Confidence::Probable;
+        };
+        sha256s.push(sha256);
+        encoded.push((path.clone(), perceptual_hash));
+    }
+    let hashes: Vec<ImageHash> = decode_hashes(encoded)
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    if hashes.len() < group.len() {
+        return Confidence::Probable;
+    }
+    tier(&sha256s, &hashes, threshold)
+}
+
+/// Only the groups at or above `min`
+pub fn retain_confidence(
+    groups: Vec<Vec<PathBuf>>,
+    min: Confidence,
+    threshold: u32,
+    cache: &HashCache,
+) -> Vec<Vec<PathBuf>> {
+    groups
+        .into_iter()
+        .filter(|group| group_confidence(group, threshold, cache) >= min)
+        .collect()
+}
+
+fn tier(sha256s: &[String], hashes: &[ImageHash], threshold: u32) -> Confidence {
+    if sha256s.windows(2).all(|pair| pair[0] == pair[1]) {
+        return Confidence::Exact;
+    }
+    // Groups are joined transitively, so the widest pair decides
+    let mut widest = 0;
+    for (i, a) in hashes.iter().enumerate() {
+        for b in &hashes[i + 1..] {
+            match a.distance(b) {
+                Ok(distance) => widest = widest.max(distance),
+                Err(_) => return Confidence::Probable,
+            }
+        }
+    }
+    if widest <= (threshold / 3) as usize {
+        Confidence::NearCertain
+    } else {
+        Confidence::Probable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(encoded: &[&str]) -> Vec<ImageHash> {
+        decode_hashes(
+            encoded
+                .iter()
+                .map(|hash| (PathBuf::from("/x.png"), hash.to_string()))
+                .collect(),
+        )
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect()
+    }
+
+    #[test]
+    fn tiers_follow_content_and_distance() {
+        let same = vec!["a".repeat(64), "a".repeat(64)];
+        let different = vec!["a".repeat(64), "b".repeat(64)];
+
+        let identical = hashes(&["ffff0000ffff0000", "ffff0000ffff0000"]);
+        assert_eq!(tier(&same, &identical, 12), Confidence::Exact);
+        assert_eq!(tier(&different, &identical, 12), Confidence::NearCertain);
+
+        // Distance 4 is within 12 / 3, but not within 9 / 3
+        let close = hashes(&["ffff0000ffff0000", "ffff0000ffff000f"]);
+        assert_eq!(tier(&different, &close, 12), Confidence::NearCertain);
+        assert_eq!(tier(&different, &close, 9), Confidence::Probable);
+
+        assert!(Confidence::Exact > Confidence::NearCertain);
+        assert!(Confidence::NearCertain > Confidence::Probable);
+    }
+}
@@ -1,32 +1,373 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::info;
 
-use crate::cache::Config;
+use crate::cache::{default_database_path, Config, ResolvedConfig, DEFAULT_GRID_SIZE};
+use crate::estimate::format_size;
+use crate::settings::{layered_config, load_stored_settings, Settings};
 
-pub fn load_config() -> Result<Config> {
+/// Location of the config file in the XDG config directory
+pub fn config_file_path() -> Result<PathBuf> {
     let config_dir =
         dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
+    Ok(config_dir.join("vibe-image-comparator.json"))
+}
 
-    let config_path = config_dir.join("vibe-image-comparator.json");
+pub fn load_config() -> Result<Config> {
+    load_config_from(&config_file_path()?)
+}
 
+/// Read a config file, defaults when it doesn't exist
+pub fn load_config_from(config_path: &Path) -> Result<Config> {
     if config_path.exists() {
-        let config_str = std::fs::read_to_string(&config_path)?;
+        let config_str = std::fs::read_to_string(config_path)?;
         let config: Config = serde_json::from_str(&config_str)?;
-        println!("Loaded config from: {}", config_path.display());
+        if config.grid_size == Some(0) {
+            bail!(
+                "grid_size in {} must be between 1 and 255",
+                config_path.display()
+            );
+        }
+        info!("Loaded config from: {}", config_path.display());
         Ok(config)
     } else {
         Ok(Config::default())
     }
 }
 
+/// Write the config file, creating the config directory if needed
+pub fn save_config(config: &Config) -> Result<PathBuf> {
+    let config_path = config_file_path()?;
+    save_config_to(config, &config_path)?;
+    Ok(config_path)
+}
+
+/// Write a config file at a given location, creating its directory if needed
+pub fn save_config_to(config: &Config, config_path: &Path) -> Result<()> {
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(config_path, serde_json::to_string_pretty(config)?)?;
+    info!("Saved config to: {}", config_path.display());
+    Ok(())
+}
+
+/// The database a config points at
+pub fn configured_database_path(config: &Config) -> PathBuf {
+    config
+        .database_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(default_database_path)
+}
+
+/// `config` with the database's stored settings and then environment variables on top
+pub fn with_settings(config: &Config) -> Result<Config> {
+    let stored = load_stored_settings(&configured_database_path(config))?;
+    Ok(layered_config(config, &stored, &Settings::from_env()?))
+}
+
+/// Where an option's effective value was set, lowest precedence first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueSource {
+    Default,
+    File,
+    /// Stored settings, see `settings::Settings`
+    Database,
+    Env,
+    Cli,
+}
+
+/// One config option, as `config schema` and `/api/config/schema` describe it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OptionSchema {
+    /// Key in the config file
+    pub name: &'static str,
+    /// JSON type in the config file
+    #[serde(rename = "type")]
+    pub value_type: &'static str,
+    pub description: &'static str,
+    pub default: Value,
+    pub value: Value,
+    pub source: ValueSource,
+}
+
+/// An option's documentation, and how to read its value back from a resolved config
+struct OptionDoc {
+    name: &'static str,
+    value_type: &'static str,
+    description: &'static str,
+    value: fn(&ResolvedConfig) -> Value,
+}
+
+/// Every option of `Config`, in the order of its fields
+const OPTIONS: &[OptionDoc] = &[
+    OptionDoc {
+        name: "grid_size",
+        value_type: "integer",
+        description: "Side of the grid images are hashed on, hashes have its square in bits",
+        value: |config| json!(config.grid_size),
+    },
+    OptionDoc {
+        name: "threshold",
+        value_type: "integer",
+        description: "Largest hash distance at which two images are duplicates",
+        value: |config| json!(config.threshold),
+    },
+    OptionDoc {
+        name: "algorithm",
+        value_type: "string",
+        description: "How images are hashed: perceptual, difference, average or blockhash",
+        value: |config| json!(config.algorithm),
+    },
+    OptionDoc {
+        name: "database_path",
+        value_type: "string",
+        description: "Location of the hash database",
+        value: |config| {
+            json!(config
+                .database_path
+                .clone()
+                .unwrap_or_else(|| default_database_path().display().to_string()))
+        },
+    },
+    OptionDoc {
+        name: "ignore_paths",
+        value_type: "array of strings",
+        description: "Paths left out of scans",
+        value: |config| json!(config.ignore_paths),
+    },
+    OptionDoc {
+        name: "scan_paths",
+        value_type: "array of strings",
+        description: "Directories scanned when none are given on the command line",
+        value: |config| json!(config.scan_paths),
+    },
+    OptionDoc {
+        name: "server_roots",
+        value_type: "array of strings",
+        description: "Directories the web server may scan and serve files from, scan_paths when unset, empty for any",
+        value: |config| json!(config.server_roots),
+    },
+    OptionDoc {
+        name: "read_only",
+        value_type: "boolean",
+        description: "Serve results for browsing only, without the routes that change anything",
+        value: |config| json!(config.read_only),
+    },
+    OptionDoc {
+        name: "busy_timeout_ms",
+        value_type: "integer",
+        description: "How long SQLite waits for another process to release a lock, in milliseconds",
+        value: |config| json!(config.connection.busy_timeout_ms),
+    },
+    OptionDoc {
+        name: "wal_mode",
+        value_type: "boolean",
+        description: "Use write-ahead logging so readers don't block the writer",
+        value: |config| json!(config.connection.wal_mode),
+    },
+    OptionDoc {
+        name: "write_retries",
+        value_type: "integer",
+        description: "How many times a write is retried after the busy timeout expires",
+        value: |config| json!(config.connection.write_retries),
+    },
+    OptionDoc {
+        name: "backup_retention",
+        value_type: "integer",
+        description: "How many database backups to keep, 0 turns backups off",
+        value: |config| json!(config.connection.backup_retention),
+    },
+    OptionDoc {
+        name: "content_types",
+        value_type: "object of strings",
+        description: "Content types served for file extensions whose contents can't be recognised",
+        value: |config| json!(config.content_types),
+    },
+    OptionDoc {
+        name: "hash_strategies",
+        value_type: "object of strings",
+        description: "How files are hashed per extension: first-frame, all-frames, rasterize or skip",
+        value: |config| json!(config.hash_strategies),
+    },
+    OptionDoc {
+        name: "confirm_scan_above",
+        value_type: "string",
+        description: "Scans reading more than this much, e.g. \"100GB\", ask before hashing. The value is in bytes, null never asks",
+        value: |config| json!(config.confirm_scan_above),
+    },
+    OptionDoc {
+        name: "temporary_file_patterns",
+        value_type: "array of strings",
+        description: "Names of files that are still being written and left out of scans",
+        value: |config| json!(config.temporary_file_patterns),
+    },
+    OptionDoc {
+        name: "debounce_seconds",
+        value_type: "object of integers",
+        description: "Seconds files in a folder are left alone after changing",
+        value: |config| json!(config.debounce_seconds),
+    },
+    OptionDoc {
+        name: "max_group_size",
+        value_type: "integer",
+        description: "Groups with more files than this are split, 0 never splits",
+        value: |config| json!(config.max_group_size),
+    },
+    OptionDoc {
+        name: "sidecars",
+        value_type: "string",
+        description: "Whether XMP/JSON sidecar files are deleted or moved along with their image",
+        value: |config| json!(config.sidecars),
+    },
+    OptionDoc {
+        name: "lang",
+        value_type: "string",
+        description: "Language for result summaries and web API messages",
+        value: |config| json!(config.lang),
+    },
+    OptionDoc {
+        name: "port",
+        value_type: "integer",
+        description: "Port the web server listens on",
+        value: |config| json!(config.port),
+    },
+    OptionDoc {
+        name: "post_scan_hook",
+        value_type: "string",
+        description: "Shell command run after each scan with a JSON summary on stdin",
+        value: |config| json!(config.post_scan_hook),
+    },
+    OptionDoc {
+        name: "job_schedule",
+        value_type: "object of integers",
+        description: "Seconds between runs of each web server job: scan, cleanup or compaction",
+        value: |config| json!(config.job_schedule),
+    },
+    OptionDoc {
+        name: "job_history",
+        value_type: "integer",
+        description: "How many job runs are kept with their logs",
+        value: |config| json!(config.job_history),
+    },
+    OptionDoc {
+        name: "serve_log_sessions",
+        value_type: "integer",
+        description: "How many web server runs' served files are kept for /api/usage",
+        value: |config| json!(config.serve_log_sessions),
+    },
+];
+
+/// Keys set in the config file itself, empty when there's no config file
+pub fn config_file_keys() -> Result<BTreeSet<String>> {
+    let config_path = config_file_path()?;
+    if !config_path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let config: Value = serde_json::from_str(&std::fs::read_to_string(&config_path)?)
+        .with_context(|| format!("Could not parse {}", config_path.display()))?;
+    Ok(config
+        .as_object()
+        .map(|options| options.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+/// Every config option with its default, its value in `effective` and where that was set.
+/// `cli_keys` are the options given on the command line
+pub fn config_schema(
+    file_keys: &BTreeSet<String>,
+    stored: &Settings,
+    env_settings: &Settings,
+    cli_keys: &BTreeSet<&str>,
+    effective: &ResolvedConfig,
+) -> Vec<OptionSchema> {
+    let defaults = Config::default().with_overrides(None, None, None);
+    let setting_keys = |settings: &Settings| -> BTreeSet<String> {
+        match serde_json::to_value(settings) {
+            Ok(Value::Object(options)) => options.keys().cloned().collect(),
+            _ => BTreeSet::new(),
+        }
+    };
+    let (stored_keys, env_keys) = (setting_keys(stored), setting_keys(env_settings));
+
+    OPTIONS
+        .iter()
+        .map(|option| {
+            let source = if cli_keys.contains(option.name) {
+                ValueSource::Cli
+            } else if env_keys.contains(option.name) {
+                ValueSource::Env
+            } else if stored_keys.contains(option.name) {
+                ValueSource::Database
+            } else if file_keys.contains(option.name) {
+                ValueSource::File
+            } else {
+                ValueSource::Default
+            };
+            OptionSchema {
+                name: option.name,
+                value_type: option.value_type,
+                description: option.description,
+                default: (option.value)(&defaults),
+                value: (option.value)(effective),
+                source,
+            }
+        })
+        .collect()
+}
+
+/// The options among the command line overrides that were given
+pub fn cli_keys(
+    threshold_override: Option<u32>,
+    grid_size_override: Option<u8>,
+) -> BTreeSet<&'static str> {
+    let mut keys = BTreeSet::new();
+    if threshold_override.is_some() {
+        keys.insert("threshold");
+    }
+    if grid_size_override.is_some() {
+        keys.insert("grid_size");
+    }
+    keys
+}
+
+/// Print the config schema as JSON, with the effective values for these overrides
+pub fn print_config_schema(
+    threshold_override: Option<u32>,
+    grid_size_override: Option<u8>,
+) -> Result<()> {
+    let file_config = load_config()?;
+    let stored = load_stored_settings(&configured_database_path(&file_config))?;
+    let env_settings = Settings::from_env()?;
+    let config = layered_config(&file_config, &stored, &env_settings);
+    let effective_config = config.with_overrides(grid_size_override, threshold_override, None);
+    let schema = config_schema(
+        &config_file_keys()?,
+        &stored,
+        &env_settings,
+        &cli_keys(threshold_override, grid_size_override),
+        &effective_config,
+    );
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
 /// Takes overrides because the CLI may want to show the config with different values
 pub fn show_config_with_overrides(
     threshold_override: Option<u32>,
-    grid_size_override: Option<u32>,
+    grid_size_override: Option<u8>,
 ) -> Result<()> {
     let config_dir =
         dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
 
-    let config = load_config()?;
+    let file_config = load_config()?;
+    let stored = load_stored_settings(&configured_database_path(&file_config))?;
+    let config = layered_config(&file_config, &stored, &Settings::from_env()?);
 
     println!("=== Configuration ===");
 
@@ -43,7 +384,7 @@ pub fn show_config_with_overrides(
                 );
             }
         } else {
-            println!("  (overridden from default: 128x128)");
+            println!("  (overridden from default: {DEFAULT_GRID_SIZE}x{DEFAULT_GRID_SIZE})");
         }
     }
 
@@ -57,15 +398,31 @@ pub fn show_config_with_overrides(
             println!("  (overridden from default: 15)");
         }
     }
+    println!("Hash algorithm: {}", effective_config.algorithm);
 
     if let Some(ref db_path) = config.database_path {
         println!("Database path: {db_path}");
     } else {
-        let cache_dir = dirs::cache_dir()
-            .unwrap_or_else(|| std::path::PathBuf::from("."))
-            .join("vibe-image-comparator");
-        let default_db_path = cache_dir.join("hashes.db");
-        println!("Database path: {} (default)", default_db_path.display());
+        println!(
+            "Database path: {} (default)",
+            default_database_path().display()
+        );
+    }
+
+    let connection = &effective_config.connection;
+    println!(
+        "Database connection: WAL {}, busy timeout {}ms, {} write retries",
+        if connection.wal_mode { "on" } else { "off" },
+        connection.busy_timeout_ms,
+        connection.write_retries
+    );
+    if connection.backup_retention == 0 {
+        println!("Database backups: off");
+    } else {
+        println!(
+            "Database backups: newest {} kept, before migrations, clean and clean --all",
+            connection.backup_retention
+        );
     }
 
     // Show ignore paths
@@ -79,6 +436,96 @@ pub fn show_config_with_overrides(
         }
     }
 
+    let scan_paths = effective_config.scan_paths;
+    if scan_paths.is_empty() {
+        println!("Scan paths: (none)");
+    } else {
+        println!("Scan paths:");
+        for path in &scan_paths {
+            println!("  - {path}");
+        }
+    }
+
+    if effective_config.server_roots.is_empty() {
+        println!("Server roots: (none, paths are refused)");
+    } else {
+        println!("Server roots:");
+        for path in &effective_config.server_roots {
+            println!("  - {path}");
+        }
+    }
+
+    println!(
+        "Read-only server: {}",
+        if effective_config.read_only {
+            "on"
+        } else {
+            "off"
+        }
+    );
+
+    if !effective_config.content_types.is_empty() {
+        println!("Content types:");
+        for (extension, content_type) in &effective_config.content_types {
+            println!("  - .{extension}: {content_type}");
+        }
+    }
+
+    if !effective_config.hash_strategies.is_empty() {
+        println!("Hash strategies:");
+        for (extension, strategy) in &effective_config.hash_strategies {
+            println!("  - .{extension}: {strategy}");
+        }
+    }
+
+    match effective_config.confirm_scan_above {
+        Some(limit) => println!("Confirm scans above: {}", format_size(limit)),
+        None => println!("Confirm scans above: (never)"),
+    }
+    if effective_config.temporary_file_patterns.is_empty() {
+        println!("Temporary file patterns: (none)");
+    } else {
+        println!(
+            "Temporary file patterns: {}",
+            effective_config.temporary_file_patterns.join(", ")
+        );
+    }
+    match effective_config.max_group_size {
+        0 => println!("Maximum group size: (no limit)"),
+        max => println!("Maximum group size: {max}"),
+    }
+    if !effective_config.debounce_seconds.is_empty() {
+        println!("Debounce intervals:");
+        for (path, seconds) in &effective_config.debounce_seconds {
+            println!("  - {path}: {seconds}s");
+        }
+    }
+    println!("Sidecar files: {}", effective_config.sidecars);
+    println!("Language: {}", effective_config.lang);
+    println!("Server port: {}", effective_config.port);
+    match &effective_config.post_scan_hook {
+        Some(command) => println!("Post-scan hook: {command}"),
+        None => println!("Post-scan hook: (none)"),
+    }
+    if !effective_config.job_schedule.is_empty() {
+        println!("Job schedule:");
+        for (kind, seconds) in &effective_config.job_schedule {
+            println!("  - {}: every {seconds}s", kind.as_str());
+        }
+    }
+    println!("Job runs kept: {}", effective_config.job_history);
+    println!(
+        "Server runs with served files kept: {}",
+        effective_config.serve_log_sessions
+    );
+
+    // Shown as JSON, the same way they're stored and returned by /api/settings
+    if stored == Settings::default() {
+        println!("Stored settings: (none)");
+    } else {
+        println!("Stored settings: {}", serde_json::to_string(&stored)?);
+    }
+
     let default_config_path = config_dir.join("vibe-image-comparator.json");
     if default_config_path.exists() {
         println!("Config file: {}", default_config_path.display());
@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Set by `--max-duration`, discovery and hashing stop once it has passed
+static DEADLINE: OnceLock<Instant> = OnceLock::new();
+
+/// Parse a duration such as `2h`, `90m`, `1h30m` or `45s`. A bare number is seconds
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 86_400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => bail!("Unknown unit '{c}' in duration '{text}', use d, h, m or s"),
+        };
+        if digits.is_empty() {
+            bail!("Missing number before '{c}' in duration '{text}'");
+        }
+        let amount: u64 = digits
+            .parse()
+            .with_context(|| format!("Invalid duration '{text}'"))?;
+        total = amount
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .with_context(|| format!("Duration '{text}' is too long"))?;
+        digits.clear();
+    }
+    if !digits.is_empty() || text.is_empty() {
+        bail!("Invalid duration '{text}', expected e.g. 2h, 90m or 1h30m");
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Give the rest of the run `budget` to finish discovery and hashing
+pub fn set_deadline(budget: Duration) {
+    if DEADLINE.set(Instant::now() + budget).is_err() {
+        warn!("A time budget was already set");
+    }
+}
+
+/// Whether the `--max-duration` budget is used up. Always false without one
+pub fn time_is_up() -> bool {
+    DEADLINE
+        .get()
+        .is_some_and(|deadline| Instant::now() >= *deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_combine_units() {
+        assert_eq!(
+            parse_duration("2h").expect("valid duration"),
+            Duration::from_secs(7200)
+        );
+        assert_eq!(
+            parse_duration("1h30m").expect("valid duration"),
+            Duration::from_secs(5400)
+        );
+        assert_eq!(
+            parse_duration("45s").expect("valid duration"),
+            Duration::from_secs(45)
+        );
+        assert_eq!(
+            parse_duration("90").expect("valid duration"),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            parse_duration("1d").expect("valid duration"),
+            Duration::from_secs(86_400)
+        );
+        assert!(parse_duration("2x").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use image::{imageops::FilterType, DynamicImage, ImageFormat, Rgb, RgbImage};
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::extract::open_image;
+
+/// Default length of the longest edge of a rendered diff image
+pub const DEFAULT_DIFF_SIZE: u32 = 512;
+
+/// Map a per-pixel delta (0-255) onto a black → blue → red → yellow heat scale
+pub(crate) fn heat_color(delta: u8) -> Rgb<u8> {
+    let d = delta as u16;
+    if d < 85 {
+        Rgb([0, 0, (d * 3) as u8])
+    } else if d < 170 {
+        let t = (d - 85) * 3;
+        Rgb([t as u8, 0, (255 - t) as u8])
+    } else {
+        let t = ((d - 170) * 3).min(255);
+        Rgb([255, t as u8, 0])
+    }
+}
+
+/// Render a heatmap of per-pixel differences between two images.
+///
+/// The first image is downscaled to fit within `max_size` (keeping its aspect ratio) and the
+/// second image is resized onto the same canvas so that rotated or rescaled copies can still be
+/// compared. Each output pixel encodes the largest channel difference at that position.
+pub fn render_diff_heatmap(a: &DynamicImage, b: &DynamicImage, max_size: u32) -> RgbImage {
+    let max_size = max_size.max(1);
+    let a = a.resize(max_size, max_size, FilterType::Triangle).to_rgb8();
+    let (width, height) = a.dimensions();
+    let b = b
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_rgb8();
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let pa = a.get_pixel(x, y);
+        let pb = b.get_pixel(x, y);
+        let delta =
+            pa.0.iter()
+                .zip(pb.0.iter())
+                .map(|(ca, cb)| ca.abs_diff(*cb))
+                .max()
+                .unwrap_or(0);
+        heat_color(delta)
+    })
+}
+
+/// Load two images from disk and return their diff heatmap encoded as PNG
+pub fn diff_heatmap_png(a: &Path, b: &Path, max_size: u32) -> Result<Vec<u8>> {
+    let img_a = open_image(a)?;
+    let img_b = open_image(b)?;
+
+    let heatmap = render_diff_heatmap(&img_a, &img_b, max_size);
+
+    let mut buffer = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(heatmap).write_to(&mut buffer, ImageFormat::Png)?;
+    Ok(buffer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_images_produce_black_heatmap() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 16, Rgb([120, 40, 200])));
+        let heatmap = render_diff_heatmap(&img, &img, 16);
+
+        assert_eq!(heatmap.dimensions(), (16, 8));
+        assert!(heatmap.pixels().all(|p| *p == Rgb([0, 0, 0])));
+    }
+
+    #[test]
+    fn differing_pixels_are_highlighted() {
+        let a = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([0, 0, 0])));
+        let b = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([255, 255, 255])));
+        let heatmap = render_diff_heatmap(&a, &b, 8);
+
+        assert!(heatmap.pixels().all(|p| *p == heat_color(255)));
+        assert_eq!(heat_color(255), Rgb([255, 255, 0]));
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Set from `--dry-run` at startup
+static DRY_RUN: OnceLock<bool> = OnceLock::new();
+
+/// Only report what deleting, linking and cleaning would do, for the rest of the process
+pub fn set_dry_run(dry_run: bool) {
+    if DRY_RUN.set(dry_run).is_err() {
+        warn!("Dry run was already set");
+    }
+}
+
+/// Whether files and cache entries are left untouched, with the changes only reported
+pub fn is_dry_run() -> bool {
+    DRY_RUN.get().copied().unwrap_or(false)
+}
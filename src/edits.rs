@@ -0,0 +1,223 @@
+use exif::{In, Reader, Tag, Value};
+use imghash::ImageHash;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::cache::HashCache;
+use crate::hasher::{decode_hashes, image_dimensions};
+use crate::paths::extended_length_path;
+
+/// A differently sized, slightly altered image with the same capture time as an image kept in
+/// its duplicate group, e.g. a Lightroom export of a camera original
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EditedVersion {
+    pub original: PathBuf,
+    pub edited: PathBuf,
+    /// EXIF capture time shared by both, as `YYYY-MM-DD HH:MM:SS`
+    pub taken_at: String,
+    /// Hamming distance between the two perceptual hashes, never 0
+    pub distance: u32,
+}
+
+/// What is known about a group member when looking for edits
+struct Member {
+    path: PathBuf,
+    taken_at: Option<String>,
+    dimensions: Option<(u32, u32)>,
+    hash: Option<ImageHash>,
+}
+
+/// EXIF `DateTimeOriginal` of an image, None if it has no EXIF data or the tag is missing
+pub fn exif_capture_time(path: &Path) -> Option<String> {
+    let file = File::open(extended_length_path(path)).ok()?;
+    let exif = match Reader::new().read_from_container(&mut BufReader::new(file)) {
+        Ok(exif) => exif,
+        Err(e) => {
+            debug!("No EXIF data in {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let date_time = exif::DateTime::from_ascii(values.first()?).ok()?;
+    Some(date_time.to_string())
+}
+
+/// Move likely edited versions out of duplicate groups so they aren't resolved like accidental
+/// copies. Groups left with a single file are dropped.
+pub fn separate_edited_versions(
+    groups: Vec<Vec<PathBuf>>,
+    cache: &HashCache,
+) -> (Vec<Vec<PathBuf>>, Vec<EditedVersion>) {
+    let mut kept_groups = Vec::new();
+    let mut edited = Vec::new();
+
+    for group in groups {
+        let members = group
+            .into_iter()
+            .map(|path| Member {
+                taken_at: exif_capture_time(&path),
+                dimensions: image_dimensions(&path, cache),
+                hash: cached_hash(&path, cache),
+                path,
+            })
+            .collect();
+        let (kept, group_edits) = split_edits(members);
+        if kept.len() > 1 {
+            kept_groups.push(kept);
+        }
+        edited.extend(group_edits);
+    }
+
+    (kept_groups, edited)
+}
+
+fn cached_hash(path: &Path, cache: &HashCache) -> Option<ImageHash> {
+    let (perceptual_hash, _) = cache.get_cached_hash_details(path).ok()??;
+    decode_hashes(vec![(path.to_path_buf(), perceptual_hash)])
+        .pop()
+        .map(|(_, hash)| hash)
+}
+
+/// Split one group into the files that stay and the edits taken out of it.
+///
+/// Among files sharing a capture time, the most common size (then the largest) is treated as
+/// the original. A file of another size taken at the same time is an edit when its hash is
+/// close to, but not the same as, an original's. Identical hashes mean a plain resized copy,
+/// which stays a duplicate, as does anything missing a capture time, size or hash.
+fn split_edits(members: Vec<Member>) -> (Vec<PathBuf>, Vec<EditedVersion>) {
+    let mut by_time: BTreeMap<&str, Vec<&Member>> = BTreeMap::new();
+    for member in &members {
+        if let (Some(taken_at), Some(_), Some(_)) =
+            (&member.taken_at, member.dimensions, &member.hash)
+        {
+            by_time.entry(taken_at).or_default().push(member);
+        }
+    }
+
+    let mut edited = Vec::new();
+    for (taken_at, same_time) in by_time {
+        let mut size_counts: BTreeMap<(u32, u32), usize> = BTreeMap::new();
+        for member in &same_time {
+            if let Some(dimensions) = member.dimensions {
+                *size_counts.entry(dimensions).or_default() += 1;
+            }
+        }
+        if size_counts.len() < 2 {
+            continue;
+        }
+        let Some(original_size) = size_counts
+            .into_iter()
+            .max_by_key(|&((width, height), count)| (count, u64::from(width) * u64::from(height)))
+            .map(|(dimensions, _)| dimensions)
+        else {
+            continue;
+        };
+
+        let (originals, others): (Vec<&Member>, Vec<&Member>) = same_time
+            .into_iter()
+            .partition(|member| member.dimensions == Some(original_size));
+        for other in others {
+            let closest = originals
+                .iter()
+                .filter_map(|original| Some((original, hash_distance(original, other)?)))
+                .min_by_key(|&(_, distance)| distance);
+            if let Some((original, distance)) = closest.filter(|&(_, distance)| distance > 0) {
+                edited.push(EditedVersion {
+                    original: original.path.clone(),
+                    edited: other.path.clone(),
+                    taken_at: taken_at.to_string(),
+                    distance,
+                });
+            }
+        }
+    }
+
+    let kept = members
+        .into_iter()
+        .map(|member| member.path)
+        .filter(|path| !edited.iter().any(|edit| &edit.edited == path))
+        .collect();
+    (kept, edited)
+}
+
+fn hash_distance(a: &Member, b: &Member) -> Option<u32> {
+    let distance = a.hash.as_ref()?.distance(b.hash.as_ref()?).ok()?;
+    u32::try_from(distance).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(path: &str, taken_at: Option<&str>, dimensions: (u32, u32), hash: &str) -> Member {
+        Member {
+            path: PathBuf::from(path),
+            taken_at: taken_at.map(str::to_string),
+            dimensions: Some(dimensions),
+            hash: decode_hashes(vec![(PathBuf::from(path), hash.to_string())])
+                .pop()
+                .map(|(_, hash)| hash),
+        }
+    }
+
+    #[test]
+    fn exports_taken_at_the_same_time_are_split_out() {
+        let taken = Some("2024-06-01 10:00:00");
+        let (kept, edited) = split_edits(vec![
+            member("/raw/IMG_1.jpg", taken, (6000, 4000), "ffff0000ffff0000"),
+            member("/backup/IMG_1.jpg", taken, (6000, 4000), "ffff0000ffff0000"),
+            member("/export/IMG_1.jpg", taken, (2048, 1365), "ffff0000ffff0001"),
+            // A plain resize hashes identically, so it's still a duplicate
+            member("/small/IMG_1.jpg", taken, (1500, 1000), "ffff0000ffff0000"),
+            // No capture time to compare
+            member("/web/IMG_1.jpg", None, (800, 533), "ffff0000ffff0003"),
+        ]);
+
+        assert_eq!(
+            kept,
+            vec![
+                PathBuf::from("/raw/IMG_1.jpg"),
+                PathBuf::from("/backup/IMG_1.jpg"),
+                PathBuf::from("/small/IMG_1.jpg"),
+                PathBuf::from("/web/IMG_1.jpg"),
+            ]
+        );
+        assert_eq!(
+            edited,
+            vec![EditedVersion {
+                original: PathBuf::from("/raw/IMG_1.jpg"),
+                edited: PathBuf::from("/export/IMG_1.jpg"),
+                taken_at: "2024-06-01 10:00:00".to_string(),
+                distance: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn different_capture_times_stay_duplicates() {
+        let (kept, edited) = split_edits(vec![
+            member(
+                "/a.jpg",
+                Some("2024-06-01 10:00:00"),
+                (6000, 4000),
+                "ffff0000ffff0000",
+            ),
+            member(
+                "/b.jpg",
+                Some("2024-06-01 10:00:01"),
+                (2048, 1365),
+                "ffff0000ffff0001",
+            ),
+        ]);
+
+        assert_eq!(kept.len(), 2);
+        assert!(edited.is_empty());
+    }
+}
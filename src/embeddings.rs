@@ -0,0 +1,255 @@
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "parquet")]
+use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt8Array};
+#[cfg(feature = "parquet")]
+use arrow_schema::{DataType, Field, Schema};
+#[cfg(feature = "parquet")]
+use parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::cache::HashCache;
+
+/// Write every cached perceptual hash as a row of 0/1 columns, for clustering and visualising
+/// the library outside this tool. The format is chosen by the file's extension. Returns how
+/// many rows were written
+///
+/// Both layouts are stable and hold the same columns: `path`, `sha256` and one column per hash
+/// bit named `b0`, `b1`, ... Bit `b0` is the most significant bit of the hash's first hex digit,
+/// so the bits read left to right like the hex hashes `cache export-hashes` writes. CSV has a
+/// header row and 0/1 fields, Parquet has UTF-8 `path` and `sha256` columns and UInt8 bits.
+/// Parquet needs a build with the `parquet` feature
+pub fn export_embeddings(cache: &HashCache, path: &Path) -> Result<usize> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let parquet = match extension.as_deref() {
+        Some("csv") => false,
+        Some("parquet") if cfg!(feature = "parquet") => true,
+        Some("parquet") => bail!(
+            "Parquet export needs a build with `--features parquet`, write a .csv file instead"
+        ),
+        _ => bail!(
+            "Unknown embedding format for {}, use .csv or .parquet",
+            path.display()
+        ),
+    };
+
+    let file =
+        File::create(path).with_context(|| format!("Could not create {}", path.display()))?;
+    let entries = cache.get_all_cached_hash_details()?;
+    if parquet {
+        #[cfg(feature = "parquet")]
+        return write_embeddings_parquet(&entries, file)
+            .with_context(|| format!("Could not write {}", path.display()));
+    }
+    let mut writer = BufWriter::new(file);
+    let rows = write_embeddings_csv(&entries, &mut writer)?;
+    writer
+        .flush()
+        .with_context(|| format!("Could not write {}", path.display()))?;
+    Ok(rows)
+}
+
+/// Write `(path, perceptual hash, sha256)` entries as CSV
+pub fn write_embeddings_csv(
+    entries: &[(PathBuf, String, String)],
+    writer: &mut impl Write,
+) -> Result<usize> {
+    let (bit_count, rows) = embedding_rows(entries);
+    let columns: Vec<String> = (0..bit_count).map(|bit| format!("b{bit}")).collect();
+    if columns.is_empty() {
+        writeln!(writer, "path,sha256")?;
+    } else {
+        writeln!(writer, "path,sha256,{}", columns.join(","))?;
+    }
+
+    for EmbeddingRow { path, sha256, bits } in &rows {
+        let bits: Vec<&str> = bits
+            .iter()
+            .map(|bit| if *bit { "1" } else { "0" })
+            .collect();
+        writeln!(
+            writer,
+            "{},{sha256},{}",
+            csv_field(&path.to_string_lossy()),
+            bits.join(",")
+        )?;
+    }
+    Ok(rows.len())
+}
+
+/// Write `(path, perceptual hash, sha256)` entries as a Parquet file of one row group
+#[cfg(feature = "parquet")]
+pub fn write_embeddings_parquet(
+    entries: &[(PathBuf, String, String)],
+    writer: impl Write + Send,
+) -> Result<usize> {
+    let (bit_count, rows) = embedding_rows(entries);
+    let mut fields = vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("sha256", DataType::Utf8, false),
+    ];
+    fields.extend((0..bit_count).map(|bit| Field::new(format!("b{bit}"), DataType::UInt8, false)));
+    let schema = Arc::new(Schema::new(fields));
+
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.path.to_string_lossy()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.sha256),
+        )),
+    ];
+    columns.extend((0..bit_count).map(|bit| -> ArrayRef {
+        Arc::new(UInt8Array::from_iter_values(
+            rows.iter().map(|row| u8::from(row.bits[bit])),
+        ))
+    }));
+
+    let batch = RecordBatch::try_new(Arc::clone(&schema), columns)?;
+    let mut writer = ArrowWriter::try_new(writer, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(rows.len())
+}
+
+/// One file of the export
+struct EmbeddingRow<'a> {
+    path: &'a Path,
+    sha256: &'a str,
+    bits: Vec<bool>,
+}
+
+/// The bit count of the export and its rows. Every row needs the same number of bits, so hashes
+/// of another length than the first are left out
+fn embedding_rows(entries: &[(PathBuf, String, String)]) -> (usize, Vec<EmbeddingRow<'_>>) {
+    let Some((_, first_hash, _)) = entries.first() else {
+        return (0, Vec::new());
+    };
+    let bit_count = first_hash.len() * 4;
+
+    let mut rows = Vec::with_capacity(entries.len());
+    for (path, hash, sha256) in entries {
+        let Some(bits) = hash_bits(hash).filter(|bits| bits.len() == bit_count) else {
+            warn!(
+                "Leaving out {}, its hash doesn't have {bit_count} bits",
+                path.display()
+            );
+            continue;
+        };
+        rows.push(EmbeddingRow { path, sha256, bits });
+    }
+    (bit_count, rows)
+}
+
+/// The bits of a hex encoded hash, most significant bit of each digit first
+fn hash_bits(hash: &str) -> Option<Vec<bool>> {
+    let mut bits = Vec::with_capacity(hash.len() * 4);
+    for digit in hash.chars() {
+        let value = digit.to_digit(16)?;
+        bits.extend((0..4).rev().map(|shift| (value >> shift) & 1 == 1));
+    }
+    Some(bits)
+}
+
+/// Quote a field when it contains a separator, quote or line break, as RFC 4180 describes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "parquet")]
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn rows_hold_one_column_per_bit() {
+        let entries = vec![
+            (
+                PathBuf::from("/photos/a.jpg"),
+                "a1".to_string(),
+                "aa".to_string(),
+            ),
+            (
+                PathBuf::from("/photos/b, \"c\".jpg"),
+                "0f".to_string(),
+                "bb".to_string(),
+            ),
+            (
+                PathBuf::from("/photos/other-grid.jpg"),
+                "0f0".to_string(),
+                "cc".to_string(),
+            ),
+        ];
+        let mut csv = Vec::new();
+        let rows = write_embeddings_csv(&entries, &mut csv).expect("writing to memory works");
+
+        assert_eq!(rows, 2);
+        assert_eq!(
+            String::from_utf8(csv).expect("CSV is UTF-8"),
+            "path,sha256,b0,b1,b2,b3,b4,b5,b6,b7\n\
+             /photos/a.jpg,aa,1,0,1,0,0,0,0,1\n\
+             \"/photos/b, \"\"c\"\".jpg\",bb,0,0,0,0,1,1,1,1\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "parquet")]
+    fn parquet_rows_match_the_csv_columns() {
+        let entries = vec![
+            (
+                PathBuf::from("/photos/a.jpg"),
+                "a1".to_string(),
+                "aa".to_string(),
+            ),
+            (
+                PathBuf::from("/photos/other-grid.jpg"),
+                "0f0".to_string(),
+                "cc".to_string(),
+            ),
+        ];
+        let file = tempfile::tempfile().expect("temp file");
+        let rows = write_embeddings_parquet(&entries, file.try_clone().expect("clone file"))
+            .expect("writing to a file works");
+        assert_eq!(rows, 1);
+
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+            .expect("Parquet footer")
+            .build()
+            .expect("Parquet reader")
+            .next()
+            .expect("one batch")
+            .expect("readable batch");
+        let columns: Vec<&str> = batch
+            .schema_ref()
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert_eq!(
+            columns,
+            ["path", "sha256", "b0", "b1", "b2", "b3", "b4", "b5", "b6", "b7"]
+        );
+        let bits: Vec<u8> = batch.columns()[2..]
+            .iter()
+            .map(|column| {
+                column
+                    .as_any()
+                    .downcast_ref::<UInt8Array>()
+                    .expect("bits are UInt8")
+                    .value(0)
+            })
+            .collect();
+        assert_eq!(bits, [1, 0, 1, 0, 0, 0, 0, 1]);
+    }
+}
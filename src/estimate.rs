@@ -0,0 +1,271 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cache::{HashCache, HashingSpeed};
+use crate::messages::{Lang, Message};
+use crate::paths::extended_length_path;
+
+/// Scans reading more than this ask for confirmation in a terminal, unless configured otherwise
+pub const DEFAULT_CONFIRM_ABOVE: u64 = 100 * 1000 * 1000 * 1000;
+/// How many recent runs the hashing speed is averaged over
+const RECENT_RUNS: usize = 5;
+
+/// What a scan is about to hash
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanEstimate {
+    pub files: usize,
+    pub bytes: u64,
+    /// Files the database has no entry for yet, they also need decoding
+    pub new_files: usize,
+    /// Projected from the speed of recent runs, None without any
+    pub duration: Option<Duration>,
+    /// The share of each scan folder, in the order they were given
+    pub roots: Vec<RootEstimate>,
+}
+
+/// What a scan is about to hash under one of its folders
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootEstimate {
+    /// Absolute, so runs from any working directory are recorded under the same folder
+    pub root: PathBuf,
+    pub files: usize,
+    pub bytes: u64,
+    /// From this folder's own recent runs, None before its first
+    pub speed: Option<HashingSpeed>,
+}
+
+/// Size up the images a scan found under `roots`. Every file is read to check its sha256, so
+/// the time is projected from the bytes per second recent runs managed. Each folder uses its
+/// own recent runs when it has any, a NAS share and a local SSD hash at very different speeds
+pub fn estimate_scan(
+    images: &[PathBuf],
+    roots: &[PathBuf],
+    cache: &HashCache,
+) -> Result<ScanEstimate> {
+    let mut estimates: Vec<RootEstimate> = roots
+        .iter()
+        .map(|root| {
+            Ok(RootEstimate {
+                root: std::path::absolute(root)?,
+                files: 0,
+                bytes: 0,
+                speed: None,
+            })
+        })
+        .collect::<Result<_>>()?;
+    // Files outside every root, e.g. when roots are given relative to another folder
+    let mut elsewhere = 0;
+    let mut new_files = 0;
+    for image in images {
+        let size = fs::metadata(extended_length_path(image)).map_or(0, |metadata| metadata.len());
+        let root = roots
+            .iter()
+            .enumerate()
+            .filter(|(_, root)| image.starts_with(root))
+            .max_by_key(|(_, root)| root.components().count());
+        match root {
+            Some((index, _)) => {
+                estimates[index].files += 1;
+                estimates[index].bytes += size;
+            }
+            None => elsewhere += size,
+        }
+        if !cache.is_path_cached(image)? {
+            new_files += 1;
+        }
+    }
+
+    let overall = cache.recent_hashing_speed(None, RECENT_RUNS)?;
+    let mut seconds = overall.map(|speed| elsewhere as f64 / speed.bytes_per_second);
+    for estimate in estimates.iter_mut() {
+        estimate.speed = cache.recent_hashing_speed(Some(&estimate.root), RECENT_RUNS)?;
+        seconds = match (seconds, estimate.speed.or(overall)) {
+            (Some(seconds), Some(speed)) => {
+                Some(seconds + estimate.bytes as f64 / speed.bytes_per_second)
+            }
+            _ => None,
+        };
+    }
+    Ok(ScanEstimate {
+        files: images.len(),
+        bytes: estimates.iter().map(|estimate| estimate.bytes).sum::<u64>() + elsewhere,
+        new_files,
+        duration: seconds.map(Duration::from_secs_f64),
+        roots: estimates,
+    })
+}
+
+/// Store how fast a scan hashed, for the estimates of later scans. A scan of several folders
+/// can't tell their speeds apart, so it only counts towards the overall speed
+pub fn record_scan_speed(
+    cache: &HashCache,
+    estimate: &ScanEstimate,
+    elapsed: Duration,
+) -> Result<()> {
+    let root = match estimate.roots.as_slice() {
+        [only] => Some(only.root.as_path()),
+        _ => None,
+    };
+    cache.record_hashing_run(root, estimate.files, estimate.bytes, elapsed)
+}
+
+/// Ask whether to go ahead with a large scan. Anything but yes cancels it
+pub fn confirm_scan<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    lang: Lang,
+) -> Result<bool> {
+    confirm(input, output, &Message::ConfirmLargeScan.text(lang))
+}
+
+/// Ask a yes or no `question`, in English or German. Anything but yes counts as no
+pub fn confirm<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+) -> Result<bool> {
+    write!(output, "{question}")?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_lowercase().as_str(),
+        "y" | "yes" | "j" | "ja"
+    ))
+}
+
+/// Parse a size such as `500MB`, `2GB`, `1.5TB` or a bare number of bytes. Units are decimal,
+/// like the sizes file managers and drive labels show
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid size '{text}', expected e.g. 500MB or 2GB"))?;
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" | "K" => 1000,
+        "MB" | "M" => 1000 * 1000,
+        "GB" | "G" => 1000 * 1000 * 1000,
+        "TB" | "T" => 1000 * 1000 * 1000 * 1000,
+        _ => bail!("Unknown unit in size '{text}', use B, KB, MB, GB or TB"),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// A size with one decimal in the largest fitting decimal unit, e.g. `1.5 GB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+/// A duration in hours and minutes, or seconds when it's shorter than a minute
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds % 3600 / 60) {
+        (0, 0) => format!("{seconds}s"),
+        (0, minutes) => format!("{minutes}m"),
+        (hours, minutes) => format!("{hours}h {minutes}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn sizes_parse_and_format() {
+        assert_eq!(parse_size("500MB").expect("valid size"), 500_000_000);
+        assert_eq!(parse_size("1.5 TB").expect("valid size"), 1_500_000_000_000);
+        assert_eq!(parse_size("2g").expect("valid size"), 2_000_000_000);
+        assert_eq!(parse_size("4096").expect("valid size"), 4096);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("5PB").is_err());
+
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1_500_000_000), "1.5 GB");
+        assert_eq!(
+            format_duration(Duration::from_secs(14 * 3600 + 300)),
+            "14h 5m"
+        );
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+    }
+
+    #[test]
+    fn each_folder_is_projected_from_its_own_runs() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let nas = temp_dir.path().join("nas");
+        let ssd = temp_dir.path().join("ssd");
+        let mut images = Vec::new();
+        for folder in [&nas, &ssd] {
+            fs::create_dir(folder).expect("create folder");
+            let image = folder.join("a.jpg");
+            fs::write(&image, vec![0; 1_000_000]).expect("write file");
+            images.push(image);
+        }
+        let cache = HashCache::new_in_memory().expect("cache");
+        let second = Duration::from_secs(1);
+        cache
+            .record_hashing_run(Some(&nas), 1, 1_000_000, second)
+            .expect("record");
+        cache
+            .record_hashing_run(Some(&ssd), 10, 100_000_000, second)
+            .expect("record");
+
+        let roots = [nas.clone(), ssd.clone()];
+        let estimate = estimate_scan(&images, &roots, &cache).expect("estimate");
+        assert_eq!(estimate.bytes, 2_000_000);
+        assert_eq!(estimate.roots[0].files, 1);
+        assert_eq!(
+            estimate.roots[1].speed,
+            Some(HashingSpeed {
+                files_per_second: 10.0,
+                bytes_per_second: 100_000_000.0
+            })
+        );
+        let duration = estimate.duration.expect("projected").as_secs_f64();
+        assert!((duration - 1.01).abs() < 1e-6, "{duration}");
+
+        // Both folders at once only count towards the overall speed
+        record_scan_speed(&cache, &estimate, second).expect("record");
+        assert_eq!(
+            cache
+                .recent_hashing_speed(Some(&nas), RECENT_RUNS)
+                .expect("speed")
+                .map(|speed| speed.bytes_per_second),
+            Some(1_000_000.0)
+        );
+    }
+
+    #[test]
+    fn only_yes_confirms() {
+        let mut output = Vec::new();
+        let mut confirm = |answer: &str| {
+            confirm_scan(&mut Cursor::new(answer), &mut output, Lang::En).expect("prompt works")
+        };
+        assert!(confirm("Y\n"));
+        assert!(confirm("ja\n"));
+        assert!(!confirm("\n"));
+        assert!(!confirm(""));
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::{anyhow, bail, Context, Result};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, Frame, ImageFormat, ImageReader, RgbaImage};
+use std::fs;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::paths::extended_length_path;
+use crate::strategy::{hash_strategy, HashStrategy};
+#[cfg(feature = "video")]
+use crate::video::{is_video, video_signature};
+
+/// Frames blended by the `all-frames` strategy, later frames are left out
+const MAX_BLENDED_FRAMES: usize = 64;
+
+/// Largest EPUB entry read, in bytes. The sizes in a zip are whatever the file claims, so a
+/// crafted one could otherwise decompress to far more than fits in memory
+const MAX_EPUB_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Ebook formats whose cover image can be compared
+pub const EBOOK_EXTENSIONS: [&str; 1] = ["epub"];
+
+/// Check whether a file is an ebook, whose cover is hashed instead of the file itself
+pub fn is_ebook(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EBOOK_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Open the image a file stands for: the image itself, the cover image of an ebook, the
+/// signature of a video, or what the hash strategy configured for its extension makes of it
+pub fn open_image(path: &Path) -> Result<DynamicImage> {
+    if is_ebook(path) {
+        let (cover, _) = extract_epub_cover(path)?;
+        return Ok(image::load_from_memory(&cover)?);
+    }
+    #[cfg(feature = "video")]
+    if is_video(path) {
+        return video_signature(path);
+    }
+    match hash_strategy(path) {
+        HashStrategy::AllFrames => {
+            if let Some(blended) = blend_frames(path)? {
+                return Ok(blended);
+            }
+        }
+        HashStrategy::EmbeddedPreview => {
+            let bytes = fs::read(extended_length_path(path))?;
+            return largest_embedded_jpeg(&bytes)
+                .with_context(|| format!("No usable preview in {}", path.display()));
+        }
+        HashStrategy::Default | HashStrategy::Skip => {}
+    }
+    Ok(image::open(extended_length_path(path))?)
+}
+
+/// Average the frames of an animated GIF, WebP or PNG into one image. None for still images
+/// and other formats, which are opened as usual
+fn blend_frames(path: &Path) -> Result<Option<DynamicImage>> {
+    let reader = BufReader::new(fs::File::open(extended_length_path(path))?);
+    let frames: Vec<Frame> = match ImageFormat::from_path(path).ok() {
+        Some(ImageFormat::Gif) => collect_frames(GifDecoder::new(reader)?)?,
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(reader)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            collect_frames(decoder)?
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng()? {
+                return Ok(None);
+            }
+            collect_frames(decoder.apng()?)?
+        }
+        _ => return Ok(None),
+    };
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    Ok(Some(average_frames(&frames)))
+}
+
+fn collect_frames<'a>(decoder: impl AnimationDecoder<'a>) -> Result<Vec<Frame>> {
+    Ok(decoder
+        .into_frames()
+        .take(MAX_BLENDED_FRAMES)
+        .collect::<Result<_, _>>()?)
+}
+
+/// Frames come out composited onto the full canvas, so they all have the same size
+pub(crate) fn average_frames(frames: &[Frame]) -> DynamicImage {
+    let first = frames[0].buffer();
+    let mut sums = vec![0u32; first.as_raw().len()];
+    for frame in frames {
+        for (sum, value) in sums.iter_mut().zip(frame.buffer().as_raw()) {
+            *sum += u32::from(*value);
+        }
+    }
+    let count = frames.len() as u32;
+    let pixels = sums.into_iter().map(|sum| (sum / count) as u8).collect();
+    let blended = RgbaImage::from_raw(first.width(), first.height(), pixels)
+        .expect("the buffer has the size of the first frame");
+    DynamicImage::ImageRgba8(blended)
+}
+
+/// Camera RAW files carry JPEG previews, often a thumbnail and a full size one. Decode the
+/// largest, found by the JPEG start of image marker and confirmed by reading its header
+fn largest_embedded_jpeg(bytes: &[u8]) -> Result<DynamicImage> {
+    let mut largest: Option<(u64, usize)> = None;
+    for (offset, window) in bytes.windows(3).enumerate() {
+        if window != [0xFF, 0xD8, 0xFF] {
+            continue;
+        }
+        let Ok((width, height)) =
+            ImageReader::with_format(Cursor::new(&bytes[offset..]), ImageFormat::Jpeg)
+                .into_dimensions()
+        else {
+            continue;
+        };
+        let area = u64::from(width) * u64::from(height);
+        if largest.is_none_or(|(largest_area, _)| area > largest_area) {
+            largest = Some((area, offset));
+        }
+    }
+    let Some((_, offset)) = largest else {
+        bail!("the file doesn't contain a JPEG preview");
+    };
+    Ok(image::load_from_memory_with_format(
+        &bytes[offset..],
+        ImageFormat::Jpeg,
+    )?)
+}
+
+/// Read the cover image of an EPUB, returning its bytes and its path inside the archive
+pub fn extract_epub_cover(path: &Path) -> Result<(Vec<u8>, String)> {
+    let file = fs::File::open(extended_length_path(path))?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("{} is not a valid EPUB archive", path.display()))?;
+
+    let container = read_entry(&mut archive, "META-INF/container.xml", MAX_EPUB_ENTRY_SIZE)?;
+    let container = String::from_utf8_lossy(&container);
+    let opf_path = find_tags(&container, "rootfile")
+        .find_map(|tag| attribute(tag, "full-path"))
+        .ok_or_else(|| anyhow!("EPUB container doesn't name a package document"))?
+        .to_string();
+
+    let opf = read_entry(&mut archive, &opf_path, MAX_EPUB_ENTRY_SIZE)?;
+    let opf = String::from_utf8_lossy(&opf);
+    let cover_href = find_cover_href(&opf)
+        .ok_or_else(|| anyhow!("No cover image found in {}", path.display()))?;
+
+    let cover_path = resolve_href(&opf_path, cover_href);
+    let cover = read_entry(&mut archive, &cover_path, MAX_EPUB_ENTRY_SIZE)?;
+    Ok((cover, cover_path))
+}
+
+/// Read an entry of at most `limit` bytes, going by both its declared and its actual size
+fn read_entry<R: std::io::Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+    limit: u64,
+) -> Result<Vec<u8>> {
+    let entry = archive
+        .by_name(name)
+        .with_context(|| format!("EPUB entry {name} is missing"))?;
+    if entry.size() > limit {
+        bail!(
+            "EPUB entry {name} is {} bytes, more than the {limit} read",
+            entry.size()
+        );
+    }
+    let mut buffer = Vec::new();
+    entry.take(limit + 1).read_to_end(&mut buffer)?;
+    if buffer.len() as u64 > limit {
+        bail!("EPUB entry {name} is more than the {limit} bytes read");
+    }
+    Ok(buffer)
+}
+
+/// Locate the cover in the package document, trying the EPUB 3 `cover-image` property, then
+/// the EPUB 2 `<meta name="cover">` entry, then any image item that looks like a cover
+fn find_cover_href(opf: &str) -> Option<&str> {
+    let items: Vec<&str> = find_tags(opf, "item").collect();
+    let is_image = |item: &&&str| {
+        attribute(item, "media-type").is_some_and(|media_type| media_type.starts_with("image/"))
+    };
+
+    let by_property = items.iter().find(|item| {
+        attribute(item, "properties")
+            .is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image"))
+    });
+
+    let by_meta = || {
+        let cover_id = find_tags(opf, "meta")
+            .find(|meta| attribute(meta, "name") == Some("cover"))
+            .and_then(|meta| attribute(meta, "content"))?;
+        items
+            .iter()
+            .find(|item| attribute(item, "id") == Some(cover_id))
+    };
+
+    let by_name = || {
+        items.iter().filter(is_image).find(|item| {
+            [attribute(item, "id"), attribute(item, "href")]
+                .into_iter()
+                .flatten()
+                .any(|value| value.to_lowercase().contains("cover"))
+        })
+    };
+
+    by_property
+        .or_else(by_meta)
+        .or_else(by_name)
+        .or_else(|| items.iter().find(is_image))
+        .and_then(|item| attribute(item, "href"))
+}
+
+/// Resolve an href relative to the package document into a path inside the archive
+fn resolve_href(opf_path: &str, href: &str) -> String {
+    let href = urlencoding::decode(href)
+        .map(|decoded| decoded.into_owned())
+        .unwrap_or_else(|_| href.to_string());
+
+    let mut parts: Vec<&str> = opf_path.split('/').collect();
+    parts.pop(); // The package document's own file name
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            segment => parts.push(segment),
+        }
+    }
+    parts.join("/")
+}
+
+/// Iterate over the attribute text of every `<name ...>` tag, ignoring any namespace prefix
+fn find_tags<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    xml.split('<').skip(1).filter_map(move |fragment| {
+        let tag = fragment.split('>').next()?;
+        let tag_name = tag.split(|c: char| c.is_whitespace() || c == '/').next()?;
+        let local_name = tag_name.rsplit(':').next()?;
+        (local_name == name).then(|| &tag[tag_name.len()..])
+    })
+}
+
+/// Get an attribute value from the attribute text of a tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(position) = rest.find(name) {
+        let preceded_by_space = rest[..position]
+            .chars()
+            .last()
+            .is_none_or(char::is_whitespace);
+        let after = rest[position + name.len()..].trim_start();
+        if preceded_by_space {
+            if let Some(value) = after.strip_prefix('=') {
+                let value = value.trim_start();
+                let quote = value.chars().next()?;
+                if quote == '"' || quote == '\'' {
+                    let value = &value[1..];
+                    return value.find(quote).map(|end| &value[..end]);
+                }
+            }
+        }
+        rest = &rest[position + name.len()..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const EPUB3_OPF: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <manifest>
+    <item id="chapter" href="text/chapter1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="img1" href="images/map.png" media-type="image/png"/>
+    <item id="c" href="images/front%20cover.jpg" media-type="image/jpeg" properties="cover-image"/>
+  </manifest>
+</package>"#;
+
+    const EPUB2_OPF: &str = r#"<opf:package>
+  <opf:metadata><opf:meta content="cov" name="cover"/></opf:metadata>
+  <opf:manifest>
+    <opf:item id="img1" href="map.png" media-type="image/png"/>
+    <opf:item href="cover.jpeg" id="cov" media-type="image/jpeg"/>
+  </opf:manifest>
+</opf:package>"#;
+
+    #[test]
+    fn entries_over_the_limit_are_not_read() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        writer
+            .start_file("cover.jpg", options)
+            .expect("start entry");
+        writer.write_all(&[0; 4096]).expect("write entry");
+        let mut archive =
+            ZipArchive::new(writer.finish().expect("finish archive")).expect("read archive");
+
+        assert_eq!(
+            read_entry(&mut archive, "cover.jpg", 4096)
+                .expect("within the limit")
+                .len(),
+            4096
+        );
+        let error = read_entry(&mut archive, "cover.jpg", 1024).expect_err("over the limit");
+        assert!(error.to_string().contains("1024"), "{error}");
+    }
+
+    #[test]
+    fn finds_epub3_cover_property() {
+        assert_eq!(find_cover_href(EPUB3_OPF), Some("images/front%20cover.jpg"));
+    }
+
+    #[test]
+    fn finds_epub2_cover_meta() {
+        assert_eq!(find_cover_href(EPUB2_OPF), Some("cover.jpeg"));
+    }
+
+    #[test]
+    fn resolves_hrefs_relative_to_package() {
+        assert_eq!(
+            resolve_href("OEBPS/content.opf", "images/front%20cover.jpg"),
+            "OEBPS/images/front cover.jpg"
+        );
+        assert_eq!(
+            resolve_href("OEBPS/text/content.opf", "../cover.jpg"),
+            "OEBPS/cover.jpg"
+        );
+        assert_eq!(resolve_href("content.opf", "cover.jpg"), "cover.jpg");
+    }
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .expect("encoding a JPEG in memory should work");
+        bytes
+    }
+
+    #[test]
+    fn picks_the_largest_embedded_preview() {
+        let mut raw = b"II*\0not a real raw header \xFF\xD8\xFF junk".to_vec();
+        raw.extend(jpeg(16, 12));
+        raw.extend(b"sensor data");
+        raw.extend(jpeg(64, 48));
+        raw.extend(b"more sensor data");
+
+        let preview = largest_embedded_jpeg(&raw).expect("a preview should be found");
+        assert_eq!((preview.width(), preview.height()), (64, 48));
+        assert!(largest_embedded_jpeg(b"no previews here").is_err());
+    }
+
+    #[test]
+    fn blends_frames_evenly() {
+        let frames = [0u8, 200].map(|value| {
+            Frame::new(RgbaImage::from_pixel(
+                2,
+                2,
+                image::Rgba([value, value, value, 255]),
+            ))
+        });
+        let blended = average_frames(&frames).to_rgba8();
+        assert_eq!(blended.get_pixel(1, 1), &image::Rgba([100, 100, 100, 255]));
+    }
+}
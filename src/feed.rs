@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+
+use crate::backup::{civil_from_days, SECONDS_PER_DAY};
+
+/// How many of the most recently discovered groups the feed lists
+pub const FEED_ENTRIES: usize = 50;
+
+/// A duplicate group the first time it was stored, files sorted by path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupDiscovery {
+    /// Stays the same for as long as the group has the same files
+    pub key: String,
+    /// Seconds since the Unix epoch
+    pub discovered_at: i64,
+    pub paths: Vec<PathBuf>,
+}
+
+/// A discovered group with what removing its duplicates would free up
+#[derive(Debug, Clone)]
+pub struct FeedEntry {
+    pub discovery: GroupDiscovery,
+    pub reclaimable_bytes: u64,
+}
+
+/// An Atom feed of discovered groups, newest first. `base_url` is where the web interface is
+/// served, entries link to it
+pub fn atom_feed(entries: &[FeedEntry], base_url: &str, threshold: u32) -> String {
+    let updated = entries
+        .iter()
+        .map(|entry| entry.discovery.discovered_at)
+        .max()
+        .unwrap_or_default();
+
+    let link = xml_escape(base_url);
+    let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str(&format!(
+        "  <title>Duplicate images (threshold {threshold})</title>\n"
+    ));
+    feed.push_str(&format!(
+        "  <id>urn:vibe-image-comparator:groups:{threshold}</id>\n"
+    ));
+    feed.push_str(&format!("  <link href=\"{link}\"/>\n"));
+    feed.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+    for entry in entries {
+        let discovery = &entry.discovery;
+        let files: Vec<String> = discovery
+            .paths
+            .iter()
+            .map(|path| format!("<li>{}</li>", xml_escape(&path.to_string_lossy())))
+            .collect();
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!(
+            "    <title>{} duplicate files, {} bytes reclaimable</title>\n",
+            discovery.paths.len(),
+            entry.reclaimable_bytes
+        ));
+        feed.push_str(&format!(
+            "    <id>urn:vibe-image-comparator:group:{}</id>\n",
+            discovery.key
+        ));
+        feed.push_str(&format!("    <link href=\"{link}\"/>\n"));
+        feed.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            rfc3339(discovery.discovered_at)
+        ));
+        feed.push_str(&format!(
+            "    <content type=\"html\">{}</content>\n",
+            xml_escape(&format!("<ul>{}</ul>", files.concat()))
+        ));
+        feed.push_str("  </entry>\n");
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ` for seconds since the Unix epoch
+fn rfc3339(seconds: i64) -> String {
+    let seconds = seconds.max(0) as u64;
+    let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_list_size_and_savings() {
+        let entries = vec![FeedEntry {
+            discovery: GroupDiscovery {
+                key: "abc".to_string(),
+                discovered_at: 1_709_251_198,
+                paths: vec![
+                    PathBuf::from("/photos/a.jpg"),
+                    PathBuf::from("/photos/b&c.jpg"),
+                ],
+            },
+            reclaimable_bytes: 1234,
+        }];
+        let feed = atom_feed(&entries, "http://localhost:8080/", 15);
+
+        assert!(feed.contains("<updated>2024-02-29T23:59:58Z</updated>"));
+        assert!(feed.contains("<title>2 duplicate files, 1234 bytes reclaimable</title>"));
+        assert!(feed.contains("<id>urn:vibe-image-comparator:group:abc</id>"));
+        // The file list is HTML, escaped once more to sit inside the XML
+        assert!(feed.contains("&lt;li&gt;/photos/b&amp;amp;c.jpg&lt;/li&gt;"));
+    }
+}
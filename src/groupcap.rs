@@ -0,0 +1,140 @@
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::warn;
+
+use crate::hasher::star_groups;
+
+/// Groups larger than this are split, unless configured otherwise
+pub const DEFAULT_MAX_GROUP_SIZE: usize = 100;
+/// Below this share of file pairs within the threshold, a group is held together by chaining
+const CHAIN_LINKED_SHARE: f64 = 0.5;
+
+/// Set from `--max-group-size` or the config file's `max_group_size` at startup
+static MAX_GROUP_SIZE: OnceLock<usize> = OnceLock::new();
+
+/// Split larger groups for the rest of the process, 0 never splits
+pub fn set_max_group_size(max: usize) {
+    if MAX_GROUP_SIZE.set(max).is_err() {
+        warn!("Maximum group size was already set");
+    }
+}
+
+/// None when groups are never split
+pub fn max_group_size() -> Option<usize> {
+    match MAX_GROUP_SIZE
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_MAX_GROUP_SIZE)
+    {
+        0 => None,
+        max => Some(max),
+    }
+}
+
+/// Split a group with more than `max` files by regrouping it at half the threshold, again and
+/// again for parts that are still too large. Files that aren't within the tighter threshold of
+/// anything drop out. Splits are logged with why the group got that large
+pub(crate) fn cap_group_size(
+    paths: &[&Path],
+    adjacent: &[Vec<(usize, u32)>],
+    group: Vec<usize>,
+    threshold: u32,
+    max: usize,
+) -> Vec<Vec<usize>> {
+    if group.len() <= max {
+        return vec![group];
+    }
+    let (parts, tightest) = split(paths, adjacent, group.clone(), threshold, max);
+
+    let size = group.len();
+    let kept: usize = parts.iter().map(Vec::len).sum();
+    let mut in_group = vec![false; paths.len()];
+    for &i in &group {
+        in_group[i] = true;
+    }
+    // Each pair within the threshold is listed from both ends
+    let linked = group
+        .iter()
+        .map(|&i| {
+            adjacent[i]
+                .iter()
+                .filter(|&&(j, distance)| distance <= threshold && in_group[j])
+                .count()
+        })
+        .sum::<usize>()
+        / 2;
+    let linked_share = linked as f64 / (size * (size - 1) / 2) as f64;
+    let reason = if linked_share < CHAIN_LINKED_SHARE {
+        format!(
+            "chain detected, only {:.0}% of its file pairs are within {threshold} of each other and the rest are linked through other files",
+            linked_share * 100.0
+        )
+    } else {
+        format!(
+            "{:.0}% of its file pairs are within {threshold} of each other, the threshold is loose for these images",
+            linked_share * 100.0
+        )
+    };
+    warn!(
+        "Group of {size} files starting at {} is larger than {max}: {reason}. Split into {} groups down to threshold {tightest}, {} files matched nothing that closely",
+        paths[group[0]].display(),
+        parts.len(),
+        size - kept
+    );
+    parts
+}
+
+/// The parts of a group regrouped until none has more than `max` files, with the tightest
+/// threshold that took
+fn split(
+    paths: &[&Path],
+    adjacent: &[Vec<(usize, u32)>],
+    group: Vec<usize>,
+    threshold: u32,
+    max: usize,
+) -> (Vec<Vec<usize>>, u32) {
+    // Identical hashes can't be told apart any further
+    if group.len() <= max || threshold == 0 {
+        return (vec![group], threshold);
+    }
+    let tighter = threshold / 2;
+    let mut parts = Vec::new();
+    let mut tightest = tighter;
+    for part in star_groups(paths, adjacent, &group, tighter) {
+        let (split_parts, part_tightest) = split(paths, adjacent, part, tighter, max);
+        parts.extend(split_parts);
+        tightest = tightest.min(part_tightest);
+    }
+    (parts, tightest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_groups_split_at_tighter_thresholds() {
+        let paths: Vec<&Path> = ["a", "b", "c", "d", "e"].map(Path::new).to_vec();
+        // `a` reaches everything at 8, the others only pair up closely
+        let mut adjacent = vec![Vec::new(); paths.len()];
+        let mut link = |i: usize, j: usize, distance: u32| {
+            adjacent[i].push((j, distance));
+            adjacent[j].push((i, distance));
+        };
+        for j in 1..5 {
+            link(0, j, 8);
+        }
+        link(1, 2, 2);
+        link(3, 4, 2);
+
+        let group = vec![0, 1, 2, 3, 4];
+        assert_eq!(
+            cap_group_size(&paths, &adjacent, group.clone(), 10, 5),
+            vec![group.clone()]
+        );
+        assert_eq!(
+            cap_group_size(&paths, &adjacent, group, 10, 3),
+            vec![vec![1, 2], vec![3, 4]]
+        );
+    }
+}
@@ -1,301 +1,711 @@
 use anyhow::Result;
-use imghash::{perceptual::PerceptualHasher, ImageHash, ImageHasher};
+use imghash::ImageHash;
+use indicatif::ProgressBar;
 use rayon::prelude::*;
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
 use std::fs;
 use std::path::{Path, PathBuf};
-use tracing::{debug, info, warn};
-
-use crate::cache::{FileMetadata, HashCache};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::thread;
+use std::time::UNIX_EPOCH;
+use tracing::field::Empty;
+use tracing::{debug, debug_span, info, instrument, warn, Span};
+
+use crate::algorithm::{hash_algorithm, GridHasher};
+use crate::annotations::pin_to_top;
+use crate::background::{throttle_io, wait_while_paused};
+use crate::bktree::HashIndex;
+use crate::cache::{hash_side, FileMetadata, GroupingKey, HashCache};
+use crate::canonical::canonical_hash_with;
+use crate::concurrency::{io_threads, on_cpu_threads};
+use crate::deadline::time_is_up;
+use crate::extract::{is_ebook, open_image};
+use crate::groupcap::{cap_group_size, max_group_size};
 use crate::hex::encode_lower_hex;
+use crate::imageinfo::dominant_color;
+use crate::overrides::apply_overrides;
+use crate::paths::extended_length_path;
+use crate::progress::progress_bar;
+use crate::report::{FileWarnings, HashReport, SkipReason, SkippedFiles, WarningKind};
+use crate::tags::{file_tags, is_keep_tagged};
+use crate::thumbnail::{load_or_create_thumbnail, save_thumbnail, thumbnail_path};
+
+/// Number of cache misses hashed in parallel before their results are written to the cache
+const HASH_BATCH_SIZE: usize = 256;
+/// Number of cached hash rows read from the database at a time when grouping from the cache
+const CACHE_READ_BLOCK_SIZE: usize = 10_000;
+/// Pairs this close are stored whenever groups are computed from the cached hashes, so other
+/// thresholds up to here regroup from the stored distances without comparing hashes again
+pub const STORED_DISTANCE_LIMIT: u32 = 20;
 
 #[derive(Debug, Clone)]
 pub struct ImageMetadata {
     pub path: PathBuf,
     pub size: u64,
     pub sha256: String,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: Option<i64>,
+    /// Tags from extended attributes, see `tags::file_tags`
+    pub tags: Vec<String>,
 }
 
+#[instrument(level = "trace", skip_all, fields(bytes = Empty))]
 pub fn calculate_file_sha256(path: &Path) -> Result<String> {
-    Ok(encode_lower_hex(Sha256::digest(&fs::read(path)?)))
+    let contents = fs::read(extended_length_path(path))?;
+    Span::current().record("bytes", contents.len());
+    throttle_io(contents.len() as u64);
+    Ok(encode_lower_hex(Sha256::digest(&contents)))
+}
+
+pub fn get_file_metadata(path: &Path) -> Result<ImageMetadata> {
+    let metadata = fs::metadata(extended_length_path(path))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64);
+    Ok(ImageMetadata {
+        path: path.to_path_buf(),
+        size: metadata.len(),
+        sha256: calculate_file_sha256(path)?,
+        modified,
+        tags: file_tags(path),
+    })
 }
 
-pub fn get_file_metadata(path: &Path) -> Result<(u64, String)> {
-    let metadata = fs::metadata(path)?;
-    let size = metadata.len();
-    let sha256 = calculate_file_sha256(path)?;
-    Ok((size, sha256))
+/// Decode a stored hash, its grid size follows from its length
+pub fn decode_hash(encoded: &str) -> Result<ImageHash> {
+    let side = hash_side(encoded);
+    Ok(ImageHash::decode(encoded, side, side)?)
 }
 
+/// The canonical hash without its orientation, see `canonical_hash`
 pub fn generate_rotation_invariant_hash_safe(
-    hasher: &PerceptualHasher,
+    hasher: &GridHasher,
     img: &image::DynamicImage,
 ) -> Result<ImageHash> {
-    let original_hash = hasher.hash_from_img(img)?;
-    let rotated_90 = img.rotate90();
-    let rotated_90_hash = hasher.hash_from_img(&rotated_90)?;
-    let rotated_180 = img.rotate180();
-    let rotated_180_hash = hasher.hash_from_img(&rotated_180)?;
-    let rotated_270 = img.rotate270();
-    let rotated_270_hash = hasher.hash_from_img(&rotated_270)?;
-
-    let mut candidates = vec![
-        (original_hash.encode()?, original_hash),
-        (rotated_90_hash.encode()?, rotated_90_hash),
-        (rotated_180_hash.encode()?, rotated_180_hash),
-        (rotated_270_hash.encode()?, rotated_270_hash),
-    ];
-
-    candidates.sort_by_key(|(encoded, _)| encoded.clone());
-    candidates
-        .into_iter()
-        .next()
-        .map(|(_, hash)| hash)
-        .ok_or_else(|| anyhow::anyhow!("No rotation candidate hashes generated"))
+    canonical_hash_with(hasher, img).map(|(hash, _)| hash)
 }
 
 pub fn generate_hashes_with_cache(
     images: &[PathBuf],
-    _grid_size: u32,
+    grid_size: u8,
     cache: &HashCache,
     debug: bool,
 ) -> Result<Vec<(PathBuf, ImageHash)>> {
-    // First, collect metadata for all images in parallel
-    let metadata_results: Vec<_> = images
-        .par_iter()
-        .map(|image_path| match get_file_metadata(image_path) {
-            Ok((size, sha256)) => Some(ImageMetadata {
-                path: image_path.clone(),
-                size,
-                sha256,
-            }),
-            Err(e) => {
-                warn!(
-                    "Could not get metadata for {} (possibly broken symlink): {}",
-                    image_path.display(),
-                    e
+    generate_hashes_with_report(images, grid_size, cache, debug).map(|(hashes, _)| hashes)
+}
+
+/// Generate hashes, also returning the files that couldn't be read or decoded and the warnings
+/// about files along the way
+pub fn generate_hashes_with_report(
+    images: &[PathBuf],
+    grid_size: u8,
+    cache: &HashCache,
+    debug: bool,
+) -> Result<(Vec<(PathBuf, ImageHash)>, HashReport)> {
+    generate_hashes_and_thumbnails(images, grid_size, cache, debug, None)
+}
+
+/// Generate hashes like `generate_hashes_with_report`, and with `thumbnails` also store a
+/// thumbnail for each image in that folder. Images that are hashed anyway are thumbnailed from
+/// the decoded image; cached images only get decoded when their thumbnail is missing.
+///
+/// Files stream from checksumming, on `io_threads` threads, straight into decoding and hashing
+/// on the CPU threads, so a slow disk is read while earlier files are hashed. Results are taken
+/// in the order the files were given, whichever thread finished first.
+#[instrument(
+    level = "debug",
+    skip_all,
+    fields(files = images.len(), cache_hits = Empty, cache_misses = Empty)
+)]
+pub fn generate_hashes_and_thumbnails(
+    images: &[PathBuf],
+    grid_size: u8,
+    cache: &HashCache,
+    debug: bool,
+    thumbnails: Option<&Path>,
+) -> Result<(Vec<(PathBuf, ImageHash)>, HashReport)> {
+    // Files are hashed in parallel, so warnings are collected behind a lock
+    let warnings = Mutex::new(FileWarnings::default());
+    let mut run = HashRun {
+        cache,
+        debug,
+        thumbnails,
+        warnings: &warnings,
+        grid_size,
+        hasher: hash_algorithm().hasher(grid_size)?,
+        hashes: Vec::new(),
+        pending: Vec::new(),
+        missing_thumbnails: Vec::new(),
+        skipped: SkippedFiles::default(),
+        cache_hits: 0,
+        cache_misses: 0,
+        keep_tagged: 0,
+        batches: 0,
+        // Debug output prints a line per file, which would break up the bar
+        progress: if debug {
+            ProgressBar::hidden()
+        } else {
+            progress_bar(images.len(), "Hashing")
+        },
+    };
+
+    let next_file = AtomicUsize::new(0);
+    // Room for a batch ahead, so reading carries on while the previous batch is hashed
+    let (sender, receiver) = mpsc::sync_channel(HASH_BATCH_SIZE);
+    thread::scope(|scope| {
+        for _ in 0..io_threads().min(images.len()) {
+            let (sender, next_file, warnings) = (sender.clone(), &next_file, &warnings);
+            scope.spawn(move || loop {
+                let index = next_file.fetch_add(1, Ordering::Relaxed);
+                let Some(image_path) = images.get(index) else {
+                    break;
+                };
+                if sender
+                    .send((index, fingerprint(image_path, warnings)))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // Files finished ahead of an earlier one wait for it
+        let mut finished_early = BTreeMap::new();
+        let mut next_index = 0;
+        for (index, fingerprint) in receiver {
+            finished_early.insert(index, fingerprint);
+            while let Some(fingerprint) = finished_early.remove(&next_index) {
+                run.add(fingerprint);
+                next_index += 1;
+            }
+        }
+    });
+    run.hash_pending();
+    run.progress.finish_and_clear();
+
+    let HashRun {
+        hashes,
+        missing_thumbnails,
+        skipped,
+        cache_hits,
+        cache_misses,
+        keep_tagged,
+        ..
+    } = run;
+    if cache_hits > 0 || cache_misses > 0 {
+        info!("Cache stats: {cache_hits} hits, {cache_misses} misses");
+    }
+    // Rows cached under another spelling before the scan roots were spelled as on disk would
+    // otherwise show up as a duplicate of the file itself
+    if let Err(e) = cache.fold_case_aliases() {
+        warn!("Could not fold paths that only differ in case: {e}");
+    }
+    Span::current()
+        .record("cache_hits", cache_hits)
+        .record("cache_misses", cache_misses);
+
+    if let Some(dir) = thumbnails.filter(|_| !missing_thumbnails.is_empty()) {
+        info!(
+            "Generating {} missing thumbnails for cached images...",
+            missing_thumbnails.len()
+        );
+        let _span = debug_span!("thumbnails", files = missing_thumbnails.len()).entered();
+        missing_thumbnails.par_iter().for_each(|metadata| {
+            if time_is_up() {
+                return;
+            }
+            wait_while_paused();
+            throttle_io(metadata.size);
+            if let Err(e) = load_or_create_thumbnail(dir, &metadata.path, &metadata.sha256) {
+                record_warning(
+                    &warnings,
+                    WarningKind::Thumbnail,
+                    &metadata.path,
+                    format!("Could not create thumbnail: {e}"),
                 );
-                None
             }
-        })
-        .collect();
+        });
+    }
 
-    // Separate cache hits from cache misses (sequential due to SQLite constraints)
-    let mut hashes = Vec::new();
-    let mut cache_hits = 0;
-    let mut cache_misses = 0;
-    let mut files_to_process: Vec<ImageMetadata> = Vec::new();
-
-    // First pass: check cache and collect cache hits
-    for metadata in metadata_results.into_iter().flatten() {
-        if let Ok(Some(hash_string)) =
-            cache.get_cached_hash(&metadata.path, metadata.size, &metadata.sha256)
-        {
+    let warnings = warnings
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    Ok((
+        hashes,
+        HashReport {
+            skipped,
+            warnings,
+            cache_hits,
+            cache_misses,
+            keep_tagged,
+        },
+    ))
+}
+
+/// Read a file's size, checksum and tags, the IO half of hashing it
+fn fingerprint(
+    image_path: &Path,
+    warnings: &Mutex<FileWarnings>,
+) -> Result<ImageMetadata, (PathBuf, SkipReason)> {
+    if time_is_up() {
+        return Err((image_path.to_path_buf(), SkipReason::OutOfTime));
+    }
+    wait_while_paused();
+    let _span = debug_span!("file_metadata", path = %image_path.display()).entered();
+    get_file_metadata(image_path).map_err(|e| {
+        record_warning(
+            warnings,
+            WarningKind::Metadata,
+            image_path,
+            format!("Could not get metadata (possibly broken symlink): {e}"),
+        );
+        (image_path.to_path_buf(), SkipReason::Inaccessible)
+    })
+}
+
+/// What `generate_hashes_and_thumbnails` has done so far. Fingerprinted files are looked up in
+/// the cache one by one, since SQLite is used from this thread only, and misses are hashed in
+/// parallel a batch at a time
+struct HashRun<'a> {
+    cache: &'a HashCache,
+    debug: bool,
+    thumbnails: Option<&'a Path>,
+    warnings: &'a Mutex<FileWarnings>,
+    grid_size: u8,
+    hasher: GridHasher,
+    hashes: Vec<(PathBuf, ImageHash)>,
+    /// Cache misses waiting for a full batch
+    pending: Vec<ImageMetadata>,
+    missing_thumbnails: Vec<ImageMetadata>,
+    skipped: SkippedFiles,
+    cache_hits: usize,
+    cache_misses: usize,
+    keep_tagged: usize,
+    batches: usize,
+    /// Files done, with the share of them found in the cache
+    progress: ProgressBar,
+}
+
+impl HashRun<'_> {
+    fn add(&mut self, fingerprint: Result<ImageMetadata, (PathBuf, SkipReason)>) {
+        let metadata = match fingerprint {
+            Ok(metadata) => metadata,
+            Err((image_path, reason)) => {
+                self.skipped.record(reason, image_path);
+                self.progress.inc(1);
+                return;
+            }
+        };
+        if is_keep_tagged(&metadata.tags) {
+            self.keep_tagged += 1;
+        }
+        if let Err(e) = self.cache.store_file_tags(&metadata.path, &metadata.tags) {
+            record_warning(
+                self.warnings,
+                WarningKind::Cache,
+                &metadata.path,
+                format!("Could not cache tags: {e}"),
+            );
+        }
+        let _span = debug_span!("cache_lookup", path = %metadata.path.display()).entered();
+        if let Ok(Some(hash_string)) = self.cache.get_cached_hash(
+            &metadata.path,
+            metadata.size,
+            &metadata.sha256,
+            self.grid_size,
+        ) {
             // Decode the string back to ImageHash
-            match ImageHash::decode(&hash_string, 8, 8) {
+            match decode_hash(&hash_string) {
                 Ok(hash) => {
-                    if debug {
+                    if self.debug {
                         debug!("Cache hit: {}", metadata.path.display());
                     }
-                    hashes.push((metadata.path, hash));
-                    cache_hits += 1;
+                    if self
+                        .thumbnails
+                        .is_some_and(|dir| !thumbnail_path(dir, &metadata.sha256).exists())
+                    {
+                        self.missing_thumbnails.push(metadata.clone());
+                    }
+                    self.hashes.push((metadata.path, hash));
+                    self.cache_hits += 1;
+                    self.show_cache_hits();
+                    self.progress.inc(1);
+                    return;
                 }
                 Err(e) => {
-                    warn!(
-                        "Invalid cached hash format for {}: {}",
-                        metadata.path.display(),
-                        e
+                    record_warning(
+                        self.warnings,
+                        WarningKind::InvalidCachedHash,
+                        &metadata.path,
+                        format!("Invalid cached hash format: {e}"),
                     );
-                    // Need to reprocess this file
-                    files_to_process.push(metadata);
                 }
             }
-        } else {
-            // Cache miss - need to process this file
-            files_to_process.push(metadata);
+        }
+        // Cache miss, or a cached hash that has to be made again
+        self.pending.push(metadata);
+        if self.pending.len() >= HASH_BATCH_SIZE {
+            self.hash_pending();
         }
     }
 
-    // Only create hasher if we have files to process
-    if !files_to_process.is_empty() {
-        let hasher = PerceptualHasher::default();
-
-        // Second pass: process files in parallel, then store results sequentially
-        let processing_results: Vec<_> = files_to_process
-            .par_iter()
-            .map(|metadata| {
-                if debug {
-                    debug!("Processing: {}", metadata.path.display());
-                }
-
-                match image::open(&metadata.path) {
-                    Ok(img) => match generate_rotation_invariant_hash_safe(&hasher, &img) {
-                        Ok(hash) => {
-                            let perceptual_hash = match hash.encode() {
-                                Ok(perceptual_hash) => perceptual_hash,
-                                Err(e) => {
-                                    warn!(
-                                        "Could not encode hash for {}: {}",
-                                        metadata.path.display(),
-                                        e
-                                    );
-                                    return Err(metadata.path.clone());
-                                }
-                            };
-                            let file_metadata = FileMetadata {
-                                path: metadata.path.clone(),
-                                size: metadata.size,
-                                sha256: metadata.sha256.clone(),
-                                perceptual_hash,
-                            };
-                            Ok((metadata.path.clone(), hash, Some(file_metadata)))
-                        }
-                        Err(e) => {
-                            warn!(
-                                "Could not generate hash for {}: {}",
-                                metadata.path.display(),
-                                e
-                            );
-                            Err(metadata.path.clone())
-                        }
-                    },
-                    Err(e) => {
-                        // Provide more specific error messages for common image format issues
-                        let error_msg = if e.to_string().contains("invalid PNG signature") {
-                            format!("Invalid PNG file (corrupted or wrong format): {e}")
-                        } else if e.to_string().contains("invalid JPEG") {
-                            format!("Invalid JPEG file (corrupted or wrong format): {e}")
-                        } else if e.to_string().contains("unsupported") {
-                            format!("Unsupported image format: {e}")
-                        } else {
-                            format!("Image decoding error: {e}")
-                        };
-
-                        if debug {
-                            debug!("Could not open {}: {}", metadata.path.display(), error_msg);
-                        } else {
-                            warn!("Skipping {}: {}", metadata.path.display(), error_msg);
-                        }
-
-                        Err(metadata.path.clone())
-                    }
-                }
-            })
-            .collect();
+    /// Hash the waiting cache misses in parallel, then store them in order. Batches follow the
+    /// hashing order, so earlier files are cached even if the scan stops
+    fn hash_pending(&mut self) {
+        let batch = std::mem::take(&mut self.pending);
+        if batch.is_empty() {
+            return;
+        }
+        // Stopping between batches keeps everything hashed so far in the cache
+        if time_is_up() {
+            self.progress.inc(batch.len() as u64);
+            for metadata in batch {
+                self.skipped.record(SkipReason::OutOfTime, metadata.path);
+            }
+            return;
+        }
+        let number = self.batches;
+        self.batches += 1;
+        let bytes: u64 = batch.iter().map(|metadata| metadata.size).sum();
+        let batch_span =
+            debug_span!("hash_batch", batch = number, files = batch.len(), bytes).entered();
+        let processing_results: Vec<_> = on_cpu_threads(|| {
+            batch
+                .par_iter()
+                .map(|metadata| {
+                    decode_and_hash(
+                        metadata,
+                        &self.hasher,
+                        self.thumbnails,
+                        self.warnings,
+                        self.debug,
+                    )
+                })
+                .inspect(|_| self.progress.inc(1))
+                .collect()
+        });
+        drop(batch_span);
 
         // Now handle cache operations and result collection sequentially
+        let _store_span = debug_span!("store_batch", batch = number, files = batch.len()).entered();
         for result in processing_results {
             match result {
-                Ok((image_path, hash, metadata_opt)) => {
-                    if let Some(metadata) = metadata_opt {
-                        if let Err(e) = cache.store_hash(&metadata) {
-                            warn!("Could not cache hash for {}: {}", image_path.display(), e);
-                        }
+                Ok((hash, metadata)) => {
+                    if let Err(e) = self.cache.store_hash(&metadata) {
+                        record_warning(
+                            self.warnings,
+                            WarningKind::Cache,
+                            &metadata.path,
+                            format!("Could not cache hash: {e}"),
+                        );
                     }
-                    hashes.push((image_path, hash));
-                    cache_misses += 1;
+                    self.hashes.push((metadata.path, hash));
+                    self.cache_misses += 1;
                 }
                 Err(image_path) => {
                     // Remove broken file from cache if it exists
-                    if let Err(cache_err) = cache.remove_file_entry(&image_path) {
-                        warn!("Could not remove broken file from cache: {cache_err}");
+                    if let Err(cache_err) = self.cache.remove_file_entry(&image_path) {
+                        record_warning(
+                            self.warnings,
+                            WarningKind::Cache,
+                            &image_path,
+                            format!("Could not remove broken file from cache: {cache_err}"),
+                        );
                     }
+                    self.skipped.record(SkipReason::DecodeError, image_path);
                 }
             }
         }
+        self.show_cache_hits();
     }
 
-    if cache_hits > 0 || cache_misses > 0 {
-        info!("Cache stats: {cache_hits} hits, {cache_misses} misses");
+    fn show_cache_hits(&self) {
+        let looked_up = self.cache_hits + self.cache_misses;
+        if let Some(percent) = (self.cache_hits * 100).checked_div(looked_up) {
+            self.progress.set_message(format!("{percent}% cache hits"));
+        }
     }
+}
 
-    Ok(hashes)
+/// Decode and hash a file, the CPU half of hashing it. Returns the hash with what's cached for
+/// the file, or the file's path when it couldn't be decoded or hashed
+fn decode_and_hash(
+    metadata: &ImageMetadata,
+    hasher: &GridHasher,
+    thumbnails: Option<&Path>,
+    warnings: &Mutex<FileWarnings>,
+    debug: bool,
+) -> Result<(ImageHash, FileMetadata), PathBuf> {
+    if debug {
+        debug!("Processing: {}", metadata.path.display());
+    }
+    let _span = debug_span!(
+        "decode_and_hash",
+        path = %metadata.path.display(),
+        bytes = metadata.size
+    )
+    .entered();
+
+    wait_while_paused();
+    throttle_io(metadata.size);
+    let img = match open_image(&metadata.path) {
+        Ok(img) => img,
+        Err(e) => {
+            // Provide more specific error messages for common image format issues
+            let error_msg = if e.to_string().contains("invalid PNG signature") {
+                format!("Invalid PNG file (corrupted or wrong format): {e}")
+            } else if e.to_string().contains("invalid JPEG") {
+                format!("Invalid JPEG file (corrupted or wrong format): {e}")
+            } else if e.to_string().contains("unsupported") {
+                format!("Unsupported image format: {e}")
+            } else {
+                format!("Image decoding error: {e}")
+            };
+            record_warning(warnings, WarningKind::Decode, &metadata.path, error_msg);
+            return Err(metadata.path.clone());
+        }
+    };
+    let hash = generate_rotation_invariant_hash_safe(hasher, &img).map_err(|e| {
+        record_warning(
+            warnings,
+            WarningKind::Hash,
+            &metadata.path,
+            format!("Could not generate hash: {e}"),
+        );
+        metadata.path.clone()
+    })?;
+    let perceptual_hash = hash.encode().map_err(|e| {
+        record_warning(
+            warnings,
+            WarningKind::Hash,
+            &metadata.path,
+            format!("Could not encode hash: {e}"),
+        );
+        metadata.path.clone()
+    })?;
+    if let Some(dir) = thumbnails {
+        if let Err(e) = save_thumbnail(dir, &metadata.sha256, &img) {
+            record_warning(
+                warnings,
+                WarningKind::Thumbnail,
+                &metadata.path,
+                format!("Could not save thumbnail: {e}"),
+            );
+        }
+    }
+    let file_metadata = FileMetadata {
+        path: metadata.path.clone(),
+        size: metadata.size,
+        sha256: metadata.sha256.clone(),
+        perceptual_hash,
+        width: Some(img.width()),
+        height: Some(img.height()),
+        dominant_color: Some(dominant_color(&img)),
+        modified: metadata.modified,
+    };
+    Ok((hash, file_metadata))
 }
 
-pub fn find_duplicates(hashes: &[(PathBuf, ImageHash)], threshold: u32) -> Vec<Vec<PathBuf>> {
-    let mut groups: Vec<Vec<PathBuf>> = Vec::new();
-    let mut processed = vec![false; hashes.len()];
+fn record_warning(
+    warnings: &Mutex<FileWarnings>,
+    kind: WarningKind,
+    path: &Path,
+    message: impl Display,
+) {
+    warnings
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .record(kind, path, message);
+}
 
-    for (i, (path1, hash1)) in hashes.iter().enumerate() {
-        if processed[i] {
-            continue;
-        }
+/// For every hash, find the later hashes within `max_threshold` and their distances.
+/// This is the expensive part of duplicate detection, so it's computed once and shared.
+#[instrument(level = "debug", skip_all, fields(hashes = hashes.len(), max_threshold))]
+fn compute_neighbours(
+    hashes: &[(PathBuf, ImageHash)],
+    max_threshold: u32,
+) -> Vec<Vec<(usize, u32)>> {
+    let mut index = HashIndex::default();
+    for (_, hash) in hashes {
+        index.insert(hash);
+    }
+    let progress = progress_bar(hashes.len(), "Matching");
+    let neighbours = index.neighbours(max_threshold, &progress);
+    progress.finish_and_clear();
+    neighbours
+}
 
-        let mut group = vec![path1.clone()];
-        processed[i] = true;
+/// Group paths using precomputed neighbour lists, only following edges within `threshold`.
+///
+/// The result doesn't depend on the order the hashes came in: each group is the lowest path not
+/// grouped yet plus its ungrouped neighbours, members are sorted by distance to it and then by
+/// path, and groups are sorted by their first path.
+#[instrument(level = "debug", skip_all, fields(files = paths.len(), threshold, groups = Empty))]
+fn group_from_neighbours(
+    paths: &[&Path],
+    neighbours: &[Vec<(usize, u32)>],
+    threshold: u32,
+) -> Vec<Vec<PathBuf>> {
+    // Each edge is listed once, from its lower id, but is needed from both ends
+    let mut adjacent: Vec<Vec<(usize, u32)>> = vec![Vec::new(); paths.len()];
+    for (i, list) in neighbours.iter().enumerate() {
+        for &(j, distance) in list {
+            if distance <= threshold {
+                adjacent[i].push((j, distance));
+                adjacent[j].push((i, distance));
+            }
+        }
+    }
 
-        // Parallelize the distance computation for remaining hashes
-        let remaining_hashes: Vec<_> = hashes
-            .iter()
-            .enumerate()
-            .skip(i + 1)
-            .filter(|(j, _)| !processed[*j])
+    let all: Vec<usize> = (0..paths.len()).collect();
+    let mut groups = star_groups(paths, &adjacent, &all, threshold);
+    if let Some(max) = max_group_size() {
+        groups = groups
+            .into_iter()
+            .flat_map(|group| cap_group_size(paths, &adjacent, group, threshold, max))
             .collect();
+        // Split groups go back in order of their first path
+        groups.sort_by(|a, b| paths[a[0]].cmp(paths[b[0]]));
+    }
 
-        let matches: Vec<_> = remaining_hashes
-            .par_iter()
-            .filter_map(|(j, (path2, hash2))| match hash1.distance(hash2) {
-                Ok(distance) => {
-                    if distance <= threshold as usize {
-                        Some((*j, path2.clone()))
-                    } else {
-                        None
-                    }
-                }
-                Err(_) => None,
-            })
-            .collect();
+    Span::current().record("groups", groups.len());
+    groups
+        .into_iter()
+        .map(|group| group.into_iter().map(|i| paths[i].to_path_buf()).collect())
+        .collect()
+}
+
+/// Group `members`, ids into `paths` and `adjacent`, as `group_from_neighbours` describes,
+/// only following edges within `threshold` between members
+pub(crate) fn star_groups(
+    paths: &[&Path],
+    adjacent: &[Vec<(usize, u32)>],
+    members: &[usize],
+    threshold: u32,
+) -> Vec<Vec<usize>> {
+    let mut order = members.to_vec();
+    order.sort_by(|&a, &b| paths[a].cmp(paths[b]));
+
+    let mut groups = Vec::new();
+    let mut candidate = vec![false; paths.len()];
+    for &i in members {
+        candidate[i] = true;
+    }
 
-        for (j, path2) in matches {
-            group.push(path2);
-            processed[j] = true;
+    for i in order {
+        if !candidate[i] {
+            continue;
+        }
+
+        let mut group = vec![(i, 0)];
+        candidate[i] = false;
+
+        for &(j, distance) in &adjacent[i] {
+            if distance <= threshold && candidate[j] {
+                group.push((j, distance));
+                candidate[j] = false;
+            }
         }
 
         if group.len() > 1 {
-            groups.push(group);
+            // Every other member sorts after the first path, so it stays in front
+            group.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| paths[a.0].cmp(paths[b.0])));
+            groups.push(group.into_iter().map(|(i, _)| i).collect());
         }
     }
-
     groups
 }
 
-pub fn get_duplicates_from_cache(
-    cache: &HashCache,
-    threshold: u32,
-    count: Option<usize>,
-    offset: Option<usize>,
-) -> Result<Vec<Vec<PathBuf>>> {
-    info!("Checking for cached duplicate groups...");
+fn hash_paths(hashes: &[(PathBuf, ImageHash)]) -> Vec<&Path> {
+    hashes.iter().map(|(path, _)| path.as_path()).collect()
+}
 
-    // Try to get pre-computed duplicate groups from cache
-    if let Some(cached_duplicates) = cache.get_cached_duplicate_groups(threshold, count, offset)? {
-        info!("Using cached duplicate groups");
-        return Ok(cached_duplicates);
-    }
+pub fn find_duplicates(hashes: &[(PathBuf, ImageHash)], threshold: u32) -> Vec<Vec<PathBuf>> {
+    let neighbours = compute_neighbours(hashes, threshold);
+    group_from_neighbours(&hash_paths(hashes), &neighbours, threshold)
+}
 
-    info!("No cached duplicate groups found, computing from hash cache...");
-    info!("Retrieving hashes from cache...");
-    let cached_data = cache.get_all_cached_hashes()?;
+/// Find duplicate groups for several thresholds at once.
+/// Pairwise distances are only computed once (up to the largest threshold) and then
+/// regrouped per threshold, so sweeping sensitivity levels costs about the same as one pass.
+pub fn find_duplicates_for_thresholds(
+    hashes: &[(PathBuf, ImageHash)],
+    thresholds: &[u32],
+) -> Vec<(u32, Vec<Vec<PathBuf>>)> {
+    let max_threshold = thresholds.iter().copied().max().unwrap_or(0);
+    let neighbours = compute_neighbours(hashes, max_threshold);
+    let paths = hash_paths(hashes);
+
+    thresholds
+        .iter()
+        .map(|&threshold| {
+            (
+                threshold,
+                group_from_neighbours(&paths, &neighbours, threshold),
+            )
+        })
+        .collect()
+}
 
-    if cached_data.is_empty() {
-        info!("No cached hashes found");
-        return Ok(Vec::new());
+/// Look up image dimensions, preferring the cache and falling back to reading the image header
+pub(crate) fn image_dimensions(path: &Path, cache: &HashCache) -> Option<(u32, u32)> {
+    if let Ok(Some(dimensions)) = cache.get_cached_dimensions(path) {
+        return Some(dimensions);
+    }
+    // Ebook covers have to be extracted before their header can be read
+    let dimensions = if is_ebook(path) {
+        open_image(path).map(|img| (img.width(), img.height()))
+    } else {
+        image::image_dimensions(extended_length_path(path)).map_err(Into::into)
+    };
+    match dimensions {
+        Ok(dimensions) => Some(dimensions),
+        Err(e) => {
+            warn!(path = %path.display(), "Could not read dimensions: {e}");
+            None
+        }
+    }
+}
+
+/// Split duplicate groups so that only images with identical width×height are grouped together.
+/// Images whose dimensions can't be determined are left out, since they can't satisfy the policy.
+pub fn split_groups_by_dimensions(
+    groups: Vec<Vec<PathBuf>>,
+    cache: &HashCache,
+) -> Vec<Vec<PathBuf>> {
+    let mut result = Vec::new();
+
+    for group in groups {
+        let mut by_dimensions: BTreeMap<(u32, u32), Vec<PathBuf>> = BTreeMap::new();
+        for path in group {
+            if let Some(dimensions) = image_dimensions(&path, cache) {
+                by_dimensions.entry(dimensions).or_default().push(path);
+            }
+        }
+        result.extend(by_dimensions.into_values().filter(|g| g.len() > 1));
     }
 
-    info!("Found {} cached entries", cached_data.len());
+    result
+}
 
-    // Convert cached data to (PathBuf, ImageHash) tuples
+/// Convert stored hash strings back to (PathBuf, ImageHash) tuples, skipping any that don't decode
+pub fn decode_hashes(encoded: Vec<(PathBuf, String)>) -> Vec<(PathBuf, ImageHash)> {
     let mut hashes = Vec::new();
     let mut failed_conversions = 0;
 
-    for (path, hash_string) in cached_data {
-        // Decode the string to ImageHash
-        match ImageHash::decode(&hash_string, 8, 8) {
+    for (path, hash_string) in encoded {
+        match decode_hash(&hash_string) {
             Ok(hash) => {
                 hashes.push((path, hash));
             }
             Err(e) => {
-                warn!("Could not decode hash for {}: {}", path.display(), e);
+                warn!(path = %path.display(), "Could not decode hash: {e}");
                 failed_conversions += 1;
             }
         }
@@ -305,16 +715,269 @@ pub fn get_duplicates_from_cache(
         warn!("Failed to convert {failed_conversions} cached entries");
     }
 
+    hashes
+}
+
+/// Duplicate groups from the cache with manual merges and splits applied, pinned groups first
+pub fn get_duplicates_from_cache(
+    cache: &HashCache,
+    threshold: u32,
+    grid_size: u8,
+    count: Option<usize>,
+    offset: Option<usize>,
+) -> Result<Vec<Vec<PathBuf>>> {
+    let key = GroupingKey::new(threshold, grid_size);
+    let overrides = cache.get_group_overrides()?;
+    let annotations = cache.get_group_annotations()?;
+    if overrides.is_empty() && !annotations.values().any(|annotation| annotation.pinned) {
+        return get_computed_duplicates_from_cache(cache, key, count, offset);
+    }
+
+    // Overrides and pins can move files between pages, so they're applied before paginating
+    let duplicates = pin_to_top(
+        apply_overrides(
+            get_computed_duplicates_from_cache(cache, key, None, None)?,
+            &overrides,
+        ),
+        &annotations,
+    );
+    Ok(duplicates
+        .into_iter()
+        .skip(offset.unwrap_or(0))
+        .take(count.unwrap_or(usize::MAX))
+        .collect())
+}
+
+/// Group the cached files from the pair distances stored the last time groups were computed
+/// from the hashes, without reading or comparing a hash. None when the stored distances don't
+/// reach `key.threshold` or the hashes have changed since
+pub fn regroup_from_stored_distances(
+    cache: &HashCache,
+    key: GroupingKey,
+) -> Result<Option<Vec<Vec<PathBuf>>>> {
+    let Some(pairs) = cache.get_pair_distances(key.threshold)? else {
+        return Ok(None);
+    };
     info!(
-        "Processing {} valid cached hashes for duplicates...",
-        hashes.len()
+        "Regrouping {} stored pair distances for threshold {}",
+        pairs.len(),
+        key.threshold
     );
 
-    // Find duplicates using the existing function
+    let mut ids: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    let mut neighbours: Vec<Vec<(usize, u32)>> = Vec::new();
+    let mut id = |path: PathBuf, neighbours: &mut Vec<Vec<(usize, u32)>>| {
+        let next = ids.len();
+        *ids.entry(path).or_insert_with(|| {
+            neighbours.push(Vec::new());
+            next
+        })
+    };
+    for (a, b, distance) in pairs {
+        let a = id(a, &mut neighbours);
+        let b = id(b, &mut neighbours);
+        neighbours[a].push((b, distance));
+    }
+    let mut paths = vec![Path::new(""); ids.len()];
+    for (path, &i) in &ids {
+        paths[i] = path;
+    }
+    let duplicates = group_from_neighbours(&paths, &neighbours, key.threshold);
+
+    if let Err(e) = cache.store_duplicate_groups(key, &duplicates) {
+        warn!("Failed to cache duplicate groups: {}", e);
+    }
+    Ok(Some(duplicates))
+}
+
+/// What `refresh_duplicate_groups` found when re-checking grouped files
+#[derive(Debug, Default)]
+pub struct RefreshReport {
+    pub checked: usize,
+    /// Files that are gone, they're removed from the cache
+    pub missing: Vec<PathBuf>,
+    /// Files whose contents changed, they were hashed again
+    pub changed: Vec<PathBuf>,
+    pub skipped: SkippedFiles,
+    pub warnings: FileWarnings,
+}
+
+/// Re-check only the files in the cached duplicate groups for `threshold` and regroup them:
+/// files that are gone are removed from the cache and files whose size or contents changed are
+/// hashed again. Much cheaper than a full scan, but files outside the groups aren't looked at,
+/// so a changed file that now matches one of them isn't found.
+pub fn refresh_duplicate_groups(
+    cache: &HashCache,
+    threshold: u32,
+    grid_size: u8,
+) -> Result<(Vec<Vec<PathBuf>>, RefreshReport)> {
+    let key = GroupingKey::new(threshold, grid_size);
+    let grouped: BTreeSet<PathBuf> = get_computed_duplicates_from_cache(cache, key, None, None)?
+        .into_iter()
+        .flatten()
+        .collect();
+    info!("Re-checking {} files in duplicate groups...", grouped.len());
+
+    let mut report = RefreshReport {
+        checked: grouped.len(),
+        ..RefreshReport::default()
+    };
+    let mut existing = Vec::new();
+    let mut cached_sha256 = BTreeMap::new();
+    for path in grouped {
+        if !extended_length_path(&path).exists() {
+            cache.remove_file_entry(&path)?;
+            report.missing.push(path);
+            continue;
+        }
+        if let Some((_, sha256)) = cache.get_cached_hash_details(&path)? {
+            cached_sha256.insert(path.clone(), sha256);
+        }
+        existing.push(path);
+    }
+
+    // Hashing compares each file's size and sha256 with the cache and only decodes changed ones
+    let (hashes, hash_report) = generate_hashes_with_report(&existing, grid_size, cache, false)?;
+    report.skipped = hash_report.skipped;
+    report.warnings = hash_report.warnings;
+    for (path, _) in &hashes {
+        let now = cache
+            .get_cached_hash_details(path)?
+            .map(|(_, sha256)| sha256);
+        if now.as_ref() != cached_sha256.get(path) {
+            report.changed.push(path.clone());
+        }
+    }
+
     let duplicates = find_duplicates(&hashes, threshold);
+    if let Err(e) = cache.store_duplicate_groups(key, &duplicates) {
+        warn!("Failed to cache duplicate groups: {}", e);
+    }
+    Ok((duplicates, report))
+}
+
+/// What `rehash_files` did with the requested files
+#[derive(Debug, Default)]
+pub struct RehashReport {
+    /// Files hashed again
+    pub rehashed: Vec<PathBuf>,
+    /// Files that are gone, they're removed from the cache
+    pub missing: Vec<PathBuf>,
+    pub skipped: SkippedFiles,
+    pub warnings: FileWarnings,
+}
+
+/// Drop the cache entries of `paths` and hash them again right away, e.g. after they were edited
+/// elsewhere, then regroup at `threshold`. Returns the groups the files are in now and the groups
+/// they were cached in before, so callers can update both without a full scan
+pub fn rehash_files(
+    cache: &HashCache,
+    paths: &[PathBuf],
+    grid_size: u8,
+    threshold: u32,
+) -> Result<(Vec<Vec<PathBuf>>, RehashReport)> {
+    let requested: BTreeSet<&PathBuf> = paths.iter().collect();
+    // Only groups that are already cached, computing them just to compare would cost a scan
+    let before: BTreeSet<PathBuf> = cache
+        .get_cached_duplicate_groups(GroupingKey::new(threshold, grid_size), None, None)?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|group| group.iter().any(|path| requested.contains(path)))
+        .flatten()
+        .collect();
+
+    let mut report = RehashReport::default();
+    let mut existing = Vec::new();
+    for path in requested {
+        cache.remove_file_entry(path)?;
+        if extended_length_path(path).exists() {
+            existing.push(path.clone());
+        } else {
+            report.missing.push(path.clone());
+        }
+    }
+    info!("Re-hashing {} files...", existing.len());
+    let (hashes, hash_report) = generate_hashes_with_report(&existing, grid_size, cache, false)?;
+    report.rehashed = hashes.into_iter().map(|(path, _)| path).collect();
+    report.skipped = hash_report.skipped;
+    report.warnings = hash_report.warnings;
+
+    let affected = get_duplicates_from_cache(cache, threshold, grid_size, None, None)?
+        .into_iter()
+        .filter(|group| {
+            group
+                .iter()
+                .any(|path| before.contains(path) || paths.contains(path))
+        })
+        .collect();
+    Ok((affected, report))
+}
+
+fn get_computed_duplicates_from_cache(
+    cache: &HashCache,
+    key: GroupingKey,
+    count: Option<usize>,
+    offset: Option<usize>,
+) -> Result<Vec<Vec<PathBuf>>> {
+    info!("Checking for cached duplicate groups...");
+    let threshold = key.threshold;
+
+    // Try to get pre-computed duplicate groups from cache
+    if let Some(cached_duplicates) = cache.get_cached_duplicate_groups(key, count, offset)? {
+        info!("Using cached duplicate groups");
+        return Ok(cached_duplicates);
+    }
+
+    if let Some(duplicates) = regroup_from_stored_distances(cache, key)? {
+        return Ok(duplicates);
+    }
+
+    info!("No cached duplicate groups found, computing from hash cache...");
+    info!("Retrieving hashes from cache...");
+
+    // Rows are decoded block by block straight into the index, so the raw rows and the
+    // decoded hashes are never all held in memory at the same time
+    let mut paths = Vec::new();
+    let mut index = HashIndex::default();
+    cache.for_each_cached_hash_block(CACHE_READ_BLOCK_SIZE, |block| {
+        for (path, hash) in decode_hashes(block) {
+            paths.push(path);
+            index.insert(hash);
+        }
+    })?;
+
+    if index.is_empty() {
+        info!("No cached hashes found");
+        return Ok(Vec::new());
+    }
+
+    info!(
+        "Processing {} valid cached hashes for duplicates...",
+        index.len()
+    );
+
+    let max_distance = threshold.max(STORED_DISTANCE_LIMIT);
+    let progress = progress_bar(index.len(), "Matching");
+    let neighbours = index.neighbours(max_distance, &progress);
+    progress.finish_and_clear();
+    drop(index);
+    let path_refs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    let pairs: Vec<(&Path, &Path, u32)> = neighbours
+        .iter()
+        .enumerate()
+        .flat_map(|(i, list)| {
+            let path_refs = &path_refs;
+            list.iter()
+                .map(move |&(j, distance)| (path_refs[i], path_refs[j], distance))
+        })
+        .collect();
+    if let Err(e) = cache.store_pair_distances(max_distance, &pairs) {
+        warn!("Failed to store pair distances: {}", e);
+    }
+    let duplicates = group_from_neighbours(&path_refs, &neighbours, threshold);
 
     // Cache the computed duplicate groups for future use
-    if let Err(e) = cache.store_duplicate_groups(threshold, &duplicates) {
+    if let Err(e) = cache.store_duplicate_groups(key, &duplicates) {
         warn!("Failed to cache duplicate groups: {}", e);
     }
 
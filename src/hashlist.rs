@@ -0,0 +1,76 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cache::HashCache;
+use crate::hasher::{decode_hashes, find_duplicates};
+
+/// Format version written to exported hash lists
+pub const HASH_LIST_VERSION: u32 = 1;
+
+/// One file in an exported hash list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashListEntry {
+    pub path: PathBuf,
+    pub perceptual_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// A portable list of hashes that can be compared without access to the original files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashList {
+    pub version: u32,
+    pub hashes: Vec<HashListEntry>,
+}
+
+impl HashList {
+    /// Export every hash in the cache
+    pub fn from_cache(cache: &HashCache) -> Result<Self> {
+        let hashes = cache
+            .get_all_cached_hash_details()?
+            .into_iter()
+            .map(|(path, perceptual_hash, sha256)| HashListEntry {
+                path,
+                perceptual_hash,
+                sha256: Some(sha256),
+            })
+            .collect();
+        Ok(Self {
+            version: HASH_LIST_VERSION,
+            hashes,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Could not read hash list {}", path.display()))?;
+        let list: HashList = serde_json::from_str(&contents)
+            .with_context(|| format!("Could not parse hash list {}", path.display()))?;
+        if list.version > HASH_LIST_VERSION {
+            bail!(
+                "Hash list {} has version {}, this build supports up to {}",
+                path.display(),
+                list.version,
+                HASH_LIST_VERSION
+            );
+        }
+        Ok(list)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Could not write hash list {}", path.display()))
+    }
+}
+
+/// Group the entries of several hash lists together, purely from the stored hashes
+pub fn match_hash_lists(lists: &[HashList], threshold: u32) -> Vec<Vec<PathBuf>> {
+    let encoded = lists
+        .iter()
+        .flat_map(|list| &list.hashes)
+        .map(|entry| (entry.path.clone(), entry.perceptual_hash.clone()))
+        .collect();
+    find_duplicates(&decode_hashes(encoded), threshold)
+}
@@ -0,0 +1,142 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// What ran the scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanSource {
+    Cli,
+    Server,
+}
+
+/// What a finished scan found, passed to `post_scan_hook` as JSON on stdin
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanSummary {
+    pub source: ScanSource,
+    pub paths: Vec<PathBuf>,
+    /// Seconds since the Unix epoch
+    pub finished_at: i64,
+    pub duration_seconds: f64,
+    pub threshold: u32,
+    /// Images found under `paths`
+    pub scanned: usize,
+    /// Images with a hash, from the cache or hashed now
+    pub hashed: usize,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+    pub skipped: usize,
+    pub duplicate_sets: usize,
+    pub duplicate_files: usize,
+    pub groups: Vec<Vec<PathBuf>>,
+}
+
+impl ScanSummary {
+    /// A summary of the groups a scan found after `elapsed`. File counts start at 0 for the
+    /// caller to fill in
+    pub fn new(
+        source: ScanSource,
+        paths: &[PathBuf],
+        threshold: u32,
+        groups: &[Vec<PathBuf>],
+        elapsed: Duration,
+    ) -> Self {
+        Self {
+            source,
+            paths: paths.to_vec(),
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs() as i64)
+                .unwrap_or_default(),
+            duration_seconds: elapsed.as_secs_f64(),
+            threshold,
+            scanned: 0,
+            hashed: 0,
+            cache_hits: 0,
+            cache_misses: 0,
+            skipped: 0,
+            duplicate_sets: groups.len(),
+            duplicate_files: groups.iter().map(Vec::len).sum(),
+            groups: groups.to_vec(),
+        }
+    }
+}
+
+/// Run the configured command through the shell with the summary as JSON on its stdin, and wait
+/// for it. Like desktop notifications, a failing hook only logs a warning
+pub fn run_post_scan_hook(command: &str, summary: &ScanSummary) {
+    match run(command, summary) {
+        Ok(()) => debug!("Ran post-scan hook: {command}"),
+        Err(e) => warn!("Post-scan hook failed: {e:#}"),
+    }
+}
+
+fn run(command: &str, summary: &ScanSummary) -> Result<()> {
+    let json = serde_json::to_vec(summary)?;
+    let mut child = shell_command(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not run {command}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that doesn't read its input closes the pipe early, that's fine
+        if let Err(e) = stdin.write_all(&json) {
+            debug!("Post-scan hook didn't read the whole summary: {e}");
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("{command} exited with {status}");
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn hooks_get_the_summary_on_stdin() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let out = temp_dir.path().join("summary.json");
+        let groups = vec![vec![PathBuf::from("/a.jpg"), PathBuf::from("/b.jpg")]];
+        let summary = ScanSummary {
+            scanned: 3,
+            ..ScanSummary::new(
+                ScanSource::Cli,
+                &[PathBuf::from("/photos")],
+                15,
+                &groups,
+                Duration::from_secs(2),
+            )
+        };
+
+        run(&format!("cat > '{}'", out.display()), &summary).expect("hook should run");
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&out).expect("hook output")).expect("JSON");
+        assert_eq!(written["source"], "cli");
+        assert_eq!(written["scanned"], 3);
+        assert_eq!(written["duplicate_files"], 2);
+        assert_eq!(written["groups"][0][1], "/b.jpg");
+
+        assert!(run("exit 3", &summary).is_err());
+    }
+}
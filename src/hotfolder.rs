@@ -0,0 +1,190 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::bktree::HashIndex;
+use crate::cache::{HashCache, ResolvedConfig};
+use crate::dryrun::is_dry_run;
+use crate::hasher::{decode_hashes, generate_hashes_with_report};
+use crate::journal::{apply_file_operations, update_cache, FileOperation};
+use crate::livephoto::live_photo_video;
+use crate::scanner::scan_for_images_with_report;
+use crate::sidecar::{affected_sidecars, move_with_sidecars, unseen_paths};
+
+/// Folder in the source that incoming duplicates are moved to
+pub const DUPLICATES_FOLDER: &str = "duplicates";
+
+/// What happens to an incoming file that's already in the library
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DuplicateAction {
+    /// Move it to the `duplicates` folder of the source
+    #[default]
+    Move,
+    /// Delete it, into the trash unless `--permanent` is given
+    Delete,
+}
+
+/// An incoming file that matched a library file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingDuplicate {
+    pub path: PathBuf,
+    /// The closest library file
+    pub matches: PathBuf,
+    pub distance: u32,
+    /// Where it was moved to, None when it was deleted
+    pub moved_to: Option<PathBuf>,
+}
+
+/// What one pass over the source folder did
+#[derive(Debug, Default)]
+pub struct FolderIngest {
+    /// Files moved into the library, (from, to)
+    pub imported: Vec<(PathBuf, PathBuf)>,
+    pub duplicates: Vec<IncomingDuplicate>,
+    /// Files that were left in the source, with the reason
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Move the images in `source` into `destination` unless the cache already has a duplicate of
+/// them within `threshold`, keeping their path below `source`. Duplicates are moved to
+/// `source/duplicates` or deleted, by `action`. Files imported earlier in the same pass count
+/// as library files, so two copies arriving together are imported once. Live Photo videos and,
+/// by the `sidecars` setting, sidecar files go along with their image. Files are renamed, or
+/// copied and removed across filesystems. With `--dry-run` the report says what would happen
+/// and nothing is moved or deleted
+pub fn ingest_folder(
+    source: &Path,
+    destination: &Path,
+    action: DuplicateAction,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<FolderIngest> {
+    if destination.starts_with(source) {
+        bail!(
+            "{} is inside {}, imported files would be ingested again",
+            destination.display(),
+            source.display()
+        );
+    }
+    let duplicates_dir = source.join(DUPLICATES_FOLDER);
+    let (mut incoming, _) = scan_for_images_with_report(
+        &[source.to_path_buf()],
+        false,
+        false,
+        false,
+        &config.ignore_paths,
+        false,
+    )?;
+    incoming.retain(|path| !path.starts_with(&duplicates_dir));
+    incoming.sort();
+
+    let mut report = FolderIngest::default();
+    if incoming.is_empty() {
+        return Ok(report);
+    }
+    let (hashes, _) = generate_hashes_with_report(&incoming, config.grid_size, cache, false)?;
+
+    let (mut library, mut index) = (Vec::new(), HashIndex::default());
+    for (path, hash) in decode_hashes(cache.get_all_cached_hashes()?) {
+        if !path.starts_with(source) {
+            library.push(path);
+            index.insert(hash);
+        }
+    }
+
+    for (path, hash) in hashes {
+        let relative = path.strip_prefix(source).unwrap_or(&path).to_path_buf();
+        // Cached files that are gone since the last scan don't count
+        let closest = index
+            .find_within(&hash, config.threshold)
+            .into_iter()
+            .filter(|&(id, _)| library[id].exists())
+            .min_by_key(|&(id, distance)| (distance, &library[id]));
+        let outcome = match closest {
+            Some((id, distance)) => {
+                let moved_to = match action {
+                    DuplicateAction::Move => Some(duplicates_dir.join(&relative)),
+                    DuplicateAction::Delete => None,
+                };
+                let set_aside = if is_dry_run() {
+                    Ok(())
+                } else {
+                    set_aside(&path, moved_to.as_deref(), config, cache)
+                };
+                set_aside.map(|()| {
+                    report.duplicates.push(IncomingDuplicate {
+                        path: path.clone(),
+                        matches: library[id].clone(),
+                        distance,
+                        moved_to,
+                    });
+                })
+            }
+            None => {
+                let to = destination.join(&relative);
+                let imported = if is_dry_run() {
+                    Ok(())
+                } else {
+                    import(&path, &to, config, cache)
+                };
+                imported.map(|()| {
+                    library.push(to.clone());
+                    index.insert(hash);
+                    report.imported.push((path.clone(), to));
+                })
+            }
+        };
+        if let Err(e) = outcome {
+            debug!("Left {} in the source: {e:#}", path.display());
+            report.failed.push((path, format!("{e:#}")));
+        }
+    }
+    Ok(report)
+}
+
+/// Move a new file into the library, its hashes go along
+fn import(from: &Path, to: &Path, config: &ResolvedConfig, cache: &HashCache) -> Result<()> {
+    if let Some(folder) = to.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    let moved = move_with_sidecars(from, to, config.sidecars)?;
+    update_cache(cache, &moves(moved))
+}
+
+/// Move a duplicate to `to`, or delete it when there's nowhere to move it
+fn set_aside(
+    path: &Path,
+    to: Option<&Path>,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<()> {
+    let Some(to) = to else {
+        let mut seen = BTreeSet::new();
+        let operations: Vec<FileOperation> = unseen_paths(
+            std::iter::once(path.to_path_buf())
+                .chain(live_photo_video(path))
+                .chain(affected_sidecars(path, config.sidecars)),
+            &mut seen,
+        )
+        .into_iter()
+        .map(FileOperation::Delete)
+        .collect();
+        apply_file_operations(&operations)?;
+        return update_cache(cache, &operations);
+    };
+    if let Some(folder) = to.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    let moved = move_with_sidecars(path, to, config.sidecars)?;
+    update_cache(cache, &moves(moved))
+}
+
+fn moves(moved: Vec<(PathBuf, PathBuf)>) -> Vec<FileOperation> {
+    moved
+        .into_iter()
+        .map(|(from, to)| FileOperation::Move { from, to })
+        .collect()
+}
@@ -0,0 +1,92 @@
+use image::{imageops::FilterType, DynamicImage};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Edge length the image is shrunk to before its colours are counted
+const DOMINANT_COLOR_SAMPLE_SIZE: u32 = 32;
+/// Low bits dropped from each channel so that near-identical colours land in the same bucket
+const COLOR_BUCKET_SHIFT: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+impl Orientation {
+    pub fn from_dimensions(width: u32, height: u32) -> Self {
+        match width.cmp(&height) {
+            std::cmp::Ordering::Greater => Orientation::Landscape,
+            std::cmp::Ordering::Less => Orientation::Portrait,
+            std::cmp::Ordering::Equal => Orientation::Square,
+        }
+    }
+}
+
+/// Find the most common colour in an image, returned as `#rrggbb`.
+///
+/// Colours are grouped into coarse buckets and the average of the largest bucket is returned,
+/// so gradients and JPEG noise don't split one visible colour into many rare ones.
+pub fn dominant_color(img: &DynamicImage) -> String {
+    let sample = img
+        .resize(
+            DOMINANT_COLOR_SAMPLE_SIZE,
+            DOMINANT_COLOR_SAMPLE_SIZE,
+            FilterType::Nearest,
+        )
+        .to_rgb8();
+
+    let mut buckets: HashMap<[u8; 3], (u32, [u64; 3])> = HashMap::new();
+    for pixel in sample.pixels() {
+        let key = pixel.0.map(|channel| channel >> COLOR_BUCKET_SHIFT);
+        let (count, sums) = buckets.entry(key).or_default();
+        *count += 1;
+        for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+            *sum += channel as u64;
+        }
+    }
+
+    // Ties go to the lowest bucket so the result doesn't depend on HashMap ordering
+    let [r, g, b] = buckets
+        .into_iter()
+        .max_by(|(key_a, (count_a, _)), (key_b, (count_b, _))| {
+            count_a.cmp(count_b).then(key_b.cmp(key_a))
+        })
+        .map(|(_, (count, sums))| sums.map(|sum| (sum / count as u64) as u8))
+        .unwrap_or_default();
+
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn orientation_follows_dimensions() {
+        assert_eq!(
+            Orientation::from_dimensions(800, 600),
+            Orientation::Landscape
+        );
+        assert_eq!(
+            Orientation::from_dimensions(600, 800),
+            Orientation::Portrait
+        );
+        assert_eq!(Orientation::from_dimensions(512, 512), Orientation::Square);
+    }
+
+    #[test]
+    fn dominant_color_picks_majority_colour() {
+        let img = RgbImage::from_fn(64, 64, |x, _| {
+            if x < 48 {
+                Rgb([200, 30, 30])
+            } else {
+                Rgb([10, 10, 240])
+            }
+        });
+        assert_eq!(dominant_color(&DynamicImage::ImageRgb8(img)), "#c81e1e");
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{anyhow, bail, Context, Result};
+use futures::stream::{self, StreamExt};
+use imghash::ImageHash;
+use reqwest::header::ACCEPT;
+use reqwest::redirect::Policy;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
+use tracing::debug;
+
+use crate::bktree::HashIndex;
+use crate::cache::HashCache;
+use crate::hasher::{decode_hashes, generate_hashes_with_report};
+use crate::hex::encode_lower_hex;
+
+/// Downloads running at once when `--concurrency` isn't given
+pub const DEFAULT_CONCURRENCY: usize = 4;
+/// Redirects followed before a URL is given up on
+const MAX_REDIRECTS: usize = 5;
+/// Largest download accepted, so a wrong URL can't fill the disk
+const MAX_DOWNLOAD_BYTES: u64 = 100 * 1024 * 1024;
+/// Time allowed to connect, and to finish the whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Folder next to the database that downloaded images are stored in
+pub fn download_dir(db_path: &Path) -> PathBuf {
+    db_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("downloads")
+}
+
+/// URLs from a text file, one per line. Blank lines and `#` comments are skipped, and a URL
+/// listed twice is only downloaded once
+pub fn read_url_list(path: &Path) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Could not read URL list {}", path.display()))?;
+    let mut seen = BTreeSet::new();
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter(|line| seen.insert(line.to_string()))
+        .map(str::to_string)
+        .collect())
+}
+
+/// How hard `download_images` may hit the servers
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadLimits {
+    /// Downloads running at once
+    pub concurrency: usize,
+    /// Downloads started per second, unlimited when None
+    pub requests_per_second: Option<f64>,
+}
+
+impl Default for DownloadLimits {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_CONCURRENCY,
+            requests_per_second: None,
+        }
+    }
+}
+
+/// A downloaded image, or why it couldn't be downloaded
+pub type Download = (String, Result<PathBuf>);
+
+/// Download every URL into `dir`, in the order given. Images are stored by content, so the
+/// same image from several URLs is only kept once
+pub async fn download_images(urls: &[String], dir: &Path, limits: DownloadLimits) -> Vec<Download> {
+    let spacing = limits
+        .requests_per_second
+        .filter(|rate| *rate > 0.0)
+        .map(|rate| Duration::from_secs_f64(1.0 / rate));
+    let next_start = Mutex::new(Instant::now());
+    let client = match download_client() {
+        Ok(client) => client,
+        Err(e) => {
+            return urls
+                .iter()
+                .map(|url| (url.clone(), Err(anyhow!("{e:#}"))))
+                .collect()
+        }
+    };
+
+    stream::iter(urls)
+        .map(|url| {
+            let (next_start, client) = (&next_start, &client);
+            async move {
+                if let Some(spacing) = spacing {
+                    // Claim the next start slot, then wait for it outside the lock
+                    let start = {
+                        let mut next = next_start.lock().await;
+                        let start = (*next).max(Instant::now());
+                        *next = start + spacing;
+                        start
+                    };
+                    sleep_until(start).await;
+                }
+                debug!("Downloading {url}");
+                let result = match fetch(client, url).await {
+                    Ok(data) => store_download(dir, &data),
+                    Err(e) => Err(e),
+                };
+                (url.clone(), result)
+            }
+        })
+        .buffered(limits.concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Save downloaded bytes as `dir/<sha256 prefix>/<sha256>.<ext>`, refusing anything that isn't
+/// an image
+fn store_download(dir: &Path, data: &[u8]) -> Result<PathBuf> {
+    let format = image::guess_format(data).map_err(|_| anyhow!("Response is not an image"))?;
+    let extension = format.extensions_str().first().copied().unwrap_or("img");
+    let sha256 = encode_lower_hex(Sha256::digest(data));
+    let folder = dir.join(&sha256[..2]);
+    let path = folder.join(format!("{sha256}.{extension}"));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    fs::create_dir_all(&folder)
+        .with_context(|| format!("Could not create download folder {}", folder.display()))?;
+    let partial = path.with_extension(format!("{extension}.partial"));
+    fs::write(&partial, data).with_context(|| format!("Could not write {}", partial.display()))?;
+    fs::rename(&partial, &path).with_context(|| format!("Could not write {}", path.display()))?;
+    Ok(path)
+}
+
+/// A downloaded image and the local files it matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngestedImage {
+    pub url: String,
+    pub path: PathBuf,
+    /// Local files within the threshold, closest first
+    pub local_matches: Vec<(PathBuf, u32)>,
+}
+
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    pub images: Vec<IngestedImage>,
+    /// URLs that couldn't be downloaded or decoded, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+impl IngestReport {
+    /// Downloaded images that aren't in the local library yet
+    pub fn new_images(&self) -> impl Iterator<Item = &IngestedImage> {
+        self.images
+            .iter()
+            .filter(|image| image.local_matches.is_empty())
+    }
+}
+
+/// Hash finished downloads into the cache, remember which URL each came from and compare them
+/// with the cached local files. Other downloads don't count as local files
+pub fn record_downloads(
+    downloads: Vec<Download>,
+    download_dir: &Path,
+    cache: &HashCache,
+    threshold: u32,
+    grid_size: u8,
+) -> Result<IngestReport> {
+    let mut report = IngestReport::default();
+    let mut downloaded = Vec::new();
+    for (url, result) in downloads {
+        match result {
+            Ok(path) => downloaded.push((url, path)),
+            Err(e) => report.failed.push((url, format!("{e:#}"))),
+        }
+    }
+
+    let paths: Vec<PathBuf> = downloaded.iter().map(|(_, path)| path.clone()).collect();
+    let (hashes, _) = generate_hashes_with_report(&paths, grid_size, cache, false)?;
+    let hashes: HashMap<PathBuf, ImageHash> = hashes.into_iter().collect();
+
+    let local = decode_hashes(
+        cache
+            .get_all_cached_hashes()?
+            .into_iter()
+            .filter(|(path, _)| !path.starts_with(download_dir))
+            .collect(),
+    );
+    let mut index = HashIndex::default();
+    for (_, hash) in &local {
+        index.insert(hash);
+    }
+
+    for (url, path) in downloaded {
+        let Some(hash) = hashes.get(&path) else {
+            report
+                .failed
+                .push((url, "Image could not be decoded".to_string()));
+            continue;
+        };
+        cache.store_url_source(&url, &path)?;
+        report.images.push(IngestedImage {
+            url,
+            local_matches: local_matches(hash, &index, &local, threshold),
+            path,
+        });
+    }
+    Ok(report)
+}
+
+fn local_matches(
+    hash: &ImageHash,
+    index: &HashIndex<&ImageHash>,
+    local: &[(PathBuf, ImageHash)],
+    threshold: u32,
+) -> Vec<(PathBuf, u32)> {
+    let mut matches: Vec<(PathBuf, u32)> = index
+        .find_within(hash, threshold)
+        .into_iter()
+        .map(|(id, distance)| (local[id].0.clone(), distance))
+        .collect();
+    matches.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    matches
+}
+
+/// The client every download goes through, over http or https (rustls with the system's
+/// certificates). Redirects are followed up to `MAX_REDIRECTS`
+fn download_client() -> Result<Client> {
+    Client::builder()
+        .user_agent(concat!("vibe-image-comparator/", env!("CARGO_PKG_VERSION")))
+        .redirect(Policy::limited(MAX_REDIRECTS))
+        .connect_timeout(REQUEST_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .context("Could not set up the HTTP client")
+}
+
+/// GET a URL and return the body of the final response
+async fn fetch(client: &Client, url: &str) -> Result<Vec<u8>> {
+    let mut response = client
+        .get(url)
+        .header(ACCEPT, "image/*")
+        .send()
+        .await
+        .map_err(|e| anyhow!("Could not download: {}", error_chain(&e)))?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("Server answered HTTP {}", status.as_u16());
+    }
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_DOWNLOAD_BYTES)
+    {
+        bail!("Response is larger than {MAX_DOWNLOAD_BYTES} bytes");
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("Download failed: {}", error_chain(&e)))?
+    {
+        if (body.len() + chunk.len()) as u64 > MAX_DOWNLOAD_BYTES {
+            bail!("Response is larger than {MAX_DOWNLOAD_BYTES} bytes");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// reqwest's errors only name the outermost cause, e.g. "error sending request"
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut text = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        text.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    text
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+use crate::cache::Config;
+use crate::scanner::expand_tilde;
+
+/// (name, description, threshold) for each strictness choice offered by the wizard
+const STRICTNESS_LEVELS: [(&str, &str, u32); 3] = [
+    ("Strict", "only near-identical copies", 5),
+    ("Balanced", "copies, re-exports and resized versions", 15),
+    ("Relaxed", "also edited or recompressed versions", 25),
+];
+/// Index into STRICTNESS_LEVELS used when the answer is left empty
+const DEFAULT_STRICTNESS: usize = 1;
+
+/// What the user chose in the setup wizard
+#[derive(Debug, Clone)]
+pub struct InitAnswers {
+    pub config: Config,
+    pub scan_now: bool,
+}
+
+/// Read one trimmed line, None at end of input
+fn read_answer<R: BufRead>(input: &mut R) -> Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+/// Ask for scan folders, matching strictness and database location, starting from `existing`
+/// so settings the wizard doesn't cover are kept
+pub fn run_init_wizard<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    existing: &Config,
+) -> Result<InitAnswers> {
+    let mut config = existing.clone();
+
+    writeln!(output, "Welcome! Let's set up duplicate image detection.")?;
+    writeln!(output)?;
+
+    writeln!(
+        output,
+        "Which folders should be scanned? Enter one per line, then an empty line to finish."
+    )?;
+    let mut scan_paths = Vec::new();
+    while let Some(answer) = read_answer(input)? {
+        if answer.is_empty() {
+            break;
+        }
+        let path = expand_tilde(&answer);
+        if !path.is_dir() {
+            writeln!(
+                output,
+                "  Note: {} isn't a folder right now",
+                path.display()
+            )?;
+        }
+        scan_paths.push(path.display().to_string());
+    }
+    if !scan_paths.is_empty() {
+        config.scan_paths = Some(scan_paths);
+    }
+    writeln!(output)?;
+
+    writeln!(
+        output,
+        "How similar do images need to be to count as duplicates?"
+    )?;
+    for (i, (name, description, _)) in STRICTNESS_LEVELS.iter().enumerate() {
+        let default_marker = if i == DEFAULT_STRICTNESS {
+            " (default)"
+        } else {
+            ""
+        };
+        writeln!(output, "  {}) {name}: {description}{default_marker}", i + 1)?;
+    }
+    let strictness = loop {
+        let Some(answer) = read_answer(input)? else {
+            break DEFAULT_STRICTNESS;
+        };
+        if answer.is_empty() {
+            break DEFAULT_STRICTNESS;
+        }
+        match answer.parse::<usize>() {
+            Ok(choice) if (1..=STRICTNESS_LEVELS.len()).contains(&choice) => break choice - 1,
+            _ => writeln!(
+                output,
+                "Please enter a number from 1 to {}",
+                STRICTNESS_LEVELS.len()
+            )?,
+        }
+    };
+    config.threshold = Some(STRICTNESS_LEVELS[strictness].2);
+    writeln!(output)?;
+
+    let current_database = config
+        .database_path
+        .clone()
+        .unwrap_or_else(|| "the default cache folder".to_string());
+    writeln!(
+        output,
+        "Where should the hash database be stored? Leave empty to keep {current_database}."
+    )?;
+    if let Some(answer) = read_answer(input)?.filter(|answer| !answer.is_empty()) {
+        config.database_path = Some(expand_tilde(&answer).display().to_string());
+    }
+    writeln!(output)?;
+
+    let scan_now = if config.scan_paths.as_ref().is_some_and(|p| !p.is_empty()) {
+        writeln!(output, "Start the first scan now? [Y/n]")?;
+        !matches!(
+            read_answer(input)?.map(|answer| answer.to_lowercase()),
+            Some(answer) if answer == "n" || answer == "no"
+        )
+    } else {
+        false
+    };
+
+    Ok(InitAnswers { config, scan_now })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn run(answers: &str) -> InitAnswers {
+        let mut output = Vec::new();
+        run_init_wizard(
+            &mut Cursor::new(answers.as_bytes()),
+            &mut output,
+            &Config::default(),
+        )
+        .expect("wizard should complete")
+    }
+
+    #[test]
+    fn collects_answers() {
+        let answers = run("/photos\n/backup/photos\n\n1\n/data/hashes.db\nn\n");
+        assert_eq!(
+            answers.config.scan_paths,
+            Some(vec!["/photos".to_string(), "/backup/photos".to_string()])
+        );
+        assert_eq!(answers.config.threshold, Some(5));
+        assert_eq!(
+            answers.config.database_path,
+            Some("/data/hashes.db".to_string())
+        );
+        assert!(!answers.scan_now);
+    }
+
+    #[test]
+    fn empty_answers_use_defaults() {
+        let answers = run("/photos\n\nnot a number\n\n\n\n");
+        assert_eq!(answers.config.threshold, Some(15));
+        assert_eq!(answers.config.database_path, None);
+        assert!(answers.scan_now);
+    }
+}
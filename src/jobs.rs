@@ -0,0 +1,330 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{info_span, warn, Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::cache::{HashCache, ResolvedConfig};
+
+/// Job runs kept in the database when `job_history` isn't set
+pub const DEFAULT_JOB_HISTORY: usize = 20;
+/// Log lines kept per run, later ones are dropped
+const MAX_LOG_LINES: usize = 1000;
+
+/// Work the web server runs on its own, at startup or on `job_schedule`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Scan `scan_paths`
+    Scan,
+    /// Remove missing files and orphaned hashes from the cache
+    Cleanup,
+    /// Reclaim the database's free pages
+    Compaction,
+}
+
+impl JobKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Scan => "scan",
+            JobKind::Cleanup => "cleanup",
+            JobKind::Compaction => "compaction",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Result<Self> {
+        Ok(match kind {
+            "scan" => JobKind::Scan,
+            "cleanup" => JobKind::Cleanup,
+            "compaction" => JobKind::Compaction,
+            other => bail!("Unknown job kind {other}"),
+        })
+    }
+}
+
+/// What started a job run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobTrigger {
+    /// `serve --scan`
+    Startup,
+    /// `job_schedule`
+    Schedule,
+}
+
+impl JobTrigger {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobTrigger::Startup => "startup",
+            JobTrigger::Schedule => "schedule",
+        }
+    }
+
+    pub fn parse(trigger: &str) -> Result<Self> {
+        Ok(match trigger {
+            "startup" => JobTrigger::Startup,
+            "schedule" => JobTrigger::Schedule,
+            other => bail!("Unknown job trigger {other}"),
+        })
+    }
+}
+
+/// How a job run went. Runs still `running` when the server starts were cut off by a stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    Running,
+    Finished,
+    Failed,
+}
+
+impl RunState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RunState::Running => "running",
+            RunState::Finished => "finished",
+            RunState::Failed => "failed",
+        }
+    }
+
+    pub fn parse(state: &str) -> Result<Self> {
+        Ok(match state {
+            "running" => RunState::Running,
+            "finished" => RunState::Finished,
+            "failed" => RunState::Failed,
+            other => bail!("Unknown job state {other}"),
+        })
+    }
+}
+
+/// An event logged while a job ran
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogLine {
+    /// Seconds since the Unix epoch
+    pub at: i64,
+    pub level: String,
+    pub message: String,
+}
+
+/// One run of a job, as kept in the database
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRun {
+    pub id: i64,
+    pub kind: JobKind,
+    pub trigger: JobTrigger,
+    /// Seconds since the Unix epoch
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub state: RunState,
+    /// The outcome, or the error chain of a failed run
+    pub message: Option<String>,
+    pub log: Vec<LogLine>,
+}
+
+/// Lines logged so far by each running job, by run id
+static JOB_LOGS: Mutex<BTreeMap<i64, Vec<LogLine>>> = Mutex::new(BTreeMap::new());
+
+/// Run `work` as a job, recording the run, what it logged and how it ended (`summary` of what it
+/// returned, or its error) in the database, keeping the newest `job_history` runs. Only events
+/// logged on the calling thread are captured, files hashed in parallel report their problems
+/// through the scan's warnings instead
+pub fn run_job<T>(
+    config: &ResolvedConfig,
+    kind: JobKind,
+    trigger: JobTrigger,
+    work: impl FnOnce() -> Result<T>,
+    summary: impl FnOnce(&T) -> String,
+) -> Result<T> {
+    let cache = HashCache::open(config)?;
+    let id = cache.start_job_run(kind, trigger)?;
+    JOB_LOGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(id, Vec::new());
+
+    let outcome = info_span!("job", job_id = id, kind = kind.as_str()).in_scope(work);
+
+    let log = JOB_LOGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&id)
+        .unwrap_or_default();
+    let (state, message) = match &outcome {
+        Ok(value) => (RunState::Finished, summary(value)),
+        Err(e) => (RunState::Failed, format!("{e:#}")),
+    };
+    if let Err(e) = cache.finish_job_run(id, state, &message, &log, config.job_history) {
+        warn!(
+            "Could not record the end of {} job {id}: {e}",
+            kind.as_str()
+        );
+    }
+    outcome
+}
+
+/// Captures events logged inside a job's span for `run_job`. Add it to the subscriber of a
+/// process that runs jobs
+pub struct JobLogLayer;
+
+/// The run id of a `job` span
+struct JobId(i64);
+
+impl<S> Layer<S> for JobLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != "job" {
+            return;
+        }
+        let mut visitor = JobIdVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(job_id), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(JobId(job_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(job_id) = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .into_iter()
+                .find_map(|span| span.extensions().get::<JobId>().map(|id| id.0))
+        }) else {
+            return;
+        };
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let mut logs = JOB_LOGS.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(lines) = logs
+            .get_mut(&job_id)
+            .filter(|lines| lines.len() < MAX_LOG_LINES)
+        {
+            lines.push(LogLine {
+                at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_secs() as i64)
+                    .unwrap_or_default(),
+                level: event.metadata().level().to_string(),
+                message: visitor.finish(),
+            });
+        }
+    }
+}
+
+struct JobIdVisitor(Option<i64>);
+
+impl Visit for JobIdVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == "job_id" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn Debug) {}
+}
+
+/// The event's message followed by its other fields, as the console log shows them
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: Vec<String>,
+}
+
+impl MessageVisitor {
+    fn finish(self) -> String {
+        let mut line = self.message;
+        for field in self.fields {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&field);
+        }
+        line
+    }
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.push(format!("{}={value}", field.name()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.fields.push(format!("{}={value:?}", field.name()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Config;
+    use tempfile::TempDir;
+    use tracing::info;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn runs_are_kept_with_their_logs() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let config = Config {
+            database_path: Some(temp_dir.path().join("jobs.db").display().to_string()),
+            job_history: Some(2),
+            ..Config::default()
+        }
+        .with_overrides(None, None, None);
+
+        let subscriber = tracing_subscriber::registry().with(JobLogLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..2 {
+                run_job(
+                    &config,
+                    JobKind::Scan,
+                    JobTrigger::Startup,
+                    || -> Result<()> { bail!("No scan paths") },
+                    |_| String::new(),
+                )
+                .expect_err("job should fail");
+            }
+            info!("Not part of a job");
+            run_job(
+                &config,
+                JobKind::Cleanup,
+                JobTrigger::Schedule,
+                || {
+                    info!(files = 3, "Removed missing files");
+                    Ok(3)
+                },
+                |files| format!("Cleaned up {files} files"),
+            )
+            .expect("job should run");
+        });
+
+        let runs = HashCache::open(&config)
+            .expect("cache")
+            .get_job_runs(10)
+            .expect("runs");
+        assert_eq!(runs.len(), 2, "only the newest runs are kept");
+        assert_eq!(runs[0].kind, JobKind::Cleanup);
+        assert_eq!(runs[0].state, RunState::Finished);
+        assert_eq!(runs[0].message.as_deref(), Some("Cleaned up 3 files"));
+        assert_eq!(runs[0].log.len(), 1);
+        assert_eq!(runs[0].log[0].level, "INFO");
+        assert_eq!(runs[0].log[0].message, "Removed missing files files=3");
+        assert_eq!(runs[1].state, RunState::Failed);
+        assert_eq!(runs[1].message.as_deref(), Some("No scan paths"));
+    }
+}
@@ -0,0 +1,419 @@
+use anyhow::Result;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+use crate::cache::HashCache;
+use crate::paths::extended_length_path;
+
+/// Suffix of files set aside for deletion until every step of their operation has worked
+const STAGED_SUFFIX: &str = ".vic-deleting";
+
+/// Set from `--permanent` at startup
+static PERMANENT_DELETE: OnceLock<bool> = OnceLock::new();
+
+/// Remove deleted files for good instead of moving them to the trash, for the rest of the process
+pub fn set_permanent_delete(permanent: bool) {
+    if PERMANENT_DELETE.set(permanent).is_err() {
+        warn!("Permanent deletion was already set");
+    }
+}
+
+/// Whether deleted files are removed for good rather than moved to the trash. Tests delete for
+/// good so they don't fill the trash of whoever runs them
+pub fn deletes_permanently() -> bool {
+    PERMANENT_DELETE.get().copied().unwrap_or(cfg!(test))
+}
+
+/// One filesystem step of an action on several files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOperation {
+    Delete(PathBuf),
+    Move { from: PathBuf, to: PathBuf },
+}
+
+impl fmt::Display for FileOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileOperation::Delete(path) => write!(f, "delete {}", path.display()),
+            FileOperation::Move { from, to } => {
+                write!(f, "move {} to {}", from.display(), to.display())
+            }
+        }
+    }
+}
+
+/// How an action on several files failed part way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationFailure {
+    pub failed: FileOperation,
+    pub error: String,
+    /// Steps that had worked and were undone
+    pub rolled_back: Vec<FileOperation>,
+    /// Steps that had worked and couldn't be undone, they still apply
+    pub left_applied: Vec<FileOperation>,
+}
+
+impl fmt::Display for OperationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not {}: {}", self.failed, self.error)?;
+        if !self.rolled_back.is_empty() {
+            write!(f, ", undid {} earlier steps", self.rolled_back.len())?;
+        }
+        for step in &self.left_applied {
+            write!(f, ", could not undo: {step}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for OperationFailure {}
+
+/// A step that has been carried out, with what's needed to undo it
+enum Applied {
+    /// Renamed next to the original, removed once everything worked
+    Staged {
+        path: PathBuf,
+        staged: PathBuf,
+    },
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+impl Applied {
+    fn operation(&self) -> FileOperation {
+        match self {
+            Applied::Staged { path, .. } => FileOperation::Delete(path.clone()),
+            Applied::Moved { from, to } => FileOperation::Move {
+                from: from.clone(),
+                to: to.clone(),
+            },
+        }
+    }
+
+    fn undo(&self) -> std::io::Result<()> {
+        match self {
+            Applied::Staged { path, staged } => rename(staged, path),
+            Applied::Moved { from, to } => move_file(to, from),
+        }
+    }
+}
+
+/// Carry out every operation or none. Deleted files are first renamed aside and only removed,
+/// or moved to the trash unless `--permanent` was given, once all steps have worked, so a
+/// failure part way puts back every file touched so far. What couldn't be put back is listed in
+/// the failure
+pub fn apply_file_operations(operations: &[FileOperation]) -> Result<(), OperationFailure> {
+    let mut journal: Vec<Applied> = Vec::new();
+    for operation in operations {
+        let applied = match operation {
+            FileOperation::Delete(path) => {
+                let staged = staged_path(path);
+                rename(path, &staged).map(|()| Applied::Staged {
+                    path: path.clone(),
+                    staged,
+                })
+            }
+            FileOperation::Move { from, to } => {
+                if extended_length_path(to).exists() {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        "the destination already exists",
+                    ))
+                } else {
+                    move_file(from, to).map(|()| Applied::Moved {
+                        from: from.clone(),
+                        to: to.clone(),
+                    })
+                }
+            }
+        };
+        match applied {
+            Ok(applied) => journal.push(applied),
+            Err(e) => return Err(roll_back(journal, operation, &e)),
+        }
+    }
+
+    // Every step worked, the files set aside can go
+    if !deletes_permanently() {
+        return trash_staged(journal, |path| {
+            trash::delete(extended_length_path(path)).map_err(anyhow::Error::from)
+        });
+    }
+    for applied in &journal {
+        if let Applied::Staged { path, staged } = applied {
+            match fs::remove_file(extended_length_path(staged)) {
+                Ok(()) => info!("Deleted {}", path.display()),
+                Err(e) => warn!(
+                    "Could not remove {}, it's left behind: {}",
+                    staged.display(),
+                    e
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move the files set aside to the trash under their original names, so the trash shows and
+/// restores them as they were. When one can't be trashed, e.g. on a network share without a
+/// trash folder, it's put back and so is everything not trashed yet. Files already trashed are
+/// listed in the failure as left applied
+fn trash_staged(
+    journal: Vec<Applied>,
+    trash: impl Fn(&Path) -> anyhow::Result<()>,
+) -> Result<(), OperationFailure> {
+    for index in 0..journal.len() {
+        let Applied::Staged { path, staged } = &journal[index] else {
+            continue;
+        };
+        let outcome = rename(staged, path)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| trash(path));
+        let Err(e) = outcome else {
+            info!("Moved {} to the trash", path.display());
+            continue;
+        };
+
+        let failed = journal[index].operation();
+        // Still set aside when it couldn't even be renamed back, undoing puts it in place
+        let still_staged = extended_length_path(staged).exists();
+        let mut unfinished = Vec::new();
+        let mut trashed = Vec::new();
+        for (i, applied) in journal.into_iter().enumerate() {
+            match applied {
+                Applied::Staged { .. } if i < index => trashed.push(applied.operation()),
+                Applied::Staged { .. } if i == index && !still_staged => {}
+                applied => unfinished.push(applied),
+            }
+        }
+        let mut failure = roll_back(unfinished, &failed, &format!("{e:#}"));
+        failure.left_applied.extend(trashed);
+        return Err(failure);
+    }
+    Ok(())
+}
+
+fn roll_back(
+    journal: Vec<Applied>,
+    failed: &FileOperation,
+    error: &dyn fmt::Display,
+) -> OperationFailure {
+    warn!(
+        "Could not {failed}: {error}, undoing {} earlier steps",
+        journal.len()
+    );
+    let mut rolled_back = Vec::new();
+    let mut left_applied = Vec::new();
+    for applied in journal.iter().rev() {
+        match applied.undo() {
+            Ok(()) => rolled_back.push(applied.operation()),
+            Err(e) => {
+                warn!("Could not undo {}: {}", applied.operation(), e);
+                left_applied.push(applied.operation());
+            }
+        }
+    }
+    OperationFailure {
+        failed: failed.clone(),
+        error: error.to_string(),
+        rolled_back,
+        left_applied,
+    }
+}
+
+/// Bring the cache in line with operations that were carried out: deleted files are forgotten
+/// and moved files keep their hashes under the new path
+pub fn update_cache(cache: &HashCache, applied: &[FileOperation]) -> Result<()> {
+    for operation in applied {
+        match operation {
+            FileOperation::Delete(path) => cache.remove_file_entry(path)?,
+            FileOperation::Move { from, to } => cache.move_file_entry(from, to)?,
+        }
+    }
+    Ok(())
+}
+
+fn staged_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(STAGED_SUFFIX);
+    let mut staged = std::ffi::OsString::from(".");
+    staged.push(name);
+    path.with_file_name(staged)
+}
+
+fn rename(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::rename(extended_length_path(from), extended_length_path(to))
+}
+
+/// Rename a file, or copy it and remove the original when `to` is on another filesystem,
+/// e.g. an inbox on a USB disk imported into a library on the internal one
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_and_remove(from, to),
+        outcome => outcome,
+    }
+}
+
+/// Copy `from` to `to` with its modification time, so the cached hash still matches, flush the
+/// copy to disk and only then remove `from`. A copy that fails part way is removed again
+fn copy_and_remove(from: &Path, to: &Path) -> std::io::Result<()> {
+    let (from, to) = (extended_length_path(from), extended_length_path(to));
+    let copied = fs::copy(&from, &to).and_then(|_| {
+        let modified = fs::metadata(&from)?.modified()?;
+        let copy = fs::File::options().write(true).open(&to)?;
+        copy.set_modified(modified)?;
+        copy.sync_all()
+    });
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&to);
+        return Err(e);
+    }
+    fs::remove_file(&from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::FileMetadata;
+    use tempfile::TempDir;
+
+    #[test]
+    fn failed_steps_put_earlier_files_back() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let image = temp_dir.path().join("a.jpg");
+        let sidecar = temp_dir.path().join("a.xmp");
+        fs::write(&image, "image").expect("write image");
+        fs::write(&sidecar, "sidecar").expect("write sidecar");
+        let missing = temp_dir.path().join("missing.jpg");
+
+        let failure = apply_file_operations(&[
+            FileOperation::Delete(image.clone()),
+            FileOperation::Move {
+                from: sidecar.clone(),
+                to: temp_dir.path().join("b.xmp"),
+            },
+            FileOperation::Delete(missing.clone()),
+        ])
+        .expect_err("the missing file can't be deleted");
+
+        assert_eq!(failure.failed, FileOperation::Delete(missing));
+        assert_eq!(failure.rolled_back.len(), 2);
+        assert!(failure.left_applied.is_empty());
+        assert_eq!(fs::read_to_string(&image).expect("image is back"), "image");
+        assert!(sidecar.exists());
+        assert_eq!(
+            fs::read_dir(temp_dir.path()).expect("list").count(),
+            2,
+            "nothing is left aside"
+        );
+
+        apply_file_operations(&[FileOperation::Delete(image.clone())]).expect("delete works");
+        assert!(!image.exists());
+        assert_eq!(fs::read_dir(temp_dir.path()).expect("list").count(), 1);
+    }
+
+    #[test]
+    fn files_that_cant_be_trashed_are_put_back() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let journal: Vec<Applied> = ["first.jpg", "second.jpg", "third.jpg"]
+            .iter()
+            .map(|name| {
+                let path = temp_dir.path().join(name);
+                fs::write(&path, name).expect("write");
+                let staged = staged_path(&path);
+                rename(&path, &staged).expect("stage");
+                Applied::Staged { path, staged }
+            })
+            .collect();
+        let second = temp_dir.path().join("second.jpg");
+
+        let failure = trash_staged(journal, |path| {
+            if path == second {
+                anyhow::bail!("no trash on this share");
+            }
+            Ok(fs::remove_file(path)?)
+        })
+        .expect_err("the second file can't be trashed");
+
+        assert_eq!(failure.failed, FileOperation::Delete(second.clone()));
+        assert_eq!(
+            failure.left_applied,
+            [FileOperation::Delete(temp_dir.path().join("first.jpg"))]
+        );
+        assert_eq!(
+            failure.rolled_back,
+            [FileOperation::Delete(temp_dir.path().join("third.jpg"))]
+        );
+        assert_eq!(fs::read_to_string(&second).expect("put back"), "second.jpg");
+        assert_eq!(
+            fs::read_dir(temp_dir.path()).expect("list").count(),
+            2,
+            "nothing is left aside"
+        );
+    }
+
+    #[test]
+    fn copied_moves_keep_the_modification_time() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let from = temp_dir.path().join("a.jpg");
+        let to = temp_dir.path().join("b.jpg");
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(86400);
+        fs::write(&from, b"photo").expect("write");
+        fs::File::options()
+            .write(true)
+            .open(&from)
+            .and_then(|file| file.set_modified(modified))
+            .expect("set time");
+
+        copy_and_remove(&from, &to).expect("move");
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).expect("read"), b"photo");
+        assert_eq!(
+            fs::metadata(&to).and_then(|m| m.modified()).expect("time"),
+            modified
+        );
+    }
+
+    #[test]
+    fn moved_files_keep_their_hashes() {
+        let cache = HashCache::new_in_memory().expect("cache");
+        let (from, to) = (
+            PathBuf::from("/photos/a.jpg"),
+            PathBuf::from("/sorted/a.jpg"),
+        );
+        cache
+            .store_hash(&FileMetadata {
+                path: from.clone(),
+                size: 5,
+                sha256: "aa".to_string(),
+                perceptual_hash: "0000000000000000".to_string(),
+                width: None,
+                height: None,
+                dominant_color: None,
+                modified: None,
+            })
+            .expect("store");
+
+        update_cache(
+            &cache,
+            &[FileOperation::Move {
+                from: from.clone(),
+                to: to.clone(),
+            }],
+        )
+        .expect("update");
+        assert!(cache
+            .get_cached_hash_details(&from)
+            .expect("lookup")
+            .is_none());
+        assert!(cache
+            .get_cached_hash_details(&to)
+            .expect("lookup")
+            .is_some());
+    }
+}
@@ -1,8 +1,71 @@
+pub mod action;
+pub mod algorithm;
+pub mod annotations;
+pub mod background;
+pub mod backup;
+pub mod baseline;
+pub mod bktree;
 pub mod cache;
+pub mod canonical;
+pub mod checksums;
+pub mod concurrency;
+pub mod confidence;
 pub mod config;
+pub mod deadline;
+pub mod diff;
+pub mod dryrun;
+pub mod edits;
+pub mod embeddings;
+pub mod estimate;
+pub mod extract;
+pub mod feed;
+pub mod groupcap;
 pub mod hasher;
+pub mod hashlist;
 pub mod hex;
+pub mod hooks;
+pub mod hotfolder;
+pub mod imageinfo;
+pub mod ingest;
+pub mod init;
+pub mod jobs;
+pub mod journal;
+pub mod listener;
+pub mod livephoto;
+pub mod lock;
+pub mod messages;
+pub mod multires;
+pub mod notify;
+pub mod overrides;
+pub mod partial;
+pub mod paths;
+pub mod policy;
+pub mod progress;
+pub mod reference;
+pub mod report;
+pub mod resolver;
+pub mod results;
+pub mod review;
 pub mod scanner;
+pub mod selftest;
 pub mod server;
+pub mod settings;
+pub mod sidecar;
+pub mod space;
+pub mod stats;
+pub mod store;
+pub mod strategy;
+pub mod synthetic;
+pub mod tags;
+pub mod takeout;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 #[cfg(test)]
 mod tests;
+pub mod thumbnail;
+pub mod usage;
+pub mod verify;
+#[cfg(feature = "video")]
+pub mod video;
+pub mod visualize;
+pub mod watch;
@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use std::env;
+use tokio::net::TcpListener;
+use tracing::warn;
+
+#[cfg(unix)]
+use std::os::fd::FromRawFd;
+
+/// First file descriptor passed by systemd socket activation (SD_LISTEN_FDS_START)
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// The listening socket passed in by systemd socket activation, if this process was activated
+pub fn systemd_listen_fd() -> Option<i32> {
+    listen_fd_from_env(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn listen_fd_from_env(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<i32> {
+    // LISTEN_PID guards against using variables inherited from an activated parent process
+    if listen_pid?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    let count: i32 = listen_fds?.parse().ok()?;
+    if count < 1 {
+        return None;
+    }
+    if count > 1 {
+        warn!("Received {count} sockets from systemd, only the first one is used");
+    }
+    Some(SD_LISTEN_FDS_START)
+}
+
+/// Take ownership of an already bound and listening TCP socket
+#[cfg(unix)]
+pub fn listener_from_fd(fd: i32) -> Result<TcpListener> {
+    // SAFETY: the descriptor is handed over by the service manager or via --fd for this process
+    // to own, nothing else in the process uses it
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener
+        .local_addr()
+        .with_context(|| format!("File descriptor {fd} is not a listening TCP socket"))?;
+    listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(listener)?)
+}
+
+#[cfg(not(unix))]
+pub fn listener_from_fd(fd: i32) -> Result<TcpListener> {
+    anyhow::bail!("Listening on an inherited file descriptor ({fd}) is only supported on Unix")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_first_fd_when_pid_matches() {
+        assert_eq!(listen_fd_from_env(Some("42"), Some("1"), 42), Some(3));
+        assert_eq!(listen_fd_from_env(Some("42"), Some("2"), 42), Some(3));
+    }
+
+    #[test]
+    fn ignores_other_processes_and_missing_sockets() {
+        assert_eq!(listen_fd_from_env(Some("41"), Some("1"), 42), None);
+        assert_eq!(listen_fd_from_env(Some("42"), Some("0"), 42), None);
+        assert_eq!(listen_fd_from_env(None, Some("1"), 42), None);
+        assert_eq!(listen_fd_from_env(Some("42"), None, 42), None);
+    }
+}
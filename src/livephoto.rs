@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::paths::extended_length_path;
+use crate::sidecar::shares_stem;
+
+/// Extensions of Live Photo stills: HEIC by default, JPEG with the "Most Compatible" setting
+pub const STILL_EXTENSIONS: [&str; 4] = ["heic", "heif", "jpg", "jpeg"];
+/// The motion part is a QuickTime video named like the still
+const VIDEO_EXTENSIONS: [&str; 2] = ["MOV", "mov"];
+/// The still and its video are written moments apart, a video further away in time is
+/// unrelated footage that happens to share the name
+pub const MAX_PAIR_GAP: Duration = Duration::from_secs(5);
+
+/// The video of a Live Photo: `IMG_0001.MOV` next to `IMG_0001.HEIC`, modified within
+/// `MAX_PAIR_GAP` of it. Deleting or moving the still without it would orphan the motion part,
+/// so it always goes along, whatever the sidecar mode. When another still shares the stem, as
+/// `IMG_0001.JPG` next to `IMG_0001.HEIC`, the video could belong to either and stays put
+pub fn live_photo_video(still: &Path) -> Option<PathBuf> {
+    let extension = still.extension()?.to_string_lossy().to_lowercase();
+    if !STILL_EXTENSIONS.contains(&extension.as_str()) || shares_stem(still) {
+        return None;
+    }
+    let still_modified = fs::metadata(extended_length_path(still))
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+
+    VIDEO_EXTENSIONS
+        .iter()
+        .map(|extension| still.with_extension(extension))
+        .find(|video| {
+            let Ok(metadata) = fs::metadata(extended_length_path(video)) else {
+                return false;
+            };
+            let Ok(video_modified) = metadata.modified() else {
+                return false;
+            };
+            let gap = video_modified
+                .duration_since(still_modified)
+                .or_else(|_| still_modified.duration_since(video_modified))
+                .unwrap_or(Duration::MAX);
+            metadata.is_file() && gap <= MAX_PAIR_GAP
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    fn write_at(path: &Path, modified: SystemTime) {
+        File::create(path)
+            .and_then(|file| file.set_modified(modified))
+            .expect("write fixture");
+    }
+
+    #[test]
+    fn pairs_videos_taken_with_the_still() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let taken = SystemTime::now() - Duration::from_secs(3600);
+        let still = temp_dir.path().join("IMG_0001.HEIC");
+        write_at(&still, taken);
+        write_at(
+            &temp_dir.path().join("IMG_0001.MOV"),
+            taken + Duration::from_secs(1),
+        );
+
+        assert_eq!(
+            live_photo_video(&still),
+            Some(temp_dir.path().join("IMG_0001.MOV"))
+        );
+
+        // Same name, but recorded long after the photo
+        let unrelated = temp_dir.path().join("IMG_0002.jpg");
+        write_at(&unrelated, taken);
+        write_at(
+            &temp_dir.path().join("IMG_0002.mov"),
+            taken + Duration::from_secs(600),
+        );
+        assert_eq!(live_photo_video(&unrelated), None);
+
+        // Only stills have a motion part
+        let png = temp_dir.path().join("IMG_0001.png");
+        write_at(&png, taken);
+        assert_eq!(live_photo_video(&png), None);
+    }
+
+    #[test]
+    fn leaves_videos_of_shared_stems_alone() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let taken = SystemTime::now() - Duration::from_secs(3600);
+        let heic = temp_dir.path().join("IMG_0001.HEIC");
+        let jpeg = temp_dir.path().join("IMG_0001.JPG");
+        write_at(&heic, taken);
+        write_at(&jpeg, taken);
+        write_at(&temp_dir.path().join("IMG_0001.MOV"), taken);
+
+        assert_eq!(live_photo_video(&heic), None);
+        assert_eq!(live_photo_video(&jpeg), None);
+    }
+}
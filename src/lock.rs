@@ -0,0 +1,107 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// An advisory lock on a database, so two command line runs don't interleave their writes.
+/// Released when dropped, or by the OS when the process dies
+#[derive(Debug)]
+pub struct DatabaseLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl DatabaseLock {
+    /// The lock file, next to the database
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// `hashes.db.lock` for `hashes.db`
+pub fn lock_path(database: &Path) -> PathBuf {
+    let mut name = database.as_os_str().to_os_string();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+/// Take the lock on `database`. When another run holds it, either wait for it with `wait` or
+/// fail with the holder's process id
+pub fn lock_database(database: &Path, wait: bool) -> Result<DatabaseLock> {
+    let path = lock_path(database);
+    if let Some(folder) = path
+        .parent()
+        .filter(|folder| !folder.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(folder)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("Could not open lock file {}", path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if wait => {
+            info!(
+                "Waiting for {} to finish with {}...",
+                holder(&mut file),
+                database.display()
+            );
+            file.lock()?;
+        }
+        Err(TryLockError::WouldBlock) => bail!(
+            "{} is using {}, try again when it's done or pass --wait to wait for it",
+            holder(&mut file),
+            database.display()
+        ),
+        Err(TryLockError::Error(e)) => {
+            return Err(e).with_context(|| format!("Could not lock {}", path.display()))
+        }
+    }
+
+    // Only for messages, the lock itself is what keeps other runs out
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", std::process::id())?;
+    debug!("Locked {}", path.display());
+    Ok(DatabaseLock { _file: file, path })
+}
+
+/// Who holds the lock, as far as the lock file says
+fn holder(file: &mut File) -> String {
+    let mut pid = String::new();
+    match file.read_to_string(&mut pid) {
+        Ok(_) if !pid.trim().is_empty() => format!("Another run (process {})", pid.trim()),
+        _ => "Another run".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn a_second_lock_fails_until_the_first_is_dropped() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let database = temp_dir.path().join("hashes.db");
+
+        let first = lock_database(&database, false).expect("first lock");
+        assert_eq!(first.path(), temp_dir.path().join("hashes.db.lock"));
+        let error = lock_database(&database, false).expect_err("already locked");
+        assert!(
+            error
+                .to_string()
+                .contains(&format!("process {}", std::process::id())),
+            "{error}"
+        );
+
+        drop(first);
+        lock_database(&database, false).expect("lock after release");
+    }
+}
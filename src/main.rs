@@ -1,43 +1,307 @@
 #![deny(clippy::unwrap_used)]
 #![deny(clippy::expect_used)]
 
-use anyhow::Result;
-use clap::Parser;
-use std::path::PathBuf;
-use tracing::{error, info, warn};
-use vibe_image_comparator::cache::HashCache;
-use vibe_image_comparator::config::{load_config, show_config_with_overrides};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn, Level};
+use tracing_chrome::ChromeLayerBuilder;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+use vibe_image_comparator::action::{apply_hardlinks, plan_hardlinks, GroupAction};
+use vibe_image_comparator::algorithm::{hash_algorithm, set_hash_algorithm, HashAlgorithm};
+use vibe_image_comparator::background::{enter_background_mode, DEFAULT_BACKGROUND_IO_LIMIT_MB};
+use vibe_image_comparator::baseline::{Baseline, ResultsJson};
+use vibe_image_comparator::cache::{
+    default_database_path, Config, GroupingKey, HashCache, ResolvedConfig, HASH_INVARIANCE,
+    TOOL_VERSION,
+};
+use vibe_image_comparator::checksums::{write_sha256sums, OutputFormat, OutputScope};
+use vibe_image_comparator::concurrency::set_concurrency;
+use vibe_image_comparator::confidence::{group_confidence, retain_confidence, Confidence};
+use vibe_image_comparator::config::{
+    config_file_path, load_config, print_config_schema, save_config, show_config_with_overrides,
+    with_settings,
+};
+use vibe_image_comparator::deadline::{parse_duration, set_deadline, time_is_up};
+use vibe_image_comparator::dryrun::{is_dry_run, set_dry_run};
+use vibe_image_comparator::edits::{separate_edited_versions, EditedVersion};
+use vibe_image_comparator::embeddings::export_embeddings;
+use vibe_image_comparator::estimate::{
+    confirm, confirm_scan, estimate_scan, format_duration, format_size, record_scan_speed,
+    ScanEstimate,
+};
+use vibe_image_comparator::extract::open_image;
+use vibe_image_comparator::groupcap::{set_max_group_size, DEFAULT_MAX_GROUP_SIZE};
 use vibe_image_comparator::hasher::{
-    find_duplicates, generate_hashes_with_cache, get_duplicates_from_cache,
+    find_duplicates, find_duplicates_for_thresholds, generate_hashes_and_thumbnails,
+    generate_rotation_invariant_hash_safe, get_duplicates_from_cache, get_file_metadata,
+    refresh_duplicate_groups, split_groups_by_dimensions,
+};
+use vibe_image_comparator::hashlist::{match_hash_lists, HashList};
+use vibe_image_comparator::hooks::{run_post_scan_hook, ScanSource, ScanSummary};
+use vibe_image_comparator::hotfolder::{ingest_folder, DuplicateAction};
+use vibe_image_comparator::ingest::{
+    download_images, read_url_list, record_downloads, DownloadLimits, DEFAULT_CONCURRENCY,
 };
-use vibe_image_comparator::scanner::scan_for_images;
+use vibe_image_comparator::init::run_init_wizard;
+use vibe_image_comparator::jobs::JobLogLayer;
+use vibe_image_comparator::journal::{deletes_permanently, set_permanent_delete};
+use vibe_image_comparator::listener::systemd_listen_fd;
+use vibe_image_comparator::lock::{lock_database, DatabaseLock};
+use vibe_image_comparator::messages::{Lang, Message};
+use vibe_image_comparator::multires::{confirm_with_fine_hashes, MatchResolutions};
+use vibe_image_comparator::notify::notify;
+use vibe_image_comparator::overrides::apply_overrides;
+use vibe_image_comparator::partial::set_partial_file_filters;
+use vibe_image_comparator::paths::{extended_length_path, hard_linked};
+use vibe_image_comparator::policy::{Policy, PolicySettings};
+use vibe_image_comparator::progress::{set_show_progress, LogWriter};
+use vibe_image_comparator::reference::ReferenceHashes;
+use vibe_image_comparator::report::{SkipReason, SkippedFiles};
+use vibe_image_comparator::resolver::{
+    apply_resolution, resolve_group, GroupResolution, KeepStrategy,
+};
+use vibe_image_comparator::results::write_results_db;
+use vibe_image_comparator::review::{run_review, Review, ReviewGroup};
+use vibe_image_comparator::scanner::{
+    expand_tilde, scan_for_images_with_report, sort_images, HashOrder,
+};
+use vibe_image_comparator::selftest::{run_selftest, SelfTest, ESTIMATE_LIBRARY_SIZES};
 use vibe_image_comparator::server;
+use vibe_image_comparator::settings::SettingKey;
+use vibe_image_comparator::sidecar::SidecarMode;
+use vibe_image_comparator::stats::duplicate_stats;
+use vibe_image_comparator::store::{link_into_store, restore_from_store};
+use vibe_image_comparator::strategy::set_hash_strategies;
+use vibe_image_comparator::tags::is_keep_tagged;
+use vibe_image_comparator::takeout::import_takeout_metadata;
+use vibe_image_comparator::verify::{parse_percentage, verify_content};
+#[cfg(feature = "video")]
+use vibe_image_comparator::video::{confirm_with_frame_hashes, set_include_videos};
+use vibe_image_comparator::visualize::DistanceGrid;
+use vibe_image_comparator::watch::{watch_paths, WatchUpdate, DEFAULT_SETTLE};
 
 #[derive(Parser)]
 #[command(name = "vibe-image-comparator")]
 #[command(about = "A tool to find duplicate images using perceptual hashing")]
-struct Args {
-    #[arg(help = "Paths to scan for images")]
-    paths: Vec<PathBuf>,
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
 
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Options every command takes, before or after its name
+#[derive(clap::Args)]
+struct GlobalArgs {
     #[arg(
         short,
         long,
-        help = "Minimum similarity threshold (0-64, lower = more similar)"
+        global = true,
+        help = "Largest number of differing hash bits between duplicates (lower = more similar)"
     )]
     threshold: Option<u32>,
 
-    #[arg(short, long, help = "Hash grid size (e.g., 64 for 64x64 grid)")]
-    grid_size: Option<u32>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_parser = clap::value_parser!(u8).range(1..),
+        help = "Hash grid size, hashes have its square in bits (default 8, 64 bits)"
+    )]
+    grid_size: Option<u8>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILES",
+        help = "Split groups with more files than this by regrouping them more strictly, e.g. chains of loosely similar images (default: 100, 0 never splits)"
+    )]
+    max_group_size: Option<usize>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "How images are hashed, cached hashes of another algorithm are made again (default: perceptual)"
+    )]
+    algorithm: Option<HashAlgorithm>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        help = "Files read and checksummed at once while hashing, raise it for network shares (default: 8)"
+    )]
+    io_threads: Option<usize>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        help = "Images decoded and hashed at once (default: one per core)"
+    )]
+    cpu_threads: Option<usize>,
+
+    #[arg(
+        long,
+        global = true,
+        visible_alias = "ephemeral",
+        help = "Use a temporary in-memory cache instead of the persistent database"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Wait for another run using the same database to finish instead of failing"
+    )]
+    wait: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Delete files for good instead of moving them to the trash"
+    )]
+    permanent: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Show what deleting, hard linking, auto-resolving and cleaning the cache would remove or change, with byte totals, without touching anything"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "Language for result summaries and web API messages (overrides the config file)"
+    )]
+    lang: Option<Lang>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Write timings of scanning, hashing, database and comparison phases as a Chrome trace (open in chrome://tracing or Perfetto)"
+    )]
+    trace_out: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Find duplicates among the images under the given paths, or the configured scan paths
+    Scan(ScanArgs),
+    /// Show the duplicates found by earlier scans from the cache, without scanning
+    Matches(MatchesArgs),
+    /// Remove missing files and orphaned hashes from the cache
+    Clean(CleanArgs),
+    /// Inspect, verify or export the cache
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Start the web server for the browser-based interface
+    Serve(ServeArgs),
+    /// Move new images from a hot folder into the library
+    Ingest(IngestArgs),
+    /// Download the images listed in a file, hash them into the cache and report which are
+    /// already in the local library
+    IngestUrls(IngestUrlsArgs),
+    /// Keep watching the given paths, or the configured scan paths, hashing images as they
+    /// arrive or change and keeping the cached duplicate groups up to date
+    Watch(WatchArgs),
+    /// Go through the cached duplicate groups in the terminal, marking files to keep or delete,
+    /// then delete the marked ones
+    Review(ReviewArgs),
+    /// Show the distances between every two files of a cached duplicate group as a heat grid
+    Visualize {
+        #[arg(
+            value_name = "GROUP",
+            help = "Number of the group, as `matches` lists them"
+        )]
+        group: usize,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Write the grid to a PNG file instead of the terminal"
+        )]
+        png: Option<PathBuf>,
+    },
+    /// Find duplicates across hash lists exported with `cache export-hashes`, without reading
+    /// any image files or the cache
+    MatchHashes {
+        #[arg(
+            value_name = "FILE",
+            num_args = 2..,
+            required = true,
+            help = "Exported hash lists to compare"
+        )]
+        lists: Vec<PathBuf>,
+    },
+    /// Show, describe or store settings
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Interactively set up scan folders, matching strictness and database location
+    Init,
+    /// Hash and group generated test images, then print throughput per stage and projected
+    /// times for large libraries, nothing is read or cached
+    Selftest,
+}
+
+#[derive(clap::Args, Default)]
+struct ScanArgs {
+    #[arg(help = "Paths to scan for images")]
+    paths: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Compute duplicate groups for several thresholds in one pass (e.g., 5,10,15,20)"
+    )]
+    thresholds: Vec<u32>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Order in which images are hashed, so interrupted scans cover the most useful files first"
+    )]
+    order: Option<HashOrder>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with = "thresholds",
+        help = "Print results to stdout in a format other tools read, logs go to stderr"
+    )]
+    output: Option<OutputFormat>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        requires = "output",
+        help = "Which files --output sha256sum lists"
+    )]
+    output_scope: OutputScope,
 
-    #[arg(long, help = "Remove missing files and orphaned hashes from database")]
-    clean_missing: bool,
+    #[arg(long, help = "Also compare the cover images of EPUB ebooks")]
+    ebooks: bool,
 
+    #[cfg(feature = "video")]
     #[arg(
         long,
-        help = "Completely clear all cache data (files, hashes, duplicate groups)"
+        help = "Also compare MP4, MOV and MKV videos by frames sampled with ffmpeg"
     )]
-    clear_cache: bool,
+    videos: bool,
 
     #[arg(short = '.', help = "Include hidden directories (starting with .)")]
     include_hidden: bool,
@@ -57,129 +321,2242 @@ struct Args {
 
     #[arg(
         long,
-        help = "Show duplicate matches from cache database only (no scanning)"
+        value_enum,
+        value_name = "KEEP",
+        conflicts_with_all = ["thresholds", "action"],
+        help = "Keep one file of each duplicate group by this rule and delete the rest, with their Live Photo videos and sidecars. Files tagged Keep and hard links to the kept file stay"
+    )]
+    auto_resolve: Option<KeepStrategy>,
+
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with = "thresholds",
+        help = "What to do with the duplicates found: hardlink replaces files with identical contents by hard links to the one the keep rule picks (see --policy), printing the bytes saved"
+    )]
+    action: Option<GroupAction>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also match scanned files against an exported hash list, e.g. of an archive kept elsewhere. Its entries are shown as reference-only and never deleted (can be repeated)"
+    )]
+    reference_hashes: Vec<PathBuf>,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    #[arg(
+        long,
+        help = "Store thumbnails for the web interface while hashing, instead of on first view"
+    )]
+    generate_thumbnails: bool,
+
+    #[arg(
+        long,
+        help = "Import capture times and descriptions from Google Takeout JSON sidecars for the given paths"
+    )]
+    import_takeout: bool,
+
+    #[arg(
+        long,
+        value_name = "STORE",
+        help = "Keep one copy of each image's contents in STORE (named by sha256) and replace the images under the given paths with links to it"
+    )]
+    dedupe_store: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "dedupe_store",
+        help = "Undo --dedupe-store: turn the links under the given paths (all when none are given) back into separate files"
+    )]
+    restore_store: bool,
+
+    #[arg(
+        long,
+        help = "With --dedupe-store or --restore-store, skip files that don't fit on their destination's disk instead of stopping before anything runs out of space"
+    )]
+    skip_when_full: bool,
+
+    #[arg(
+        long,
+        help = "Run at low CPU and I/O priority with throttled reads, for scans while you work"
+    )]
+    background: bool,
+
+    #[arg(
+        long,
+        value_name = "MB_PER_SEC",
+        requires = "background",
+        help = "File read rate limit for --background (default: 20)"
+    )]
+    io_limit: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_max_duration,
+        help = "Stop finding and hashing images after this long (e.g. 2h, 90m, 1h30m), keeping what was hashed so the next run carries on"
     )]
-    show_matches: bool,
+    max_duration: Option<Duration>,
 
-    #[arg(long, help = "Show current configuration settings")]
-    show_config: bool,
+    #[arg(
+        long,
+        help = "Don't ask for confirmation before large scans (see confirm_scan_above in the config file) or before --auto-resolve deletes files. Needed for --auto-resolve without a terminal"
+    )]
+    yes: bool,
 
-    #[arg(long, help = "Start web server for browser-based interface")]
-    server: bool,
+    #[arg(long, help = "Show a desktop notification when the scan finishes")]
+    notify: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize tracing subscriber
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
+/// How `scan` and `matches` filter and list the duplicates they find
+#[derive(clap::Args, Default)]
+struct ReportArgs {
+    #[arg(
+        long,
+        help = "Only group images with identical dimensions (width x height)"
+    )]
+    same_dimensions: bool,
 
-    let args = Args::parse();
+    #[arg(
+        long,
+        help = "Confirm matches with 16x16 hashes of the grouped files, so images that only look alike at 8x8 (e.g. sparse screenshots) drop out. Each group shows which size confirmed it"
+    )]
+    multi_resolution: bool,
 
-    let config = load_config()?;
+    #[arg(
+        long,
+        help = "List differently sized edits with the same EXIF capture time (e.g. Lightroom exports) separately instead of as duplicates"
+    )]
+    edited_versions: bool,
 
-    // Handle show_config flag
-    if args.show_config {
-        show_config_with_overrides(args.threshold, args.grid_size)?;
-        return Ok(());
-    }
+    #[arg(
+        long,
+        value_enum,
+        value_name = "TIER",
+        help = "Only report groups at least this certain: probable, near-certain (within a third of the threshold) or exact (identical files)"
+    )]
+    min_confidence: Option<Confidence>,
 
-    // Handle server flag
-    if args.server {
-        let config = config.clone();
-        return server::start_server(config, args.threshold, args.grid_size).await;
-    }
+    #[arg(
+        long,
+        value_enum,
+        help = "Use a built-in policy's threshold, filters and keep rule (explicit flags still win). scan resolves the groups of policies safe to resolve, like screenshots, as --auto-resolve does"
+    )]
+    policy: Option<Policy>,
+
+    #[arg(
+        long,
+        help = "Include each file's perceptual hash and sha256 in the duplicate listing"
+    )]
+    show_hashes: bool,
 
-    let effective_config = config.with_overrides(args.grid_size, args.threshold, None);
-    let cache = HashCache::new(effective_config.database_path.as_deref())?;
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write duplicate groups, file details and keep/delete decisions to a standalone SQLite file"
+    )]
+    results_db: Option<PathBuf>,
 
-    if args.clean_missing {
-        let (files_removed, hashes_removed) = cache.cleanup_missing_files_and_hashes()?;
-        info!("Cleaned up {files_removed} missing files and {hashes_removed} orphaned hashes from database");
-        if args.paths.is_empty() {
-            return Ok(());
-        }
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Only report duplicate sets that aren't in an earlier run's --output json file, marking files it didn't have as new"
+    )]
+    baseline: Option<PathBuf>,
+}
+
+impl ReportArgs {
+    fn policy_settings(&self) -> Option<PolicySettings> {
+        self.policy.map(|policy| policy.settings())
     }
 
-    if args.clear_cache {
-        cache.clear_all_cache()?;
-        info!("Completely cleared all cache data");
-        if args.paths.is_empty() {
-            return Ok(());
-        }
+    fn same_dimensions(&self) -> bool {
+        self.same_dimensions
+            || self
+                .policy_settings()
+                .is_some_and(|settings| settings.same_dimensions)
     }
 
-    // Handle show_matches flag - only show cached duplicates
-    if args.show_matches {
-        let threshold = args.threshold.unwrap_or(effective_config.threshold);
-        info!("Using threshold: {threshold}");
-        info!("Hash caching enabled");
+    fn keep(&self) -> KeepStrategy {
+        self.policy_settings()
+            .map_or(KeepStrategy::KeepLargest, |settings| settings.keep)
+    }
+}
 
-        let duplicates = get_duplicates_from_cache(&cache, threshold, None, None)?;
+#[derive(clap::Args)]
+#[command(group(ArgGroup::new("query").args([
+    "refresh",
+    "by_hash",
+    "find_similar",
+    "compare_cache",
+])))]
+struct MatchesArgs {
+    #[arg(
+        long,
+        help = "Re-check only the files in cached duplicate groups (gone, resized or changed), update the groups and show them"
+    )]
+    refresh: bool,
 
-        if duplicates.is_empty() {
-            info!("No duplicate images found in cache");
-        } else {
-            info!("Found {} duplicate sets in cache:", duplicates.len());
-            for (i, group) in duplicates.iter().enumerate() {
-                info!("  Group {}:", i + 1);
-                for path in group {
-                    info!("    {}", path.display());
-                }
-            }
-        }
+    #[arg(
+        long,
+        help = "List cached files that share an identical perceptual hash, e.g. one picture saved many times. Read from the cache without grouping, so much quicker than finding duplicates"
+    )]
+    by_hash: bool,
 
-        return Ok(());
-    }
+    #[arg(
+        long,
+        value_name = "IMAGE",
+        help = "List cached files similar to one image, looked up through indexed hash prefixes instead of comparing every cached hash"
+    )]
+    find_similar: Option<PathBuf>,
 
-    if args.paths.is_empty() {
-        error!("Please provide at least one path to scan");
-        std::process::exit(1);
-    }
+    #[arg(
+        long,
+        value_name = "DATABASE",
+        help = "List cached files that match a file in another cache database (e.g. a NAS's), compared in SQLite without loading either into memory"
+    )]
+    compare_cache: Option<PathBuf>,
 
-    let threshold = args.threshold.unwrap_or(effective_config.threshold);
-    let grid_size = args.grid_size.unwrap_or(effective_config.grid_size);
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        help = "Print the groups to stdout in a format other tools read, logs go to stderr"
+    )]
+    output: Option<OutputFormat>,
 
-    info!("Using grid size: {grid_size}x{grid_size}, threshold: {threshold}");
-    info!("Hash caching enabled");
+    #[command(flatten)]
+    report: ReportArgs,
+}
 
-    info!("Scanning paths for images...");
-    let images = scan_for_images(
-        &args.paths,
-        args.include_hidden,
-        args.debug,
-        args.skip_validation,
-        &effective_config.ignore_paths,
-    )?;
+#[derive(clap::Args)]
+struct CleanArgs {
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        help = "Only clean up entries under this folder, which must be mounted and not empty"
+    )]
+    only_under: Option<String>,
 
-    info!("Found {} images", images.len());
-    info!("Generating perceptual hashes...");
+    #[arg(
+        long,
+        conflicts_with = "only_under",
+        help = "Completely clear all cache data (files, hashes, duplicate groups) instead"
+    )]
+    all: bool,
+}
 
-    let hashes = generate_hashes_with_cache(&images, grid_size, &cache, args.debug)?;
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Show which tool versions and hash settings made the cached hashes and duplicate groups
+    Info,
+    /// Re-read cached files and report those whose contents no longer match their cached
+    /// sha256 (bit rot, silent modification)
+    #[command(alias = "verify")]
+    VerifyContent {
+        #[arg(
+            long,
+            value_name = "PERCENT",
+            value_parser = parse_sample,
+            help = "Only verify this share of the cached files (e.g. 10%), those checked longest ago first, so repeated runs cover everything"
+        )]
+        sample: Option<f64>,
+    },
+    /// Forget the hashes cached by a tool version so they're made again on the next scan
+    Forget {
+        #[arg(
+            value_name = "VERSION",
+            help = "Tool version, \"unknown\" for hashes cached before versions were recorded"
+        )]
+        version: String,
+    },
+    /// Export every cached hash to a JSON hash list
+    ExportHashes {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Export every cached hash as a row of 0/1 bit columns to a CSV or Parquet file (by its
+    /// extension), for clustering or visualisation elsewhere
+    ExportEmbeddings {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Only hash the images under the given paths, or the configured scan paths, into the
+    /// cache: no grouping and only warnings logged, for cron jobs
+    Warm(WarmArgs),
+}
 
-    info!("Finding duplicate sets...");
-    let duplicates = find_duplicates(&hashes, threshold);
+/// The options of `scan` that apply to hashing alone
+#[derive(clap::Args)]
+struct WarmArgs {
+    #[arg(help = "Paths to hash images under, the configured scan paths when none are given")]
+    paths: Vec<PathBuf>,
 
-    // Cache the duplicate groups for future use
-    if let Err(e) = cache.store_duplicate_groups(threshold, &duplicates) {
-        warn!("Failed to cache duplicate groups: {}", e);
-    }
+    #[arg(
+        long,
+        value_enum,
+        help = "Order in which images are hashed, so interrupted runs cover the most useful files first"
+    )]
+    order: Option<HashOrder>,
 
-    if duplicates.is_empty() {
-        info!("No duplicate images found");
-    } else {
-        info!("Found {} duplicate sets:", duplicates.len());
-        for (i, group) in duplicates.iter().enumerate() {
-            info!("  Group {}:", i + 1);
-            for path in group {
-                info!("    {}", path.display());
-            }
-        }
-    }
+    #[arg(long, help = "Also hash the cover images of EPUB ebooks")]
+    ebooks: bool,
+
+    #[cfg(feature = "video")]
+    #[arg(
+        long,
+        help = "Also hash MP4, MOV and MKV videos by frames sampled with ffmpeg"
+    )]
+    videos: bool,
 
+    #[arg(short = '.', help = "Include hidden directories (starting with .)")]
+    include_hidden: bool,
+
+    #[arg(
+        long,
+        help = "Print debug information including filenames as they're processed"
+    )]
+    debug: bool,
+
+    #[arg(
+        long,
+        help = "Skip file format validation (process files even with wrong magic numbers)"
+    )]
+    skip_validation: bool,
+
+    #[arg(
+        long,
+        help = "Store thumbnails for the web interface while hashing, instead of on first view"
+    )]
+    generate_thumbnails: bool,
+
+    #[arg(
+        long,
+        help = "Run at low CPU and I/O priority with throttled reads, for runs while you work"
+    )]
+    background: bool,
+
+    #[arg(
+        long,
+        value_name = "MB_PER_SEC",
+        requires = "background",
+        help = "File read rate limit for --background (default: 20)"
+    )]
+    io_limit: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_max_duration,
+        help = "Stop finding and hashing images after this long (e.g. 2h, 90m, 1h30m), keeping what was hashed so the next run carries on"
+    )]
+    max_duration: Option<Duration>,
+
+    #[arg(
+        long,
+        help = "Don't ask for confirmation before large runs (see confirm_scan_above in the config file)"
+    )]
+    yes: bool,
+
+    #[arg(long, help = "Show a desktop notification when hashing finishes")]
+    notify: bool,
+}
+
+impl WarmArgs {
+    /// The scan these options stand for, so warming shares its hashing with `scan`
+    fn to_scan_args(&self, paths: Vec<PathBuf>) -> ScanArgs {
+        ScanArgs {
+            paths,
+            order: self.order,
+            ebooks: self.ebooks,
+            #[cfg(feature = "video")]
+            videos: self.videos,
+            include_hidden: self.include_hidden,
+            debug: self.debug,
+            skip_validation: self.skip_validation,
+            generate_thumbnails: self.generate_thumbnails,
+            background: self.background,
+            io_limit: self.io_limit,
+            max_duration: self.max_duration,
+            yes: self.yes,
+            notify: self.notify,
+            ..ScanArgs::default()
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct ServeArgs {
+    #[arg(help = "Paths for --scan, the configured scan paths when none are given")]
+    paths: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Scan the given paths (or the configured scan paths) in the background, following along in the web interface"
+    )]
+    scan: bool,
+
+    #[arg(
+        long = "fd",
+        value_name = "FD",
+        help = "Serve on an already listening socket file descriptor instead of binding one (systemd socket activation is detected automatically)"
+    )]
+    listen_fd: Option<i32>,
+
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Port to listen on (overrides stored settings and the config file)"
+    )]
+    port: Option<u16>,
+}
+
+#[derive(clap::Args)]
+struct IngestArgs {
+    #[arg(
+        num_args = 2,
+        required = true,
+        value_names = ["SRC", "DEST"],
+        help = "Move the images in SRC into DEST unless the library already has a duplicate of them, duplicates are moved to SRC/duplicates (see --duplicates)"
+    )]
+    folders: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DuplicateAction::Move,
+        help = "What happens to images from SRC that are already in the library"
+    )]
+    duplicates: DuplicateAction,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_max_duration,
+        help = "Keep watching SRC, checking it again this long (e.g. 30s, 5m) after each pass"
+    )]
+    every: Option<Duration>,
+}
+
+#[derive(clap::Args)]
+struct IngestUrlsArgs {
+    #[arg(
+        value_name = "FILE",
+        help = "File listing the http:// and https:// image URLs to download, one per line, # starts a comment"
+    )]
+    urls: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Downloads running at once (default: 4)"
+    )]
+    concurrency: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "PER_SEC",
+        help = "Start at most this many downloads per second"
+    )]
+    rate_limit: Option<f64>,
+}
+
+#[derive(clap::Args)]
+struct WatchArgs {
+    #[arg(help = "Paths to watch for images, the configured scan paths when none are given")]
+    paths: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_max_duration,
+        help = "Handle changes once the paths have been quiet this long (e.g. 5s, default: 2s)"
+    )]
+    settle: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_max_duration,
+        help = "Stop watching after this long (e.g. 8h, 1h30m)"
+    )]
+    max_duration: Option<Duration>,
+
+    #[arg(
+        long,
+        help = "Show a desktop notification whenever new or changed images turn out to be duplicates"
+    )]
+    notify: bool,
+}
+
+#[derive(clap::Args)]
+struct ReviewArgs {
+    #[arg(
+        long,
+        value_enum,
+        value_name = "KEEP",
+        help = "Start with every file but the one this rule keeps marked for deletion"
+    )]
+    keep: Option<KeepStrategy>,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Show current configuration settings
+    Show,
+    /// Print every config option as JSON, with its type, default, effective value and where
+    /// that was set (default, file, database, env or cli)
+    Schema,
+    /// Store settings in the database: threshold, ignore-paths (separated by ':') or port
+    Set {
+        #[arg(value_name = "KEY=VALUE", required = true, value_parser = parse_setting)]
+        settings: Vec<(SettingKey, String)>,
+    },
+    /// Remove stored settings so the config file's values apply again
+    Unset {
+        #[arg(value_enum, value_name = "KEY", required = true)]
+        keys: Vec<SettingKey>,
+    },
+}
+
+/// `KEY=VALUE` for `--set`
+fn parse_setting(assignment: &str) -> Result<(SettingKey, String), String> {
+    let (key, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{assignment}'"))?;
+    let key = SettingKey::from_str(key.trim(), true)?;
+    Ok((key, value.to_string()))
+}
+
+/// `--sample`, e.g. `10%`
+fn parse_sample(text: &str) -> Result<f64, String> {
+    parse_percentage(text).map_err(|e| e.to_string())
+}
+
+/// `--max-duration`, e.g. `2h` or `1h30m`
+fn parse_max_duration(text: &str) -> Result<Duration, String> {
+    parse_duration(text).map_err(|e| e.to_string())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let Cli { global, command } = Cli::parse();
+
+    // Cache warming is meant for cron jobs, so only warnings are logged by default
+    let default_log_level = match &command {
+        Command::Cache(CacheCommand::Warm(_)) => "warn",
+        _ => "info",
+    };
+
+    // With --output, stdout is reserved for the results
+    let logs_to_stderr = match &command {
+        Command::Scan(scan) => scan.output.is_some(),
+        Command::Matches(matches) => matches.output.is_some(),
+        _ => false,
+    };
+
+    // With --trace-out, every span of this crate also goes to a Chrome trace, whatever the log
+    // level. The guard writes the end of the file when main returns
+    let (trace_layer, _trace_guard) = match &global.trace_out {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Could not create trace file {}", path.display()))?;
+            let (layer, guard) = ChromeLayerBuilder::new()
+                .writer(file)
+                .include_args(true)
+                .build();
+            let spans = Targets::new().with_target("vibe_image_comparator", Level::TRACE);
+            (Some(layer.with_filter(spans)), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    // Initialize tracing subscriber
+    let log_layer = tracing_subscriber::fmt::layer()
+        .with_writer(move || -> Box<dyn Write> {
+            if logs_to_stderr {
+                Box::new(LogWriter::new(io::stderr()))
+            } else {
+                Box::new(LogWriter::new(io::stdout()))
+            }
+        })
+        .with_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_log_level)),
+        );
+    // Only the web server runs jobs, their INFO and above lines are kept with each run
+    let job_log_layer =
+        matches!(command, Command::Serve(_)).then(|| JobLogLayer.with_filter(LevelFilter::INFO));
+    tracing_subscriber::registry()
+        .with(log_layer)
+        .with(trace_layer)
+        .with(job_log_layer)
+        .init();
+
+    // Before the config is loaded, so a scan straight after the wizard uses its answers
+    if matches!(command, Command::Init) && !init()? {
+        return Ok(());
+    }
+
+    let mut file_config = load_config()?;
+    if global.lang.is_some() {
+        file_config.lang = global.lang;
+    }
+    // Both scans and the web server's rescans pick files and decode them by these
+    if let Some(strategies) = &file_config.hash_strategies {
+        set_hash_strategies(strategies);
+    }
+    set_concurrency(global.io_threads, global.cpu_threads);
+    set_permanent_delete(global.permanent);
+    set_dry_run(global.dry_run);
+    // Bars would be mixed into the results on stdout, or into a log file
+    set_show_progress(!logs_to_stderr && io::stdout().is_terminal());
+    set_max_group_size(
+        global
+            .max_group_size
+            .or(file_config.max_group_size)
+            .unwrap_or(DEFAULT_MAX_GROUP_SIZE),
+    );
+    set_hash_algorithm(
+        global
+            .algorithm
+            .or(file_config.algorithm)
+            .unwrap_or_default(),
+    );
+    set_partial_file_filters(
+        file_config.temporary_file_patterns.as_deref(),
+        &file_config.debounce_seconds.clone().unwrap_or_default(),
+    );
+
+    // A policy's threshold applies unless one is given
+    let policy_threshold = match &command {
+        Command::Scan(scan) => scan.report.policy_settings(),
+        Command::Matches(matches) => matches.report.policy_settings(),
+        _ => None,
+    }
+    .map(|settings| settings.threshold);
+    let threshold = global.threshold.or(policy_threshold);
+    let resolve = |file_config: &Config| -> Result<ResolvedConfig> {
+        Ok(with_settings(file_config)?.with_overrides(global.grid_size, threshold, None))
+    };
+
+    match command {
+        Command::Config(ConfigCommand::Show) => {
+            show_config_with_overrides(threshold, global.grid_size)
+        }
+        Command::Config(ConfigCommand::Schema) => print_config_schema(threshold, global.grid_size),
+        Command::Config(ConfigCommand::Set { settings }) => {
+            let (cache, _lock) = open_cache(&global, &resolve(&file_config)?)?;
+            update_settings(&global, &cache, &[], &settings)
+        }
+        Command::Config(ConfigCommand::Unset { keys }) => {
+            let (cache, _lock) = open_cache(&global, &resolve(&file_config)?)?;
+            update_settings(&global, &cache, &keys, &[])
+        }
+        Command::Serve(serve) => {
+            if global.no_cache {
+                bail!("The web server works from the cache database, serve can't be used with --no-cache");
+            }
+            let listen_fd = serve.listen_fd.or_else(systemd_listen_fd);
+            let scan_paths = if serve.scan {
+                background_scan_paths(&serve.paths, &file_config)?
+            } else {
+                Vec::new()
+            };
+            server::start_server(
+                file_config,
+                threshold,
+                global.grid_size,
+                serve.port,
+                listen_fd,
+                scan_paths,
+            )
+            .await
+        }
+        Command::Selftest => {
+            let config = resolve(&file_config)?;
+            selftest(config.threshold, config.lang)
+        }
+        // Matching hash lists works purely on the files given, the cache isn't opened
+        Command::MatchHashes { lists } => match_hashes(&lists, resolve(&file_config)?.threshold),
+        Command::Matches(matches) => {
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            show_matches(&global, &matches, &config, &cache)
+        }
+        Command::Clean(clean) => {
+            let (cache, _lock) = open_cache(&global, &resolve(&file_config)?)?;
+            clean_cache(&clean, &cache)
+        }
+        Command::Cache(command) => {
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            manage_cache(&global, &command, &cache, &config)
+        }
+        Command::Ingest(ingest) => {
+            if global.no_cache {
+                bail!("Incoming images are compared with the cached library, ingest can't be used with --no-cache");
+            }
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            ingest_images(&ingest, &config, &cache).await
+        }
+        Command::IngestUrls(ingest) => {
+            if global.no_cache {
+                bail!("Downloads are compared with the cached library, ingest-urls can't be used with --no-cache");
+            }
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            ingest_urls(&ingest, &config, &cache).await
+        }
+        Command::Watch(watch) => {
+            if global.no_cache {
+                bail!("Watching keeps the cache up to date, watch can't be used with --no-cache");
+            }
+            if let Some(budget) = watch.max_duration {
+                set_deadline(budget);
+            }
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            watch_folders(&watch, &config, &cache)
+        }
+        Command::Visualize { group, png } => {
+            if global.no_cache {
+                bail!("Visualize shows a cached group, it can't be used with --no-cache");
+            }
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            visualize_group(
+                &cache,
+                group,
+                config.threshold,
+                config.grid_size,
+                png.as_deref(),
+                config.lang,
+            )
+        }
+        Command::Review(review) => {
+            if global.no_cache {
+                bail!("Review goes through the cached duplicate groups, it can't be used with --no-cache");
+            }
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            review_groups(&review, &config, &cache)
+        }
+        Command::Scan(scan) => {
+            if scan.background {
+                enter_background_mode(scan.io_limit.unwrap_or(DEFAULT_BACKGROUND_IO_LIMIT_MB))?;
+            }
+            if let Some(budget) = scan.max_duration {
+                set_deadline(budget);
+            }
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            scan_paths(&global, scan, &config, &cache)
+        }
+        Command::Init => {
+            let config = resolve(&file_config)?;
+            let (cache, _lock) = open_cache(&global, &config)?;
+            scan_paths(&global, ScanArgs::default(), &config, &cache)
+        }
+    }
+}
+
+/// Run the setup wizard, saving its answers. Returns whether to scan straight away
+fn init() -> Result<bool> {
+    let config_path = config_file_path()?;
+    if config_path.exists() {
+        println!("Updating existing config at {}", config_path.display());
+    }
+    let answers = run_init_wizard(
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+        &load_config()?,
+    )?;
+    save_config(&answers.config)?;
+    Ok(answers.scan_now)
+}
+
+/// The cache a command works on, with the lock on its database. The lock is held until it's
+/// dropped, so two runs against one database take turns
+fn open_cache(
+    global: &GlobalArgs,
+    config: &ResolvedConfig,
+) -> Result<(HashCache, Option<DatabaseLock>)> {
+    if global.no_cache {
+        return Ok((HashCache::new_in_memory()?, None));
+    }
+    let database = config
+        .database_path
+        .as_deref()
+        .map_or_else(default_database_path, PathBuf::from);
+    let lock = lock_database(&database, global.wait)?;
+    Ok((HashCache::open(config)?, Some(lock)))
+}
+
+fn cache_status(global: &GlobalArgs) -> &'static str {
+    if global.no_cache {
+        "Using temporary in-memory cache (nothing will be persisted)"
+    } else {
+        "Hash caching enabled"
+    }
+}
+
+/// `matches`: list the cached duplicate groups, or answer one of its queries about the cache
+fn show_matches(
+    global: &GlobalArgs,
+    args: &MatchesArgs,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<()> {
+    let (threshold, grid_size, lang) = (config.threshold, config.grid_size, config.lang);
+
+    if let Some(other) = &args.compare_cache {
+        return compare_cache(cache, other, threshold, grid_size, lang);
+    }
+
+    if let Some(image) = &args.find_similar {
+        return find_similar(cache, image, threshold, grid_size, lang);
+    }
+
+    if args.by_hash {
+        if global.no_cache {
+            bail!("--by-hash lists the cached files, it can't be used with --no-cache");
+        }
+        return list_shared_hashes(cache, lang);
+    }
+
+    info!("Using threshold: {threshold}");
+    info!("{}", cache_status(global));
+
+    if args.refresh {
+        let (_, report) = refresh_duplicate_groups(cache, threshold, grid_size)?;
+        info!(
+            "Re-checked {} files: {} gone, {} changed",
+            report.checked,
+            report.missing.len(),
+            report.changed.len()
+        );
+        report.warnings.log_summary();
+        report.skipped.log_summary();
+    }
+
+    let report = &args.report;
+    let mut duplicates = get_duplicates_from_cache(cache, threshold, grid_size, None, None)?;
+    let mut resolutions = MatchResolutions::default();
+    if report.multi_resolution {
+        (duplicates, resolutions) = confirm_with_fine_hashes(duplicates, threshold, cache)?;
+    }
+    #[cfg(feature = "video")]
+    {
+        duplicates = confirm_with_frame_hashes(duplicates, threshold, grid_size, cache)?;
+    }
+    if report.same_dimensions() {
+        duplicates = split_groups_by_dimensions(duplicates, cache);
+    }
+    let mut edited = Vec::new();
+    if report.edited_versions {
+        (duplicates, edited) = separate_edited_versions(duplicates, cache);
+    }
+    if let Some(min_confidence) = report.min_confidence {
+        duplicates = retain_confidence(duplicates, min_confidence, threshold, cache);
+    }
+    let baseline = load_baseline(report)?;
+    let duplicates = new_findings(&baseline, duplicates, lang);
+
+    let keep = report.keep();
+    if duplicates.is_empty() {
+        info!("{}", Message::NoDuplicatesInCache.text(lang));
+    } else {
+        info!(
+            "{}",
+            Message::FoundDuplicateSetsInCache(duplicates.len()).text(lang)
+        );
+        print_duplicate_groups(
+            &duplicates,
+            threshold,
+            cache,
+            &GroupMarks {
+                references: &ReferenceHashes::default(),
+                resolutions: &resolutions,
+                baseline: &baseline,
+            },
+            report.show_hashes,
+            lang,
+        );
+        duplicate_stats(&duplicates, keep, cache).log_summary(keep, lang);
+    }
+    print_edited_versions(&edited, lang);
+    match args.output {
+        Some(OutputFormat::Sha256sum) => {
+            write_sha256sums(&duplicates.concat(), cache, &mut io::stdout().lock())?;
+        }
+        Some(OutputFormat::Json) => {
+            ResultsJson::new(&duplicates, threshold, cache).write(&mut io::stdout().lock())?;
+        }
+        None => {}
+    }
+    if let Some(results_db) = &report.results_db {
+        write_results_db(results_db, &duplicates, threshold, keep, cache)?;
+        info!("Wrote results to {}", results_db.display());
+    }
+
+    Ok(())
+}
+
+/// `clean`: drop what's no longer on disk from the cache, or everything with `--all`
+fn clean_cache(args: &CleanArgs, cache: &HashCache) -> Result<()> {
+    if is_dry_run() {
+        return preview_clean(args, cache);
+    }
+    if args.all {
+        cache.clear_all_cache()?;
+        info!("Completely cleared all cache data");
+        return Ok(());
+    }
+
+    let only_under = args.only_under.as_deref().map(expand_tilde);
+    let (files_removed, hashes_removed) =
+        cache.cleanup_missing_files_and_hashes(only_under.as_deref())?;
+    info!("Cleaned up {files_removed} missing files and {hashes_removed} orphaned hashes from database");
+    let aliases = cache.fold_case_aliases()?;
+    if !aliases.is_empty() {
+        info!(
+            "Removed {} paths that only differ in case from a cached file",
+            aliases.len()
+        );
+    }
+    Ok(())
+}
+
+/// `clean --dry-run`: list the entries cleaning would remove
+fn preview_clean(args: &CleanArgs, cache: &HashCache) -> Result<()> {
+    if args.all {
+        info!(
+            "Dry run, nothing was cleared: clearing would remove all cache data, {} cached files",
+            cache.cached_file_count()?
+        );
+        return Ok(());
+    }
+    let only_under = args.only_under.as_deref().map(expand_tilde);
+    let missing = cache.find_missing_files(only_under.as_deref())?;
+    for path in &missing {
+        info!("  remove {}", path.display());
+    }
+    info!(
+        "Dry run, nothing was removed: cleaning would remove {} missing files from the database",
+        missing.len()
+    );
+    Ok(())
+}
+
+fn manage_cache(
+    global: &GlobalArgs,
+    command: &CacheCommand,
+    cache: &HashCache,
+    config: &ResolvedConfig,
+) -> Result<()> {
+    match command {
+        CacheCommand::Info => print_cache_provenance(cache, config.grid_size),
+        CacheCommand::VerifyContent { sample } => {
+            if global.no_cache {
+                bail!("cache verify-content checks the cached files, it can't be used with --no-cache");
+            }
+            verify_cached_content(*sample, cache, config.lang)
+        }
+        CacheCommand::Forget { .. } if is_dry_run() => {
+            bail!("cache forget can't be previewed, run it without --dry-run")
+        }
+        CacheCommand::Forget { version } => {
+            let version = Some(version.as_str()).filter(|version| *version != "unknown");
+            let forgotten = cache.forget_hashes_made_by(version)?;
+            info!(
+                "Forgot {forgotten} hashes made by version {}",
+                version.unwrap_or("unknown")
+            );
+            Ok(())
+        }
+        CacheCommand::ExportHashes { file } => {
+            let hash_list = HashList::from_cache(cache)?;
+            hash_list.save(file)?;
+            info!(
+                "Exported {} hashes to {}",
+                hash_list.hashes.len(),
+                file.display()
+            );
+            Ok(())
+        }
+        CacheCommand::ExportEmbeddings { file } => {
+            let rows = export_embeddings(cache, file)?;
+            info!("Exported {} hashes to {}", rows, file.display());
+            Ok(())
+        }
+        CacheCommand::Warm(warm) => {
+            if global.no_cache {
+                bail!("cache warm fills the cache, it can't be used with --no-cache");
+            }
+            let paths = if warm.paths.is_empty() {
+                info!("No paths given, hashing the configured scan paths");
+                config.scan_paths.iter().map(PathBuf::from).collect()
+            } else {
+                warm.paths.clone()
+            };
+            if paths.is_empty() {
+                bail!("Please provide at least one path to hash");
+            }
+            let args = warm.to_scan_args(paths);
+            if args.background {
+                enter_background_mode(args.io_limit.unwrap_or(DEFAULT_BACKGROUND_IO_LIMIT_MB))?;
+            }
+            if let Some(budget) = args.max_duration {
+                set_deadline(budget);
+            }
+            #[cfg(feature = "video")]
+            set_include_videos(args.videos);
+            warm_cache(&args, config, cache)
+        }
+    }
+}
+
+/// `ingest`: a hot folder, once or every `--every`, or a list of URLs
+async fn ingest_images(
+    args: &IngestArgs,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<()> {
+    let [source, destination] = args.folders.as_slice() else {
+        bail!("Please provide the folder to ingest from and the library folder");
+    };
+    loop {
+        ingest_hot_folder(source, destination, args.duplicates, config, cache)?;
+        let Some(interval) = args.every else {
+            return Ok(());
+        };
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// `scan`: find and report the duplicates under the scan paths
+fn scan_paths(
+    global: &GlobalArgs,
+    mut args: ScanArgs,
+    effective_config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<()> {
+    let lang = effective_config.lang;
+    #[cfg(feature = "video")]
+    set_include_videos(args.videos);
+
+    if (args.dedupe_store.is_some() || args.restore_store) && global.no_cache {
+        bail!("Store links are recorded in the database so they can be undone, --dedupe-store and --restore-store can't be used with --no-cache");
+    }
+    if (args.dedupe_store.is_some() || args.restore_store) && is_dry_run() {
+        bail!("--dedupe-store and --restore-store can't be previewed, run them without --dry-run");
+    }
+    if args.restore_store {
+        return restore_store(&args, cache);
+    }
+
+    if args.paths.is_empty() {
+        if effective_config.scan_paths.is_empty() {
+            error!("Please provide at least one path to scan");
+            std::process::exit(1);
+        }
+        info!("No paths given, scanning the configured scan paths");
+        args.paths = effective_config
+            .scan_paths
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+    }
+
+    if args.import_takeout {
+        return import_takeout(&args, effective_config, cache);
+    }
+
+    if let Some(store) = &args.dedupe_store {
+        return dedupe_store(&args, store, effective_config, cache);
+    }
+
+    let threshold = effective_config.threshold;
+    let grid_size = effective_config.grid_size;
+
+    info!("Using grid size: {grid_size}x{grid_size}, threshold: {threshold}");
+    info!("{}", cache_status(global));
+    let references = ReferenceHashes::load(&args.reference_hashes)?;
+
+    info!("Scanning paths for images...");
+    let scan_started = Instant::now();
+    let (mut images, mut skipped) = scan_for_images_with_report(
+        &args.paths,
+        args.include_hidden,
+        args.debug,
+        args.skip_validation,
+        &effective_config.ignore_paths,
+        args.ebooks,
+    )?;
+
+    info!("{}", Message::FoundImages(images.len()).text(lang));
+    if let Some(order) = args.order {
+        sort_images(&mut images, order);
+    }
+    let Some(estimate) = estimate_and_confirm(&args, effective_config, cache, &images)? else {
+        return Ok(());
+    };
+    info!("Generating perceptual hashes...");
+
+    let started = Instant::now();
+    let thumbnails = thumbnail_dir(&args, cache);
+    let (hashes, hash_report) = generate_hashes_and_thumbnails(
+        &images,
+        grid_size,
+        cache,
+        args.debug,
+        thumbnails.as_deref(),
+    )?;
+    skipped.extend(hash_report.skipped);
+    let warnings = hash_report.warnings;
+    if hash_report.keep_tagged > 0 {
+        info!(
+            "{}",
+            Message::KeepTaggedFiles(hash_report.keep_tagged).text(lang)
+        );
+    }
+
+    // Groups from part of the library would be misleading, so a cut-short run only reports progress
+    if time_is_up() {
+        warnings.log_summary();
+        report_out_of_time(&args, hashes.len(), &skipped, lang);
+        return Ok(());
+    }
+    record_hashing_run(&args, cache, &estimate, started);
+
+    let report = &args.report;
+    if !args.thresholds.is_empty() {
+        info!(
+            "Finding duplicate sets for thresholds {:?}...",
+            args.thresholds
+        );
+        let sweep = find_duplicates_for_thresholds(&hashes, &args.thresholds);
+
+        info!("Threshold sweep results:");
+        for (sweep_threshold, duplicates) in sweep {
+            let duplicates = if report.same_dimensions() {
+                split_groups_by_dimensions(duplicates, cache)
+            } else {
+                if let Err(e) = cache.store_duplicate_groups(
+                    GroupingKey::new(sweep_threshold, grid_size),
+                    &duplicates,
+                ) {
+                    warn!(
+                        "Failed to cache duplicate groups for threshold {}: {}",
+                        sweep_threshold, e
+                    );
+                }
+                duplicates
+            };
+            let files_in_groups: usize = duplicates.iter().map(|g| g.len()).sum();
+            info!(
+                "  Threshold {sweep_threshold}: {} duplicate sets ({files_in_groups} files)",
+                duplicates.len()
+            );
+        }
+
+        warnings.log_summary();
+        skipped.log_summary();
+        let outcome = Message::SweepComplete {
+            images: hashes.len(),
+            thresholds: args.thresholds.len(),
+        };
+        notify_finished(&args, outcome, lang);
+        return Ok(());
+    }
+
+    info!("Finding duplicate sets...");
+    let mut duplicates = find_duplicates(&hashes, threshold);
+
+    if report.same_dimensions() {
+        // Filtered groups aren't cached, the cache holds the unfiltered groups per threshold
+        duplicates = split_groups_by_dimensions(duplicates, cache);
+    } else if let Err(e) =
+        cache.store_duplicate_groups(GroupingKey::new(threshold, grid_size), &duplicates)
+    {
+        warn!("Failed to cache duplicate groups: {}", e);
+    }
+    // Confirmed groups aren't cached either, the cache keeps the coarse ones
+    let mut resolutions = MatchResolutions::default();
+    if report.multi_resolution {
+        (duplicates, resolutions) = confirm_with_fine_hashes(duplicates, threshold, cache)?;
+    }
+    #[cfg(feature = "video")]
+    {
+        duplicates = confirm_with_frame_hashes(duplicates, threshold, grid_size, cache)?;
+    }
+    let mut edited = Vec::new();
+    if report.edited_versions {
+        (duplicates, edited) = separate_edited_versions(duplicates, cache);
+    }
+    // Manual merges and splits are applied on top, the cache keeps the computed groups
+    let mut duplicates = apply_overrides(duplicates, &cache.get_group_overrides()?);
+    if let Some(min_confidence) = report.min_confidence {
+        duplicates = retain_confidence(duplicates, min_confidence, threshold, cache);
+    }
+    let baseline = load_baseline(report)?;
+    let duplicates = new_findings(&baseline, duplicates, lang);
+    // Reference entries are only shown, stats and resolution stick to the scanned files
+    let matched = references.attach(duplicates.clone(), &hashes, threshold);
+
+    let keep = report.keep();
+    if matched.is_empty() {
+        info!("{}", Message::NoDuplicates.text(lang));
+    } else {
+        info!("{}", Message::FoundDuplicateSets(matched.len()).text(lang));
+        print_duplicate_groups(
+            &matched,
+            threshold,
+            cache,
+            &GroupMarks {
+                references: &references,
+                resolutions: &resolutions,
+                baseline: &baseline,
+            },
+            report.show_hashes,
+            lang,
+        );
+        duplicate_stats(&duplicates, keep, cache).log_summary(keep, lang);
+
+        // A policy that's safe to resolve resolves by its keep rule, behind the same confirmation
+        let policy_keep = report
+            .policy_settings()
+            .filter(|settings| settings.auto_resolve)
+            .map(|settings| settings.keep);
+        if let Some(keep) = args.auto_resolve.or(policy_keep) {
+            auto_resolve(
+                &duplicates,
+                keep,
+                effective_config.sidecars,
+                args.yes,
+                cache,
+                lang,
+            )?;
+        }
+        if let Some(GroupAction::Hardlink) = args.action {
+            hardlink_duplicates(&duplicates, keep, cache);
+        }
+    }
+    print_edited_versions(&edited, lang);
+    match args.output {
+        Some(OutputFormat::Sha256sum) => {
+            let paths: Vec<PathBuf> = match args.output_scope {
+                OutputScope::All => hashes.iter().map(|(path, _)| path.clone()).collect(),
+                OutputScope::Duplicates => matched
+                    .iter()
+                    .flatten()
+                    .filter(|path| !references.contains(path))
+                    .cloned()
+                    .collect(),
+            };
+            write_sha256sums(&paths, cache, &mut io::stdout().lock())?;
+        }
+        Some(OutputFormat::Json) => {
+            ResultsJson::new(&duplicates, threshold, cache).write(&mut io::stdout().lock())?;
+        }
+        None => {}
+    }
+    if let Some(results_db) = &report.results_db {
+        write_results_db(results_db, &duplicates, threshold, keep, cache)?;
+        info!("Wrote results to {}", results_db.display());
+    }
+
+    warnings.log_summary();
+    skipped.log_summary();
+    notify_finished(
+        &args,
+        Message::ScanComplete {
+            images: hashes.len(),
+            sets: duplicates.len(),
+        },
+        lang,
+    );
+    if let Some(command) = &effective_config.post_scan_hook {
+        let summary = ScanSummary {
+            scanned: images.len(),
+            hashed: hashes.len(),
+            cache_hits: hash_report.cache_hits,
+            cache_misses: hash_report.cache_misses,
+            skipped: skipped.total(),
+            ..ScanSummary::new(
+                ScanSource::Cli,
+                &args.paths,
+                threshold,
+                &duplicates,
+                scan_started.elapsed(),
+            )
+        };
+        run_post_scan_hook(command, &summary);
+    }
+
+    Ok(())
+}
+
+fn match_hashes(hash_list_paths: &[PathBuf], threshold: u32) -> Result<()> {
+    let mut lists = Vec::new();
+    for path in hash_list_paths {
+        let list = HashList::load(path)?;
+        info!(
+            "Loaded {} hashes from {}",
+            list.hashes.len(),
+            path.display()
+        );
+        lists.push(list);
+    }
+
+    info!("Using threshold: {threshold}");
+    let duplicates = match_hash_lists(&lists, threshold);
+
+    if duplicates.is_empty() {
+        info!("No duplicate images found in hash lists");
+    } else {
+        info!("Found {} duplicate sets in hash lists:", duplicates.len());
+        for (i, group) in duplicates.iter().enumerate() {
+            info!("  Group {}:", i + 1);
+            for path in group {
+                info!("    {}", path.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// List the matches between the cache and another cache database as they're found
+fn compare_cache(
+    cache: &HashCache,
+    other: &Path,
+    threshold: u32,
+    grid_size: u8,
+    lang: Lang,
+) -> Result<()> {
+    info!(
+        "Comparing with {} (threshold {threshold})...",
+        other.display()
+    );
+    let (mut identical, mut similar) = (0, 0);
+    cache.for_each_cross_cache_match(other, threshold, grid_size, |found| {
+        match found.distance {
+            None => identical += 1,
+            Some(_) => similar += 1,
+        }
+        let message = Message::CrossCacheMatch {
+            local: &found.local,
+            other: &found.other,
+            distance: found.distance,
+        };
+        info!("  {}", message.text(lang));
+    })?;
+    info!(
+        "{}",
+        Message::CrossCacheSummary { identical, similar }.text(lang)
+    );
+    Ok(())
+}
+
+/// Show the distances within one of the cached groups at `threshold`, `number` counting from 1
+/// as the groups are listed
+fn visualize_group(
+    cache: &HashCache,
+    number: usize,
+    threshold: u32,
+    grid_size: u8,
+    png: Option<&Path>,
+    lang: Lang,
+) -> Result<()> {
+    let duplicates = get_duplicates_from_cache(cache, threshold, grid_size, None, None)?;
+    let Some(group) = number
+        .checked_sub(1)
+        .and_then(|index| duplicates.get(index))
+    else {
+        bail!(
+            "There's no group {number}, the cache has {} duplicate groups at threshold {threshold}",
+            duplicates.len()
+        );
+    };
+    let grid = DistanceGrid::from_cache(group, cache);
+    match png {
+        Some(path) => {
+            grid.render_image(threshold)
+                .save(path)
+                .with_context(|| format!("Could not write {}", path.display()))?;
+            info!("Wrote the distance grid to {}", path.display());
+        }
+        None => print!(
+            "{}",
+            grid.render_text(threshold, io::stdout().is_terminal())
+        ),
+    }
+    let (within, pairs) = grid.pairs_within(threshold);
+    info!(
+        "{}",
+        Message::GroupSpread {
+            widest: grid.widest(),
+            within,
+            pairs,
+            threshold,
+        }
+        .text(lang)
+    );
+    Ok(())
+}
+
+/// List the files sharing each perceptual hash, straight from the cache
+fn list_shared_hashes(cache: &HashCache, lang: Lang) -> Result<()> {
+    let shared = cache.get_shared_hashes()?;
+    for hash in &shared {
+        let heading = Message::SharedHash {
+            hash: &hash.perceptual_hash,
+            files: hash.paths.len(),
+        };
+        info!("  {}", heading.text(lang));
+        for path in &hash.paths {
+            info!("    {}", path.display());
+        }
+    }
+    let summary = Message::SharedHashSummary {
+        hashes: shared.len(),
+        files: shared.iter().map(|hash| hash.paths.len()).sum(),
+    };
+    info!("{}", summary.text(lang));
+    Ok(())
+}
+
+/// List the cached hashes and duplicate groups by the version and settings that made them
+fn print_cache_provenance(cache: &HashCache, grid_size: u8) -> Result<()> {
+    let unknown = || "unknown".to_string();
+    info!(
+        "This version: {TOOL_VERSION}, {} {grid_size}x{grid_size}, invariant to {HASH_INVARIANCE}",
+        hash_algorithm().stored_name()
+    );
+
+    let provenance = cache.provenance()?;
+    info!("Cached hashes:");
+    for hashes in provenance.hashes {
+        let size = hashes
+            .hash_size
+            .map(|size| format!("{size}x{size}"))
+            .unwrap_or_else(unknown);
+        info!(
+            "  {:>8}  version {}, {} {}, invariant to {}",
+            hashes.hashes,
+            hashes.tool_version.unwrap_or_else(unknown),
+            hashes.algorithm.unwrap_or_else(unknown),
+            size,
+            hashes.invariance.unwrap_or_else(unknown)
+        );
+    }
+    info!("Cached duplicate groups:");
+    for groups in provenance.group_sets {
+        info!(
+            "  {:>8}  version {}, threshold {}, {}",
+            groups.groups,
+            groups.tool_version.unwrap_or_else(unknown),
+            groups.threshold,
+            groups.comparison
+        );
+    }
+    Ok(())
+}
+
+/// Time the scan stages on generated images and project the rates onto larger libraries
+fn selftest(threshold: u32, lang: Lang) -> Result<()> {
+    info!("Running self-test...");
+    let report = run_selftest(SelfTest::default(), threshold)?;
+    let throughput = Message::SelfTestThroughput {
+        images_per_second: report.images_per_second(),
+        comparisons_per_second: report.comparisons_per_second(),
+        threads: report.threads,
+    };
+    info!("{}", throughput.text(lang));
+    for images in ESTIMATE_LIBRARY_SIZES {
+        let (hashing, grouping) = report.estimate(images);
+        let estimate = Message::SelfTestEstimate {
+            images,
+            hashing: &format_duration(hashing),
+            grouping: &format_duration(grouping),
+        };
+        info!("{}", estimate.text(lang));
+    }
+    Ok(())
+}
+
+/// List the cached files within the threshold of one image, using its cached hash when there
+/// is one
+fn find_similar(
+    cache: &HashCache,
+    image: &Path,
+    threshold: u32,
+    grid_size: u8,
+    lang: Lang,
+) -> Result<()> {
+    let metadata =
+        get_file_metadata(image).with_context(|| format!("Could not read {}", image.display()))?;
+    let cached = cache.get_cached_hash(image, metadata.size, &metadata.sha256, grid_size)?;
+    let perceptual_hash = match cached {
+        Some(perceptual_hash) => perceptual_hash,
+        None => {
+            let hasher = hash_algorithm().hasher(grid_size)?;
+            generate_rotation_invariant_hash_safe(&hasher, &open_image(image)?)?.encode()?
+        }
+    };
+    info!(
+        "Looking up files similar to {} (threshold {threshold})...",
+        image.display()
+    );
+    let similar: Vec<(PathBuf, u32)> = cache
+        .find_similar(&perceptual_hash, threshold)?
+        .into_iter()
+        .filter(|(path, _)| path != image)
+        .collect();
+    for (path, distance) in &similar {
+        info!(
+            "  {}",
+            Message::SimilarFile {
+                path,
+                distance: *distance
+            }
+            .text(lang)
+        );
+    }
+    info!("{}", Message::SimilarFound(similar.len()).text(lang));
+    Ok(())
+}
+
+/// `--auto-resolve`: keep one file of each group by `keep` and delete the others, once the plan
+/// is confirmed
+fn auto_resolve(
+    duplicates: &[Vec<PathBuf>],
+    keep: KeepStrategy,
+    sidecars: SidecarMode,
+    yes: bool,
+    cache: &HashCache,
+    lang: Lang,
+) -> Result<()> {
+    info!("Resolving duplicate groups ({keep}):");
+    let resolutions: Vec<(usize, GroupResolution)> = duplicates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, group)| Some((i + 1, resolve_group(group, keep, sidecars, cache)?)))
+        .filter(|(_, resolution)| !resolution.delete.is_empty())
+        .collect();
+    print_resolutions(&resolutions);
+    if resolutions.is_empty() || is_dry_run() {
+        apply_resolutions(&resolutions, cache);
+        return Ok(());
+    }
+    let files = resolutions
+        .iter()
+        .map(|(_, resolution)| deleted_files(resolution).count())
+        .sum();
+    let size = format_size(
+        resolutions
+            .iter()
+            .map(|(_, resolution)| resolution.bytes_reclaimed)
+            .sum(),
+    );
+    if !yes {
+        // Scheduled and piped runs have nobody to answer
+        if !(io::stdin().is_terminal() && io::stderr().is_terminal()) {
+            bail!("{}", Message::DeletionNeedsYes.text(lang));
+        }
+        let question = Message::ConfirmDeletion { files, size: &size }.text(lang);
+        if !confirm(&mut io::stdin().lock(), &mut io::stderr(), &question)? {
+            info!("{}", Message::DeletionCancelled.text(lang));
+            return Ok(());
+        }
+    }
+    apply_resolutions(&resolutions, cache);
+    Ok(())
+}
+
+/// Every file a resolution deletes, with the Live Photo videos and sidecars going along
+fn deleted_files(resolution: &GroupResolution) -> impl Iterator<Item = &PathBuf> {
+    resolution
+        .delete
+        .iter()
+        .chain(&resolution.live_photo_videos)
+        .chain(&resolution.sidecars)
+}
+
+/// Print what each numbered group's resolution keeps and deletes
+fn print_resolutions(resolutions: &[(usize, GroupResolution)]) {
+    for (number, resolution) in resolutions {
+        if resolution.delete.is_empty() {
+            continue;
+        }
+        info!("  Group {number}: keep {}", resolution.keep.display());
+        for path in deleted_files(resolution) {
+            info!(
+                "    delete {} ({} bytes)",
+                path.display(),
+                size_on_disk(path)
+            );
+        }
+    }
+}
+
+/// Delete what each resolution gave up group by group, unless this is a dry run
+fn apply_resolutions(resolutions: &[(usize, GroupResolution)], cache: &HashCache) {
+    let dry_run = is_dry_run();
+    let (mut resolved, mut failed, mut bytes_reclaimed) = (0, 0, 0);
+    for (number, resolution) in resolutions {
+        if resolution.delete.is_empty() {
+            continue;
+        }
+        if dry_run {
+            resolved += 1;
+            bytes_reclaimed += resolution.bytes_reclaimed;
+            continue;
+        }
+        match apply_resolution(resolution, cache) {
+            Ok(()) => {
+                resolved += 1;
+                bytes_reclaimed += resolution.bytes_reclaimed;
+            }
+            Err(e) => {
+                warn!("  Group {number} not resolved: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+    if dry_run {
+        info!("Dry run, nothing was deleted: resolving {resolved} groups would reclaim {bytes_reclaimed} bytes");
+        return;
+    }
+    info!("Resolved {resolved} groups ({failed} failed), reclaiming {bytes_reclaimed} bytes");
+}
+
+/// Size of a file, 0 when it can't be read
+fn size_on_disk(path: &Path) -> u64 {
+    fs::metadata(extended_length_path(path)).map_or(0, |metadata| metadata.len())
+}
+
+/// `--action hardlink`: show which identical files become links to their kept copy, then link them
+fn hardlink_duplicates(duplicates: &[Vec<PathBuf>], keep: KeepStrategy, cache: &HashCache) {
+    let plan = plan_hardlinks(duplicates, keep, cache);
+    info!("Hard linking identical files ({keep}):");
+    for link in &plan.links {
+        info!(
+            "  link {} to {} ({} bytes)",
+            link.path.display(),
+            link.keeper.display(),
+            link.bytes_saved
+        );
+    }
+    for path in &plan.protected {
+        info!("  keep {} (tagged Keep)", path.display());
+    }
+    info!(
+        "Linking {} files ({} already linked) will save {} bytes",
+        plan.links.len(),
+        plan.already_linked,
+        plan.bytes_saved
+    );
+
+    if is_dry_run() {
+        info!("Dry run, nothing was linked");
+        return;
+    }
+    let report = apply_hardlinks(&plan);
+    for (path, reason) in &report.failed {
+        warn!("  not linked: {}: {}", path.display(), reason);
+    }
+    info!(
+        "Linked {} files ({} failed), saving {} bytes",
+        report.linked.len(),
+        report.failed.len(),
+        report.bytes_saved
+    );
+}
+
+fn print_edited_versions(edited: &[EditedVersion], lang: Lang) {
+    if edited.is_empty() {
+        return;
+    }
+    info!(
+        "{}",
+        Message::EditedVersionsHeading(edited.len()).text(lang)
+    );
+    for edit in edited {
+        let message = Message::EditedVersion {
+            edited: &edit.edited,
+            original: &edit.original,
+            taken_at: &edit.taken_at,
+            distance: edit.distance,
+        };
+        info!("  {}", message.text(lang));
+    }
+}
+
+/// What's noted alongside the listed groups and files
+struct GroupMarks<'a> {
+    references: &'a ReferenceHashes,
+    resolutions: &'a MatchResolutions,
+    baseline: &'a Baseline,
+}
+
+/// The baseline given with `--baseline`, empty without one
+fn load_baseline(args: &ReportArgs) -> Result<Baseline> {
+    match &args.baseline {
+        Some(path) => Baseline::load(path),
+        None => Ok(Baseline::default()),
+    }
+}
+
+/// Drop duplicate sets the baseline already had, saying how many
+fn new_findings(
+    baseline: &Baseline,
+    duplicates: Vec<Vec<PathBuf>>,
+    lang: Lang,
+) -> Vec<Vec<PathBuf>> {
+    let before = duplicates.len();
+    let duplicates = baseline.new_findings(duplicates);
+    if duplicates.len() < before {
+        info!(
+            "{}",
+            Message::BaselineKnown(before - duplicates.len()).text(lang)
+        );
+    }
+    duplicates
+}
+
+fn print_duplicate_groups(
+    duplicates: &[Vec<PathBuf>],
+    threshold: u32,
+    cache: &HashCache,
+    marks: &GroupMarks,
+    show_hashes: bool,
+    lang: Lang,
+) {
+    for (i, group) in duplicates.iter().enumerate() {
+        let linked = hard_linked(group);
+        let heading = Message::GroupHeading {
+            number: i + 1,
+            confidence: group_confidence(group, threshold, cache),
+        };
+        info!("  {}", heading.text(lang));
+        if let Some(resolution) = marks.resolutions.of_group(group) {
+            info!("    {}", Message::MatchedAt(resolution).text(lang));
+        }
+        for path in group {
+            if marks.references.contains(path) {
+                info!("    {}", Message::ReferenceEntry(path).text(lang));
+                continue;
+            }
+            let mut entry = if marks.baseline.is_empty() || marks.baseline.contains(path) {
+                path.display().to_string()
+            } else {
+                Message::NewEntry(path).text(lang)
+            };
+            if is_keep_tagged(&cache.get_file_tags(path).unwrap_or_default()) {
+                entry = format!("{entry} {}", Message::KeepTaggedMark.text(lang));
+            }
+            if linked.contains(path.as_path()) {
+                entry = format!("{entry} {}", Message::AlreadyLinkedMark.text(lang));
+            }
+            if !show_hashes {
+                info!("    {entry}");
+                continue;
+            }
+            match cache.get_cached_hash_details(path) {
+                Ok(Some((perceptual_hash, sha256))) => {
+                    info!("    {entry} (phash: {perceptual_hash}, sha256: {sha256})")
+                }
+                _ => info!("    {entry} (hashes not cached)"),
+            }
+        }
+    }
+}
+
+/// Compare cached files with their contents and exit with an error when any don't match
+fn verify_cached_content(sample: Option<f64>, cache: &HashCache, lang: Lang) -> Result<()> {
+    let report = verify_content(cache, sample)?;
+    for (path, problem) in &report.problems {
+        warn!("  {}", Message::ContentProblem { path, problem }.text(lang));
+    }
+    if report.unavailable > 0 {
+        info!(
+            "{}",
+            Message::ContentUnavailable(report.unavailable).text(lang)
+        );
+    }
+    let summary = Message::ContentVerified {
+        checked: report.checked,
+        problems: report.problems.len(),
+    };
+    if report.problems.is_empty() {
+        info!("{}", summary.text(lang));
+        Ok(())
+    } else {
+        error!("{}", summary.text(lang));
+        std::process::exit(1);
+    }
+}
+
+/// Progress of a run that `--max-duration` stopped early
+fn report_out_of_time(args: &ScanArgs, hashed: usize, skipped: &SkippedFiles, lang: Lang) {
+    let left = skipped.get(SkipReason::OutOfTime).len();
+    warn!(
+        "Time budget used up: {hashed} images hashed, {left} found but not reached, and discovery may have stopped early. Everything hashed is cached, run again to continue"
+    );
+    skipped.log_summary();
+    notify_finished(args, Message::ScanStopped(hashed), lang);
+}
+
+/// With `--notify`, tell the desktop that the run is over
+fn notify_finished(args: &ScanArgs, outcome: Message, lang: Lang) {
+    if args.notify {
+        notify(&Message::ScanFinished.text(lang), &outcome.text(lang));
+    }
+}
+
+/// `ingest-urls`: download the listed images, hash them into the cache and report which are
+/// already local
+async fn ingest_urls(
+    args: &IngestUrlsArgs,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<()> {
+    let limits = DownloadLimits {
+        concurrency: args.concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+        requests_per_second: args.rate_limit,
+    };
+    let urls = read_url_list(&args.urls)?;
+    let dir = cache
+        .download_dir()
+        .ok_or_else(|| anyhow!("Downloads are stored next to the database, which isn't on disk"))?;
+    info!(
+        "Downloading {} images to {} ({} at a time)...",
+        urls.len(),
+        dir.display(),
+        limits.concurrency
+    );
+
+    let downloads = download_images(&urls, &dir, limits).await;
+    let report = record_downloads(downloads, &dir, cache, config.threshold, config.grid_size)?;
+
+    for image in &report.images {
+        match image.local_matches.first() {
+            None => info!("  new: {}", image.url),
+            Some((path, distance)) => {
+                info!("  duplicate: {}", image.url);
+                info!("    matches {} (distance {distance})", path.display());
+                for (path, distance) in &image.local_matches[1..] {
+                    info!("    matches {} (distance {distance})", path.display());
+                }
+            }
+        }
+    }
+    for (url, reason) in &report.failed {
+        warn!("  failed: {url}: {reason}");
+    }
+    info!(
+        "Ingested {} images: {} new, {} already local, {} failed",
+        report.images.len(),
+        report.new_images().count(),
+        report.images.len() - report.new_images().count(),
+        report.failed.len()
+    );
+    Ok(())
+}
+
+/// One pass of `--ingest`
+fn ingest_hot_folder(
+    source: &Path,
+    destination: &Path,
+    action: DuplicateAction,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<()> {
+    let report = ingest_folder(source, destination, action, config, cache)?;
+    for (from, to) in &report.imported {
+        info!("  imported: {} -> {}", from.display(), to.display());
+    }
+    for duplicate in &report.duplicates {
+        match &duplicate.moved_to {
+            Some(to) => info!(
+                "  duplicate: {} -> {}",
+                duplicate.path.display(),
+                to.display()
+            ),
+            None if deletes_permanently() => {
+                info!("  duplicate, deleted: {}", duplicate.path.display())
+            }
+            None => info!("  duplicate, trashed: {}", duplicate.path.display()),
+        }
+        info!(
+            "    matches {} (distance {})",
+            duplicate.matches.display(),
+            duplicate.distance
+        );
+    }
+    for (path, reason) in &report.failed {
+        warn!("  failed: {}: {reason}", path.display());
+    }
+    if !report.imported.is_empty() || !report.duplicates.is_empty() || !report.failed.is_empty() {
+        info!(
+            "Ingested {} images: {} imported, {} duplicates, {} left in {}",
+            report.imported.len() + report.duplicates.len() + report.failed.len(),
+            report.imported.len(),
+            report.duplicates.len(),
+            report.failed.len(),
+            source.display()
+        );
+    }
+    if is_dry_run() && !report.duplicates.is_empty() {
+        let bytes: u64 = report
+            .duplicates
+            .iter()
+            .map(|duplicate| size_on_disk(&duplicate.path))
+            .sum();
+        info!("Dry run, nothing was moved or deleted: the duplicates take up {bytes} bytes");
+    } else if is_dry_run() {
+        info!("Dry run, nothing was moved or deleted");
+    }
+    Ok(())
+}
+
+/// `watch`: hash images under the watched paths as they change, listing the groups they join
+fn watch_folders(args: &WatchArgs, config: &ResolvedConfig, cache: &HashCache) -> Result<()> {
+    let paths = if args.paths.is_empty() {
+        config.scan_paths.iter().map(PathBuf::from).collect()
+    } else {
+        args.paths.clone()
+    };
+    if paths.is_empty() {
+        bail!("Please provide at least one path to watch");
+    }
+    info!(
+        "Watching {} for new and changed images, press Ctrl-C to stop",
+        paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    // The catch-up pass finds the groups that were there before watching started
+    let mut caught_up = false;
+    watch_paths(
+        &paths,
+        args.settle.unwrap_or(DEFAULT_SETTLE),
+        config,
+        cache,
+        |update: &WatchUpdate| {
+            if args.notify && caught_up && !update.groups.is_empty() {
+                notify(
+                    &Message::NewDuplicatesFound.text(config.lang),
+                    &Message::GroupsWithChangedImages(update.groups.len()).text(config.lang),
+                );
+            }
+            caught_up = true;
+            for path in &update.removed {
+                info!("  removed: {}", path.display());
+            }
+            print_duplicate_groups(
+                &update.groups,
+                config.threshold,
+                cache,
+                &GroupMarks {
+                    references: &ReferenceHashes::default(),
+                    resolutions: &MatchResolutions::default(),
+                    baseline: &Baseline::default(),
+                },
+                false,
+                config.lang,
+            );
+            if !update.skipped.is_empty() {
+                update.skipped.log_summary();
+            }
+        },
+    )
+}
+
+/// `review`: mark files of the cached duplicate groups in the terminal, then delete the marked ones
+fn review_groups(args: &ReviewArgs, config: &ResolvedConfig, cache: &HashCache) -> Result<()> {
+    if !io::stdout().is_terminal() {
+        bail!("Review needs a terminal, use matches to list the duplicate groups instead");
+    }
+    let duplicates =
+        get_duplicates_from_cache(cache, config.threshold, config.grid_size, None, None)?;
+    let groups: Vec<ReviewGroup> = duplicates
+        .iter()
+        .filter_map(|group| ReviewGroup::from_cache(group, config.threshold, cache))
+        .collect();
+    if groups.is_empty() {
+        info!(
+            "No duplicate groups are cached at threshold {}, run a scan first",
+            config.threshold
+        );
+        return Ok(());
+    }
+
+    let mut review = Review::new(groups);
+    if let Some(keep) = args.keep {
+        review.premark(keep);
+    }
+    let mut terminal = ratatui::try_init().context("Could not set up the terminal")?;
+    let apply = run_review(&mut terminal, &mut review);
+    ratatui::restore();
+    if !apply? {
+        info!("Left the review, nothing was deleted");
+        return Ok(());
+    }
+    info!("Deleting the files marked in the review:");
+    // Applying the review was the confirmation
+    let resolutions = review.resolutions(config.sidecars);
+    print_resolutions(&resolutions);
+    apply_resolutions(&resolutions, cache);
+    Ok(())
+}
+
+/// Paths for `serve --scan`: the ones given, or the configured scan paths
+fn background_scan_paths(paths: &[PathBuf], file_config: &Config) -> Result<Vec<PathBuf>> {
+    if !paths.is_empty() {
+        return Ok(paths.to_vec());
+    }
+    let scan_paths = with_settings(file_config)?
+        .with_overrides(None, None, None)
+        .scan_paths;
+    if scan_paths.is_empty() {
+        bail!("Please provide at least one path to scan");
+    }
+    info!("No paths given, scanning the configured scan paths");
+    Ok(scan_paths.iter().map(PathBuf::from).collect())
+}
+
+/// Link every image under the scan paths into the content-addressed store
+fn dedupe_store(
+    args: &ScanArgs,
+    store: &Path,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<()> {
+    let (images, _) = scan_for_images_with_report(
+        &args.paths,
+        args.include_hidden,
+        args.debug,
+        args.skip_validation,
+        &config.ignore_paths,
+        args.ebooks,
+    )?;
+    info!(
+        "Linking {} images into the store at {}...",
+        images.len(),
+        store.display()
+    );
+
+    let report = link_into_store(&images, store, cache, args.skip_when_full)?;
+    for (path, reason) in &report.failed {
+        warn!("  not linked: {}: {}", path.display(), reason);
+    }
+    info!(
+        "Linked {} images ({} already linked, {} failed), saving {} bytes",
+        report.linked,
+        report.already_linked,
+        report.failed.len(),
+        report.bytes_saved
+    );
+    Ok(())
+}
+
+/// Turn store links back into separate files
+fn restore_store(args: &ScanArgs, cache: &HashCache) -> Result<()> {
+    let under = args
+        .paths
+        .iter()
+        .map(std::path::absolute)
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let report = restore_from_store(cache, &under, args.skip_when_full)?;
+    for (path, reason) in &report.failed {
+        warn!("  not restored: {}: {}", path.display(), reason);
+    }
+    info!(
+        "Restored {} files ({} no longer linked, {} failed), removed {} unused files from the store",
+        report.restored,
+        report.detached,
+        report.failed.len(),
+        report.removed_from_store
+    );
+    Ok(())
+}
+
+/// Import Google Takeout sidecar metadata for every image under the scan paths
+fn import_takeout(args: &ScanArgs, config: &ResolvedConfig, cache: &HashCache) -> Result<()> {
+    let (images, _) = scan_for_images_with_report(
+        &args.paths,
+        args.include_hidden,
+        args.debug,
+        args.skip_validation,
+        &config.ignore_paths,
+        args.ebooks,
+    )?;
+    info!("Importing Takeout metadata for {} images...", images.len());
+
+    let result = import_takeout_metadata(&images, cache)?;
+    info!(
+        "Imported metadata for {} images, {} without a sidecar, {} invalid sidecars",
+        result.imported, result.without_sidecar, result.invalid
+    );
+    Ok(())
+}
+
+/// Remove `unset` and then apply `set` to the settings stored in the database
+fn update_settings(
+    global: &GlobalArgs,
+    cache: &HashCache,
+    unset: &[SettingKey],
+    set: &[(SettingKey, String)],
+) -> Result<()> {
+    if global.no_cache {
+        bail!("Settings are stored in the database, config set and config unset can't be used with --no-cache");
+    }
+    let mut settings = cache.get_settings()?;
+    for key in unset {
+        settings.unset(*key);
+    }
+    for (key, value) in set {
+        settings.set(*key, value)?;
+    }
+    cache.store_settings(&settings)?;
+    println!("Stored settings: {}", serde_json::to_string(&settings)?);
+    Ok(())
+}
+
+/// Where `--generate-thumbnails` stores thumbnails, None when it isn't set or there's no
+/// database to store them next to
+fn thumbnail_dir(args: &ScanArgs, cache: &HashCache) -> Option<PathBuf> {
+    if !args.generate_thumbnails {
+        return None;
+    }
+    let dir = cache.thumbnail_dir();
+    if dir.is_none() {
+        warn!("--generate-thumbnails needs the hash cache, not generating thumbnails");
+    }
+    dir
+}
+
+/// Log what a scan is about to hash and, when run in a terminal, ask before starting one larger
+/// than `confirm_scan_above`. None when the scan was cancelled
+fn estimate_and_confirm(
+    args: &ScanArgs,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+    images: &[PathBuf],
+) -> Result<Option<ScanEstimate>> {
+    let estimate = estimate_scan(images, &args.paths, cache)?;
+    if estimate.roots.len() > 1 {
+        for root in &estimate.roots {
+            match root.speed {
+                Some(speed) => info!(
+                    "  {}: {} files, {} at {}/s ({:.0} files/s)",
+                    root.root.display(),
+                    root.files,
+                    format_size(root.bytes),
+                    format_size(speed.bytes_per_second as u64),
+                    speed.files_per_second
+                ),
+                None => info!(
+                    "  {}: {} files, {}, no earlier runs of this folder alone",
+                    root.root.display(),
+                    root.files,
+                    format_size(root.bytes)
+                ),
+            }
+        }
+    }
+    let size = format_size(estimate.bytes);
+    let duration = estimate.duration.map(format_duration);
+    info!(
+        "{}",
+        Message::ScanEstimate {
+            files: estimate.files,
+            new_files: estimate.new_files,
+            size: &size,
+            duration: duration.as_deref(),
+        }
+        .text(config.lang)
+    );
+
+    let large = config
+        .confirm_scan_above
+        .is_some_and(|limit| estimate.bytes > limit);
+    // Scheduled and piped runs have nobody to answer, they go ahead
+    let interactive = io::stdin().is_terminal() && io::stderr().is_terminal();
+    if large
+        && interactive
+        && !args.yes
+        && !confirm_scan(&mut io::stdin().lock(), &mut io::stderr(), config.lang)?
+    {
+        info!("{}", Message::ScanCancelled.text(config.lang));
+        return Ok(None);
+    }
+    Ok(Some(estimate))
+}
+
+/// Store how fast this run hashed, for the estimates of later scans
+fn record_hashing_run(
+    args: &ScanArgs,
+    cache: &HashCache,
+    estimate: &ScanEstimate,
+    started: Instant,
+) {
+    // Throttled runs would make later estimates far too pessimistic
+    if args.background {
+        return;
+    }
+    if let Err(e) = record_scan_speed(cache, estimate, started.elapsed()) {
+        warn!("Failed to record how long hashing took: {e}");
+    }
+}
+
+/// Compute and store metadata and hashes for every image under the scan paths, nothing else
+fn warm_cache(args: &ScanArgs, config: &ResolvedConfig, cache: &HashCache) -> Result<()> {
+    let (mut images, mut skipped) = scan_for_images_with_report(
+        &args.paths,
+        args.include_hidden,
+        args.debug,
+        args.skip_validation,
+        &config.ignore_paths,
+        args.ebooks,
+    )?;
+    if let Some(order) = args.order {
+        sort_images(&mut images, order);
+    }
+    let Some(estimate) = estimate_and_confirm(args, config, cache, &images)? else {
+        return Ok(());
+    };
+
+    let started = Instant::now();
+    let thumbnails = thumbnail_dir(args, cache);
+    let (hashes, hash_report) = generate_hashes_and_thumbnails(
+        &images,
+        config.grid_size,
+        cache,
+        args.debug,
+        thumbnails.as_deref(),
+    )?;
+    skipped.extend(hash_report.skipped);
+    hash_report.warnings.log_summary();
+
+    if time_is_up() {
+        report_out_of_time(args, hashes.len(), &skipped, config.lang);
+        return Ok(());
+    }
+    record_hashing_run(args, cache, &estimate, started);
+    println!(
+        "Cache warmed: {} images hashed, {} skipped",
+        hashes.len(),
+        skipped.total()
+    );
+    notify_finished(args, Message::CacheWarmed(hashes.len()), config.lang);
     Ok(())
 }
@@ -0,0 +1,763 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::confidence::Confidence;
+use crate::multires::MatchResolution;
+use crate::resolver::KeepStrategy;
+use crate::verify::ContentProblem;
+
+/// Language for result summaries and API messages. Debug logging stays in English
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    /// English
+    #[default]
+    En,
+    /// German
+    De,
+}
+
+impl std::fmt::Display for Lang {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            Lang::En => "en",
+            Lang::De => "de",
+        };
+        write!(f, "{code}")
+    }
+}
+
+/// User-facing text that's shown in the chosen language
+#[derive(Debug, Clone, Copy)]
+pub enum Message<'a> {
+    FoundImages(usize),
+    ScanEstimate {
+        files: usize,
+        new_files: usize,
+        size: &'a str,
+        duration: Option<&'a str>,
+    },
+    ConfirmLargeScan,
+    ScanCancelled,
+    ConfirmDeletion {
+        files: usize,
+        size: &'a str,
+    },
+    DeletionNeedsYes,
+    DeletionCancelled,
+    NoDuplicates,
+    NoDuplicatesInCache,
+    FoundDuplicateSets(usize),
+    FoundDuplicateSetsInCache(usize),
+    GroupHeading {
+        number: usize,
+        confidence: Confidence,
+    },
+    DuplicateSummary {
+        duplicate_files: usize,
+        groups: usize,
+        redundant_files: usize,
+        reclaimable_bytes: u64,
+        strategy: KeepStrategy,
+    },
+    FormatSummary {
+        format: &'a str,
+        duplicate_files: usize,
+        redundant_files: usize,
+        reclaimable_bytes: u64,
+    },
+    EditedVersionsHeading(usize),
+    EditedVersion {
+        edited: &'a Path,
+        original: &'a Path,
+        taken_at: &'a str,
+        distance: u32,
+    },
+    ScanComplete {
+        images: usize,
+        sets: usize,
+    },
+    SweepComplete {
+        images: usize,
+        thresholds: usize,
+    },
+    Rehashed {
+        files: usize,
+        sets: usize,
+    },
+    ScanFinished,
+    ScanRunning,
+    ScanFailed(&'a str),
+    NewDuplicatesFound,
+    GroupsWithChangedImages(usize),
+    NoScanPaths,
+    CleanupFinished {
+        files: usize,
+        hashes: usize,
+    },
+    CompactionFinished,
+    JobInterrupted,
+    ScanStopped(usize),
+    CacheWarmed(usize),
+    SelfTestThroughput {
+        images_per_second: f64,
+        comparisons_per_second: f64,
+        threads: usize,
+    },
+    SelfTestEstimate {
+        images: usize,
+        hashing: &'a str,
+        grouping: &'a str,
+    },
+    ReferenceEntry(&'a Path),
+    NewEntry(&'a Path),
+    KeepTaggedMark,
+    AlreadyLinkedMark,
+    AlreadyLinkedFiles(usize),
+    KeepTaggedFiles(usize),
+    BaselineKnown(usize),
+    MatchedAt(MatchResolution),
+    ContentProblem {
+        path: &'a Path,
+        problem: &'a ContentProblem,
+    },
+    ContentUnavailable(usize),
+    CrossCacheMatch {
+        local: &'a Path,
+        other: &'a Path,
+        /// None for identical contents
+        distance: Option<u32>,
+    },
+    CrossCacheSummary {
+        identical: usize,
+        similar: usize,
+    },
+    SharedHash {
+        hash: &'a str,
+        files: usize,
+    },
+    SharedHashSummary {
+        hashes: usize,
+        files: usize,
+    },
+    GroupSpread {
+        widest: Option<u32>,
+        within: usize,
+        pairs: usize,
+        threshold: u32,
+    },
+    SimilarFile {
+        path: &'a Path,
+        distance: u32,
+    },
+    SimilarFound(usize),
+    ContentVerified {
+        checked: usize,
+        problems: usize,
+    },
+    PathNotAbsolute,
+    PathsNotAbsolute,
+    PathMissing(&'a Path),
+    OutsideServerRoots {
+        path: &'a Path,
+        roots: &'a str,
+    },
+    NoServerRoots,
+    FileNotFound,
+    NotAFile,
+    TaggedKeep,
+    FileDeleted,
+    FileAndSidecarsDeleted(usize),
+    FileTrashed,
+    FileAndSidecarsTrashed(usize),
+    DeleteFailed(&'a str),
+    DeleteDryRun {
+        files: usize,
+        bytes: u64,
+    },
+    CannotMergeWithItself,
+    OverrideSaved,
+    OverrideFailed(&'a str),
+    OverridesCleared(usize),
+    OverridesClearFailed(&'a str),
+    GroupPinned,
+    GroupUnpinned,
+    NoteSaved,
+    NoteRemoved,
+    AnnotationFailed(&'a str),
+    SettingsSaveFailed(&'a str),
+    NoStoredDistances(u32),
+}
+
+impl Message<'_> {
+    pub fn text(&self, lang: Lang) -> String {
+        match (*self, lang) {
+            (Message::FoundImages(count), Lang::En) => format!("Found {count} images"),
+            (Message::FoundImages(count), Lang::De) => format!("{count} Bilder gefunden"),
+            (
+                Message::ScanEstimate {
+                    files,
+                    new_files,
+                    size,
+                    duration,
+                },
+                Lang::En,
+            ) => match duration {
+                Some(duration) => format!(
+                    "About to hash {files} files ({size}, {new_files} new), projected to take {duration}"
+                ),
+                None => format!(
+                    "About to hash {files} files ({size}, {new_files} new), no earlier runs to estimate the time from"
+                ),
+            },
+            (
+                Message::ScanEstimate {
+                    files,
+                    new_files,
+                    size,
+                    duration,
+                },
+                Lang::De,
+            ) => match duration {
+                Some(duration) => format!(
+                    "{files} Dateien werden gehasht ({size}, {new_files} neu), voraussichtliche Dauer {duration}"
+                ),
+                None => format!(
+                    "{files} Dateien werden gehasht ({size}, {new_files} neu), keine früheren Läufe zur Zeitschätzung"
+                ),
+            },
+            (Message::ConfirmLargeScan, Lang::En) => {
+                "This is a large scan, start it? [y/N] ".to_string()
+            }
+            (Message::ConfirmLargeScan, Lang::De) => {
+                "Das ist eine große Suche, starten? [j/N] ".to_string()
+            }
+            (Message::ScanCancelled, Lang::En) => "Scan cancelled".to_string(),
+            (Message::ScanCancelled, Lang::De) => "Suche abgebrochen".to_string(),
+            (Message::ConfirmDeletion { files, size }, Lang::En) => {
+                format!("Delete these {files} files ({size})? [y/N] ")
+            }
+            (Message::ConfirmDeletion { files, size }, Lang::De) => {
+                format!("Diese {files} Dateien ({size}) löschen? [j/N] ")
+            }
+            (Message::DeletionNeedsYes, Lang::En) => {
+                "Nobody can confirm the deletion without a terminal, pass --yes to delete".to_string()
+            }
+            (Message::DeletionNeedsYes, Lang::De) => {
+                "Ohne Terminal kann niemand das Löschen bestätigen, zum Löschen --yes angeben"
+                    .to_string()
+            }
+            (Message::DeletionCancelled, Lang::En) => "Nothing was deleted".to_string(),
+            (Message::DeletionCancelled, Lang::De) => "Es wurde nichts gelöscht".to_string(),
+            (Message::NoDuplicates, Lang::En) => "No duplicate images found".to_string(),
+            (Message::NoDuplicates, Lang::De) => "Keine doppelten Bilder gefunden".to_string(),
+            (Message::NoDuplicatesInCache, Lang::En) => {
+                "No duplicate images found in cache".to_string()
+            }
+            (Message::NoDuplicatesInCache, Lang::De) => {
+                "Keine doppelten Bilder im Cache gefunden".to_string()
+            }
+            (Message::FoundDuplicateSets(count), Lang::En) => {
+                format!("Found {count} duplicate sets:")
+            }
+            (Message::FoundDuplicateSets(count), Lang::De) => {
+                format!("{count} Gruppen doppelter Bilder gefunden:")
+            }
+            (Message::FoundDuplicateSetsInCache(count), Lang::En) => {
+                format!("Found {count} duplicate sets in cache:")
+            }
+            (Message::FoundDuplicateSetsInCache(count), Lang::De) => {
+                format!("{count} Gruppen doppelter Bilder im Cache gefunden:")
+            }
+            (Message::GroupHeading { number, confidence }, Lang::En) => {
+                format!("Group {number} ({confidence}):")
+            }
+            (Message::GroupHeading { number, confidence }, Lang::De) => {
+                let confidence = match confidence {
+                    Confidence::Probable => "wahrscheinlich",
+                    Confidence::NearCertain => "nahezu sicher",
+                    Confidence::Exact => "identisch",
+                };
+                format!("Gruppe {number} ({confidence}):")
+            }
+            (
+                Message::DuplicateSummary {
+                    duplicate_files,
+                    groups,
+                    redundant_files,
+                    reclaimable_bytes,
+                    strategy,
+                },
+                Lang::En,
+            ) => format!(
+                "{duplicate_files} duplicate files in {groups} groups, {redundant_files} redundant \
+                 ({reclaimable_bytes} bytes reclaimable with {strategy})"
+            ),
+            (
+                Message::DuplicateSummary {
+                    duplicate_files,
+                    groups,
+                    redundant_files,
+                    reclaimable_bytes,
+                    strategy,
+                },
+                Lang::De,
+            ) => format!(
+                "{duplicate_files} doppelte Dateien in {groups} Gruppen, {redundant_files} \
+                 überflüssig ({reclaimable_bytes} Bytes freigebbar mit {strategy})"
+            ),
+            (
+                Message::FormatSummary {
+                    format,
+                    duplicate_files,
+                    redundant_files,
+                    reclaimable_bytes,
+                },
+                Lang::En,
+            ) => format!(
+                "{format}: {duplicate_files} duplicate files, {redundant_files} redundant, \
+                 {reclaimable_bytes} bytes reclaimable"
+            ),
+            (
+                Message::FormatSummary {
+                    format,
+                    duplicate_files,
+                    redundant_files,
+                    reclaimable_bytes,
+                },
+                Lang::De,
+            ) => format!(
+                "{format}: {duplicate_files} doppelte Dateien, {redundant_files} überflüssig, \
+                 {reclaimable_bytes} Bytes freigebbar"
+            ),
+            (Message::EditedVersionsHeading(count), Lang::En) => {
+                format!("Found {count} likely edited versions, not counted as duplicates:")
+            }
+            (Message::EditedVersionsHeading(count), Lang::De) => format!(
+                "{count} wahrscheinlich bearbeitete Versionen gefunden, nicht als Duplikate gezählt:"
+            ),
+            (
+                Message::EditedVersion {
+                    edited,
+                    original,
+                    taken_at,
+                    distance,
+                },
+                Lang::En,
+            ) => format!(
+                "{} is an edit of {} (taken {taken_at}, distance {distance})",
+                edited.display(),
+                original.display()
+            ),
+            (
+                Message::EditedVersion {
+                    edited,
+                    original,
+                    taken_at,
+                    distance,
+                },
+                Lang::De,
+            ) => format!(
+                "{} ist eine Bearbeitung von {} (aufgenommen {taken_at}, Abstand {distance})",
+                edited.display(),
+                original.display()
+            ),
+            (Message::ScanComplete { images, sets }, Lang::En) => {
+                format!("Scanned {images} images, found {sets} duplicate sets")
+            }
+            (Message::ScanComplete { images, sets }, Lang::De) => {
+                format!("{images} Bilder durchsucht, {sets} Gruppen doppelter Bilder gefunden")
+            }
+            (Message::Rehashed { files, sets }, Lang::En) => {
+                format!("Re-hashed {files} files, {sets} duplicate sets contain them")
+            }
+            (Message::Rehashed { files, sets }, Lang::De) => {
+                format!("{files} Dateien neu gehasht, {sets} Duplikatgruppen enthalten sie")
+            }
+            (Message::SweepComplete { images, thresholds }, Lang::En) => {
+                format!("Compared {images} images at {thresholds} thresholds")
+            }
+            (Message::SweepComplete { images, thresholds }, Lang::De) => {
+                format!("{images} Bilder mit {thresholds} Schwellenwerten verglichen")
+            }
+            (Message::ScanFinished, Lang::En) => "Scan finished".to_string(),
+            (Message::ScanFinished, Lang::De) => "Suche abgeschlossen".to_string(),
+            (Message::NewDuplicatesFound, Lang::En) => "New duplicates found".to_string(),
+            (Message::NewDuplicatesFound, Lang::De) => "Neue Duplikate gefunden".to_string(),
+            (Message::GroupsWithChangedImages(count), Lang::En) => {
+                format!("{count} duplicate sets include new or changed images")
+            }
+            (Message::GroupsWithChangedImages(count), Lang::De) => {
+                format!("{count} Duplikatgruppen enthalten neue oder geänderte Bilder")
+            }
+            (Message::ScanRunning, Lang::En) => "Scanning in the background".to_string(),
+            (Message::ScanRunning, Lang::De) => "Suche läuft im Hintergrund".to_string(),
+            (Message::ScanFailed(error), Lang::En) => format!("Scan failed: {error}"),
+            (Message::ScanFailed(error), Lang::De) => format!("Suche fehlgeschlagen: {error}"),
+            (Message::NoScanPaths, Lang::En) => {
+                "No scan_paths are configured for scheduled scans".to_string()
+            }
+            (Message::NoScanPaths, Lang::De) => {
+                "Für geplante Suchen sind keine scan_paths eingestellt".to_string()
+            }
+            (Message::CleanupFinished { files, hashes }, Lang::En) => {
+                format!("Removed {files} missing files and {hashes} orphaned hashes")
+            }
+            (Message::CleanupFinished { files, hashes }, Lang::De) => {
+                format!("{files} fehlende Dateien und {hashes} verwaiste Hashes entfernt")
+            }
+            (Message::CompactionFinished, Lang::En) => "Database compacted".to_string(),
+            (Message::CompactionFinished, Lang::De) => "Datenbank verdichtet".to_string(),
+            (Message::JobInterrupted, Lang::En) => {
+                "The server stopped before the job finished".to_string()
+            }
+            (Message::JobInterrupted, Lang::De) => {
+                "Der Server wurde vor dem Ende des Auftrags beendet".to_string()
+            }
+            (Message::ScanStopped(hashed), Lang::En) => {
+                format!("Time budget used up after hashing {hashed} images, run again to continue")
+            }
+            (Message::ScanStopped(hashed), Lang::De) => format!(
+                "Zeitbudget nach {hashed} gehashten Bildern aufgebraucht, zum Fortsetzen erneut starten"
+            ),
+            (Message::CacheWarmed(hashed), Lang::En) => format!("Cached hashes of {hashed} images"),
+            (Message::CacheWarmed(hashed), Lang::De) => {
+                format!("Hashes von {hashed} Bildern zwischengespeichert")
+            }
+            (
+                Message::SelfTestThroughput {
+                    images_per_second,
+                    comparisons_per_second,
+                    threads,
+                },
+                Lang::En,
+            ) => format!(
+                "{images_per_second:.0} images/s, {comparisons_per_second:.0} comparisons/s on {threads} threads"
+            ),
+            (
+                Message::SelfTestThroughput {
+                    images_per_second,
+                    comparisons_per_second,
+                    threads,
+                },
+                Lang::De,
+            ) => format!(
+                "{images_per_second:.0} Bilder/s, {comparisons_per_second:.0} Vergleiche/s mit {threads} Threads"
+            ),
+            (
+                Message::SelfTestEstimate {
+                    images,
+                    hashing,
+                    grouping,
+                },
+                Lang::En,
+            ) => format!("{images} new images: about {hashing} to hash, at most {grouping} to group"),
+            (
+                Message::SelfTestEstimate {
+                    images,
+                    hashing,
+                    grouping,
+                },
+                Lang::De,
+            ) => format!(
+                "{images} neue Bilder: etwa {hashing} zum Hashen, höchstens {grouping} zum Gruppieren"
+            ),
+            (Message::ReferenceEntry(path), Lang::En) => {
+                format!("{} (reference only)", path.display())
+            }
+            (Message::ReferenceEntry(path), Lang::De) => {
+                format!("{} (nur Referenz)", path.display())
+            }
+            (Message::NewEntry(path), Lang::En) => format!("{} (new)", path.display()),
+            (Message::NewEntry(path), Lang::De) => format!("{} (neu)", path.display()),
+            (Message::KeepTaggedMark, Lang::En) => "(tagged Keep)".to_string(),
+            (Message::KeepTaggedMark, Lang::De) => "(als Keep markiert)".to_string(),
+            (Message::AlreadyLinkedMark, Lang::En) => "(already linked)".to_string(),
+            (Message::AlreadyLinkedMark, Lang::De) => "(bereits verlinkt)".to_string(),
+            (Message::AlreadyLinkedFiles(files), Lang::En) => {
+                format!("{files} files are hard links to a kept file and free no space")
+            }
+            (Message::AlreadyLinkedFiles(files), Lang::De) => format!(
+                "{files} Dateien sind harte Links auf eine behaltene Datei und geben keinen Platz frei"
+            ),
+            (Message::KeepTaggedFiles(files), Lang::En) => {
+                format!("{files} files are tagged Keep and are never deleted")
+            }
+            (Message::KeepTaggedFiles(files), Lang::De) => {
+                format!("{files} Dateien sind als Keep markiert und werden nie gelöscht")
+            }
+            (Message::BaselineKnown(sets), Lang::En) => {
+                format!("{sets} duplicate sets already in the baseline left out")
+            }
+            (Message::BaselineKnown(sets), Lang::De) => {
+                format!("{sets} bereits in der Vergleichsbasis enthaltene Duplikatgruppen ausgelassen")
+            }
+            (Message::MatchedAt(MatchResolution::Fine), Lang::En) => {
+                format!("confirmed at {}", MatchResolution::Fine)
+            }
+            (Message::MatchedAt(MatchResolution::Fine), Lang::De) => {
+                format!("bestätigt bei {}", MatchResolution::Fine)
+            }
+            (Message::MatchedAt(MatchResolution::Coarse), Lang::En) => format!(
+                "matched at {} only, a file has no finer hash",
+                MatchResolution::Coarse
+            ),
+            (Message::MatchedAt(MatchResolution::Coarse), Lang::De) => format!(
+                "nur bei {} gefunden, einer Datei fehlt der feinere Hash",
+                MatchResolution::Coarse
+            ),
+            (Message::ContentProblem { path, problem }, Lang::En) => {
+                let problem = match problem {
+                    ContentProblem::Missing => "missing",
+                    ContentProblem::Unreadable => "could not be read",
+                    ContentProblem::Modified => "modified since it was hashed",
+                    ContentProblem::Corrupted => {
+                        "contents changed without a new modification time (possible bit rot)"
+                    }
+                };
+                format!("{}: {problem}", path.display())
+            }
+            (Message::ContentProblem { path, problem }, Lang::De) => {
+                let problem = match problem {
+                    ContentProblem::Missing => "fehlt",
+                    ContentProblem::Unreadable => "konnte nicht gelesen werden",
+                    ContentProblem::Modified => "seit dem Hashen geändert",
+                    ContentProblem::Corrupted => {
+                        "Inhalt ohne neue Änderungszeit verändert (möglicher Datenverfall)"
+                    }
+                };
+                format!("{}: {problem}", path.display())
+            }
+            (Message::ContentUnavailable(count), Lang::En) => {
+                format!("{count} files are in folders that aren't available and weren't checked")
+            }
+            (Message::ContentUnavailable(count), Lang::De) => {
+                format!("{count} Dateien liegen in nicht verfügbaren Ordnern und wurden nicht geprüft")
+            }
+            (Message::ContentVerified { checked, problems }, Lang::En) => {
+                format!("Verified {checked} files, {problems} don't match the cache")
+            }
+            (Message::ContentVerified { checked, problems }, Lang::De) => {
+                format!("{checked} Dateien geprüft, {problems} stimmen nicht mit dem Cache überein")
+            }
+            (Message::CrossCacheMatch { local, other, distance }, Lang::En) => match distance {
+                None => format!("{} is identical to {}", local.display(), other.display()),
+                Some(distance) => format!(
+                    "{} is similar to {} (distance {distance})",
+                    local.display(),
+                    other.display()
+                ),
+            },
+            (Message::CrossCacheMatch { local, other, distance }, Lang::De) => match distance {
+                None => format!("{} ist identisch mit {}", local.display(), other.display()),
+                Some(distance) => format!(
+                    "{} ähnelt {} (Abstand {distance})",
+                    local.display(),
+                    other.display()
+                ),
+            },
+            (Message::CrossCacheSummary { identical, similar }, Lang::En) => {
+                format!("{identical} identical and {similar} similar files in the other cache")
+            }
+            (Message::CrossCacheSummary { identical, similar }, Lang::De) => {
+                format!("{identical} identische und {similar} ähnliche Dateien im anderen Cache")
+            }
+            (Message::SharedHash { hash, files }, Lang::En) => {
+                format!("Hash {hash}: {files} files")
+            }
+            (Message::SharedHash { hash, files }, Lang::De) => {
+                format!("Hash {hash}: {files} Dateien")
+            }
+            (Message::SharedHashSummary { hashes, files }, Lang::En) => {
+                format!("{files} files share {hashes} identical hashes")
+            }
+            (Message::SharedHashSummary { hashes, files }, Lang::De) => {
+                format!("{files} Dateien teilen sich {hashes} identische Hashes")
+            }
+            (
+                Message::GroupSpread {
+                    widest,
+                    within,
+                    pairs,
+                    threshold,
+                },
+                Lang::En,
+            ) => match widest {
+                Some(widest) => format!(
+                    "{within} of {pairs} pairs are within threshold {threshold}, the widest is {widest} apart"
+                ),
+                None => format!(
+                    "{within} of {pairs} pairs are within threshold {threshold}, some hashes couldn't be compared"
+                ),
+            },
+            (
+                Message::GroupSpread {
+                    widest,
+                    within,
+                    pairs,
+                    threshold,
+                },
+                Lang::De,
+            ) => match widest {
+                Some(widest) => format!(
+                    "{within} von {pairs} Paaren liegen innerhalb von Schwellenwert {threshold}, das entfernteste hat Abstand {widest}"
+                ),
+                None => format!(
+                    "{within} von {pairs} Paaren liegen innerhalb von Schwellenwert {threshold}, manche Hashes ließen sich nicht vergleichen"
+                ),
+            },
+            (Message::SimilarFile { path, distance }, Lang::En) => {
+                format!("{} (distance {distance})", path.display())
+            }
+            (Message::SimilarFile { path, distance }, Lang::De) => {
+                format!("{} (Abstand {distance})", path.display())
+            }
+            (Message::SimilarFound(files), Lang::En) => format!("{files} similar cached files"),
+            (Message::SimilarFound(files), Lang::De) => {
+                format!("{files} ähnliche Dateien im Cache")
+            }
+            (Message::PathNotAbsolute, Lang::En) => "Path must be absolute".to_string(),
+            (Message::PathNotAbsolute, Lang::De) => "Der Pfad muss absolut sein".to_string(),
+            (Message::PathsNotAbsolute, Lang::En) => "Paths must be absolute".to_string(),
+            (Message::PathsNotAbsolute, Lang::De) => "Die Pfade müssen absolut sein".to_string(),
+            (Message::PathMissing(path), Lang::En) => {
+                format!("{} does not exist", path.display())
+            }
+            (Message::PathMissing(path), Lang::De) => {
+                format!("{} existiert nicht", path.display())
+            }
+            (Message::OutsideServerRoots { path, roots }, Lang::En) => format!(
+                "{} is outside the folders this server may access ({roots}). Add it to \
+                 server_roots in the config file to allow it",
+                path.display()
+            ),
+            (Message::OutsideServerRoots { path, roots }, Lang::De) => format!(
+                "{} liegt außerhalb der Ordner, auf die dieser Server zugreifen darf ({roots}). \
+                 Zum Freigeben in server_roots in der Konfigurationsdatei eintragen",
+                path.display()
+            ),
+            (Message::NoServerRoots, Lang::En) => "This server may not access any folders. Set \
+                 server_roots or scan_paths in the config file, or start it with serve --scan \
+                 <paths>"
+                .to_string(),
+            (Message::NoServerRoots, Lang::De) => "Dieser Server darf auf keine Ordner zugreifen. \
+                 server_roots oder scan_paths in der Konfigurationsdatei setzen oder ihn mit \
+                 serve --scan <Pfade> starten"
+                .to_string(),
+            (Message::FileNotFound, Lang::En) => "File does not exist".to_string(),
+            (Message::FileNotFound, Lang::De) => "Die Datei existiert nicht".to_string(),
+            (Message::NotAFile, Lang::En) => "Path is not a file".to_string(),
+            (Message::NotAFile, Lang::De) => "Der Pfad ist keine Datei".to_string(),
+            (Message::TaggedKeep, Lang::En) => {
+                "File is tagged Keep and can't be deleted".to_string()
+            }
+            (Message::TaggedKeep, Lang::De) => {
+                "Die Datei ist als Keep markiert und kann nicht gelöscht werden".to_string()
+            }
+            (Message::FileDeleted, Lang::En) => "File deleted successfully".to_string(),
+            (Message::FileDeleted, Lang::De) => "Datei gelöscht".to_string(),
+            (Message::FileAndSidecarsDeleted(count), Lang::En) => {
+                format!("File and {count} sidecar files deleted successfully")
+            }
+            (Message::FileAndSidecarsDeleted(count), Lang::De) => {
+                format!("Datei und {count} Begleitdateien gelöscht")
+            }
+            (Message::FileTrashed, Lang::En) => "File moved to the trash".to_string(),
+            (Message::FileTrashed, Lang::De) => "Datei in den Papierkorb verschoben".to_string(),
+            (Message::FileAndSidecarsTrashed(count), Lang::En) => {
+                format!("File and {count} sidecar files moved to the trash")
+            }
+            (Message::FileAndSidecarsTrashed(count), Lang::De) => {
+                format!("Datei und {count} Begleitdateien in den Papierkorb verschoben")
+            }
+            (Message::DeleteDryRun { files, bytes }, Lang::En) => {
+                format!("Dry run, nothing was deleted: {files} files ({bytes} bytes) would be deleted")
+            }
+            (Message::DeleteDryRun { files, bytes }, Lang::De) => {
+                format!("Probelauf, nichts wurde gelöscht: {files} Dateien ({bytes} Bytes) würden gelöscht")
+            }
+            (Message::DeleteFailed(error), Lang::En) => format!("Failed to delete file: {error}"),
+            (Message::DeleteFailed(error), Lang::De) => {
+                format!("Datei konnte nicht gelöscht werden: {error}")
+            }
+            (Message::CannotMergeWithItself, Lang::En) => {
+                "Cannot merge a file with itself".to_string()
+            }
+            (Message::CannotMergeWithItself, Lang::De) => {
+                "Eine Datei kann nicht mit sich selbst zusammengeführt werden".to_string()
+            }
+            (Message::OverrideSaved, Lang::En) => "Group override saved".to_string(),
+            (Message::OverrideSaved, Lang::De) => "Gruppenänderung gespeichert".to_string(),
+            (Message::OverrideFailed(error), Lang::En) => {
+                format!("Failed to save group override: {error}")
+            }
+            (Message::OverrideFailed(error), Lang::De) => {
+                format!("Gruppenänderung konnte nicht gespeichert werden: {error}")
+            }
+            (Message::OverridesCleared(count), Lang::En) => {
+                format!("Removed {count} group overrides")
+            }
+            (Message::OverridesCleared(count), Lang::De) => {
+                format!("{count} Gruppenänderungen entfernt")
+            }
+            (Message::OverridesClearFailed(error), Lang::En) => {
+                format!("Failed to clear group overrides: {error}")
+            }
+            (Message::OverridesClearFailed(error), Lang::De) => {
+                format!("Gruppenänderungen konnten nicht entfernt werden: {error}")
+            }
+            (Message::GroupPinned, Lang::En) => "Group pinned".to_string(),
+            (Message::GroupPinned, Lang::De) => "Gruppe angeheftet".to_string(),
+            (Message::GroupUnpinned, Lang::En) => "Group unpinned".to_string(),
+            (Message::GroupUnpinned, Lang::De) => "Gruppe nicht mehr angeheftet".to_string(),
+            (Message::NoteSaved, Lang::En) => "Note saved".to_string(),
+            (Message::NoteSaved, Lang::De) => "Notiz gespeichert".to_string(),
+            (Message::NoteRemoved, Lang::En) => "Note removed".to_string(),
+            (Message::NoteRemoved, Lang::De) => "Notiz entfernt".to_string(),
+            (Message::AnnotationFailed(error), Lang::En) => {
+                format!("Failed to save the group's pin or note: {error}")
+            }
+            (Message::AnnotationFailed(error), Lang::De) => {
+                format!("Anheften oder Notiz konnte nicht gespeichert werden: {error}")
+            }
+            (Message::SettingsSaveFailed(error), Lang::En) => {
+                format!("Failed to save settings: {error}")
+            }
+            (Message::SettingsSaveFailed(error), Lang::De) => {
+                format!("Die Einstellungen konnten nicht gespeichert werden: {error}")
+            }
+            (Message::NoStoredDistances(threshold), Lang::En) => format!(
+                "No stored distances reach threshold {threshold}, load the matches to compute them"
+            ),
+            (Message::NoStoredDistances(threshold), Lang::De) => format!(
+                "Keine gespeicherten Abstände reichen bis Schwellenwert {threshold}, Treffer laden, um sie zu berechnen"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_follow_the_language() {
+        let message = Message::ScanComplete {
+            images: 10,
+            sets: 2,
+        };
+        assert_eq!(
+            message.text(Lang::En),
+            "Scanned 10 images, found 2 duplicate sets"
+        );
+        assert_eq!(
+            message.text(Lang::De),
+            "10 Bilder durchsucht, 2 Gruppen doppelter Bilder gefunden"
+        );
+        assert_eq!(
+            serde_json::from_str::<Lang>("\"de\"").expect("lang should parse"),
+            Lang::De
+        );
+    }
+}
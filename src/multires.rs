@@ -0,0 +1,236 @@
+use anyhow::Result;
+use imghash::ImageHash;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug_span, info, warn};
+
+use crate::algorithm::{GridHasher, HashAlgorithm};
+use crate::background::throttle_io;
+use crate::cache::HashCache;
+use crate::extract::open_image;
+use crate::hasher::{find_duplicates, generate_rotation_invariant_hash_safe};
+use crate::paths::extended_length_path;
+
+/// Side of the hashes that scans compare, and that the coarse pass uses
+pub const COARSE_GRID: u8 = 8;
+/// Side of the hashes that confirm coarse matches. Four times the bits, so thresholds scale by four
+pub const FINE_GRID: u8 = 16;
+
+/// Which hash size a group's files were matched with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchResolution {
+    /// Only the coarse hashes matched, a fine hash was missing for some file
+    Coarse,
+    /// The coarse match was confirmed by the fine hashes
+    Fine,
+}
+
+impl MatchResolution {
+    pub fn grid(self) -> u8 {
+        match self {
+            MatchResolution::Coarse => COARSE_GRID,
+            MatchResolution::Fine => FINE_GRID,
+        }
+    }
+}
+
+impl fmt::Display for MatchResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let grid = self.grid();
+        write!(f, "{grid}x{grid}")
+    }
+}
+
+/// How each grouped file was matched, from a multi-resolution pass. Empty otherwise
+#[derive(Debug, Clone, Default)]
+pub struct MatchResolutions {
+    by_path: BTreeMap<PathBuf, MatchResolution>,
+}
+
+impl MatchResolutions {
+    /// The coarsest resolution any of the group's files were matched at, None for groups that
+    /// didn't come from a multi-resolution pass
+    pub fn of_group(&self, group: &[PathBuf]) -> Option<MatchResolution> {
+        group
+            .iter()
+            .filter_map(|path| self.by_path.get(path))
+            .min()
+            .copied()
+    }
+}
+
+/// Check coarse groups against fine hashes. Fine hashes are only computed for files in coarse
+/// groups, and each coarse group is regrouped by them with the threshold scaled to the fine
+/// hash size, so files that only looked alike at the coarse size drop out. Groups with a file
+/// that has no fine hash are kept as the coarse pass found them
+pub fn confirm_with_fine_hashes(
+    groups: Vec<Vec<PathBuf>>,
+    threshold: u32,
+    cache: &HashCache,
+) -> Result<(Vec<Vec<PathBuf>>, MatchResolutions)> {
+    let paths: Vec<PathBuf> = groups.iter().flatten().cloned().collect();
+    let fine_hashes = fine_hashes(&paths, cache)?;
+    let fine_threshold = threshold * u32::from(FINE_GRID) * u32::from(FINE_GRID)
+        / (u32::from(COARSE_GRID) * u32::from(COARSE_GRID));
+
+    let mut confirmed = Vec::new();
+    let mut resolutions = MatchResolutions::default();
+    let mut rejected = 0;
+    for group in groups {
+        let hashes: Vec<(PathBuf, ImageHash)> = group
+            .iter()
+            .filter_map(|path| Some((path.clone(), fine_hashes.get(path)?.clone())))
+            .collect();
+        if hashes.len() < group.len() {
+            for path in &group {
+                resolutions
+                    .by_path
+                    .insert(path.clone(), MatchResolution::Coarse);
+            }
+            confirmed.push(group);
+            continue;
+        }
+        let regrouped = find_duplicates(&hashes, fine_threshold);
+        rejected += group.len() - regrouped.iter().map(Vec::len).sum::<usize>();
+        for path in regrouped.iter().flatten() {
+            resolutions
+                .by_path
+                .insert(path.clone(), MatchResolution::Fine);
+        }
+        confirmed.extend(regrouped);
+    }
+    if rejected > 0 {
+        info!("{rejected} files matched at {COARSE_GRID}x{COARSE_GRID} but not at {FINE_GRID}x{FINE_GRID}");
+    }
+    // Regrouping splits groups, keep them sorted by their first path like the coarse ones
+    confirmed.sort();
+    Ok((confirmed, resolutions))
+}
+
+/// Fine hashes for these files, from the cache or computed and cached. Files that aren't in the
+/// cache or can't be decoded are left out
+fn fine_hashes(paths: &[PathBuf], cache: &HashCache) -> Result<BTreeMap<PathBuf, ImageHash>> {
+    let _span = debug_span!("fine_hashes", files = paths.len()).entered();
+    let mut hashes = BTreeMap::new();
+    let mut missing = Vec::new();
+    for path in paths {
+        let Some((_, sha256)) = cache.get_cached_hash_details(path)? else {
+            continue;
+        };
+        match cache.get_fine_hash(&sha256)? {
+            Some(encoded) => match ImageHash::decode(&encoded, FINE_GRID, FINE_GRID) {
+                Ok(hash) => {
+                    hashes.insert(path.clone(), hash);
+                }
+                Err(_) => missing.push((path, sha256)),
+            },
+            None => missing.push((path, sha256)),
+        }
+    }
+
+    if !missing.is_empty() {
+        info!(
+            "Generating {FINE_GRID}x{FINE_GRID} hashes for {} files...",
+            missing.len()
+        );
+    }
+    let hasher = HashAlgorithm::Perceptual.hasher(FINE_GRID)?;
+    let computed: Vec<_> = missing
+        .par_iter()
+        .filter_map(|(path, sha256)| match fine_hash(&hasher, path) {
+            Ok((hash, encoded)) => Some(((*path).clone(), sha256, hash, encoded)),
+            Err(e) => {
+                warn!(
+                    "Could not generate {FINE_GRID}x{FINE_GRID} hash for {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        })
+        .collect();
+    for (path, sha256, hash, encoded) in computed {
+        if let Err(e) = cache.store_fine_hash(sha256, &encoded) {
+            warn!("Could not cache fine hash for {}: {}", path.display(), e);
+        }
+        hashes.insert(path, hash);
+    }
+    Ok(hashes)
+}
+
+fn fine_hash(hasher: &GridHasher, path: &Path) -> Result<(ImageHash, String)> {
+    if let Ok(metadata) = fs::metadata(extended_length_path(path)) {
+        throttle_io(metadata.len());
+    }
+    let img = open_image(path)?;
+    let hash = generate_rotation_invariant_hash_safe(hasher, &img)?;
+    let encoded = hash.encode()?;
+    Ok((hash, encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::FileMetadata;
+    use image::{ImageBuffer, Rgb};
+    use tempfile::TempDir;
+
+    /// Mostly white with a dark block, like a screenshot of a sparse page
+    fn save_screenshot(dir: &Path, name: &str, block: (u32, u32), cache: &HashCache) -> PathBuf {
+        let img = ImageBuffer::from_fn(64, 64, |x, y| {
+            let inside = (block.0..block.0 + 4).contains(&x) && (block.1..block.1 + 4).contains(&y);
+            if inside {
+                Rgb([0u8, 0, 0])
+            } else {
+                Rgb([255u8, 255, 255])
+            }
+        });
+        let path = dir.join(name);
+        img.save(&path).expect("save image");
+        cache
+            .store_hash(&FileMetadata {
+                path: path.clone(),
+                size: 1,
+                sha256: name.to_string(),
+                perceptual_hash: "0000000000000000".to_string(),
+                width: Some(64),
+                height: Some(64),
+                dominant_color: None,
+                modified: None,
+            })
+            .expect("store hash");
+        path
+    }
+
+    #[test]
+    fn fine_hashes_split_coarse_groups() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let cache = HashCache::new_in_memory().expect("cache");
+        let a = save_screenshot(temp_dir.path(), "a.png", (8, 8), &cache);
+        let b = save_screenshot(temp_dir.path(), "b.png", (8, 8), &cache);
+        let c = save_screenshot(temp_dir.path(), "c.png", (40, 24), &cache);
+        let d = save_screenshot(temp_dir.path(), "d.png", (8, 8), &cache);
+        let missing = temp_dir.path().join("missing.png");
+
+        let (groups, resolutions) = confirm_with_fine_hashes(
+            vec![
+                vec![a.clone(), b.clone(), c],
+                vec![d.clone(), missing.clone()],
+            ],
+            0,
+            &cache,
+        )
+        .expect("confirm");
+
+        assert_eq!(groups, vec![vec![a.clone(), b], vec![d, missing]]);
+        assert_eq!(resolutions.of_group(&[a]), Some(MatchResolution::Fine));
+        assert_eq!(
+            resolutions.of_group(&groups[1]),
+            Some(MatchResolution::Coarse)
+        );
+        assert!(cache.get_fine_hash("a.png").expect("lookup").is_some());
+    }
+}
@@ -0,0 +1,26 @@
+use anyhow::{Context, Result};
+use notify_rust::Notification;
+use tracing::{debug, warn};
+
+/// Title of every notification
+const APP_NAME: &str = "Vibe Image Comparator";
+
+/// Show a desktop notification through the platform's notification service: D-Bus on Linux
+/// and the BSDs, Notification Center on macOS, toasts on Windows. Failing to notify logs a
+/// warning with the reason, it never fails a scan
+pub fn notify(summary: &str, body: &str) {
+    match send(summary, body) {
+        Ok(()) => debug!("Sent notification: {summary}"),
+        Err(e) => warn!("Could not show a desktop notification: {e:#}"),
+    }
+}
+
+fn send(summary: &str, body: &str) -> Result<()> {
+    Notification::new()
+        .appname(APP_NAME)
+        .summary(&format!("{APP_NAME}: {summary}"))
+        .body(body)
+        .show()
+        .map(drop)
+        .context("no notification service took it")
+}
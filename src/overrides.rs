@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A manual adjustment to the computed duplicate groups.
+/// Overrides are stored separately and replayed in order on top of every computed result.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GroupOverride {
+    /// Join the groups containing these two files
+    Merge { a: PathBuf, b: PathBuf },
+    /// Take a file out of whatever group it's in
+    Split { path: PathBuf },
+}
+
+fn group_of(groups: &[Vec<PathBuf>], path: &Path) -> Option<usize> {
+    groups
+        .iter()
+        .position(|group| group.iter().any(|p| p == path))
+}
+
+/// Apply overrides, in order, to algorithmically computed duplicate groups
+pub fn apply_overrides(
+    groups: Vec<Vec<PathBuf>>,
+    overrides: &[GroupOverride],
+) -> Vec<Vec<PathBuf>> {
+    let mut groups = groups;
+
+    // Groups are allowed to shrink to a single file while replaying, so that a later merge
+    // can still find the file left behind by a split
+    for group_override in overrides {
+        match group_override {
+            GroupOverride::Merge { a, b } if a != b => {
+                match (group_of(&groups, a), group_of(&groups, b)) {
+                    (Some(group_a), Some(group_b)) if group_a == group_b => {}
+                    (Some(group_a), Some(group_b)) => {
+                        let (keep, take) = (group_a.min(group_b), group_a.max(group_b));
+                        let taken = groups.remove(take);
+                        groups[keep].extend(taken);
+                    }
+                    (Some(group_a), None) => groups[group_a].push(b.clone()),
+                    (None, Some(group_b)) => groups[group_b].push(a.clone()),
+                    (None, None) => groups.push(vec![a.clone(), b.clone()]),
+                }
+            }
+            GroupOverride::Merge { .. } => {}
+            GroupOverride::Split { path } => {
+                if let Some(group) = group_of(&groups, path) {
+                    groups[group].retain(|p| p != path);
+                }
+            }
+        }
+    }
+
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn merge(a: &str, b: &str) -> GroupOverride {
+        GroupOverride::Merge {
+            a: PathBuf::from(a),
+            b: PathBuf::from(b),
+        }
+    }
+
+    fn split(path: &str) -> GroupOverride {
+        GroupOverride::Split {
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn merge_joins_two_groups() {
+        let groups = vec![paths(&["/a", "/b"]), paths(&["/c", "/d"])];
+        let result = apply_overrides(groups, &[merge("/d", "/a")]);
+        assert_eq!(result, vec![paths(&["/a", "/b", "/c", "/d"])]);
+    }
+
+    #[test]
+    fn split_removes_member_and_drops_singletons() {
+        let groups = vec![paths(&["/a", "/b", "/c"]), paths(&["/d", "/e"])];
+        let result = apply_overrides(groups, &[split("/b"), split("/e")]);
+        assert_eq!(result, vec![paths(&["/a", "/c"])]);
+    }
+
+    #[test]
+    fn overrides_replay_in_order() {
+        let groups = vec![paths(&["/a", "/b"])];
+        let result = apply_overrides(groups.clone(), &[split("/b"), merge("/a", "/c")]);
+        assert_eq!(result, vec![paths(&["/a", "/c"])]);
+
+        let result = apply_overrides(groups, &[merge("/a", "/c"), split("/a")]);
+        assert_eq!(result, vec![paths(&["/b", "/c"])]);
+    }
+}
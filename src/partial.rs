@@ -0,0 +1,233 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+use crate::scanner::expand_tilde;
+
+/// Names of files that are still being written: partial downloads of Firefox (`.part`),
+/// Chrome and Edge (`.crdownload`) and Safari (a `.download` folder holding the file under its
+/// final name), editor temp files and Office-style lock files
+pub const DEFAULT_TEMPORARY_PATTERNS: [&str; 7] = [
+    "*.part",
+    "*.partial",
+    "*.crdownload",
+    "*.download",
+    "*.tmp",
+    "~$*",
+    ".~lock.*",
+];
+
+/// Set from the config file's `temporary_file_patterns` and `debounce_seconds` at startup
+static FILTERS: OnceLock<PartialFileFilters> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PartialFileFilters {
+    patterns: Vec<String>,
+    /// Folders and how long files in them have to be left alone after changing, deepest first
+    debounce: Vec<(PathBuf, Duration)>,
+}
+
+impl Default for PartialFileFilters {
+    fn default() -> Self {
+        Self {
+            patterns: default_temporary_patterns(),
+            debounce: Vec::new(),
+        }
+    }
+}
+
+pub fn default_temporary_patterns() -> Vec<String> {
+    DEFAULT_TEMPORARY_PATTERNS
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect()
+}
+
+/// Use these filters for the rest of the process. `patterns` replaces the default temporary
+/// file patterns, `debounce` maps folders to seconds
+pub fn set_partial_file_filters(patterns: Option<&[String]>, debounce: &BTreeMap<String, u64>) {
+    let mut filters = PartialFileFilters::default();
+    if let Some(patterns) = patterns {
+        filters.patterns = patterns.to_vec();
+    }
+    filters.debounce = debounce
+        .iter()
+        .map(|(path, seconds)| (expand_tilde(path), Duration::from_secs(*seconds)))
+        .collect();
+    // The most specific folder wins
+    filters
+        .debounce
+        .sort_by_key(|(path, _)| Reverse(path.components().count()));
+    if FILTERS.set(filters).is_err() {
+        warn!("Partial file filters were already set");
+    }
+}
+
+/// Whether a file looks like it's still being written: its name or a folder it's in below the
+/// scanned `root` matches a temporary file pattern, or it changed more recently than its folder's
+/// debounce interval. Folders at or above `root` were chosen to be scanned, so `~/backup.tmp`
+/// can hold a library
+pub fn is_partial_file(path: &Path, root: &Path, modified: Option<SystemTime>) -> bool {
+    with_filters(|filters| filters.matches(path, root, modified, SystemTime::now()))
+}
+
+/// How long until a file that changed at `modified` is past its folder's debounce interval.
+/// None when it's already past, or its folder has no interval
+pub fn settles_in(path: &Path, modified: Option<SystemTime>) -> Option<Duration> {
+    with_filters(|filters| filters.settles_in(path, modified?, SystemTime::now()))
+}
+
+fn with_filters<T>(f: impl FnOnce(&PartialFileFilters) -> T) -> T {
+    match FILTERS.get() {
+        Some(filters) => f(filters),
+        None => f(&PartialFileFilters::default()),
+    }
+}
+
+impl PartialFileFilters {
+    fn matches(
+        &self,
+        path: &Path,
+        root: &Path,
+        modified: Option<SystemTime>,
+        now: SystemTime,
+    ) -> bool {
+        let below_root = path.strip_prefix(root).ok();
+        let temporary = below_root
+            .into_iter()
+            .flat_map(Path::components)
+            .map(|component| component.as_os_str())
+            .chain(path.file_name())
+            .any(|name| {
+                let name = name.to_string_lossy();
+                self.patterns
+                    .iter()
+                    .any(|pattern| wildcard_match(pattern, &name))
+            });
+        if temporary {
+            return true;
+        }
+
+        let interval = self.debounce_interval(path);
+        match (interval, modified) {
+            (Some(interval), Some(modified)) => now
+                .duration_since(modified)
+                .map_or(true, |age| age < interval),
+            _ => false,
+        }
+    }
+
+    fn settles_in(&self, path: &Path, modified: SystemTime, now: SystemTime) -> Option<Duration> {
+        let interval = self.debounce_interval(path)?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        interval
+            .checked_sub(age)
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// The deepest configured folder's interval
+    fn debounce_interval(&self, path: &Path) -> Option<Duration> {
+        self.debounce
+            .iter()
+            .find(|(folder, _)| path.starts_with(folder))
+            .map(|(_, interval)| *interval)
+    }
+}
+
+/// Match a file name against a pattern where `*` stands for any run of characters
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut remaining) = name.strip_prefix(prefix) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let suffix = parts.pop().unwrap_or_default();
+    for part in parts {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.len() >= suffix.len() && remaining.ends_with(suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temporary_names_and_fresh_files_are_partial() {
+        let filters = PartialFileFilters {
+            debounce: vec![
+                (
+                    PathBuf::from("/home/me/Downloads/slow"),
+                    Duration::from_secs(300),
+                ),
+                (PathBuf::from("/home/me/Downloads"), Duration::from_secs(30)),
+            ],
+            ..PartialFileFilters::default()
+        };
+        let now = SystemTime::now();
+        let ago = |seconds| Some(now - Duration::from_secs(seconds));
+        let root = Path::new("/");
+
+        assert!(filters.matches(Path::new("/photos/cat.jpg.part"), root, None, now));
+        assert!(filters.matches(
+            Path::new("/home/me/Downloads/cat.jpg.download/cat.jpg"),
+            root,
+            ago(3600),
+            now
+        ));
+        assert!(filters.matches(Path::new("/photos/~$cat.jpg"), root, None, now));
+        assert!(!filters.matches(Path::new("/photos/partial-eclipse.jpg"), root, None, now));
+
+        // Folders at or above the scanned one don't count, it was chosen to be scanned
+        let backup = Path::new("/mnt/backup.tmp/photos/cat.jpg");
+        assert!(!filters.matches(backup, Path::new("/mnt/backup.tmp"), None, now));
+        assert!(!filters.matches(backup, Path::new("/mnt/backup.tmp/photos"), None, now));
+        assert!(filters.matches(backup, Path::new("/mnt"), None, now));
+
+        // Only files in a folder with an interval wait, the deepest folder decides
+        assert!(filters.matches(Path::new("/home/me/Downloads/cat.jpg"), root, ago(10), now));
+        assert!(!filters.matches(Path::new("/home/me/Downloads/cat.jpg"), root, ago(60), now));
+        assert!(filters.matches(
+            Path::new("/home/me/Downloads/slow/cat.jpg"),
+            root,
+            ago(60),
+            now
+        ));
+        assert!(!filters.matches(Path::new("/photos/cat.jpg"), root, ago(0), now));
+
+        let fresh = now - Duration::from_secs(10);
+        assert_eq!(
+            filters.settles_in(Path::new("/home/me/Downloads/cat.jpg"), fresh, now),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            filters.settles_in(
+                Path::new("/home/me/Downloads/cat.jpg"),
+                now - Duration::from_secs(60),
+                now
+            ),
+            None
+        );
+        assert_eq!(
+            filters.settles_in(Path::new("/photos/cat.jpg"), fresh, now),
+            None
+        );
+    }
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        assert!(wildcard_match("*.part", "a.jpg.part"));
+        assert!(wildcard_match(".~lock.*", ".~lock.notes.odt#"));
+        assert!(wildcard_match("IMG_*_edit*.jpg", "IMG_0001_edit2.jpg"));
+        assert!(!wildcard_match("*.part", "a.partial"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+/// Windows verbatim prefix, disables path normalisation and the MAX_PATH limit
+#[cfg(windows)]
+const VERBATIM_PREFIX: &str = r"\\?\";
+/// Windows verbatim prefix for UNC network paths (`\\?\UNC\server\share\...`)
+#[cfg(windows)]
+const VERBATIM_UNC_PREFIX: &str = r"\\?\UNC\";
+/// Windows device namespace prefix (`\\.\`), never a regular file path
+#[cfg(windows)]
+const DEVICE_PREFIX: &str = r"\\.\";
+/// Paths this long fail on Windows unless they use the verbatim prefix
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Check whether a path is a UNC network path such as `\\server\share\photo.jpg`
+#[cfg(windows)]
+pub fn is_unc_path(path: &Path) -> bool {
+    let path_str = path.to_string_lossy();
+    if path_str.starts_with(VERBATIM_UNC_PREFIX) {
+        return true;
+    }
+    path_str.starts_with(r"\\")
+        && !path_str.starts_with(VERBATIM_PREFIX)
+        && !path_str.starts_with(DEVICE_PREFIX)
+        && path_str.len() > 2
+}
+
+/// Backslashes are part of file names outside Windows, so there are no UNC paths
+#[cfg(not(windows))]
+pub fn is_unc_path(_path: &Path) -> bool {
+    false
+}
+
+/// Check whether a path is absolute, treating UNC and verbatim paths as absolute but not
+/// device paths such as `\\.\PhysicalDrive0`
+#[cfg(windows)]
+pub fn is_absolute_path(path: &Path) -> bool {
+    let path_str = path.to_string_lossy();
+    if path_str.starts_with(DEVICE_PREFIX) {
+        return false;
+    }
+    path.is_absolute() || is_unc_path(path) || path_str.starts_with(VERBATIM_PREFIX)
+}
+
+/// Check whether a path is absolute. `\\server\share` is a relative file name here
+#[cfg(not(windows))]
+pub fn is_absolute_path(path: &Path) -> bool {
+    path.is_absolute()
+}
+
+/// Remove the `\\?\` verbatim prefix so the same file always maps to the same cache key
+/// (`\\?\C:\x` → `C:\x`, `\\?\UNC\server\share\x` → `\\server\share\x`)
+#[cfg(windows)]
+pub fn strip_verbatim_prefix(path: &Path) -> PathBuf {
+    let path_str = path.to_string_lossy();
+    if let Some(rest) = path_str.strip_prefix(VERBATIM_UNC_PREFIX) {
+        PathBuf::from(format!(r"\\{rest}"))
+    } else if let Some(rest) = path_str.strip_prefix(VERBATIM_PREFIX) {
+        PathBuf::from(rest)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Only Windows has verbatim paths, elsewhere the path is returned unchanged
+#[cfg(not(windows))]
+pub fn strip_verbatim_prefix(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// The string stored in the cache for a path
+pub fn path_key(path: &Path) -> String {
+    strip_verbatim_prefix(path).to_string_lossy().into_owned()
+}
+
+/// Whether the volume holding `path` ignores case in names, as macOS and Windows volumes usually
+/// do. Found by looking up the nearest existing name with letters in the other case; false when
+/// there's nothing to try
+pub fn is_case_insensitive(path: &Path) -> bool {
+    path.ancestors()
+        .filter(|ancestor| fs::symlink_metadata(extended_length_path(ancestor)).is_ok())
+        .find_map(|ancestor| {
+            let name = ancestor.file_name()?.to_str()?;
+            let swapped = swap_case(name);
+            (swapped != name).then(|| same_file(ancestor, &ancestor.with_file_name(swapped)))
+        })
+        .unwrap_or(false)
+}
+
+/// `path` with every name spelled the way it is on disk, so one file on a case-insensitive
+/// volume always gets one cache key. Paths on case-sensitive volumes are returned as they are,
+/// names that can't be found are kept as given
+pub fn on_disk_case(path: &Path) -> PathBuf {
+    if !is_case_insensitive(path) {
+        return path.to_path_buf();
+    }
+    let mut spelled = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                let on_disk = disk_name(&spelled, name).unwrap_or_else(|| name.to_os_string());
+                spelled.push(on_disk);
+            }
+            other => spelled.push(other),
+        }
+    }
+    spelled
+}
+
+/// The entry of `folder` that `name` refers to, an exact match first
+fn disk_name(folder: &Path, name: &OsStr) -> Option<OsString> {
+    let entries: Vec<OsString> = fs::read_dir(extended_length_path(folder))
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.file_name()))
+        .collect();
+    if entries.iter().any(|entry| entry == name) {
+        return Some(name.to_os_string());
+    }
+    let folded = name.to_string_lossy().to_lowercase();
+    entries
+        .into_iter()
+        .find(|entry| entry.to_string_lossy().to_lowercase() == folded)
+}
+
+fn swap_case(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_lowercase() {
+                c.to_uppercase().next().unwrap_or(c)
+            } else {
+                c.to_lowercase().next().unwrap_or(c)
+            }
+        })
+        .collect()
+}
+
+/// Whether `a` and `b` name one file: hard links to it, or two spellings of its name on a
+/// case-insensitive volume. A symbolic link is never the file it points at
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    let (Ok(a), Ok(b)) = (
+        fs::symlink_metadata(extended_length_path(a)),
+        fs::symlink_metadata(extended_length_path(b)),
+    ) else {
+        return false;
+    };
+    #[cfg(unix)]
+    return a.dev() == b.dev() && a.ino() == b.ino();
+    // Only Unix has file ids on stable Rust. Hard links share their metadata, so without them
+    // entries of the same kind, size and modification time are taken to be the same file
+    #[cfg(not(unix))]
+    return a.file_type() == b.file_type()
+        && a.len() == b.len()
+        && a.modified()
+            .ok()
+            .is_some_and(|time| b.modified().ok() == Some(time));
+}
+
+/// The device and inode of a file, equal for every hard link to it
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<(u64, u64)> {
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+/// Hard links can't be told apart from copies without inode numbers
+#[cfg(not(unix))]
+pub fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// The members of a group that are hard links to another member
+pub fn hard_linked(group: &[PathBuf]) -> BTreeSet<&Path> {
+    let mut links: BTreeMap<(u64, u64), Vec<&Path>> = BTreeMap::new();
+    for path in group {
+        if let Some(id) = file_id(path) {
+            links.entry(id).or_default().push(path);
+        }
+    }
+    links
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .flatten()
+        .collect()
+}
+
+/// Return a path that filesystem calls can use even when it exceeds MAX_PATH on Windows.
+/// On other platforms the path is returned unchanged.
+#[cfg(windows)]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    let path_str = path.to_string_lossy();
+    if path_str.len() < MAX_PATH || path_str.starts_with(VERBATIM_PREFIX) || !path.is_absolute() {
+        return path.to_path_buf();
+    }
+    if let Some(rest) = path_str.strip_prefix(r"\\") {
+        PathBuf::from(format!("{VERBATIM_UNC_PREFIX}{rest}"))
+    } else {
+        PathBuf::from(format!("{VERBATIM_PREFIX}{path_str}"))
+    }
+}
+
+/// Return a path that filesystem calls can use even when it exceeds MAX_PATH on Windows.
+/// On other platforms the path is returned unchanged.
+#[cfg(not(windows))]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn detects_unc_paths() {
+        assert!(is_unc_path(Path::new(r"\\nas\photos\a.jpg")));
+        assert!(is_unc_path(Path::new(r"\\?\UNC\nas\photos\a.jpg")));
+        assert!(!is_unc_path(Path::new(r"\\?\C:\photos\a.jpg")));
+        assert!(!is_unc_path(Path::new(r"\\.\PhysicalDrive0")));
+        assert!(!is_unc_path(Path::new("photos/a.jpg")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn unc_and_verbatim_paths_are_absolute() {
+        assert!(is_absolute_path(Path::new(r"\\nas\photos\a.jpg")));
+        assert!(is_absolute_path(Path::new(r"\\?\C:\photos\a.jpg")));
+        assert!(!is_absolute_path(Path::new(r"\\.\PhysicalDrive0")));
+        assert!(!is_absolute_path(Path::new("relative/a.jpg")));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn backslash_names_are_relative_outside_windows() {
+        assert!(!is_unc_path(Path::new(r"\\nas\photos\a.jpg")));
+        assert!(!is_absolute_path(Path::new(r"\\nas\photos\a.jpg")));
+        assert!(!is_absolute_path(Path::new(r"\\?\C:\photos\a.jpg")));
+        assert!(is_absolute_path(Path::new("/photos/a.jpg")));
+        assert_eq!(
+            path_key(Path::new(r"\\?\C:\photos\a.jpg")),
+            r"\\?\C:\photos\a.jpg"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn strips_verbatim_prefixes() {
+        assert_eq!(
+            path_key(Path::new(r"\\?\C:\photos\a.jpg")),
+            r"C:\photos\a.jpg"
+        );
+        assert_eq!(
+            path_key(Path::new(r"\\?\UNC\nas\photos\a.jpg")),
+            r"\\nas\photos\a.jpg"
+        );
+        assert_eq!(path_key(Path::new("/photos/a.jpg")), "/photos/a.jpg");
+    }
+
+    #[test]
+    fn case_is_only_folded_where_the_volume_ignores_it() {
+        let temp_dir = tempfile::TempDir::new().expect("temp dir");
+        let upper = temp_dir.path().join("Photo.JPG");
+        fs::write(&upper, "upper").expect("write file");
+        let lower = temp_dir.path().join("photo.jpg");
+
+        if is_case_insensitive(&upper) {
+            assert_eq!(on_disk_case(&lower), upper);
+        } else {
+            // Two files that only differ in case are both real
+            fs::write(&lower, "lower").expect("write file");
+            assert_eq!(on_disk_case(&lower), lower);
+            assert_eq!(on_disk_case(&upper), upper);
+        }
+        assert_eq!(swap_case("Photo_1.jpg"), "pHOTO_1.JPG");
+    }
+}
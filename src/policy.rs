@@ -0,0 +1,46 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::resolver::KeepStrategy;
+
+/// Built-in bundles of matching settings and keep rules for common cleanup jobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    /// Near-identical screenshots of the same size, keeping the newest
+    Screenshots,
+}
+
+/// The settings a policy stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicySettings {
+    pub threshold: u32,
+    pub same_dimensions: bool,
+    pub keep: KeepStrategy,
+    /// Groups found under this policy are safe to resolve without reviewing each one
+    pub auto_resolve: bool,
+}
+
+impl Policy {
+    pub fn settings(&self) -> PolicySettings {
+        match self {
+            // Screenshots of the same screen only differ in small areas like the clock, while
+            // anything resized is a different capture and shouldn't be matched
+            Policy::Screenshots => PolicySettings {
+                threshold: 3,
+                same_dimensions: true,
+                keep: KeepStrategy::KeepNewest,
+                auto_resolve: true,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Policy::Screenshots => "screenshots",
+        };
+        write!(f, "{name}")
+    }
+}
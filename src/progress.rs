@@ -0,0 +1,82 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use tracing::warn;
+
+const TEMPLATE: &str = "{prefix} [{bar:30}] {pos}/{len} ({per_sec}, {eta} left) {msg}";
+
+/// Every bar shown, so log lines can clear them while they're written
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// Set at startup, on when stdout is a terminal that isn't reserved for `--output`
+static SHOW_PROGRESS: OnceLock<bool> = OnceLock::new();
+
+/// Draw progress bars while hashing and matching for the rest of the process
+pub fn set_show_progress(show: bool) {
+    if SHOW_PROGRESS.set(show).is_err() {
+        warn!("Progress bars were already set");
+    }
+}
+
+pub fn progress_shown() -> bool {
+    SHOW_PROGRESS.get().copied().unwrap_or(false)
+}
+
+/// A bar counting `len` items, with their rate and the time left, labelled with `prefix`.
+/// Hidden unless progress is shown
+pub fn progress_bar(len: usize, prefix: &'static str) -> ProgressBar {
+    if !progress_shown() {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template(TEMPLATE)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+    bars().add(
+        ProgressBar::new(len as u64)
+            .with_style(style)
+            .with_prefix(prefix),
+    )
+}
+
+/// Writes a log line with the progress bars cleared, then draws them again below it. The bars
+/// are drawn on stderr and logs usually go to stdout, so without this a bar's next redraw
+/// overwrites the line. Buffers the line until it's dropped, the way the log layer's writers are
+pub struct LogWriter<W: Write> {
+    inner: W,
+    line: Vec<u8>,
+}
+
+impl<W: Write> LogWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            line: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for LogWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let line = std::mem::take(&mut self.line);
+        let inner = &mut self.inner;
+        bars().suspend(|| inner.write_all(&line).and_then(|()| inner.flush()))
+    }
+}
+
+impl<W: Write> Drop for LogWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::Result;
+use imghash::ImageHash;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::bktree::HashIndex;
+use crate::hasher::decode_hashes;
+use crate::hashlist::HashList;
+
+/// Hashes of a canonical archive kept elsewhere, loaded from exported hash lists. They're
+/// matched against the scanned files, but their files may not exist here and are never looked
+/// up, deleted or moved
+#[derive(Default)]
+pub struct ReferenceHashes {
+    paths: Vec<PathBuf>,
+    index: HashIndex<ImageHash>,
+    known: BTreeSet<PathBuf>,
+}
+
+impl ReferenceHashes {
+    pub fn load(hash_list_paths: &[PathBuf]) -> Result<Self> {
+        let mut lists = Vec::new();
+        for path in hash_list_paths {
+            let list = HashList::load(path)?;
+            info!(
+                "Loaded {} reference hashes from {}",
+                list.hashes.len(),
+                path.display()
+            );
+            lists.push(list);
+        }
+        Ok(Self::from_lists(&lists))
+    }
+
+    pub fn from_lists(lists: &[HashList]) -> Self {
+        let encoded = lists
+            .iter()
+            .flat_map(|list| &list.hashes)
+            .map(|entry| (entry.path.clone(), entry.perceptual_hash.clone()))
+            .collect();
+        let mut references = Self::default();
+        for (path, hash) in decode_hashes(encoded) {
+            references.index.insert(hash);
+            references.known.insert(path.clone());
+            references.paths.push(path);
+        }
+        references
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Whether a path is a reference entry. A scanned file at the same path counts as one too,
+    /// so it's never offered for deletion
+    pub fn contains(&self, path: &Path) -> bool {
+        self.known.contains(path)
+    }
+
+    /// Add the reference entries within `threshold` of a group's files to the group. Scanned
+    /// files that only match references form new groups with them, after the existing ones.
+    /// A reference entry can end up in several groups
+    pub fn attach(
+        &self,
+        groups: Vec<Vec<PathBuf>>,
+        hashes: &[(PathBuf, ImageHash)],
+        threshold: u32,
+    ) -> Vec<Vec<PathBuf>> {
+        if self.is_empty() {
+            return groups;
+        }
+        let mut matches: BTreeMap<&Path, BTreeSet<usize>> = BTreeMap::new();
+        for (path, hash) in hashes {
+            let found = self.index.find_within(hash, threshold);
+            if !found.is_empty() {
+                matches.insert(path, found.into_iter().map(|(id, _)| id).collect());
+            }
+        }
+
+        let mut attached = Vec::with_capacity(groups.len());
+        for mut group in groups {
+            let ids: BTreeSet<usize> = group
+                .iter()
+                .filter_map(|path| matches.remove(path.as_path()))
+                .flatten()
+                .collect();
+            group.extend(self.references(&ids, &group));
+            attached.push(group);
+        }
+        for (path, ids) in matches {
+            let mut group = vec![path.to_path_buf()];
+            group.extend(self.references(&ids, &group));
+            if group.len() > 1 {
+                attached.push(group);
+            }
+        }
+        attached
+    }
+
+    fn references(&self, ids: &BTreeSet<usize>, group: &[PathBuf]) -> Vec<PathBuf> {
+        ids.iter()
+            .map(|&id| self.paths[id].clone())
+            .filter(|path| !group.contains(path))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashlist::{HashListEntry, HASH_LIST_VERSION};
+
+    fn hash_list(entries: &[(&str, &str)]) -> HashList {
+        HashList {
+            version: HASH_LIST_VERSION,
+            hashes: entries
+                .iter()
+                .map(|(path, hash)| HashListEntry {
+                    path: PathBuf::from(path),
+                    perceptual_hash: hash.to_string(),
+                    sha256: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn references_join_matching_groups() {
+        let references = ReferenceHashes::from_lists(&[hash_list(&[
+            ("archive/a.jpg", "0000000000000001"),
+            ("archive/b.jpg", "ffffffffffffffff"),
+            ("archive/c.jpg", "00000000ffff0000"),
+        ])]);
+        let hashes = decode_hashes(vec![
+            (PathBuf::from("/new/a1.jpg"), "0000000000000000".to_string()),
+            (PathBuf::from("/new/a2.jpg"), "0000000000000003".to_string()),
+            (PathBuf::from("/new/b.jpg"), "fffffffffffffff7".to_string()),
+            (PathBuf::from("/new/d.jpg"), "0f0f0f0f0f0f0f0f".to_string()),
+        ]);
+        let groups = vec![vec![
+            PathBuf::from("/new/a1.jpg"),
+            PathBuf::from("/new/a2.jpg"),
+        ]];
+
+        assert_eq!(
+            references.attach(groups, &hashes, 2),
+            vec![
+                vec![
+                    PathBuf::from("/new/a1.jpg"),
+                    PathBuf::from("/new/a2.jpg"),
+                    PathBuf::from("archive/a.jpg"),
+                ],
+                vec![PathBuf::from("/new/b.jpg"), PathBuf::from("archive/b.jpg")],
+            ]
+        );
+        assert!(references.contains(Path::new("archive/c.jpg")));
+        assert!(!references.contains(Path::new("/new/d.jpg")));
+    }
+}
@@ -0,0 +1,246 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Number of example paths listed per reason in the CLI summary
+const SUMMARY_EXAMPLES_PER_REASON: usize = 10;
+/// Number of example warnings kept per kind, later ones are only counted
+const WARNING_EXAMPLES_PER_KIND: usize = 10;
+
+/// Why a file was left out of a scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Matched a configured ignore path
+    Ignored,
+    /// Could not be read (broken symlink, permissions, vanished during the scan)
+    Inaccessible,
+    /// Contents don't match the file extension's magic number
+    ValidationFailed,
+    /// Passed validation but the image couldn't be decoded or hashed
+    DecodeError,
+    /// Left for the next run because the `--max-duration` budget ran out
+    OutOfTime,
+    /// A partial download or temp file, or changed within its folder's debounce interval
+    StillWriting,
+}
+
+impl SkipReason {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SkipReason::Ignored => "ignored by configuration",
+            SkipReason::Inaccessible => "inaccessible",
+            SkipReason::ValidationFailed => "failed format validation",
+            SkipReason::DecodeError => "could not be decoded",
+            SkipReason::OutOfTime => "not reached before --max-duration ran out",
+            SkipReason::StillWriting => "still being written",
+        }
+    }
+}
+
+/// Files skipped during a scan, grouped by reason
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct SkippedFiles {
+    by_reason: BTreeMap<SkipReason, Vec<PathBuf>>,
+}
+
+impl SkippedFiles {
+    pub fn record(&mut self, reason: SkipReason, path: PathBuf) {
+        self.by_reason.entry(reason).or_default().push(path);
+    }
+
+    pub fn extend(&mut self, other: SkippedFiles) {
+        for (reason, paths) in other.by_reason {
+            self.by_reason.entry(reason).or_default().extend(paths);
+        }
+    }
+
+    pub fn get(&self, reason: SkipReason) -> &[PathBuf] {
+        self.by_reason
+            .get(&reason)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SkipReason, &[PathBuf])> {
+        self.by_reason
+            .iter()
+            .map(|(reason, paths)| (*reason, paths.as_slice()))
+    }
+
+    pub fn total(&self) -> usize {
+        self.by_reason.values().map(Vec::len).sum()
+    }
+
+    /// How many files were skipped for each reason
+    pub fn counts(&self) -> BTreeMap<SkipReason, usize> {
+        self.iter()
+            .map(|(reason, paths)| (reason, paths.len()))
+            .collect()
+    }
+
+    /// The first `max_per_reason` files of each reason, see `counts` for how many there were
+    pub fn capped(&self, max_per_reason: usize) -> SkippedFiles {
+        let by_reason = self
+            .iter()
+            .map(|(reason, paths)| (reason, paths.iter().take(max_per_reason).cloned().collect()))
+            .collect();
+        SkippedFiles { by_reason }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// Log the end-of-scan summary of skipped files
+    pub fn log_summary(&self) {
+        if self.is_empty() {
+            info!("No files were skipped");
+            return;
+        }
+
+        info!("Skipped {} files:", self.total());
+        for (reason, paths) in self.iter() {
+            info!("  {} ({}):", reason.description(), paths.len());
+            for path in paths.iter().take(SUMMARY_EXAMPLES_PER_REASON) {
+                info!("    {}", path.display());
+            }
+            if paths.len() > SUMMARY_EXAMPLES_PER_REASON {
+                info!(
+                    "    ... and {} more",
+                    paths.len() - SUMMARY_EXAMPLES_PER_REASON
+                );
+            }
+        }
+    }
+}
+
+/// What went wrong with a file while hashing. Some also skip the file, others only lose a
+/// thumbnail or a cache entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Its size or contents couldn't be read
+    Metadata,
+    /// The cached hash didn't decode, so the file was hashed again
+    InvalidCachedHash,
+    /// The image couldn't be decoded
+    Decode,
+    /// The decoded image couldn't be hashed
+    Hash,
+    /// Its thumbnail couldn't be saved
+    Thumbnail,
+    /// Its cache entry couldn't be written or removed
+    Cache,
+}
+
+impl WarningKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarningKind::Metadata => "metadata",
+            WarningKind::InvalidCachedHash => "invalid_cached_hash",
+            WarningKind::Decode => "decode",
+            WarningKind::Hash => "hash",
+            WarningKind::Thumbnail => "thumbnail",
+            WarningKind::Cache => "cache",
+        }
+    }
+}
+
+/// A warning about one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileWarning {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+/// How often a kind of warning came up, with the first few
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WarningSummary {
+    pub count: usize,
+    pub examples: Vec<FileWarning>,
+}
+
+/// Warnings about individual files during a scan, grouped by kind. Each is also logged with
+/// `path` and `kind` fields, so they go wherever tracing output goes rather than to stdout
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct FileWarnings {
+    by_kind: BTreeMap<WarningKind, WarningSummary>,
+}
+
+impl FileWarnings {
+    /// Log a warning about a file and count it
+    pub fn record(&mut self, kind: WarningKind, path: &Path, message: impl Display) {
+        let message = message.to_string();
+        warn!(path = %path.display(), kind = kind.as_str(), "{message}");
+        let summary = self.by_kind.entry(kind).or_default();
+        summary.count += 1;
+        if summary.examples.len() < WARNING_EXAMPLES_PER_KIND {
+            summary.examples.push(FileWarning {
+                path: path.to_path_buf(),
+                message,
+            });
+        }
+    }
+
+    pub fn extend(&mut self, other: FileWarnings) {
+        for (kind, other) in other.by_kind {
+            let summary = self.by_kind.entry(kind).or_default();
+            summary.count += other.count;
+            let room = WARNING_EXAMPLES_PER_KIND.saturating_sub(summary.examples.len());
+            summary
+                .examples
+                .extend(other.examples.into_iter().take(room));
+        }
+    }
+
+    pub fn get(&self, kind: WarningKind) -> Option<&WarningSummary> {
+        self.by_kind.get(&kind)
+    }
+
+    pub fn total(&self) -> usize {
+        self.by_kind.values().map(|summary| summary.count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// Log the end-of-scan summary of warnings, nothing when there were none
+    pub fn log_summary(&self) {
+        if self.is_empty() {
+            return;
+        }
+
+        info!("{} warnings about files:", self.total());
+        for (kind, summary) in &self.by_kind {
+            info!("  {} ({}):", kind.as_str(), summary.count);
+            for example in &summary.examples {
+                info!("    {}: {}", example.path.display(), example.message);
+            }
+            if summary.count > summary.examples.len() {
+                info!(
+                    "    ... and {} more",
+                    summary.count - summary.examples.len()
+                );
+            }
+        }
+    }
+}
+
+/// Files a hashing pass skipped and the warnings it ran into
+#[derive(Debug, Clone, Default)]
+pub struct HashReport {
+    pub skipped: SkippedFiles,
+    pub warnings: FileWarnings,
+    /// Files whose cached hash was still valid
+    pub cache_hits: usize,
+    /// Files that were decoded and hashed
+    pub cache_misses: usize,
+    /// Files tagged Keep, which no keep rule deletes
+    pub keep_tagged: usize,
+}
@@ -0,0 +1,380 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cache::HashCache;
+use crate::journal::{apply_file_operations, update_cache, FileOperation};
+use crate::livephoto::live_photo_video;
+use crate::paths::{extended_length_path, file_id};
+use crate::sidecar::{affected_sidecars, unseen_paths, SidecarMode};
+use crate::tags::{file_tags, is_keep_tagged};
+
+/// How to pick the single file to keep from a group of duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeepStrategy {
+    /// Keep the largest file on disk
+    KeepLargest,
+    /// Keep the image with the most pixels
+    KeepHighestRes,
+    /// Keep the file with the oldest modification time
+    KeepOldest,
+    /// Keep the file with the newest modification time
+    KeepNewest,
+    /// Keep the file with the shortest path
+    KeepShortestPath,
+}
+
+impl std::fmt::Display for KeepStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            KeepStrategy::KeepLargest => "keep-largest",
+            KeepStrategy::KeepHighestRes => "keep-highest-res",
+            KeepStrategy::KeepOldest => "keep-oldest",
+            KeepStrategy::KeepNewest => "keep-newest",
+            KeepStrategy::KeepShortestPath => "keep-shortest-path",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The facts about a file that keep strategies are evaluated against
+#[derive(Debug, Clone)]
+pub struct FileCandidate {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub dimensions: Option<(u32, u32)>,
+    /// Tagged Keep, see `tags::KEEP_TAG`
+    pub keep_tagged: bool,
+    /// Device and inode, shared by hard links to the same file
+    pub file_id: Option<(u64, u64)>,
+}
+
+impl FileCandidate {
+    /// Gather candidate details from the filesystem and cache, returns None for missing files.
+    /// An imported capture time (e.g. from Google Takeout) is used instead of the modification
+    /// time, since exports and copies reset it. Tags are read from the file rather than the
+    /// cache, so one set after the last scan still counts.
+    pub fn from_path(path: &Path, cache: &HashCache) -> Option<Self> {
+        let metadata = fs::metadata(extended_length_path(path)).ok()?;
+        let dimensions = cache.get_cached_dimensions(path).ok().flatten();
+        let taken_at = cache
+            .get_photo_metadata(path)
+            .ok()
+            .flatten()
+            .and_then(|photo| photo.taken_at)
+            .and_then(|secs| u64::try_from(secs).ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        Some(Self {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: taken_at.or_else(|| metadata.modified().ok()),
+            dimensions,
+            keep_tagged: is_keep_tagged(&file_tags(path)),
+            file_id: file_id(path),
+        })
+    }
+
+    fn pixel_count(&self) -> u64 {
+        self.dimensions
+            .map(|(width, height)| width as u64 * height as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// What a strategy decided for one duplicate group
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupResolution {
+    pub keep: PathBuf,
+    pub delete: Vec<PathBuf>,
+    /// Other files tagged Keep, kept along with `keep`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub protected: Vec<PathBuf>,
+    /// Hard links to a kept file, deleting them wouldn't free anything
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub already_linked: Vec<PathBuf>,
+    /// Sidecar files deleted along with `delete`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sidecars: Vec<PathBuf>,
+    /// Motion parts of Live Photos in `delete`, they always go with their still
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub live_photo_videos: Vec<PathBuf>,
+    pub bytes_reclaimed: u64,
+}
+
+/// Pick the index of the file to keep. Files tagged Keep come first whatever the strategy, ties
+/// are broken by path so the result is deterministic.
+pub fn select_keeper(candidates: &[FileCandidate], strategy: KeepStrategy) -> Option<usize> {
+    candidates
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            let tagged = b.keep_tagged.cmp(&a.keep_tagged);
+            let preference = match strategy {
+                KeepStrategy::KeepLargest => b.size.cmp(&a.size),
+                KeepStrategy::KeepHighestRes => b
+                    .pixel_count()
+                    .cmp(&a.pixel_count())
+                    .then(b.size.cmp(&a.size)),
+                // Files without a modification time sort last for both time based strategies
+                KeepStrategy::KeepOldest => match (a.modified, b.modified) {
+                    (Some(a_time), Some(b_time)) => a_time.cmp(&b_time),
+                    (a_time, b_time) => b_time.is_some().cmp(&a_time.is_some()),
+                },
+                KeepStrategy::KeepNewest => match (a.modified, b.modified) {
+                    (Some(a_time), Some(b_time)) => b_time.cmp(&a_time),
+                    (a_time, b_time) => b_time.is_some().cmp(&a_time.is_some()),
+                },
+                KeepStrategy::KeepShortestPath => {
+                    a.path.as_os_str().len().cmp(&b.path.as_os_str().len())
+                }
+            };
+            tagged.then(preference).then_with(|| a.path.cmp(&b.path))
+        })
+        .map(|(index, _)| index)
+}
+
+/// Decide which files of a group to keep and delete, without touching the filesystem.
+/// Missing files are left out; groups with fewer than two existing files need no action.
+/// Sidecars of deleted files are listed according to `sidecars`, Live Photo videos always are.
+pub fn resolve_group(
+    group: &[PathBuf],
+    strategy: KeepStrategy,
+    sidecars: SidecarMode,
+    cache: &HashCache,
+) -> Option<GroupResolution> {
+    let candidates: Vec<FileCandidate> = group
+        .iter()
+        .filter_map(|path| FileCandidate::from_path(path, cache))
+        .collect();
+    Some(resolve_candidates(candidates, strategy)?.with_companions(sidecars))
+}
+
+impl GroupResolution {
+    /// Add the sidecars and Live Photo videos that go along with the deleted files
+    pub fn with_companions(mut self, sidecars: SidecarMode) -> Self {
+        let mut seen: BTreeSet<PathBuf> = self.delete.iter().cloned().collect();
+        self.live_photo_videos = unseen_paths(
+            self.delete.iter().filter_map(|path| live_photo_video(path)),
+            &mut seen,
+        );
+        self.sidecars = unseen_paths(
+            self.delete
+                .iter()
+                .flat_map(|path| affected_sidecars(path, sidecars)),
+            &mut seen,
+        );
+        self.bytes_reclaimed += self
+            .live_photo_videos
+            .iter()
+            .filter_map(|video| fs::metadata(extended_length_path(video)).ok())
+            .map(|metadata| metadata.len())
+            .sum::<u64>();
+        self
+    }
+}
+
+/// Delete the files a resolution gave up, with their Live Photo videos and sidecars, all or
+/// nothing, and forget them in the cache. Deleted files go to the trash unless `--permanent`
+/// was given
+pub fn apply_resolution(resolution: &GroupResolution, cache: &HashCache) -> Result<()> {
+    let operations: Vec<FileOperation> = resolution
+        .delete
+        .iter()
+        .chain(&resolution.live_photo_videos)
+        .chain(&resolution.sidecars)
+        .cloned()
+        .map(FileOperation::Delete)
+        .collect();
+    let outcome = apply_file_operations(&operations);
+    let applied = match &outcome {
+        Ok(()) => operations.as_slice(),
+        Err(failure) => failure.left_applied.as_slice(),
+    };
+    update_cache(cache, applied)?;
+    Ok(outcome?)
+}
+
+/// Apply a strategy to already gathered candidates. Files tagged Keep are never deleted, nor
+/// are hard links to a kept file. Deleted files linked to each other are only counted once
+pub fn resolve_candidates(
+    candidates: Vec<FileCandidate>,
+    strategy: KeepStrategy,
+) -> Option<GroupResolution> {
+    if candidates.len() < 2 {
+        return None;
+    }
+
+    let keep_index = select_keeper(&candidates, strategy)?;
+    let kept_ids: BTreeSet<(u64, u64)> = candidates
+        .iter()
+        .enumerate()
+        .filter(|&(index, candidate)| index == keep_index || candidate.keep_tagged)
+        .filter_map(|(_, candidate)| candidate.file_id)
+        .collect();
+    let mut deleted_ids = BTreeSet::new();
+    let mut keep = None;
+    let mut delete = Vec::new();
+    let mut protected = Vec::new();
+    let mut already_linked = Vec::new();
+    let mut bytes_reclaimed = 0;
+
+    for (index, candidate) in candidates.into_iter().enumerate() {
+        if index == keep_index {
+            keep = Some(candidate.path);
+        } else if candidate.keep_tagged {
+            protected.push(candidate.path);
+        } else if candidate.file_id.is_some_and(|id| kept_ids.contains(&id)) {
+            already_linked.push(candidate.path);
+        } else {
+            if candidate.file_id.is_none_or(|id| deleted_ids.insert(id)) {
+                bytes_reclaimed += candidate.size;
+            }
+            delete.push(candidate.path);
+        }
+    }
+
+    Some(GroupResolution {
+        keep: keep?,
+        delete,
+        protected,
+        already_linked,
+        sidecars: Vec::new(),
+        live_photo_videos: Vec::new(),
+        bytes_reclaimed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn candidate(path: &str, size: u64, age_secs: u64, dimensions: (u32, u32)) -> FileCandidate {
+        FileCandidate {
+            path: PathBuf::from(path),
+            size,
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000 - age_secs)),
+            dimensions: Some(dimensions),
+            keep_tagged: false,
+            file_id: None,
+        }
+    }
+
+    fn sample() -> Vec<FileCandidate> {
+        vec![
+            candidate("/photos/a/big-file.png", 5_000, 100, (800, 600)),
+            candidate("/photos/hires.jpg", 3_000, 50, (4000, 3000)),
+            candidate("/photos/b/c/old.jpg", 1_000, 900, (800, 600)),
+        ]
+    }
+
+    fn keeper(strategy: KeepStrategy) -> String {
+        let candidates = sample();
+        let index = select_keeper(&candidates, strategy).expect("should pick a keeper");
+        candidates[index].path.display().to_string()
+    }
+
+    #[test]
+    fn keep_largest_picks_biggest_file() {
+        assert_eq!(keeper(KeepStrategy::KeepLargest), "/photos/a/big-file.png");
+    }
+
+    #[test]
+    fn keep_highest_res_picks_most_pixels() {
+        assert_eq!(keeper(KeepStrategy::KeepHighestRes), "/photos/hires.jpg");
+    }
+
+    #[test]
+    fn keep_oldest_and_newest_use_modification_time() {
+        assert_eq!(keeper(KeepStrategy::KeepOldest), "/photos/b/c/old.jpg");
+        assert_eq!(keeper(KeepStrategy::KeepNewest), "/photos/hires.jpg");
+    }
+
+    #[test]
+    fn keep_shortest_path_picks_shortest() {
+        assert_eq!(keeper(KeepStrategy::KeepShortestPath), "/photos/hires.jpg");
+    }
+
+    #[test]
+    fn ties_are_broken_by_path() {
+        let candidates = vec![
+            candidate("/b.jpg", 10, 0, (1, 1)),
+            candidate("/a.jpg", 10, 0, (1, 1)),
+        ];
+        let index = select_keeper(&candidates, KeepStrategy::KeepLargest);
+        assert_eq!(index, Some(1));
+    }
+
+    #[test]
+    fn resolution_totals_deleted_bytes() {
+        let resolution =
+            resolve_candidates(sample(), KeepStrategy::KeepLargest).expect("group should resolve");
+        assert_eq!(resolution.keep, PathBuf::from("/photos/a/big-file.png"));
+        assert_eq!(resolution.delete.len(), 2);
+        assert_eq!(resolution.bytes_reclaimed, 4_000);
+    }
+
+    #[test]
+    fn keep_tagged_files_are_never_deleted() {
+        let mut candidates = sample();
+        candidates[1].keep_tagged = true;
+        candidates[2].keep_tagged = true;
+        // Tagged files win over the strategy, the strategy decides between them
+        assert_eq!(
+            select_keeper(&candidates, KeepStrategy::KeepLargest),
+            Some(1)
+        );
+
+        let resolution = resolve_candidates(candidates, KeepStrategy::KeepLargest)
+            .expect("group should resolve");
+        assert_eq!(resolution.keep, PathBuf::from("/photos/hires.jpg"));
+        assert_eq!(
+            resolution.protected,
+            vec![PathBuf::from("/photos/b/c/old.jpg")]
+        );
+        assert_eq!(
+            resolution.delete,
+            vec![PathBuf::from("/photos/a/big-file.png")]
+        );
+        assert_eq!(resolution.bytes_reclaimed, 5_000);
+    }
+
+    #[test]
+    fn hard_links_are_neither_deleted_nor_counted() {
+        let mut candidates = sample();
+        candidates.push(candidate(
+            "/snapshots/1/big-file.png",
+            5_000,
+            100,
+            (800, 600),
+        ));
+        candidates.push(candidate("/snapshots/1/old.jpg", 1_000, 900, (800, 600)));
+        // Two links to the keeper, two to one deleted file
+        for (index, id) in [(0, 1), (3, 1), (2, 2), (4, 2)] {
+            candidates[index].file_id = Some((1, id));
+        }
+
+        let resolution = resolve_candidates(candidates, KeepStrategy::KeepLargest)
+            .expect("group should resolve");
+        assert_eq!(resolution.keep, PathBuf::from("/photos/a/big-file.png"));
+        assert_eq!(
+            resolution.already_linked,
+            vec![PathBuf::from("/snapshots/1/big-file.png")]
+        );
+        assert_eq!(resolution.delete.len(), 3);
+        assert_eq!(resolution.bytes_reclaimed, 4_000);
+    }
+
+    #[test]
+    fn single_file_groups_need_no_action() {
+        let mut candidates = sample();
+        candidates.truncate(1);
+        assert!(resolve_candidates(candidates, KeepStrategy::KeepLargest).is_none());
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cache::HashCache;
+use crate::confidence::group_confidence;
+use crate::paths::extended_length_path;
+use crate::resolver::{resolve_group, KeepStrategy};
+use crate::sidecar::SidecarMode;
+
+/// Schema version stored in the results database's `run` table
+pub const RESULTS_DB_VERSION: u32 = 2;
+
+const RESULTS_SCHEMA: &str = "
+    CREATE TABLE run (
+        version INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        threshold INTEGER NOT NULL,
+        keep_strategy TEXT NOT NULL
+    );
+    CREATE TABLE duplicate_groups (
+        id INTEGER PRIMARY KEY,
+        keep_path TEXT,
+        bytes_reclaimed INTEGER NOT NULL,
+        confidence TEXT NOT NULL
+    );
+    CREATE TABLE group_members (
+        group_id INTEGER NOT NULL REFERENCES duplicate_groups(id),
+        path TEXT NOT NULL,
+        size INTEGER,
+        width INTEGER,
+        height INTEGER,
+        modified INTEGER,
+        taken_at INTEGER,
+        description TEXT,
+        sha256 TEXT,
+        perceptual_hash TEXT,
+        decision TEXT NOT NULL
+    );
+    CREATE INDEX idx_group_members_group ON group_members(group_id);
+";
+
+/// What resolving a group would do with a member. Files tagged Keep are kept with the keeper
+fn decision(
+    path: &Path,
+    keep: Option<&Path>,
+    protected: &[PathBuf],
+    delete: &[PathBuf],
+) -> &'static str {
+    if keep == Some(path) || protected.iter().any(|kept| kept == path) {
+        "keep"
+    } else if delete.iter().any(|deleted| deleted == path) {
+        "delete"
+    } else {
+        // Missing files can't be compared, so they get no decision
+        "undecided"
+    }
+}
+
+/// Write duplicate groups, their members' details and what `strategy` would keep or delete to a
+/// standalone SQLite file that can be shared without the hash cache. An existing file at `path`
+/// is replaced; it's written next to it first so a failure leaves the old one in place.
+pub fn write_results_db(
+    path: &Path,
+    duplicates: &[Vec<PathBuf>],
+    threshold: u32,
+    strategy: KeepStrategy,
+    cache: &HashCache,
+) -> Result<()> {
+    let mut partial_name = path.file_name().unwrap_or_default().to_os_string();
+    partial_name.push(".partial");
+    let partial = path.with_file_name(partial_name);
+    if partial.exists() {
+        fs::remove_file(&partial)
+            .with_context(|| format!("Could not remove {}", partial.display()))?;
+    }
+
+    let mut conn = Connection::open(&partial)
+        .with_context(|| format!("Could not create results database {}", partial.display()))?;
+    conn.execute_batch(RESULTS_SCHEMA)?;
+
+    let tx = conn.transaction()?;
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default();
+    tx.execute(
+        "INSERT INTO run (version, created_at, threshold, keep_strategy) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            RESULTS_DB_VERSION,
+            created_at,
+            threshold,
+            strategy.to_string()
+        ],
+    )?;
+
+    for group in duplicates {
+        // Sidecars aren't part of the results, only the images' own decisions
+        let resolution = resolve_group(group, strategy, SidecarMode::Ignore, cache);
+        let keep = resolution
+            .as_ref()
+            .map(|resolution| resolution.keep.as_path());
+        let delete = resolution
+            .as_ref()
+            .map(|resolution| resolution.delete.as_slice())
+            .unwrap_or_default();
+        let protected = resolution
+            .as_ref()
+            .map(|resolution| resolution.protected.as_slice())
+            .unwrap_or_default();
+
+        tx.execute(
+            "INSERT INTO duplicate_groups (keep_path, bytes_reclaimed, confidence)
+             VALUES (?1, ?2, ?3)",
+            params![
+                keep.map(|keep| keep.display().to_string()),
+                resolution
+                    .as_ref()
+                    .map_or(0, |resolution| resolution.bytes_reclaimed),
+                group_confidence(group, threshold, cache).to_string()
+            ],
+        )?;
+        let group_id = tx.last_insert_rowid();
+
+        for member in group {
+            let size = fs::metadata(extended_length_path(member))
+                .map(|metadata| metadata.len())
+                .ok();
+            let details = cache.get_cached_image_details(member)?;
+            let dimensions = details.as_ref().and_then(|details| details.dimensions);
+            tx.execute(
+                "INSERT INTO group_members (group_id, path, size, width, height, modified,
+                     taken_at, description, sha256, perceptual_hash, decision)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    group_id,
+                    member.display().to_string(),
+                    size,
+                    dimensions.map(|(width, _)| width),
+                    dimensions.map(|(_, height)| height),
+                    details.as_ref().and_then(|details| details.modified),
+                    details.as_ref().and_then(|details| details.photo.taken_at),
+                    details
+                        .as_ref()
+                        .and_then(|details| details.photo.description.clone()),
+                    details.as_ref().map(|details| details.sha256.clone()),
+                    details
+                        .as_ref()
+                        .map(|details| details.perceptual_hash.clone()),
+                    decision(member, keep, protected, delete),
+                ],
+            )?;
+        }
+    }
+    tx.commit()?;
+    drop(conn);
+
+    fs::rename(&partial, path)
+        .with_context(|| format!("Could not write results database {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn writes_groups_members_and_decisions() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let large = temp_dir.path().join("large.jpg");
+        let small = temp_dir.path().join("small.jpg");
+        fs::write(&large, vec![0u8; 200]).expect("write");
+        fs::write(&small, vec![0u8; 100]).expect("write");
+        let missing = temp_dir.path().join("missing.jpg");
+        let results_path = temp_dir.path().join("results.sqlite");
+        fs::write(&results_path, b"stale").expect("write");
+
+        let cache = HashCache::new_in_memory().expect("cache");
+        let groups = vec![vec![small.clone(), large.clone(), missing.clone()]];
+        write_results_db(
+            &results_path,
+            &groups,
+            10,
+            KeepStrategy::KeepLargest,
+            &cache,
+        )
+        .expect("results should be written");
+
+        let conn = Connection::open(&results_path).expect("open results");
+        let (threshold, strategy): (u32, String) = conn
+            .query_row("SELECT threshold, keep_strategy FROM run", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .expect("run row");
+        assert_eq!((threshold, strategy.as_str()), (10, "keep-largest"));
+        // Nothing is cached, so the files can't be told apart with any certainty
+        let confidence: String = conn
+            .query_row("SELECT confidence FROM duplicate_groups", [], |row| {
+                row.get(0)
+            })
+            .expect("group row");
+        assert_eq!(confidence, "probable");
+
+        let mut stmt = conn
+            .prepare("SELECT path, size, decision FROM group_members ORDER BY path")
+            .expect("prepare");
+        let members: Vec<(String, Option<u64>, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .expect("query")
+            .collect::<rusqlite::Result<_>>()
+            .expect("rows");
+        assert_eq!(
+            members,
+            vec![
+                (large.display().to_string(), Some(200), "keep".to_string()),
+                (missing.display().to_string(), None, "undecided".to_string()),
+                (small.display().to_string(), Some(100), "delete".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use anyhow::Result;
+use imghash::ImageHash;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::cache::HashCache;
+use crate::confidence::{group_confidence, Confidence};
+use crate::estimate::format_size;
+use crate::hasher::decode_hash;
+use crate::resolver::{select_keeper, FileCandidate, GroupResolution, KeepStrategy};
+use crate::sidecar::SidecarMode;
+
+const HELP: &str = "↑/↓ file  ←/→ group  d delete  k keep  a keep only this  enter apply  q quit";
+
+/// One file of a group under review
+pub struct ReviewFile {
+    pub candidate: FileCandidate,
+    /// None when the hash isn't cached, distances to it are unknown
+    pub hash: Option<ImageHash>,
+}
+
+/// A duplicate group under review
+pub struct ReviewGroup {
+    pub files: Vec<ReviewFile>,
+    pub confidence: Confidence,
+}
+
+impl ReviewGroup {
+    /// The group's files that still exist, with their cached details. None when fewer than two
+    /// are left
+    pub fn from_cache(group: &[PathBuf], threshold: u32, cache: &HashCache) -> Option<Self> {
+        let files: Vec<ReviewFile> = group
+            .iter()
+            .filter_map(|path| {
+                let candidate = FileCandidate::from_path(path, cache)?;
+                let hash = cache
+                    .get_cached_hash_details(path)
+                    .ok()
+                    .flatten()
+                    .and_then(|(perceptual_hash, _)| decode_hash(&perceptual_hash).ok());
+                Some(ReviewFile { candidate, hash })
+            })
+            .collect();
+        (files.len() > 1).then(|| Self {
+            files,
+            confidence: group_confidence(group, threshold, cache),
+        })
+    }
+}
+
+/// What a key press asks the review to do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    /// Delete the marked files
+    Apply,
+    /// Leave without deleting anything
+    Quit,
+}
+
+/// Going through duplicate groups, marking files to delete. Every group keeps at least one
+/// file, and files tagged Keep can't be marked
+pub struct Review {
+    groups: Vec<ReviewGroup>,
+    group: usize,
+    file: usize,
+    marked: BTreeSet<PathBuf>,
+    status: String,
+}
+
+impl Review {
+    pub fn new(groups: Vec<ReviewGroup>) -> Self {
+        Self {
+            groups,
+            group: 0,
+            file: 0,
+            marked: BTreeSet::new(),
+            status: String::new(),
+        }
+    }
+
+    /// Mark every file but the one `strategy` keeps, like `scan --auto-resolve` would delete
+    pub fn premark(&mut self, strategy: KeepStrategy) {
+        for group in &self.groups {
+            let candidates: Vec<FileCandidate> = group
+                .files
+                .iter()
+                .map(|file| file.candidate.clone())
+                .collect();
+            let Some(keep_index) = select_keeper(&candidates, strategy) else {
+                continue;
+            };
+            for (index, candidate) in candidates.into_iter().enumerate() {
+                if index != keep_index && !candidate.keep_tagged {
+                    self.marked.insert(candidate.path);
+                }
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, code: KeyCode) -> Step {
+        self.status.clear();
+        let len = self
+            .groups
+            .get(self.group)
+            .map_or(0, |group| group.files.len());
+        match code {
+            KeyCode::Up => self.file = self.file.saturating_sub(1),
+            KeyCode::Down => self.file = (self.file + 1).min(len.saturating_sub(1)),
+            KeyCode::Left => self.select_group(self.group.saturating_sub(1)),
+            KeyCode::Right => {
+                self.select_group((self.group + 1).min(self.groups.len().saturating_sub(1)))
+            }
+            KeyCode::Char('d') => self.mark_selected(),
+            KeyCode::Char('k') => {
+                if let Some(path) = self.selected_path() {
+                    self.marked.remove(&path);
+                }
+            }
+            KeyCode::Char('a') => {
+                let paths: Vec<PathBuf> = self.groups[self.group]
+                    .files
+                    .iter()
+                    .filter(|file| !file.candidate.keep_tagged)
+                    .map(|file| file.candidate.path.clone())
+                    .collect();
+                let selected = self.selected_path();
+                for path in paths {
+                    if Some(&path) == selected.as_ref() {
+                        self.marked.remove(&path);
+                    } else {
+                        self.marked.insert(path);
+                    }
+                }
+            }
+            KeyCode::Enter => return Step::Apply,
+            KeyCode::Char('q') | KeyCode::Esc => return Step::Quit,
+            _ => {}
+        }
+        Step::Continue
+    }
+
+    fn select_group(&mut self, group: usize) {
+        if group != self.group {
+            self.group = group;
+            self.file = 0;
+        }
+    }
+
+    fn selected_path(&self) -> Option<PathBuf> {
+        let group = self.groups.get(self.group)?;
+        Some(group.files.get(self.file)?.candidate.path.clone())
+    }
+
+    fn mark_selected(&mut self) {
+        let Some(group) = self.groups.get(self.group) else {
+            return;
+        };
+        let selected = &group.files[self.file].candidate;
+        if selected.keep_tagged {
+            self.status = "Tagged Keep, it can't be deleted".to_string();
+            return;
+        }
+        let others_kept = group.files.iter().any(|file| {
+            file.candidate.path != selected.path && !self.marked.contains(&file.candidate.path)
+        });
+        if !others_kept {
+            self.status = "Every group has to keep at least one file".to_string();
+            return;
+        }
+        self.marked.insert(selected.path.clone());
+    }
+
+    fn is_marked(&self, path: &Path) -> bool {
+        self.marked.contains(path)
+    }
+
+    /// What to do with each group that has marked files, by group number counting from 1: the
+    /// first file left is kept and the marked ones are deleted with their companions
+    pub fn resolutions(&self, sidecars: SidecarMode) -> Vec<(usize, GroupResolution)> {
+        let mut resolutions = Vec::new();
+        for (index, group) in self.groups.iter().enumerate() {
+            let (delete, kept): (Vec<&FileCandidate>, Vec<&FileCandidate>) = group
+                .files
+                .iter()
+                .map(|file| &file.candidate)
+                .partition(|candidate| self.marked.contains(&candidate.path));
+            let Some(keep) = kept.first() else {
+                continue;
+            };
+            if delete.is_empty() {
+                continue;
+            }
+            let mut deleted_ids = BTreeSet::new();
+            let bytes_reclaimed = delete
+                .iter()
+                .filter(|candidate| candidate.file_id.is_none_or(|id| deleted_ids.insert(id)))
+                .map(|candidate| candidate.size)
+                .sum();
+            let resolution = GroupResolution {
+                keep: keep.path.clone(),
+                delete: delete
+                    .iter()
+                    .map(|candidate| candidate.path.clone())
+                    .collect(),
+                protected: Vec::new(),
+                already_linked: Vec::new(),
+                sidecars: Vec::new(),
+                live_photo_videos: Vec::new(),
+                bytes_reclaimed,
+            };
+            resolutions.push((index + 1, resolution.with_companions(sidecars)));
+        }
+        resolutions
+    }
+
+    /// Draw the selected group: every file with its size, resolution and hash distance to the
+    /// selected file
+    fn draw(&self, frame: &mut Frame) {
+        let [title_area, table_area, help_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+        let group = &self.groups[self.group];
+        let marked = group
+            .files
+            .iter()
+            .filter(|file| self.is_marked(&file.candidate.path))
+            .count();
+        frame.render_widget(
+            Paragraph::new(format!(
+                "Group {} of {} ({}), {marked} of {} files marked for deletion",
+                self.group + 1,
+                self.groups.len(),
+                group.confidence,
+                group.files.len()
+            )),
+            title_area,
+        );
+
+        let selected_hash = group.files[self.file].hash.as_ref();
+        let rows = group.files.iter().enumerate().map(|(index, file)| {
+            let candidate = &file.candidate;
+            let deleted = self.is_marked(&candidate.path);
+            let mark = match (deleted, candidate.keep_tagged) {
+                (true, _) => "delete",
+                (false, true) => "keep (tagged)",
+                (false, false) => "keep",
+            };
+            let resolution = candidate
+                .dimensions
+                .map_or("?".to_string(), |(width, height)| {
+                    format!("{width}x{height}")
+                });
+            let distance = if index == self.file {
+                "-".to_string()
+            } else {
+                selected_hash
+                    .zip(file.hash.as_ref())
+                    .and_then(|(a, b)| a.distance(b).ok())
+                    .map_or("?".to_string(), |distance| distance.to_string())
+            };
+            let row = Row::new([
+                mark.to_string(),
+                format_size(candidate.size),
+                resolution,
+                distance,
+                candidate.path.display().to_string(),
+            ]);
+            if deleted {
+                row.style(Style::new().fg(Color::Red))
+            } else {
+                row
+            }
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(13),
+                Constraint::Length(9),
+                Constraint::Length(11),
+                Constraint::Length(8),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(["Action", "Size", "Resolution", "Distance", "Path"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered())
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::default().with_selected(Some(self.file));
+        frame.render_stateful_widget(table, table_area, &mut state);
+
+        frame.render_widget(
+            Paragraph::new(vec![Line::from(HELP), Line::from(self.status.as_str())]),
+            help_area,
+        );
+    }
+}
+
+/// Run the review in the terminal until it's applied or left. Returns whether to apply the
+/// marked deletions
+pub fn run_review(terminal: &mut DefaultTerminal, review: &mut Review) -> Result<bool> {
+    if review.groups.is_empty() {
+        return Ok(false);
+    }
+    loop {
+        terminal.draw(|frame| review.draw(frame))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match review.handle_key(key.code) {
+            Step::Continue => {}
+            Step::Apply => return Ok(true),
+            Step::Quit => return Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(paths: &[&str]) -> ReviewGroup {
+        ReviewGroup {
+            files: paths
+                .iter()
+                .map(|path| ReviewFile {
+                    candidate: FileCandidate {
+                        path: PathBuf::from(path),
+                        size: 1_000,
+                        modified: None,
+                        dimensions: None,
+                        keep_tagged: path.contains("keep"),
+                        file_id: None,
+                    },
+                    hash: None,
+                })
+                .collect(),
+            confidence: Confidence::Probable,
+        }
+    }
+
+    fn marked(review: &Review) -> Vec<String> {
+        review
+            .marked
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn every_group_keeps_a_file() {
+        let mut review = Review::new(vec![
+            group(&["/photos/a.jpg", "/photos/b.jpg"]),
+            group(&["/photos/c.jpg", "/photos/keep.jpg", "/photos/d.jpg"]),
+        ]);
+        assert_eq!(review.handle_key(KeyCode::Char('d')), Step::Continue);
+        review.handle_key(KeyCode::Down);
+        review.handle_key(KeyCode::Char('d'));
+        assert_eq!(marked(&review), ["/photos/a.jpg"]);
+        assert!(!review.status.is_empty());
+
+        review.handle_key(KeyCode::Char('k'));
+        review.handle_key(KeyCode::Char('d'));
+        assert_eq!(marked(&review), ["/photos/a.jpg"]);
+
+        // Moving to the next group starts at its first file, and files tagged Keep stay
+        review.handle_key(KeyCode::Right);
+        review.handle_key(KeyCode::Down);
+        review.handle_key(KeyCode::Char('d'));
+        review.handle_key(KeyCode::Down);
+        review.handle_key(KeyCode::Char('a'));
+        assert_eq!(marked(&review), ["/photos/a.jpg", "/photos/c.jpg"]);
+
+        let resolutions = review.resolutions(SidecarMode::default());
+        assert_eq!(resolutions.len(), 2);
+        assert_eq!(resolutions[0].0, 1);
+        assert_eq!(resolutions[0].1.keep, PathBuf::from("/photos/b.jpg"));
+        assert_eq!(resolutions[1].1.delete, [PathBuf::from("/photos/c.jpg")]);
+        assert_eq!(resolutions[1].1.bytes_reclaimed, 1_000);
+        assert_eq!(review.handle_key(KeyCode::Enter), Step::Apply);
+        assert_eq!(review.handle_key(KeyCode::Char('q')), Step::Quit);
+    }
+
+    #[test]
+    fn premarking_keeps_what_the_strategy_picks() {
+        let mut review = Review::new(vec![
+            group(&["/photos/a/long-name.jpg", "/photos/b.jpg"]),
+            group(&["/photos/c.jpg", "/photos/d/keep.jpg"]),
+        ]);
+        review.premark(KeepStrategy::KeepShortestPath);
+        assert_eq!(
+            marked(&review),
+            ["/photos/a/long-name.jpg", "/photos/c.jpg"]
+        );
+    }
+}
@@ -1,13 +1,27 @@
 use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::cmp::Reverse;
 use std::env;
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
-use tracing::{debug, warn};
+use std::time::SystemTime;
+use tracing::field::Empty;
+use tracing::{debug, instrument, warn, Span};
 use walkdir::WalkDir;
 
+use crate::deadline::time_is_up;
+use crate::extract::EBOOK_EXTENSIONS;
+use crate::partial::is_partial_file;
+use crate::paths::{extended_length_path, on_disk_case};
+use crate::report::{SkipReason, SkippedFiles};
+use crate::strategy;
+#[cfg(feature = "video")]
+use crate::video::{videos_included, VIDEO_EXTENSIONS};
+
 /// Expand tilde (~) in a path to the user's home directory
-fn expand_tilde(path: &str) -> PathBuf {
+pub fn expand_tilde(path: &str) -> PathBuf {
     if path.starts_with("~/") || path == "~" {
         if let Some(home) = env::var_os("HOME") {
             let home_path = PathBuf::from(home);
@@ -45,9 +59,119 @@ fn should_ignore_path(path: &Path, ignore_paths: &[String]) -> bool {
     false
 }
 
+/// A file format recognised by its magic number
+struct MagicFormat {
+    extensions: &'static [&'static str],
+    content_type: &'static str,
+    matches: fn(&[u8]) -> bool,
+}
+
+const MAGIC_FORMATS: [MagicFormat; 13] = [
+    MagicFormat {
+        extensions: &["png"],
+        content_type: "image/png",
+        // PNG magic number: 89 50 4E 47 0D 0A 1A 0A
+        matches: |bytes| bytes.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]),
+    },
+    MagicFormat {
+        extensions: &["jpg", "jpeg"],
+        content_type: "image/jpeg",
+        // JPEG magic number: FF D8 FF
+        matches: |bytes| bytes.starts_with(&[0xFF, 0xD8, 0xFF]),
+    },
+    MagicFormat {
+        extensions: &["gif"],
+        content_type: "image/gif",
+        // GIF magic number: GIF87a or GIF89a
+        matches: |bytes| bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a"),
+    },
+    MagicFormat {
+        extensions: &["webp"],
+        content_type: "image/webp",
+        // WebP magic number: RIFF ... WEBP
+        matches: |bytes| {
+            bytes.starts_with(b"RIFF") && bytes.len() >= 12 && &bytes[8..12] == b"WEBP"
+        },
+    },
+    MagicFormat {
+        extensions: &["bmp"],
+        content_type: "image/bmp",
+        // BMP magic number: BM
+        matches: |bytes| bytes.starts_with(b"BM"),
+    },
+    MagicFormat {
+        extensions: &["tiff", "tif"],
+        content_type: "image/tiff",
+        // TIFF magic number: MM00 (big endian) or II*\0 (little endian)
+        matches: |bytes| {
+            bytes.starts_with(&[0x4D, 0x4D, 0x00, 0x2A])
+                || bytes.starts_with(&[0x49, 0x49, 0x2A, 0x00])
+        },
+    },
+    MagicFormat {
+        extensions: &["avif"],
+        content_type: "image/avif",
+        // AVIF is an ISO media file: ....ftypavif, or ftypavis for image sequences
+        matches: |bytes| {
+            bytes.len() >= 12
+                && &bytes[4..8] == b"ftyp"
+                && (&bytes[8..12] == b"avif" || &bytes[8..12] == b"avis")
+        },
+    },
+    MagicFormat {
+        extensions: &["jxl"],
+        content_type: "image/jxl",
+        // JPEG XL magic number: FF 0A for a bare codestream, or the JXL container box
+        matches: |bytes| {
+            bytes.starts_with(&[0xFF, 0x0A])
+                || bytes.starts_with(&[
+                    0x00, 0x00, 0x00, 0x0C, 0x4A, 0x58, 0x4C, 0x20, 0x0D, 0x0A, 0x87, 0x0A,
+                ])
+        },
+    },
+    MagicFormat {
+        extensions: &["arw", "cr2", "dng", "nef", "nrw", "pef", "srw"],
+        content_type: "image/x-dcraw",
+        // Most camera RAW formats are TIFF files with their own tags
+        matches: |bytes| {
+            bytes.starts_with(&[0x4D, 0x4D, 0x00, 0x2A])
+                || bytes.starts_with(&[0x49, 0x49, 0x2A, 0x00])
+        },
+    },
+    MagicFormat {
+        extensions: &["orf"],
+        content_type: "image/x-olympus-orf",
+        // Olympus RAW magic number: IIRO, IIRS or MMOR
+        matches: |bytes| {
+            bytes.starts_with(b"IIRO") || bytes.starts_with(b"IIRS") || bytes.starts_with(b"MMOR")
+        },
+    },
+    MagicFormat {
+        extensions: &["rw2"],
+        content_type: "image/x-panasonic-rw2",
+        // Panasonic RAW magic number: IIU\0
+        matches: |bytes| bytes.starts_with(&[0x49, 0x49, 0x55, 0x00]),
+    },
+    MagicFormat {
+        extensions: &["raf"],
+        content_type: "image/x-fuji-raf",
+        // Fujifilm RAW magic number: FUJIFILMCCD-RAW
+        matches: |bytes| bytes.starts_with(b"FUJIFILMCCD-RAW"),
+    },
+    MagicFormat {
+        extensions: &["epub"],
+        content_type: "application/epub+zip",
+        // EPUB files are ZIP archives: PK\x03\x04
+        matches: |bytes| bytes.starts_with(b"PK\x03\x04"),
+    },
+];
+
+/// Number of leading bytes needed to recognise any supported format
+pub const MAGIC_BYTES: usize = 16;
+
 pub fn validate_image_format(path: &Path) -> Result<bool> {
-    let mut file = fs::File::open(path)?;
-    let mut buffer = [0u8; 16]; // Read first 16 bytes for magic number checking
+    let mut file = fs::File::open(extended_length_path(path))?;
+    let mut buffer = [0u8; MAGIC_BYTES]; // Read first 16 bytes for magic number checking
     let bytes_read = file.read(&mut buffer)?;
 
     if bytes_read < 4 {
@@ -60,61 +184,74 @@ pub fn validate_image_format(path: &Path) -> Result<bool> {
         .map(|s| s.to_lowercase())
         .unwrap_or_default();
 
-    match extension.as_str() {
-        "png" => {
-            // PNG magic number: 89 50 4E 47 0D 0A 1A 0A
-            Ok(buffer.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]))
-        }
-        "jpg" | "jpeg" => {
-            // JPEG magic number: FF D8 FF
-            Ok(buffer.starts_with(&[0xFF, 0xD8, 0xFF]))
-        }
-        "gif" => {
-            // GIF magic number: GIF87a or GIF89a
-            Ok(buffer.starts_with(b"GIF87a") || buffer.starts_with(b"GIF89a"))
-        }
-        "webp" => {
-            // WebP magic number: RIFF ... WEBP
-            Ok(buffer.starts_with(b"RIFF") && bytes_read >= 12 && &buffer[8..12] == b"WEBP")
-        }
-        "bmp" => {
-            // BMP magic number: BM
-            Ok(buffer.starts_with(b"BM"))
-        }
-        "tiff" | "tif" => {
-            // TIFF magic number: MM00 (big endian) or II*\0 (little endian)
-            Ok(buffer.starts_with(&[0x4D, 0x4D, 0x00, 0x2A])
-                || buffer.starts_with(&[0x49, 0x49, 0x2A, 0x00]))
-        }
-        _ => Ok(true), // For unknown extensions, let the image crate handle validation
+    match MAGIC_FORMATS
+        .iter()
+        .find(|format| format.extensions.contains(&extension.as_str()))
+    {
+        Some(format) => Ok((format.matches)(&buffer[..bytes_read])),
+        None => Ok(true), // For unknown extensions, let the image crate handle validation
     }
 }
 
-pub fn should_process_image_file(
+/// Content type of a file from its leading bytes, regardless of its extension
+pub fn sniff_content_type(bytes: &[u8]) -> Option<&'static str> {
+    MAGIC_FORMATS
+        .iter()
+        .find(|format| (format.matches)(bytes))
+        .map(|format| format.content_type)
+}
+
+/// Outcome of checking a single file during a scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileCheck {
+    /// A valid image that should be hashed
+    Image,
+    /// Not an image file (wrong or missing extension), silently left out
+    NotImage,
+    /// An image file that was skipped, reported in the scan summary
+    Skipped(SkipReason),
+}
+
+/// Check a file found under the scanned `root`, which is the file itself when it was given
+/// directly
+pub fn check_image_file(
     path: &Path,
+    root: &Path,
     image_extensions: &[&str],
     skip_validation: bool,
     debug: bool,
-) -> bool {
+) -> FileCheck {
     // Check if file is accessible (handles broken symlinks)
-    if !path.exists() || fs::metadata(path).is_err() {
-        warn!("Skipping inaccessible file: {}", path.display());
-        return false;
-    }
+    let fs_path = extended_length_path(path);
+    let metadata = match fs::metadata(&fs_path) {
+        Ok(metadata) if fs_path.exists() => metadata,
+        _ => {
+            warn!("Skipping inaccessible file: {}", path.display());
+            return FileCheck::Skipped(SkipReason::Inaccessible);
+        }
+    };
 
     let Some(ext) = path.extension() else {
-        return false;
+        return FileCheck::NotImage;
     };
 
     if !image_extensions.contains(&ext.to_string_lossy().to_lowercase().as_str()) {
-        return false;
+        return FileCheck::NotImage;
+    }
+
+    // Half-downloaded files would be hashed from whatever has arrived so far
+    if is_partial_file(path, root, metadata.modified().ok()) {
+        if debug {
+            debug!("Still being written: {}", path.display());
+        }
+        return FileCheck::Skipped(SkipReason::StillWriting);
     }
 
     if skip_validation {
         if debug {
             debug!("Found image (validation skipped): {}", path.display());
         }
-        return true;
+        return FileCheck::Image;
     }
 
     // Validate file format before adding to processing list
@@ -123,7 +260,7 @@ pub fn should_process_image_file(
             if debug {
                 debug!("Validated: {}", path.display());
             }
-            true
+            FileCheck::Image
         }
         Ok(false) => {
             if debug {
@@ -133,27 +270,41 @@ pub fn should_process_image_file(
                     ext.to_string_lossy()
                 );
             }
-            false
+            FileCheck::Skipped(SkipReason::ValidationFailed)
         }
         Err(e) => {
             if debug {
                 warn!("Could not validate {}: {}", path.display(), e);
             }
-            false
+            FileCheck::Skipped(SkipReason::ValidationFailed)
         }
     }
 }
 
+pub fn should_process_image_file(
+    path: &Path,
+    image_extensions: &[&str],
+    skip_validation: bool,
+    debug: bool,
+) -> bool {
+    check_image_file(path, path, image_extensions, skip_validation, debug) == FileCheck::Image
+}
+
 pub fn process_file(
     path: &Path,
+    root: &Path,
     image_extensions: &[&str],
     skip_validation: bool,
     debug: bool,
+    skipped: &mut SkippedFiles,
 ) -> Vec<PathBuf> {
-    if should_process_image_file(path, image_extensions, skip_validation, debug) {
-        vec![path.to_path_buf()]
-    } else {
-        vec![]
+    match check_image_file(path, root, image_extensions, skip_validation, debug) {
+        FileCheck::Image => vec![path.to_path_buf()],
+        FileCheck::NotImage => vec![],
+        FileCheck::Skipped(reason) => {
+            skipped.record(reason, path.to_path_buf());
+            vec![]
+        }
     }
 }
 
@@ -164,8 +315,11 @@ pub fn process_dir(
     skip_validation: bool,
     debug: bool,
     ignore_paths: &[String],
+    skipped: &mut SkippedFiles,
 ) -> Result<Vec<PathBuf>> {
     let mut images = Vec::new();
+    let mut ignored = Vec::new();
+    let root = path;
     let walker = WalkDir::new(path)
         .follow_links(true)
         .into_iter()
@@ -174,6 +328,7 @@ pub fn process_dir(
 
             // First check if this path should be ignored
             if should_ignore_path(entry_path, ignore_paths) {
+                ignored.push(entry_path.to_path_buf());
                 return false;
             }
 
@@ -196,19 +351,36 @@ pub fn process_dir(
         });
 
     for entry in walker {
+        if time_is_up() {
+            break;
+        }
         match entry {
             Ok(entry) => {
                 let path = entry.path();
                 if path.is_file() {
-                    images.extend(process_file(path, image_extensions, skip_validation, debug));
+                    images.extend(process_file(
+                        path,
+                        root,
+                        image_extensions,
+                        skip_validation,
+                        debug,
+                        skipped,
+                    ));
                 }
             }
             Err(e) => {
                 warn!("Could not access directory entry: {e}");
+                if let Some(path) = e.path() {
+                    skipped.record(SkipReason::Inaccessible, path.to_path_buf());
+                }
             }
         }
     }
 
+    for path in ignored {
+        skipped.record(SkipReason::Ignored, path);
+    }
+
     Ok(images)
 }
 
@@ -219,22 +391,63 @@ pub fn scan_for_images(
     skip_validation: bool,
     ignore_paths: &[String],
 ) -> Result<Vec<PathBuf>> {
+    scan_for_images_with_report(
+        paths,
+        include_hidden,
+        debug,
+        skip_validation,
+        ignore_paths,
+        false,
+    )
+    .map(|(images, _)| images)
+}
+
+/// Scan paths for images, also returning the files that were skipped and why.
+/// With `include_ebooks`, ebooks are picked up too so their cover images can be compared.
+#[instrument(level = "debug", skip_all, fields(paths = paths.len(), images = Empty, skipped = Empty))]
+pub fn scan_for_images_with_report(
+    paths: &[PathBuf],
+    include_hidden: bool,
+    debug: bool,
+    skip_validation: bool,
+    ignore_paths: &[String],
+    include_ebooks: bool,
+) -> Result<(Vec<PathBuf>, SkippedFiles)> {
     let mut images = Vec::new();
-    let image_extensions = ["jpg", "jpeg", "png", "gif", "bmp", "tiff", "tif", "webp"];
+    let mut skipped = SkippedFiles::default();
+    let configured_extensions = strategy::image_extensions();
+    let mut image_extensions: Vec<&str> =
+        configured_extensions.iter().map(String::as_str).collect();
+    if include_ebooks {
+        image_extensions.extend(EBOOK_EXTENSIONS);
+    }
+    #[cfg(feature = "video")]
+    if videos_included() {
+        image_extensions.extend(VIDEO_EXTENSIONS);
+    }
 
     for path in paths {
+        if time_is_up() {
+            break;
+        }
+        // Spelled as on disk, so files found under `/users/me` and `/Users/me` on a
+        // case-insensitive volume are cached once
+        let path = &on_disk_case(path);
         // Check if the path itself should be ignored
         if should_ignore_path(path, ignore_paths) {
             debug!("Skipping ignored path: {}", path.display());
+            skipped.record(SkipReason::Ignored, path.clone());
             continue;
         }
 
         if path.is_file() {
             images.extend(process_file(
+                path,
                 path,
                 &image_extensions,
                 skip_validation,
                 debug,
+                &mut skipped,
             ));
         } else if path.is_dir() {
             images.extend(process_dir(
@@ -244,9 +457,50 @@ pub fn scan_for_images(
                 skip_validation,
                 debug,
                 ignore_paths,
+                &mut skipped,
             )?);
+        } else {
+            warn!(
+                "Path does not exist or is not accessible: {}",
+                path.display()
+            );
+            skipped.record(SkipReason::Inaccessible, path.clone());
         }
     }
 
-    Ok(images)
+    Span::current()
+        .record("images", images.len())
+        .record("skipped", skipped.total());
+    Ok((images, skipped))
+}
+
+/// Order in which discovered images are hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashOrder {
+    /// Largest files first, the biggest potential space savings
+    SizeDesc,
+    /// Most recently modified files first
+    MtimeDesc,
+    /// Alphabetical by path
+    Path,
+}
+
+/// Sort images into hashing order. Files whose metadata can't be read go last.
+pub fn sort_images(images: &mut [PathBuf], order: HashOrder) {
+    match order {
+        HashOrder::SizeDesc => images.sort_by_cached_key(|path| {
+            let size = fs::metadata(extended_length_path(path))
+                .map(|m| m.len())
+                .ok();
+            (Reverse(size), path.clone())
+        }),
+        HashOrder::MtimeDesc => images.sort_by_cached_key(|path| {
+            let modified: Option<SystemTime> = fs::metadata(extended_length_path(path))
+                .and_then(|m| m.modified())
+                .ok();
+            (Reverse(modified), path.clone())
+        }),
+        HashOrder::Path => images.sort(),
+    }
 }
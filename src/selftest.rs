@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use image::{DynamicImage, ImageFormat};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::algorithm::hash_algorithm;
+use crate::cache::DEFAULT_GRID_SIZE;
+use crate::hasher::{find_duplicates, generate_rotation_invariant_hash_safe};
+use crate::synthetic::{pattern, random_hashes};
+
+/// Size of the generated images, about a small photo's pixel count
+const IMAGE_WIDTH: u32 = 640;
+const IMAGE_HEIGHT: u32 = 480;
+/// Library sizes the measured rates are projected onto
+pub const ESTIMATE_LIBRARY_SIZES: [usize; 2] = [10_000, 100_000];
+
+/// How much work the self-test does. Every image is saved in four orientations, which must end
+/// up in one group each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTest {
+    pub images: usize,
+    /// Unrelated hashes grouped to measure comparisons, far more than there are images
+    pub grouping_hashes: usize,
+}
+
+impl Default for SelfTest {
+    fn default() -> Self {
+        Self {
+            images: 48,
+            grouping_hashes: 10_000,
+        }
+    }
+}
+
+/// Time one stage took for its items
+#[derive(Debug, Clone, Copy)]
+pub struct StageTiming {
+    pub name: &'static str,
+    pub items: usize,
+    pub elapsed: Duration,
+}
+
+impl StageTiming {
+    pub fn per_second(&self) -> f64 {
+        self.items as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    pub threads: usize,
+    /// Checksum, decode and hash, in the order a scan runs them
+    pub stages: Vec<StageTiming>,
+    /// Pairs of hashes the grouping covered
+    pub grouping: StageTiming,
+}
+
+impl SelfTestReport {
+    /// Files a scan gets through per second, all stages included
+    pub fn images_per_second(&self) -> f64 {
+        let seconds: f64 = self
+            .stages
+            .iter()
+            .map(|stage| 1.0 / stage.per_second())
+            .sum();
+        1.0 / seconds.max(f64::EPSILON)
+    }
+
+    pub fn comparisons_per_second(&self) -> f64 {
+        self.grouping.per_second()
+    }
+
+    /// Projected time to hash and to group a library of `images` files that aren't cached yet
+    pub fn estimate(&self, images: usize) -> (Duration, Duration) {
+        let hashing = images as f64 / self.images_per_second();
+        let grouping = pairs(images) as f64 / self.comparisons_per_second();
+        (
+            Duration::from_secs_f64(hashing),
+            Duration::from_secs_f64(grouping),
+        )
+    }
+}
+
+/// Hash generated images through the same stages a scan uses and time each of them. Fails when
+/// an image's rotated copies don't end up in one group, as the numbers wouldn't mean much then
+pub fn run_selftest(test: SelfTest, threshold: u32) -> Result<SelfTestReport> {
+    info!(
+        "Generating {} test images ({IMAGE_WIDTH}x{IMAGE_HEIGHT}, 4 orientations each)...",
+        test.images
+    );
+    let encoded: Vec<(PathBuf, Vec<u8>)> = (0..test.images)
+        .into_par_iter()
+        .flat_map_iter(|number| {
+            let img = pattern(IMAGE_WIDTH, IMAGE_HEIGHT, number as u64);
+            [
+                img.clone(),
+                img.rotate90(),
+                img.rotate180(),
+                img.rotate270(),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(move |(turn, img)| (test_image_path(number, turn), img))
+        })
+        .map(|(path, img)| Ok((path, encode_png(&img)?)))
+        .collect::<Result<_>>()?;
+    let files = encoded.len();
+
+    let started = Instant::now();
+    encoded.par_iter().for_each(|(_, bytes)| {
+        black_box(Sha256::digest(bytes));
+    });
+    let checksum = stage("checksum", files, started);
+
+    let started = Instant::now();
+    let decoded: Vec<(PathBuf, DynamicImage)> = encoded
+        .into_par_iter()
+        .map(|(path, bytes)| Ok((path, image::load_from_memory(&bytes)?)))
+        .collect::<Result<_>>()?;
+    let decode = stage("decode", files, started);
+
+    let started = Instant::now();
+    let hasher = hash_algorithm().hasher(DEFAULT_GRID_SIZE)?;
+    let hashes: Vec<_> = decoded
+        .par_iter()
+        .map(|(path, img)| {
+            Ok((
+                path.clone(),
+                generate_rotation_invariant_hash_safe(&hasher, img)?,
+            ))
+        })
+        .collect::<Result<_>>()?;
+    let hash = stage("hash", files, started);
+
+    let group_of: BTreeMap<PathBuf, usize> = find_duplicates(&hashes, threshold)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(id, group)| group.into_iter().map(move |path| (path, id)))
+        .collect();
+    for number in 0..test.images {
+        let first = group_of.get(&test_image_path(number, 0));
+        if first.is_none()
+            || (1..4).any(|turn| group_of.get(&test_image_path(number, turn)) != first)
+        {
+            bail!("Self-test image {number} didn't group with its rotated copies at threshold {threshold}");
+        }
+    }
+
+    let unrelated = random_hashes(test.grouping_hashes, 1);
+    let started = Instant::now();
+    black_box(find_duplicates(&unrelated, threshold));
+    let grouping = stage("group", pairs(unrelated.len()), started);
+
+    Ok(SelfTestReport {
+        threads: rayon::current_num_threads(),
+        stages: vec![checksum, decode, hash],
+        grouping,
+    })
+}
+
+fn test_image_path(number: usize, turn: usize) -> PathBuf {
+    PathBuf::from(format!("/selftest/{number}_{}.png", turn * 90))
+}
+
+fn encode_png(img: &DynamicImage) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(bytes)
+}
+
+fn stage(name: &'static str, items: usize, started: Instant) -> StageTiming {
+    let timing = StageTiming {
+        name,
+        items,
+        elapsed: started.elapsed(),
+    };
+    info!(
+        "  {name}: {items} in {:.2}s ({:.0}/s)",
+        timing.elapsed.as_secs_f64(),
+        timing.per_second()
+    );
+    timing
+}
+
+/// Comparisons needed to check every file against every other
+fn pairs(files: usize) -> usize {
+    files * files.saturating_sub(1) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_test_images_group_and_rates_are_measured() {
+        let test = SelfTest {
+            images: 3,
+            grouping_hashes: 200,
+        };
+        let report = run_selftest(test, 15).expect("self-test should pass");
+
+        assert_eq!(report.stages.len(), 3);
+        assert!(report.stages.iter().all(|stage| stage.items == 12));
+        assert_eq!(report.grouping.items, 200 * 199 / 2);
+        assert!(report.images_per_second() > 0.0);
+        let (hashing, grouping) = report.estimate(1000);
+        assert!(hashing > Duration::ZERO && grouping > Duration::ZERO);
+    }
+}
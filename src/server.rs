@@ -1,66 +1,240 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use axum::{
+    error_handling::HandleErrorLayer,
     extract::{Path, Query, State},
-    http::{header, StatusCode},
-    response::{Json, Response},
-    routing::{get, post},
-    Router,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
+    BoxError, Router,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::net::TcpListener;
+use tower::timeout::error::Elapsed;
+use tower::ServiceBuilder;
 use tracing::{error, info, instrument, warn};
 
-use crate::cache::{Config, HashCache};
-use crate::hasher::{find_duplicates, generate_hashes_with_cache, get_duplicates_from_cache};
-use crate::scanner::scan_for_images;
+use crate::annotations::{group_annotation, GroupAnnotation};
+use crate::background::{hashing_paused, pause_hashing, resume_hashing};
+use crate::cache::{
+    CacheProvenance, Config, GroupingKey, HashCache, HashProvenance, ResolvedConfig, SharedHash,
+};
+use crate::confidence::{group_confidence, Confidence};
+use crate::config::{
+    cli_keys, config_file_keys, config_schema, configured_database_path, with_settings,
+    OptionSchema,
+};
+use crate::diff::{diff_heatmap_png, DEFAULT_DIFF_SIZE};
+use crate::dryrun::is_dry_run;
+use crate::estimate::{estimate_scan, record_scan_speed, ScanEstimate};
+use crate::extract::{extract_epub_cover, is_ebook, open_image};
+use crate::feed::{atom_feed, FeedEntry, FEED_ENTRIES};
+use crate::hasher::{
+    calculate_file_sha256, find_duplicates, generate_hashes_with_report, get_duplicates_from_cache,
+    regroup_from_stored_distances, rehash_files, split_groups_by_dimensions,
+};
+use crate::hooks::{run_post_scan_hook, ScanSource, ScanSummary};
+use crate::imageinfo::Orientation;
+use crate::jobs::{run_job, JobKind, JobRun, JobTrigger};
+use crate::journal::{apply_file_operations, deletes_permanently, update_cache, FileOperation};
+use crate::listener::listener_from_fd;
+use crate::livephoto::live_photo_video;
+use crate::messages::{Lang, Message};
+use crate::overrides::{apply_overrides, GroupOverride};
+use crate::paths::{extended_length_path, is_absolute_path, strip_verbatim_prefix};
+use crate::report::{FileWarnings, SkipReason, SkippedFiles};
+use crate::resolver::{resolve_group, KeepStrategy};
+use crate::scanner::{
+    expand_tilde, scan_for_images_with_report, sniff_content_type, sort_images, HashOrder,
+};
+use crate::settings::{layered_config, load_stored_settings, Settings};
+use crate::sidecar::{affected_sidecars, unseen_paths};
+use crate::stats::{duplicate_stats, DuplicateStats};
+use crate::tags::{file_tags, is_keep_tagged};
+use crate::thumbnail::{
+    load_or_create_preview, load_or_create_thumbnail, render_thumbnail, PREVIEW_SIZES,
+};
+use crate::usage::{ServeKind, ServedFile, SessionUsage};
 
-fn get_file_info_with_details(path: &std::path::Path, cache: &HashCache) -> FileInfo {
+fn get_file_info_with_details(
+    path: &std::path::Path,
+    cache: &HashCache,
+    include_sha256: bool,
+) -> FileInfo {
     let path_str = path.display().to_string();
-    let exists = path.exists();
+    let fs_path = extended_length_path(path);
+    let exists = fs_path.exists();
 
     let size = if exists {
-        std::fs::metadata(path).map(|m| m.len()).ok()
+        std::fs::metadata(&fs_path).map(|m| m.len()).ok()
     } else {
         None
     };
 
-    // Try to get hash from cache
-    let hash = if exists {
-        cache.get_all_cached_hashes().ok().and_then(|hashes| {
-            hashes
-                .iter()
-                .find(|(p, _)| p == path)
-                .map(|(_, h)| h.clone())
-        })
-    } else {
-        None
+    // Try to get hashes and image details from cache
+    let details = match exists.then(|| cache.get_cached_image_details(path)) {
+        Some(Ok(details)) => details,
+        _ => None,
+    };
+    let Some(details) = details else {
+        return FileInfo::without_details(path_str, exists, size);
     };
+    let dimensions = details.dimensions;
 
     FileInfo {
         path: path_str,
         exists,
         size,
-        hash,
+        hash: Some(details.perceptual_hash),
+        sha256: include_sha256.then_some(details.sha256),
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        orientation: dimensions.map(|(width, height)| Orientation::from_dimensions(width, height)),
+        dominant_color: details.dominant_color,
+        modified: details.modified,
+        taken_at: details.photo.taken_at,
+        description: details.photo.description,
+        tags: cache.get_file_tags(path).unwrap_or_default(),
     }
 }
 
-#[derive(Clone)]
+/// Time budget for requests that scan or group the whole library
+const JOB_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Time budget for everything else: pages, images, metadata and single-file changes
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Skipped files listed per reason in scan responses, the rest are only counted
+const SKIPPED_FILES_PER_REASON: usize = 100;
+
 pub struct AppState {
+    /// The config file, without stored settings
     config: Config,
+    /// Settings stored in the database, changed through the API while running
+    settings: RwLock<Settings>,
+    /// Settings from environment variables, which win over stored ones
+    env_settings: Settings,
     threshold_override: Option<u32>,
-    grid_size_override: Option<u32>,
+    grid_size_override: Option<u8>,
+    /// When this server run started, in seconds since the Unix epoch. Served files are logged
+    /// against it so usage can be totalled per run
+    session_started: i64,
+    /// The scan started along with the server by `serve --scan`
+    scan_job: RwLock<ScanJobStatus>,
 }
 
-#[derive(Deserialize)]
+impl AppState {
+    fn new(
+        config: Config,
+        threshold_override: Option<u32>,
+        grid_size_override: Option<u8>,
+    ) -> Self {
+        let settings =
+            load_stored_settings(&configured_database_path(&config)).unwrap_or_else(|e| {
+                warn!("Could not load stored settings: {}", e);
+                Settings::default()
+            });
+        let env_settings = Settings::from_env().unwrap_or_else(|e| {
+            warn!("Ignoring settings from the environment: {}", e);
+            Settings::default()
+        });
+        Self {
+            config,
+            settings: RwLock::new(settings),
+            env_settings,
+            threshold_override,
+            grid_size_override,
+            session_started: unix_now(),
+            scan_job: RwLock::new(ScanJobStatus::default()),
+        }
+    }
+
+    /// The config with the current stored settings and environment variables on top
+    fn config(&self) -> Config {
+        let settings = self.settings.read().unwrap_or_else(PoisonError::into_inner);
+        layered_config(&self.config, &settings, &self.env_settings)
+    }
+
+    /// Log a file sent to a client. The write runs on a blocking thread so it doesn't hold up
+    /// the runtime's workers. Failing to log doesn't fail the response
+    async fn record_served(
+        self: &Arc<Self>,
+        path: &std::path::Path,
+        kind: ServeKind,
+        bytes: usize,
+    ) {
+        let state = Arc::clone(self);
+        let path = path.to_path_buf();
+        let logged = tokio::task::spawn_blocking(move || {
+            let effective_config = state.config().with_overrides(
+                state.grid_size_override,
+                state.threshold_override,
+                None,
+            );
+            let logged = HashCache::open(&effective_config).and_then(|cache| {
+                cache.log_served(state.session_started, &path, kind, bytes as u64)
+            });
+            if let Err(e) = logged {
+                warn!("Could not log served file {}: {}", path.display(), e);
+            }
+        })
+        .await;
+        if let Err(e) = logged {
+            warn!("Logging a served file failed: {}", e);
+        }
+    }
+
+    /// Change the stored settings, saving them to the database first so a failed save leaves
+    /// the running server as it was
+    fn update_settings(&self, change: impl FnOnce(&mut Settings)) -> Result<Settings> {
+        let mut settings = self
+            .settings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut updated = settings.clone();
+        change(&mut updated);
+
+        let effective_config = layered_config(&self.config, &updated, &self.env_settings)
+            .with_overrides(self.grid_size_override, self.threshold_override, None);
+        HashCache::open(&effective_config)?.store_settings(&updated)?;
+        *settings = updated.clone();
+        Ok(updated)
+    }
+
+    /// Language API messages are written in
+    fn lang(&self) -> Lang {
+        self.config().lang.unwrap_or_default()
+    }
+
+    /// Whether the server was configured for browsing only
+    fn read_only(&self) -> bool {
+        self.config().with_overrides(None, None, None).read_only
+    }
+
+    /// Directories requests may touch, empty when any path is allowed
+    fn roots(&self) -> Vec<PathBuf> {
+        self.config()
+            .with_overrides(None, None, None)
+            .server_roots
+            .iter()
+            .map(|root| expand_tilde(root))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Default)]
 pub struct ScanRequest {
     paths: Vec<String>,
     threshold: Option<u32>,
-    grid_size: Option<u32>,
+    grid_size: Option<u8>,
     include_hidden: Option<bool>,
     debug: Option<bool>,
     skip_validation: Option<bool>,
+    same_dimensions: Option<bool>,
+    include_ebooks: Option<bool>,
+    order: Option<HashOrder>,
+    include_hashes: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -69,6 +243,41 @@ pub struct FileInfo {
     exists: bool,
     size: Option<u64>,
     hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    orientation: Option<Orientation>,
+    dominant_color: Option<String>,
+    /// Modification time in seconds since the Unix epoch
+    modified: Option<i64>,
+    /// Original capture time in seconds since the Unix epoch, when imported
+    taken_at: Option<i64>,
+    description: Option<String>,
+    /// Tags from extended attributes as of the last scan, files tagged Keep can't be deleted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+impl FileInfo {
+    /// File info for a file the cache knows nothing about
+    fn without_details(path: String, exists: bool, size: Option<u64>) -> Self {
+        Self {
+            path,
+            exists,
+            size,
+            hash: None,
+            sha256: None,
+            width: None,
+            height: None,
+            orientation: None,
+            dominant_color: None,
+            modified: None,
+            taken_at: None,
+            description: None,
+            tags: Vec::new(),
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -77,6 +286,20 @@ pub struct ScanResponse {
     message: String,
     duplicate_count: usize,
     duplicates: Vec<Vec<FileInfo>>,
+    /// Confidence tier of each group in `duplicates`
+    confidence: Vec<Confidence>,
+    /// Images the scan found
+    scanned_count: usize,
+    /// Images with a hash, from the cache or hashed now
+    hashed_count: usize,
+    cache_hits: usize,
+    cache_misses: usize,
+    skipped_count: usize,
+    skipped_by_reason: BTreeMap<SkipReason, usize>,
+    /// The first skipped files of each reason
+    skipped: SkippedFiles,
+    /// Per-file warnings by kind, each with a count and the first few examples
+    warnings: FileWarnings,
 }
 
 #[derive(Deserialize, Debug)]
@@ -84,20 +307,69 @@ pub struct MatchesQuery {
     threshold: Option<u32>,
     count: Option<usize>,
     offset: Option<usize>,
+    same_dimensions: Option<bool>,
+    include_hashes: Option<bool>,
+    /// Leave out groups below this tier
+    min_confidence: Option<Confidence>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SharedHashesQuery {
+    count: Option<usize>,
+    offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct SharedHashesResponse {
+    success: bool,
+    hashes: Vec<SharedHash>,
+    /// Shared hashes before `count` and `offset` were applied
+    total: usize,
 }
 
 #[derive(Serialize)]
 pub struct MatchesResponse {
     success: bool,
     duplicates: Vec<Vec<FileInfo>>,
+    /// Confidence tier of each group in `duplicates`
+    confidence: Vec<Confidence>,
+    /// Pin and note of each group in `duplicates`
+    annotations: Vec<GroupAnnotation>,
     threshold: u32,
 }
 
 #[derive(Serialize)]
 pub struct ConfigResponse {
-    grid_size: u32,
+    grid_size: u8,
     threshold: u32,
     database_path: Option<String>,
+    lang: Lang,
+    /// Whether only browsing routes are served
+    read_only: bool,
+    /// The version and hash settings new hashes are made with
+    hashing: HashProvenance,
+    /// The cached hashes and duplicate groups by the version and settings that made them
+    cached: CacheProvenance,
+}
+
+#[derive(Deserialize)]
+pub struct RehashRequest {
+    paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct RehashResponse {
+    success: bool,
+    message: String,
+    rehashed: Vec<PathBuf>,
+    /// Requested files that are gone, removed from the cache
+    missing: Vec<PathBuf>,
+    skipped: SkippedFiles,
+    warnings: FileWarnings,
+    /// Groups the files are in now, and groups they were in before
+    duplicates: Vec<Vec<FileInfo>>,
+    /// Confidence tier of each group in `duplicates`
+    confidence: Vec<Confidence>,
 }
 
 #[derive(Deserialize)]
@@ -110,41 +382,399 @@ pub struct CheckFilesResponse {
     files: Vec<FileInfo>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ImageQuery {
+    /// Downscale to fit within this many pixels, see `thumbnail::PREVIEW_SIZES`
+    max: Option<u32>,
+}
+
 #[derive(Deserialize)]
 pub struct DeleteFileRequest {
     path: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ResolvePreviewQuery {
+    strategy: KeepStrategy,
+    threshold: Option<u32>,
+    same_dimensions: Option<bool>,
+    /// Only resolve groups at or above this tier, e.g. `exact` for a hands-off cleanup
+    min_confidence: Option<Confidence>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StatsQuery {
+    threshold: Option<u32>,
+    /// Which file of each group counts as kept, defaults to keep-largest
+    strategy: Option<KeepStrategy>,
+    same_dimensions: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    success: bool,
+    threshold: u32,
+    strategy: KeepStrategy,
+    #[serde(flatten)]
+    stats: DuplicateStats,
+}
+
+#[derive(Serialize)]
+pub struct ResolvePreviewGroup {
+    keep: String,
+    delete: Vec<String>,
+    /// Sidecar files deleted along with `delete`, when sidecars follow their image
+    sidecars: Vec<String>,
+    /// Motion parts of Live Photos in `delete`, deleted along with them
+    live_photo_videos: Vec<String>,
+    /// Other files tagged Keep, kept along with `keep`
+    protected: Vec<String>,
+    /// Hard links to a kept file, left alone since deleting them frees nothing
+    already_linked: Vec<String>,
+    bytes_reclaimed: u64,
+    confidence: Confidence,
+}
+
+#[derive(Serialize)]
+pub struct ResolvePreviewResponse {
+    success: bool,
+    strategy: KeepStrategy,
+    threshold: u32,
+    groups: Vec<ResolvePreviewGroup>,
+    files_to_delete: usize,
+    total_bytes_reclaimed: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UsageQuery {
+    /// How many of this run's most recently served files to list, 50 by default
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct UsageResponse {
+    success: bool,
+    /// This server run
+    session: SessionUsage,
+    /// Every logged run, newest first
+    sessions: Vec<SessionUsage>,
+    recent: Vec<ServedFile>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DiffQuery {
+    a: String,
+    b: String,
+    size: Option<u32>,
+}
+
 #[derive(Serialize)]
 pub struct DeleteFileResponse {
     success: bool,
     message: String,
+    /// Sidecar files deleted along with the image
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    deleted_sidecars: Vec<String>,
+    /// The Live Photo video deleted along with the image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_live_photo_video: Option<String>,
 }
 
-pub async fn start_server(
+#[derive(Deserialize, Debug)]
+pub struct MergeGroupsRequest {
+    /// Any file of the first group
+    a: String,
+    /// Any file of the second group
+    b: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SplitGroupRequest {
+    path: String,
+}
+
+/// Files, confidence tiers and annotations of the groups in a `MatchesResponse`
+type MatchedGroups = (Vec<Vec<FileInfo>>, Vec<Confidence>, Vec<GroupAnnotation>);
+
+#[derive(Deserialize, Debug)]
+pub struct PinGroupRequest {
+    /// Every file of the group
+    paths: Vec<String>,
+    pinned: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GroupNoteRequest {
+    /// Every file of the group
+    paths: Vec<String>,
+    /// Empty or missing removes the note
+    note: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct GroupOverrideResponse {
+    success: bool,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct GroupOverridesResponse {
+    success: bool,
+    overrides: Vec<GroupOverride>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IgnorePaths {
+    ignore_paths: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// No scan was started along with the server
+    #[default]
+    Idle,
+    Running,
+    Finished,
+    Failed,
+}
+
+/// Whether hashing is held by `/api/worker/pause`
+#[derive(Serialize)]
+pub struct WorkerStatus {
+    paused: bool,
+}
+
+/// Progress of the scan started with `serve --scan`
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ScanJobStatus {
+    state: JobState,
+    paths: Vec<String>,
+    /// Seconds since the Unix epoch
+    started_at: Option<i64>,
+    finished_at: Option<i64>,
+    /// Projected from the recent speed of each scanned folder, None without earlier runs
+    expected_finish_at: Option<i64>,
+    duplicate_count: Option<usize>,
+    skipped_count: Option<usize>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JobsQuery {
+    /// `history=1` adds the kept job runs
+    history: Option<u8>,
+}
+
+#[derive(Serialize)]
+pub struct ScheduledJob {
+    kind: JobKind,
+    every_seconds: u64,
+}
+
+#[derive(Serialize)]
+pub struct JobsResponse {
+    scheduled: Vec<ScheduledJob>,
+    /// Newest first, with the lines each run logged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history: Option<Vec<JobRun>>,
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    success: bool,
+    message: String,
+}
+
+/// Build the web app's routes, separately from binding a listener so tests can call it directly
+pub fn router(
     config: Config,
     threshold_override: Option<u32>,
-    grid_size_override: Option<u32>,
-) -> Result<()> {
-    let state = AppState {
+    grid_size_override: Option<u8>,
+) -> Router {
+    routes(Arc::new(AppState::new(
         config,
         threshold_override,
         grid_size_override,
-    };
+    )))
+}
+
+/// Build the web app's routes with a scan of `paths` already running in the background, its
+/// progress served at `/api/scan/status`. Must be called from within the Tokio runtime
+pub fn router_with_scan(
+    config: Config,
+    threshold_override: Option<u32>,
+    grid_size_override: Option<u8>,
+    paths: Vec<PathBuf>,
+) -> Router {
+    let state = Arc::new(AppState::new(
+        with_default_roots(config, &paths),
+        threshold_override,
+        grid_size_override,
+    ));
+    start_background_scan(Arc::clone(&state), paths);
+    routes(state)
+}
+
+/// With neither server_roots nor scan_paths configured, the paths given to `serve --scan`
+/// become the folders the server may access
+fn with_default_roots(config: Config, scan_paths: &[PathBuf]) -> Config {
+    if config.server_roots.is_some() || config.scan_paths.is_some() || scan_paths.is_empty() {
+        return config;
+    }
+    Config {
+        server_roots: Some(
+            scan_paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+        ),
+        ..config
+    }
+}
 
-    let app = Router::new()
+fn routes(state: Arc<AppState>) -> Router {
+    let mut jobs = Router::new()
+        .route("/api/matches", get(handle_matches))
+        .route("/api/matches/recompute", get(handle_recompute_matches))
+        .route("/api/shared-hashes", get(handle_shared_hashes))
+        .route("/api/stats", get(handle_stats));
+    let mut browse = Router::new()
         .route("/", get(serve_index))
         .route("/styles.css", get(serve_css))
-        .route("/api/scan", post(handle_scan))
-        .route("/api/matches", get(handle_matches))
+        .route("/feed.xml", get(handle_feed))
         .route("/api/config", get(handle_config))
+        .route("/api/config/schema", get(handle_config_schema))
         .route("/api/image/{*path}", get(serve_image))
+        .route("/api/thumbnail/{*path}", get(serve_thumbnail))
+        .route("/api/diff", get(serve_diff))
         .route("/api/check-files", post(check_files_exist))
-        .route("/api/delete-file", post(delete_file))
-        .with_state(Arc::new(state));
+        .route("/api/groups/overrides", get(handle_list_overrides))
+        .route("/api/usage", get(handle_usage))
+        .route("/api/scan/status", get(handle_scan_status))
+        .route("/api/jobs", get(handle_jobs))
+        .route("/api/worker", get(handle_worker_status));
+
+    // A read-only server doesn't have the routes that change anything, rather than refusing them
+    if state.read_only() {
+        info!(
+            "Read-only mode: scanning, re-hashing, pausing, deleting, resolving and editing groups or settings are off"
+        );
+    } else {
+        jobs = jobs
+            .route("/api/scan", post(handle_scan))
+            .route("/api/rehash", post(handle_rehash))
+            .route("/api/resolve-preview", get(handle_resolve_preview));
+        browse = browse
+            .route(
+                "/api/config/ignore-paths",
+                get(handle_get_ignore_paths).put(handle_put_ignore_paths),
+            )
+            .route(
+                "/api/settings",
+                get(handle_get_settings).put(handle_put_settings),
+            )
+            .route("/api/groups/merge", post(handle_merge_groups))
+            .route("/api/groups/split", post(handle_split_group))
+            .route("/api/groups/overrides", delete(handle_clear_overrides))
+            .route("/api/groups/pin", post(handle_pin_group))
+            .route("/api/groups/note", put(handle_group_note))
+            .route("/api/delete-file", post(delete_file))
+            .route("/api/worker/pause", post(handle_worker_pause))
+            .route("/api/worker/resume", post(handle_worker_resume));
+    }
+
+    let jobs = jobs.route_layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_timeout_error))
+            .timeout(JOB_TIMEOUT),
+    );
+    browse
+        .route_layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_timeout_error))
+                .timeout(REQUEST_TIMEOUT),
+        )
+        .merge(jobs)
+        .with_state(state)
+}
+
+/// Turn a request that ran past its time budget into a JSON error, so a stuck filesystem
+/// can't hold the connection open. Blocking work already started still runs to completion.
+async fn handle_timeout_error(err: BoxError) -> Response {
+    let (status, message) = if err.is::<Elapsed>() {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The request took too long, the filesystem may be unresponsive".to_string(),
+        )
+    } else {
+        error!("Request failed: {}", err);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Request failed: {err}"),
+        )
+    };
+
+    (
+        status,
+        Json(ErrorResponse {
+            success: false,
+            message,
+        }),
+    )
+        .into_response()
+}
+
+pub async fn start_server(
+    config: Config,
+    threshold_override: Option<u32>,
+    grid_size_override: Option<u8>,
+    port_override: Option<u16>,
+    listen_fd: Option<i32>,
+    scan_paths: Vec<PathBuf>,
+) -> Result<()> {
+    // Fails early on unreadable stored settings or bad environment variables
+    let config = with_default_roots(config, &scan_paths);
+    let effective_config = with_settings(&config)?.with_overrides(None, None, None);
+    if effective_config.server_roots.is_empty() {
+        warn!("No server_roots or scan_paths configured, requests for any path will be refused");
+    }
+    // The web interface couldn't show images outside the roots, so refuse before starting
+    let roots: Vec<PathBuf> = effective_config
+        .server_roots
+        .iter()
+        .map(|root| expand_tilde(root))
+        .collect();
+    for path in &scan_paths {
+        if let Err(message) = check_within_roots(path, &roots, effective_config.lang) {
+            bail!(message);
+        }
+    }
+    let port = port_override.unwrap_or(effective_config.port);
 
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
-    info!("🌐 Web server running at http://127.0.0.1:8080");
+    let listener = match listen_fd {
+        Some(fd) => listener_from_fd(fd)?,
+        None => TcpListener::bind(("127.0.0.1", port)).await?,
+    };
+    let state = Arc::new(AppState::new(
+        config,
+        threshold_override,
+        grid_size_override,
+    ));
+    // The log of served files grows with every request, older runs are dropped at each start,
+    // leaving room for this one
+    let pruned = HashCache::open(&effective_config).and_then(|cache| {
+        cache.prune_serve_log(effective_config.serve_log_sessions.saturating_sub(1))
+    });
+    if let Err(e) = pruned {
+        warn!("Could not prune the log of served files: {}", e);
+    }
+    start_scheduled_jobs(Arc::clone(&state));
+    if !scan_paths.is_empty() {
+        start_background_scan(Arc::clone(&state), scan_paths);
+    }
+    let app = routes(state);
+    info!("🌐 Web server running at http://{}", listener.local_addr()?);
     info!("Press Ctrl+C to stop the server");
 
     axum::serve(listener, app).await?;
@@ -169,13 +799,25 @@ async fn serve_index() -> Result<Response, StatusCode> {
 async fn handle_scan(
     State(state): State<Arc<AppState>>,
     Json(request): Json<ScanRequest>,
-) -> Result<Json<ScanResponse>, StatusCode> {
+) -> Result<Response, StatusCode> {
+    if request.grid_size == Some(0) {
+        warn!("Refusing to scan with an empty hash grid");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let roots = state.roots();
+    for path in &request.paths {
+        if let Err(message) = check_within_roots(std::path::Path::new(path), &roots, state.lang()) {
+            warn!("Refusing to scan: {}", message);
+            return Ok(forbidden(message));
+        }
+    }
+
     let effective_config =
         state
-            .config
+            .config()
             .with_overrides(state.grid_size_override, state.threshold_override, None);
-    let cache = HashCache::new(effective_config.database_path.as_deref())
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let threshold = request
         .threshold
@@ -186,121 +828,920 @@ async fn handle_scan(
         .or(state.grid_size_override)
         .unwrap_or(effective_config.grid_size);
 
-    let paths: Vec<PathBuf> = request.paths.iter().map(PathBuf::from).collect();
-    let ignore_paths = effective_config.ignore_paths.clone();
+    let lang = state.lang();
 
     // Run the expensive scanning and processing in a blocking task
-    let scan_result =
-        tokio::task::spawn_blocking(move || -> Result<ScanResponse, anyhow::Error> {
-            let images = scan_for_images(
-                &paths,
-                request.include_hidden.unwrap_or(false),
-                request.debug.unwrap_or(false),
-                request.skip_validation.unwrap_or(false),
-                &ignore_paths,
-            )?;
-
-            let hashes = generate_hashes_with_cache(&images, grid_size, &cache, false)?;
+    let scan_result = tokio::task::spawn_blocking(move || {
+        run_scan(
+            &request,
+            &cache,
+            threshold,
+            grid_size,
+            &effective_config,
+            lang,
+            |_| {},
+        )
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(scan_result).into_response())
+}
 
-            let duplicates = find_duplicates(&hashes, threshold);
+/// Forget the cached hashes of some files and hash them again now, regrouping the cache at the
+/// server's threshold
+async fn handle_rehash(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RehashRequest>,
+) -> Result<Response, StatusCode> {
+    let lang = state.lang();
+    let paths: Vec<PathBuf> = request.paths.iter().map(PathBuf::from).collect();
+    if !paths.iter().all(|path| is_absolute_path(path)) {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                success: false,
+                message: Message::PathsNotAbsolute.text(lang),
+            }),
+        )
+            .into_response());
+    }
+    let roots = state.roots();
+    for path in &paths {
+        if let Err(message) = check_within_roots(path, &roots, lang) {
+            warn!("Refusing to re-hash: {}", message);
+            return Ok(forbidden(message));
+        }
+    }
 
-            // Cache the duplicate groups for future use
-            if let Err(e) = cache.store_duplicate_groups(threshold, &duplicates) {
-                warn!("Failed to cache duplicate groups: {}", e);
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let response = tokio::task::spawn_blocking(move || -> Result<RehashResponse> {
+        let threshold = effective_config.threshold;
+        let (duplicates, report) =
+            rehash_files(&cache, &paths, effective_config.grid_size, threshold)?;
+        Ok(RehashResponse {
+            success: true,
+            message: Message::Rehashed {
+                files: report.rehashed.len(),
+                sets: duplicates.len(),
             }
-
-            let duplicate_file_infos: Vec<Vec<FileInfo>> = duplicates
+            .text(lang),
+            rehashed: report.rehashed,
+            missing: report.missing,
+            skipped: report.skipped,
+            warnings: report.warnings,
+            duplicates: duplicates
                 .iter()
                 .map(|group| {
                     group
                         .iter()
-                        .map(|p| get_file_info_with_details(p, &cache))
+                        .map(|path| get_file_info_with_details(path, &cache, false))
                         .collect()
                 })
-                .collect();
-
-            Ok(ScanResponse {
-                success: true,
-                message: format!(
-                    "Scanned {} images, found {} duplicate sets",
-                    images.len(),
-                    duplicates.len()
-                ),
-                duplicate_count: duplicates.len(),
-                duplicates: duplicate_file_infos,
-            })
+                .collect(),
+            confidence: duplicates
+                .iter()
+                .map(|group| group_confidence(group, threshold, &cache))
+                .collect(),
         })
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    Ok(Json(scan_result))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        error!("Re-hash failed: {:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(response).into_response())
 }
 
-#[instrument(level = "info", skip(state))]
-async fn handle_matches(
-    State(state): State<Arc<AppState>>,
-    Query(query): Query<MatchesQuery>,
-) -> Result<Json<MatchesResponse>, StatusCode> {
-    let effective_config =
-        state
-            .config
-            .with_overrides(state.grid_size_override, state.threshold_override, None);
-    let cache = HashCache::new(effective_config.database_path.as_deref())
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Scan, hash and group the requested paths, storing the groups for `/api/matches`. The
+/// configured ignore paths are left out, and the post-scan hook is started once it's done
+fn run_scan(
+    request: &ScanRequest,
+    cache: &HashCache,
+    threshold: u32,
+    grid_size: u8,
+    config: &ResolvedConfig,
+    lang: Lang,
+    on_estimate: impl FnOnce(&ScanEstimate),
+) -> Result<ScanResponse> {
+    let scan_started = Instant::now();
+    let paths: Vec<PathBuf> = request.paths.iter().map(PathBuf::from).collect();
+    let (mut images, mut skipped) = scan_for_images_with_report(
+        &paths,
+        request.include_hidden.unwrap_or(false),
+        request.debug.unwrap_or(false),
+        request.skip_validation.unwrap_or(false),
+        &config.ignore_paths,
+        request.include_ebooks.unwrap_or(false),
+    )?;
+
+    if let Some(order) = request.order {
+        sort_images(&mut images, order);
+    }
 
-    let threshold = query
-        .threshold
-        .or(state.threshold_override)
-        .unwrap_or(effective_config.threshold);
+    let estimate = estimate_scan(&images, &paths, cache)?;
+    on_estimate(&estimate);
 
-    // Run the expensive computation in a blocking task to avoid blocking the async runtime
-    let result =
-        tokio::task::spawn_blocking(move || -> Result<Vec<Vec<FileInfo>>, anyhow::Error> {
-            let duplicates =
-                get_duplicates_from_cache(&cache, threshold, query.count, query.offset)?;
+    let include_hashes = request.include_hashes.unwrap_or(false);
+    let started = Instant::now();
+    let (hashes, hash_report) = generate_hashes_with_report(&images, grid_size, cache, false)?;
+    skipped.extend(hash_report.skipped);
+    if let Err(e) = record_scan_speed(cache, &estimate, started.elapsed()) {
+        warn!("Failed to record how long hashing took: {e}");
+    }
 
-            let duplicate_file_infos: Vec<Vec<FileInfo>> = duplicates
-                .iter()
-                .map(|group| {
-                    group
-                        .iter()
-                        .map(|p| get_file_info_with_details(p, &cache))
-                        .collect()
-                })
-                .collect();
+    let mut duplicates = find_duplicates(&hashes, threshold);
+
+    if request.same_dimensions.unwrap_or(false) {
+        duplicates = split_groups_by_dimensions(duplicates, cache);
+    } else if let Err(e) =
+        cache.store_duplicate_groups(GroupingKey::new(threshold, grid_size), &duplicates)
+    {
+        // Cache the duplicate groups for future use
+        warn!("Failed to cache duplicate groups: {}", e);
+    }
+    let duplicates = apply_overrides(duplicates, &cache.get_group_overrides()?);
+
+    // Run on its own, a slow hook doesn't hold up the response
+    if let Some(command) = config.post_scan_hook.clone() {
+        let summary = ScanSummary {
+            scanned: images.len(),
+            hashed: hashes.len(),
+            cache_hits: hash_report.cache_hits,
+            cache_misses: hash_report.cache_misses,
+            skipped: skipped.total(),
+            ..ScanSummary::new(
+                ScanSource::Server,
+                &paths,
+                threshold,
+                &duplicates,
+                scan_started.elapsed(),
+            )
+        };
+        std::thread::spawn(move || run_post_scan_hook(&command, &summary));
+    }
 
-            Ok(duplicate_file_infos)
+    let duplicate_file_infos: Vec<Vec<FileInfo>> = duplicates
+        .iter()
+        .map(|group| {
+            group
+                .iter()
+                .map(|p| get_file_info_with_details(p, cache, include_hashes))
+                .collect()
         })
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .collect();
+    let confidence = duplicates
+        .iter()
+        .map(|group| group_confidence(group, threshold, cache))
+        .collect();
 
-    let response = MatchesResponse {
+    Ok(ScanResponse {
         success: true,
-        duplicates: result,
-        threshold,
-    };
-
-    Ok(Json(response))
+        message: Message::ScanComplete {
+            images: images.len(),
+            sets: duplicates.len(),
+        }
+        .text(lang),
+        duplicate_count: duplicates.len(),
+        duplicates: duplicate_file_infos,
+        confidence,
+        scanned_count: images.len(),
+        hashed_count: hashes.len(),
+        cache_hits: hash_report.cache_hits,
+        cache_misses: hash_report.cache_misses,
+        skipped_count: skipped.total(),
+        skipped_by_reason: skipped.counts(),
+        skipped: skipped.capped(SKIPPED_FILES_PER_REASON),
+        warnings: hash_report.warnings,
+    })
 }
 
-async fn handle_config(State(state): State<Arc<AppState>>) -> Json<ConfigResponse> {
-    let response = ConfigResponse {
-        grid_size: state
-            .grid_size_override
-            .unwrap_or(state.config.grid_size.unwrap_or(128)),
-        threshold: state
-            .threshold_override
-            .unwrap_or(state.config.threshold.unwrap_or(15)),
-        database_path: state.config.database_path.clone(),
+/// Run the `--scan` paths the way `/api/scan` would, with the configured threshold and grid
+/// size, keeping `/api/scan/status` up to date so the web interface can follow along
+fn start_background_scan(state: Arc<AppState>, paths: Vec<PathBuf>) {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let lang = state.lang();
+    let request = ScanRequest {
+        paths: paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        ..ScanRequest::default()
     };
-
-    Json(response)
-}
-
-#[instrument(level = "info")]
-async fn serve_image(Path(image_path): Path<String>) -> Result<Response, StatusCode> {
+    *state
+        .scan_job
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = ScanJobStatus {
+        state: JobState::Running,
+        paths: request.paths.clone(),
+        started_at: Some(unix_now()),
+        message: Some(Message::ScanRunning.text(lang)),
+        ..ScanJobStatus::default()
+    };
+    info!("Scanning {} paths in the background", request.paths.len());
+
+    tokio::task::spawn_blocking(move || {
+        let outcome = run_job(
+            &effective_config,
+            JobKind::Scan,
+            JobTrigger::Startup,
+            || {
+                run_scan(
+                    &request,
+                    &HashCache::open(&effective_config)?,
+                    effective_config.threshold,
+                    effective_config.grid_size,
+                    &effective_config,
+                    lang,
+                    |estimate| {
+                        let mut job = state
+                            .scan_job
+                            .write()
+                            .unwrap_or_else(PoisonError::into_inner);
+                        job.expected_finish_at = estimate
+                            .duration
+                            .map(|duration| unix_now() + duration.as_secs() as i64);
+                    },
+                )
+            },
+            |response| response.message.clone(),
+        );
+
+        let mut job = state
+            .scan_job
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        job.finished_at = Some(unix_now());
+        match outcome {
+            Ok(response) => {
+                info!("Background scan finished: {}", response.message);
+                job.state = JobState::Finished;
+                job.duplicate_count = Some(response.duplicate_count);
+                job.skipped_count = Some(response.skipped_count);
+                job.message = Some(response.message);
+            }
+            Err(e) => {
+                error!("Background scan failed: {:#}", e);
+                job.state = JobState::Failed;
+                job.message = Some(Message::ScanFailed(&format!("{e:#}")).text(lang));
+            }
+        }
+    });
+}
+
+/// Run each job in `job_schedule` every so often, first one interval after starting. Runs of one
+/// job never overlap, a run that's due while the last one is still going is skipped
+fn start_scheduled_jobs(state: Arc<AppState>) {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    if effective_config.job_schedule.is_empty() {
+        return;
+    }
+    let interrupted = HashCache::open(&effective_config).and_then(|cache| {
+        cache.fail_interrupted_job_runs(&Message::JobInterrupted.text(state.lang()))
+    });
+    match interrupted {
+        Ok(0) => {}
+        Ok(runs) => warn!("{runs} jobs were cut off when the server last stopped"),
+        Err(e) => warn!("Could not check for interrupted jobs: {}", e),
+    }
+
+    for (&kind, &seconds) in &effective_config.job_schedule {
+        if seconds == 0 {
+            warn!("Not scheduling {} jobs, their interval is 0", kind.as_str());
+            continue;
+        }
+        info!("Running {} jobs every {seconds}s", kind.as_str());
+        let state = Arc::clone(&state);
+        let period = Duration::from_secs(seconds);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                let state = Arc::clone(&state);
+                if let Err(e) =
+                    tokio::task::spawn_blocking(move || run_scheduled_job(&state, kind)).await
+                {
+                    error!("Scheduled {} job panicked: {}", kind.as_str(), e);
+                }
+            }
+        });
+    }
+}
+
+fn run_scheduled_job(state: &AppState, kind: JobKind) {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let lang = state.lang();
+    let work = || -> Result<String> {
+        let cache = HashCache::open(&effective_config)?;
+        match kind {
+            JobKind::Scan => {
+                if effective_config.scan_paths.is_empty() {
+                    bail!(Message::NoScanPaths.text(lang));
+                }
+                let request = ScanRequest {
+                    paths: effective_config.scan_paths.clone(),
+                    ..ScanRequest::default()
+                };
+                let response = run_scan(
+                    &request,
+                    &cache,
+                    effective_config.threshold,
+                    effective_config.grid_size,
+                    &effective_config,
+                    lang,
+                    |_| {},
+                )?;
+                Ok(response.message)
+            }
+            JobKind::Cleanup => {
+                let (files, hashes) = cache.cleanup_missing_files_and_hashes(None)?;
+                cache.fold_case_aliases()?;
+                Ok(Message::CleanupFinished { files, hashes }.text(lang))
+            }
+            JobKind::Compaction => {
+                cache.compact()?;
+                Ok(Message::CompactionFinished.text(lang))
+            }
+        }
+    };
+    match run_job(
+        &effective_config,
+        kind,
+        JobTrigger::Schedule,
+        work,
+        String::clone,
+    ) {
+        Ok(message) => info!("Scheduled {} job finished: {}", kind.as_str(), message),
+        Err(e) => error!("Scheduled {} job failed: {:#}", kind.as_str(), e),
+    }
+}
+
+/// The job schedule, and with `history=1` the kept runs with what they logged
+#[instrument(level = "info", skip(state))]
+async fn handle_jobs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<JobsQuery>,
+) -> Result<Json<JobsResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let scheduled = effective_config
+        .job_schedule
+        .iter()
+        .map(|(&kind, &every_seconds)| ScheduledJob {
+            kind,
+            every_seconds,
+        })
+        .collect();
+    let history = if query.history.unwrap_or(0) != 0 {
+        let runs = tokio::task::spawn_blocking(move || {
+            HashCache::open(&effective_config)?.get_job_runs(effective_config.job_history)
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("Failed to read job runs: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        Some(runs)
+    } else {
+        None
+    };
+    Ok(Json(JobsResponse { scheduled, history }))
+}
+
+async fn handle_scan_status(State(state): State<Arc<AppState>>) -> Json<ScanJobStatus> {
+    Json(
+        state
+            .scan_job
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone(),
+    )
+}
+
+async fn handle_worker_status() -> Json<WorkerStatus> {
+    Json(WorkerStatus {
+        paused: hashing_paused(),
+    })
+}
+
+/// Hold hashing for every scan and re-hash in this process, each finishing the files it's on.
+/// Scans stay where they are and carry on once resumed
+async fn handle_worker_pause() -> Json<WorkerStatus> {
+    pause_hashing();
+    handle_worker_status().await
+}
+
+async fn handle_worker_resume() -> Json<WorkerStatus> {
+    resume_hashing();
+    handle_worker_status().await
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[instrument(level = "info", skip(state))]
+/// Files sharing each perceptual hash, read from the cache without grouping
+#[instrument(level = "info", skip(state))]
+async fn handle_shared_hashes(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SharedHashesQuery>,
+) -> Result<Json<SharedHashesResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let shared = tokio::task::spawn_blocking(move || {
+        HashCache::open(&effective_config)?.get_shared_hashes()
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let total = shared.len();
+    let hashes = shared
+        .into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.count.unwrap_or(usize::MAX))
+        .collect();
+    Ok(Json(SharedHashesResponse {
+        success: true,
+        hashes,
+        total,
+    }))
+}
+
+async fn handle_matches(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MatchesQuery>,
+) -> Result<Json<MatchesResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let threshold = query
+        .threshold
+        .or(state.threshold_override)
+        .unwrap_or(effective_config.threshold);
+    let grid_size = effective_config.grid_size;
+
+    // Run the expensive computation in a blocking task to avoid blocking the async runtime
+    let (duplicates, confidence, annotations) =
+        tokio::task::spawn_blocking(move || matched_groups(&cache, threshold, grid_size, &query))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let response = MatchesResponse {
+        success: true,
+        duplicates,
+        confidence,
+        annotations,
+        threshold,
+    };
+
+    Ok(Json(response))
+}
+
+/// Regroup the cached files at another threshold from the pair distances stored when groups
+/// were last computed, without opening an image or comparing hashes. Refused with 409 when the
+/// stored distances don't reach the threshold or the hashes changed since
+#[instrument(level = "info", skip(state))]
+async fn handle_recompute_matches(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MatchesQuery>,
+) -> Response {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let threshold = query
+        .threshold
+        .or(state.threshold_override)
+        .unwrap_or(effective_config.threshold);
+    let grid_size = effective_config.grid_size;
+
+    let matched = tokio::task::spawn_blocking(move || -> anyhow::Result<Option<MatchedGroups>> {
+        let cache = HashCache::open(&effective_config)?;
+        let key = GroupingKey::new(threshold, grid_size);
+        if regroup_from_stored_distances(&cache, key)?.is_none() {
+            return Ok(None);
+        }
+        matched_groups(&cache, threshold, grid_size, &query).map(Some)
+    })
+    .await;
+
+    match matched {
+        Ok(Ok(Some((duplicates, confidence, annotations)))) => Json(MatchesResponse {
+            success: true,
+            duplicates,
+            confidence,
+            annotations,
+            threshold,
+        })
+        .into_response(),
+        Ok(Ok(None)) => (
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                success: false,
+                message: Message::NoStoredDistances(threshold).text(state.lang()),
+            }),
+        )
+            .into_response(),
+        Ok(Err(e)) => {
+            error!("Failed to regroup matches: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// The cached groups at `threshold` as the matches endpoints send them
+fn matched_groups(
+    cache: &HashCache,
+    threshold: u32,
+    grid_size: u8,
+    query: &MatchesQuery,
+) -> anyhow::Result<MatchedGroups> {
+    let include_hashes = query.include_hashes.unwrap_or(false);
+    let filtered = query.same_dimensions.unwrap_or(false) || query.min_confidence.is_some();
+    let mut duplicates = if filtered {
+        // Splitting and filtering change which groups are on a page, so they come first
+        get_duplicates_from_cache(cache, threshold, grid_size, None, None)?
+    } else {
+        get_duplicates_from_cache(cache, threshold, grid_size, query.count, query.offset)?
+    };
+    if query.same_dimensions.unwrap_or(false) {
+        duplicates = split_groups_by_dimensions(duplicates, cache);
+    }
+    let mut groups: Vec<(Vec<PathBuf>, Confidence)> = duplicates
+        .into_iter()
+        .map(|group| {
+            let tier = group_confidence(&group, threshold, cache);
+            (group, tier)
+        })
+        .filter(|(_, tier)| query.min_confidence.is_none_or(|min| *tier >= min))
+        .collect();
+    if filtered {
+        groups = groups
+            .into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.count.unwrap_or(usize::MAX))
+            .collect();
+    }
+    let stored_annotations = cache.get_group_annotations()?;
+
+    let mut duplicate_file_infos = Vec::new();
+    let mut confidence = Vec::new();
+    let mut annotations = Vec::new();
+    for (group, tier) in &groups {
+        duplicate_file_infos.push(
+            group
+                .iter()
+                .map(|p| get_file_info_with_details(p, cache, include_hashes))
+                .collect(),
+        );
+        confidence.push(*tier);
+        annotations.push(group_annotation(group, &stored_annotations));
+    }
+
+    Ok((duplicate_file_infos, confidence, annotations))
+}
+
+/// Show what a keep strategy would do to every cached duplicate group, without acting on it
+#[instrument(level = "info", skip(state))]
+async fn handle_resolve_preview(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ResolvePreviewQuery>,
+) -> Result<Json<ResolvePreviewResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let threshold = query
+        .threshold
+        .or(state.threshold_override)
+        .unwrap_or(effective_config.threshold);
+    let grid_size = effective_config.grid_size;
+    let strategy = query.strategy;
+    let same_dimensions = query.same_dimensions.unwrap_or(false);
+    let min_confidence = query.min_confidence.unwrap_or(Confidence::Probable);
+    let sidecars = effective_config.sidecars;
+
+    let groups =
+        tokio::task::spawn_blocking(move || -> Result<Vec<ResolvePreviewGroup>, anyhow::Error> {
+            let mut duplicates =
+                get_duplicates_from_cache(&cache, threshold, grid_size, None, None)?;
+            if same_dimensions {
+                duplicates = split_groups_by_dimensions(duplicates, &cache);
+            }
+
+            Ok(duplicates
+                .iter()
+                .map(|group| (group, group_confidence(group, threshold, &cache)))
+                .filter(|&(_, confidence)| confidence >= min_confidence)
+                .filter_map(|(group, confidence)| {
+                    Some((
+                        resolve_group(group, strategy, sidecars, &cache)?,
+                        confidence,
+                    ))
+                })
+                .map(|(resolution, confidence)| ResolvePreviewGroup {
+                    keep: resolution.keep.display().to_string(),
+                    delete: resolution
+                        .delete
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect(),
+                    sidecars: resolution
+                        .sidecars
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect(),
+                    live_photo_videos: resolution
+                        .live_photo_videos
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect(),
+                    protected: resolution
+                        .protected
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect(),
+                    already_linked: resolution
+                        .already_linked
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect(),
+                    bytes_reclaimed: resolution.bytes_reclaimed,
+                    confidence,
+                })
+                .collect())
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(ResolvePreviewResponse {
+        success: true,
+        strategy,
+        threshold,
+        files_to_delete: groups.iter().map(|g| g.delete.len()).sum(),
+        total_bytes_reclaimed: groups.iter().map(|g| g.bytes_reclaimed).sum(),
+        groups,
+    }))
+}
+
+#[instrument(level = "info", skip(state))]
+async fn handle_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<StatsResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let threshold = query
+        .threshold
+        .or(state.threshold_override)
+        .unwrap_or(effective_config.threshold);
+    let grid_size = effective_config.grid_size;
+    let strategy = query.strategy.unwrap_or(KeepStrategy::KeepLargest);
+    let same_dimensions = query.same_dimensions.unwrap_or(false);
+
+    let stats = tokio::task::spawn_blocking(move || -> Result<DuplicateStats, anyhow::Error> {
+        let mut duplicates = get_duplicates_from_cache(&cache, threshold, grid_size, None, None)?;
+        if same_dimensions {
+            duplicates = split_groups_by_dimensions(duplicates, &cache);
+        }
+        Ok(duplicate_stats(&duplicates, strategy, &cache))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(StatsResponse {
+        success: true,
+        threshold,
+        strategy,
+        stats,
+    }))
+}
+
+/// Atom feed of the newest duplicate groups found by scans, with what each would free up when
+/// keeping the largest file
+async fn handle_feed(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let threshold = effective_config.threshold;
+    let sidecars = effective_config.sidecars;
+    let base_url = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map_or_else(|| "/".to_string(), |host| format!("http://{host}/"));
+
+    let feed = tokio::task::spawn_blocking(move || -> Result<String, anyhow::Error> {
+        let entries: Vec<FeedEntry> = cache
+            .get_group_discoveries(threshold, FEED_ENTRIES)?
+            .into_iter()
+            .map(|discovery| FeedEntry {
+                reclaimable_bytes: resolve_group(
+                    &discovery.paths,
+                    KeepStrategy::KeepLargest,
+                    sidecars,
+                    &cache,
+                )
+                .map_or(0, |resolution| resolution.bytes_reclaimed),
+                discovery,
+            })
+            .collect();
+        Ok(atom_feed(&entries, &base_url, threshold))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")
+        .body(feed.into())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn handle_config(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ConfigResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let grid_size = effective_config.grid_size;
+    let cached = tokio::task::spawn_blocking(move || {
+        HashCache::open(&effective_config).and_then(|cache| cache.provenance())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        error!("Could not read the cache's provenance: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let response = ConfigResponse {
+        grid_size,
+        threshold: state
+            .threshold_override
+            .unwrap_or(state.config().threshold.unwrap_or(15)),
+        database_path: state.config().database_path.clone(),
+        lang: state.lang(),
+        read_only: state.read_only(),
+        hashing: HashProvenance::current(grid_size),
+        cached,
+    };
+
+    Ok(Json(response))
+}
+
+/// Every config option with its default, effective value and where that was set
+async fn handle_config_schema(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<OptionSchema>>, StatusCode> {
+    let file_keys = config_file_keys().map_err(|e| {
+        error!("Could not read the config file: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let stored = state
+        .settings
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    Ok(Json(config_schema(
+        &file_keys,
+        &stored,
+        &state.env_settings,
+        &cli_keys(state.threshold_override, state.grid_size_override),
+        &effective_config,
+    )))
+}
+
+async fn handle_get_ignore_paths(State(state): State<Arc<AppState>>) -> Json<IgnorePaths> {
+    Json(IgnorePaths {
+        ignore_paths: state.config().ignore_paths.unwrap_or_default(),
+    })
+}
+
+/// Replace the ignore list, storing it in the database's settings so it survives a restart.
+/// Later scans use it straight away. Returns the list in effect, which is still the
+/// environment's when `VIBE_IMAGE_COMPARATOR_IGNORE_PATHS` is set
+#[instrument(level = "info", skip(state))]
+async fn handle_put_ignore_paths(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<IgnorePaths>,
+) -> Response {
+    let mut ignore_paths: Vec<String> = Vec::new();
+    for path in request.ignore_paths {
+        let path = path.trim();
+        if !path.is_empty() && !ignore_paths.iter().any(|existing| existing == path) {
+            ignore_paths.push(path.to_string());
+        }
+    }
+
+    info!("Setting ignore paths to {:?}", ignore_paths);
+    if let Err(e) = state.update_settings(|settings| settings.ignore_paths = Some(ignore_paths)) {
+        return settings_save_failed(&state, &e);
+    }
+    Json(IgnorePaths {
+        ignore_paths: state.config().ignore_paths.unwrap_or_default(),
+    })
+    .into_response()
+}
+
+async fn handle_get_settings(State(state): State<Arc<AppState>>) -> Json<Settings> {
+    Json(
+        state
+            .settings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone(),
+    )
+}
+
+/// Replace the stored settings, options left out are removed. The port applies from the
+/// server's next start, everything else straight away
+#[instrument(level = "info", skip(state))]
+async fn handle_put_settings(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<Settings>,
+) -> Response {
+    match state.update_settings(|settings| *settings = request) {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => settings_save_failed(&state, &e),
+    }
+}
+
+fn settings_save_failed(state: &AppState, e: &anyhow::Error) -> Response {
+    error!("Failed to save settings: {}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            success: false,
+            message: Message::SettingsSaveFailed(&e.to_string()).text(state.lang()),
+        }),
+    )
+        .into_response()
+}
+
+#[instrument(level = "info", skip(state))]
+async fn serve_image(
+    State(state): State<Arc<AppState>>,
+    Path(image_path): Path<String>,
+    Query(query): Query<ImageQuery>,
+) -> Result<Response, StatusCode> {
     // URL decode the path first
     let decoded_path = match urlencoding::decode(&image_path) {
         Ok(path) => path.to_string(),
@@ -312,45 +1753,335 @@ async fn serve_image(Path(image_path): Path<String>) -> Result<Response, StatusC
 
     let file_path = std::path::Path::new(&decoded_path);
 
-    // Security check: ensure the path is absolute and exists
-    if !file_path.is_absolute() {
+    // Security check: ensure the path is absolute and is an existing file
+    validate_requested_file(file_path)?;
+    if let Err(message) = check_within_roots(file_path, &state.roots(), state.lang()) {
+        warn!("Refusing to serve image: {}", message);
+        return Ok(forbidden(message));
+    }
+
+    // Images that already fit are sent as they are
+    if let Some(max) = query.max {
+        if !PREVIEW_SIZES.contains(&max) {
+            warn!("Refusing to downscale to {max} pixels: {}", decoded_path);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if let Some(preview) = downscaled_image(&state, file_path, max).await? {
+            state
+                .record_served(file_path, ServeKind::Image, preview.len())
+                .await;
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "image/jpeg")
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .header(header::CACHE_CONTROL, "public, max-age=3600")
+                .body(preview.into())
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    // Read the image file, for ebooks the cover image is served instead
+    let (image_data, image_name) = if is_ebook(file_path) {
+        let ebook_path = file_path.to_path_buf();
+        tokio::task::spawn_blocking(move || extract_epub_cover(&ebook_path))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    } else {
+        match tokio::fs::read(extended_length_path(file_path)).await {
+            Ok(data) => (data, decoded_path.clone()),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    };
+
+    let content_type = image_content_type(
+        &image_data,
+        &image_name,
+        state.config().content_types.as_ref(),
+    )
+    .ok_or_else(|| {
+        warn!("Refusing to serve non-image file: {}", decoded_path);
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    })?;
+
+    state
+        .record_served(file_path, ServeKind::Image, image_data.len())
+        .await;
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CACHE_CONTROL, "public, max-age=3600") // Cache for 1 hour
+        .body(image_data.into())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(response)
+}
+
+/// An image downscaled to fit within `max` pixels, None when it's no larger than that. Copies
+/// are kept in the thumbnail store, so each size is only made once per contents
+async fn downscaled_image(
+    state: &AppState,
+    file_path: &std::path::Path,
+    max: u32,
+) -> Result<Option<Vec<u8>>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let file_path = file_path.to_path_buf();
+    let decoded_path = file_path.display().to_string();
+    tokio::task::spawn_blocking(move || -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let cache = HashCache::open(&effective_config)?;
+        // Known dimensions save decoding an image that fits anyway
+        if let Some((width, height)) = cache.get_cached_dimensions(&file_path)? {
+            if width <= max && height <= max {
+                return Ok(None);
+            }
+        }
+        let sha256 = match cache.get_cached_hash_details(&file_path)? {
+            Some((_, sha256)) => sha256,
+            None => calculate_file_sha256(&file_path)?,
+        };
+        load_or_create_preview(cache.thumbnail_dir().as_deref(), &file_path, &sha256, max)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        warn!("Could not downscale {}: {}", decoded_path, e);
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    })
+}
+
+/// A small JPEG of an image for the result list, from the thumbnail store when the image was
+/// thumbnailed already (e.g. with `--generate-thumbnails`), otherwise created and stored now
+async fn serve_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Path(image_path): Path<String>,
+) -> Result<Response, StatusCode> {
+    let decoded_path = match urlencoding::decode(&image_path) {
+        Ok(path) => path.to_string(),
+        Err(e) => {
+            error!("Failed to decode URL path '{}': {}", image_path, e);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let file_path = PathBuf::from(&decoded_path);
+    validate_requested_file(&file_path)?;
+    if let Err(message) = check_within_roots(&file_path, &state.roots(), state.lang()) {
+        warn!("Refusing to serve thumbnail: {}", message);
+        return Ok(forbidden(message));
+    }
+
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let thumbnail_path = file_path.clone();
+    let thumbnail = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, anyhow::Error> {
+        let cache = HashCache::open(&effective_config)?;
+        let sha256 = match cache.get_cached_hash_details(&file_path)? {
+            Some((_, sha256)) => sha256,
+            None => calculate_file_sha256(&file_path)?,
+        };
+        match cache.thumbnail_dir() {
+            Some(dir) => load_or_create_thumbnail(&dir, &file_path, &sha256),
+            None => render_thumbnail(&open_image(&file_path)?),
+        }
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        warn!("Could not create thumbnail for {}: {}", decoded_path, e);
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    })?;
+
+    state
+        .record_served(&thumbnail_path, ServeKind::Thumbnail, thumbnail.len())
+        .await;
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "image/jpeg")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CACHE_CONTROL, "public, max-age=3600")
+        .body(thumbnail.into())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Bytes sent per server run and the files sent most recently in this one
+#[instrument(level = "info", skip(state))]
+async fn handle_usage(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let sessions = cache
+        .get_session_usage()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let recent = cache
+        .get_served_files(state.session_started, query.limit.unwrap_or(50))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let session = sessions
+        .iter()
+        .find(|session| session.started_at == state.session_started)
+        .cloned()
+        .unwrap_or(SessionUsage {
+            started_at: state.session_started,
+            requests: 0,
+            files: 0,
+            bytes: 0,
+            image_bytes: 0,
+            thumbnail_bytes: 0,
+        });
+
+    Ok(Json(UsageResponse {
+        success: true,
+        session,
+        sessions,
+        recent,
+    }))
+}
+
+/// Content type to serve an image with: sniffed from its contents, falling back to the
+/// configured type for its extension for formats that can't be recognised. None for anything
+/// that isn't a raster image, including SVG since it can carry scripts
+fn image_content_type(
+    data: &[u8],
+    name: &str,
+    configured: Option<&BTreeMap<String, String>>,
+) -> Option<String> {
+    let extension = std::path::Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+
+    let content_type = match sniff_content_type(data) {
+        Some(sniffed) => sniffed.to_string(),
+        None => configured?.get(&extension)?.clone(),
+    };
+
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    (essence.starts_with("image/") && essence != "image/svg+xml").then_some(content_type)
+}
+
+/// Check that `path` lies inside one of `roots`, describing why not otherwise. Both sides are
+/// resolved first so `..` and symlinks can't lead outside a root. Any path passes without roots
+fn check_within_roots(path: &std::path::Path, roots: &[PathBuf], lang: Lang) -> Result<(), String> {
+    if roots.is_empty() {
+        return Err(Message::NoServerRoots.text(lang));
+    }
+
+    let Some(resolved) = resolved_path(path) else {
+        return Err(Message::PathMissing(path).text(lang));
+    };
+    if roots
+        .iter()
+        .filter_map(|root| resolved_path(root))
+        .any(|root| resolved.starts_with(root))
+    {
+        return Ok(());
+    }
+
+    let allowed: Vec<String> = roots
+        .iter()
+        .map(|root| root.display().to_string())
+        .collect();
+    Err(Message::OutsideServerRoots {
+        path,
+        roots: &allowed.join(", "),
+    }
+    .text(lang))
+}
+
+/// Absolute path with symlinks and `..` resolved, None if it doesn't exist
+fn resolved_path(path: &std::path::Path) -> Option<PathBuf> {
+    std::fs::canonicalize(extended_length_path(path))
+        .ok()
+        .map(|resolved| strip_verbatim_prefix(&resolved))
+}
+
+/// 403 response explaining why a path was refused
+fn forbidden(message: String) -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse {
+            success: false,
+            message,
+        }),
+    )
+        .into_response()
+}
+
+/// Validate that a requested path is an absolute path to an existing file
+fn validate_requested_file(file_path: &std::path::Path) -> Result<(), StatusCode> {
+    if !is_absolute_path(file_path) {
         error!("Requested path is not absolute: {}", file_path.display());
         return Err(StatusCode::BAD_REQUEST);
     }
 
-    if !file_path.exists() {
+    let fs_path = extended_length_path(file_path);
+    if !fs_path.exists() {
         error!("Requested file does not exist: {}", file_path.display());
         return Err(StatusCode::NOT_FOUND);
     }
 
-    // Check if it's actually a file (not a directory)
-    if !file_path.is_file() {
+    if !fs_path.is_file() {
         error!("Requested path is not a file: {}", file_path.display());
         return Err(StatusCode::BAD_REQUEST);
     }
 
-    // Read the image file
-    let image_data = match tokio::fs::read(file_path).await {
-        Ok(data) => data,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
+    Ok(())
+}
 
-    // Determine content type based on file extension
-    let content_type = match file_path.extension().and_then(|ext| ext.to_str()) {
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("png") => "image/png",
-        Some("gif") => "image/gif",
-        Some("webp") => "image/webp",
-        Some("bmp") => "image/bmp",
-        Some("tiff") | Some("tif") => "image/tiff",
-        _ => "application/octet-stream",
-    };
+#[instrument(level = "info", skip(state))]
+async fn serve_diff(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Response, StatusCode> {
+    let path_a = PathBuf::from(&query.a);
+    let path_b = PathBuf::from(&query.b);
+    validate_requested_file(&path_a)?;
+    validate_requested_file(&path_b)?;
+    let roots = state.roots();
+    for path in [&path_a, &path_b] {
+        if let Err(message) = check_within_roots(path, &roots, state.lang()) {
+            warn!("Refusing to diff: {}", message);
+            return Ok(forbidden(message));
+        }
+    }
+
+    let size = query.size.unwrap_or(DEFAULT_DIFF_SIZE).clamp(16, 2048);
+
+    // Decoding and diffing full-resolution images is CPU heavy, keep it off the async runtime
+    let png_data = tokio::task::spawn_blocking(move || diff_heatmap_png(&path_a, &path_b, size))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!(
+                "Failed to render diff for {} and {}: {}",
+                query.a, query.b, e
+            );
+            StatusCode::UNPROCESSABLE_ENTITY
+        })?;
 
     let response = Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, content_type)
-        .header(header::CACHE_CONTROL, "public, max-age=3600") // Cache for 1 hour
-        .body(image_data.into())
+        .header(header::CONTENT_TYPE, "image/png")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(png_data.into())
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     Ok(response)
@@ -359,43 +2090,54 @@ async fn serve_image(Path(image_path): Path<String>) -> Result<Response, StatusC
 async fn check_files_exist(
     State(state): State<Arc<AppState>>,
     Json(request): Json<CheckFilesRequest>,
-) -> Json<CheckFilesResponse> {
+) -> Response {
+    let roots = state.roots();
+    for path_str in &request.paths {
+        let path = std::path::Path::new(path_str);
+        // A file that's gone is reported missing as long as its folder is inside a root
+        let checked = match path.parent() {
+            Some(parent) if !extended_length_path(path).exists() => parent,
+            _ => path,
+        };
+        if let Err(message) = check_within_roots(checked, &roots, state.lang()) {
+            warn!("Refusing to check files: {}", message);
+            return forbidden(message);
+        }
+    }
+
     let effective_config =
         state
-            .config
+            .config()
             .with_overrides(state.grid_size_override, state.threshold_override, None);
 
-    let files: Vec<FileInfo> =
-        if let Ok(cache) = HashCache::new(effective_config.database_path.as_deref()) {
-            request
-                .paths
-                .iter()
-                .map(|path_str| {
-                    let path = std::path::Path::new(path_str);
-                    get_file_info_with_details(path, &cache)
-                })
-                .collect()
-        } else {
-            // Fallback if cache is not available
-            request
-                .paths
-                .iter()
-                .map(|path_str| {
-                    let path = std::path::Path::new(path_str);
-                    FileInfo {
-                        path: path_str.clone(),
-                        exists: path.exists(),
-                        size: path
-                            .exists()
-                            .then(|| std::fs::metadata(path).map(|m| m.len()).ok())
-                            .flatten(),
-                        hash: None,
-                    }
-                })
-                .collect()
-        };
+    let files: Vec<FileInfo> = if let Ok(cache) = HashCache::open(&effective_config) {
+        request
+            .paths
+            .iter()
+            .map(|path_str| {
+                let path = std::path::Path::new(path_str);
+                get_file_info_with_details(path, &cache, false)
+            })
+            .collect()
+    } else {
+        // Fallback if cache is not available
+        request
+            .paths
+            .iter()
+            .map(|path_str| {
+                let path = extended_length_path(std::path::Path::new(path_str));
+                FileInfo::without_details(
+                    path_str.clone(),
+                    path.exists(),
+                    path.exists()
+                        .then(|| std::fs::metadata(&path).map(|m| m.len()).ok())
+                        .flatten(),
+                )
+            })
+            .collect()
+    };
 
-    Json(CheckFilesResponse { files })
+    Json(CheckFilesResponse { files }).into_response()
 }
 
 async fn serve_css() -> Result<Response, StatusCode> {
@@ -403,7 +2145,7 @@ async fn serve_css() -> Result<Response, StatusCode> {
 
     let response = Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/css")
+        .header(header::CONTENT_TYPE, "text/css; charset=utf-8")
         .header(header::CACHE_CONTROL, "no-cache, no-store, must-revalidate")
         .header(header::PRAGMA, "no-cache")
         .header(header::EXPIRES, "0")
@@ -418,63 +2160,432 @@ async fn delete_file(
     Json(request): Json<DeleteFileRequest>,
 ) -> Json<DeleteFileResponse> {
     let file_path = std::path::Path::new(&request.path);
+    let lang = state.lang();
 
-    // Security check: ensure the path is absolute
-    if !file_path.is_absolute() {
+    // Security check: ensure the path is absolute (UNC and verbatim paths included on Windows)
+    if !is_absolute_path(file_path) {
         return Json(DeleteFileResponse {
             success: false,
-            message: "Path must be absolute".to_string(),
+            message: Message::PathNotAbsolute.text(lang),
+            deleted_sidecars: Vec::new(),
+            deleted_live_photo_video: None,
         });
     }
 
+    let fs_path = extended_length_path(file_path);
+
     // Check if file exists
-    if !file_path.exists() {
+    if !fs_path.exists() {
         return Json(DeleteFileResponse {
             success: false,
-            message: "File does not exist".to_string(),
+            message: Message::FileNotFound.text(lang),
+            deleted_sidecars: Vec::new(),
+            deleted_live_photo_video: None,
         });
     }
 
     // Check if it's actually a file (not a directory)
-    if !file_path.is_file() {
+    if !fs_path.is_file() {
+        return Json(DeleteFileResponse {
+            success: false,
+            message: Message::NotAFile.text(lang),
+            deleted_sidecars: Vec::new(),
+            deleted_live_photo_video: None,
+        });
+    }
+
+    if let Err(message) = check_within_roots(file_path, &state.roots(), lang) {
+        warn!("Refusing to delete: {}", message);
+        return Json(DeleteFileResponse {
+            success: false,
+            message,
+            deleted_sidecars: Vec::new(),
+            deleted_live_photo_video: None,
+        });
+    }
+
+    // Read from the file itself, a tag set since the last scan counts too
+    if is_keep_tagged(&file_tags(file_path)) {
+        warn!("Refusing to delete {}: tagged Keep", file_path.display());
         return Json(DeleteFileResponse {
             success: false,
-            message: "Path is not a file".to_string(),
+            message: Message::TaggedKeep.text(lang),
+            deleted_sidecars: Vec::new(),
+            deleted_live_photo_video: None,
         });
     }
 
     // Get the effective config for database path
     let effective_config =
         state
-            .config
+            .config()
             .with_overrides(state.grid_size_override, state.threshold_override, None);
 
-    // Attempt to delete the file
-    match std::fs::remove_file(file_path) {
-        Ok(()) => {
-            info!("Deleted file: {}", file_path.display());
+    // Paired by modification time, so it has to be found before the still is gone
+    let live_photo_video = live_photo_video(file_path);
+    let mut seen: BTreeSet<PathBuf> = std::iter::once(file_path.to_path_buf())
+        .chain(live_photo_video.clone())
+        .collect();
+    let sidecars = unseen_paths(
+        affected_sidecars(file_path, effective_config.sidecars),
+        &mut seen,
+    );
+
+    let mut deleted = vec![file_path.to_path_buf()];
+    deleted.extend(live_photo_video.iter().cloned());
+    deleted.extend(sidecars.iter().cloned());
+    if is_dry_run() {
+        let bytes = deleted
+            .iter()
+            .filter_map(|path| std::fs::metadata(extended_length_path(path)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        for path in &deleted {
+            info!("Would delete {}", path.display());
+        }
+        return Json(DeleteFileResponse {
+            success: false,
+            message: Message::DeleteDryRun {
+                files: deleted.len(),
+                bytes,
+            }
+            .text(lang),
+            deleted_sidecars: Vec::new(),
+            deleted_live_photo_video: None,
+        });
+    }
 
-            // Remove file from database
-            if let Ok(cache) = HashCache::new(effective_config.database_path.as_deref()) {
-                if let Err(e) = cache.remove_file_entry(file_path) {
-                    warn!("Failed to remove file from database: {}", e);
-                    // Don't fail the entire operation if database cleanup fails
-                }
-            } else {
-                warn!("Failed to connect to database for cleanup");
+    // All files go or none do, the motion part left behind would be an orphan no duplicate
+    // scan finds
+    let operations: Vec<FileOperation> = deleted.into_iter().map(FileOperation::Delete).collect();
+    let outcome = apply_file_operations(&operations);
+
+    let applied = match &outcome {
+        Ok(()) => operations.as_slice(),
+        Err(failure) => failure.left_applied.as_slice(),
+    };
+    match HashCache::open(&effective_config) {
+        // Don't fail the entire operation if database cleanup fails
+        Ok(cache) => {
+            if let Err(e) = update_cache(&cache, applied) {
+                warn!("Failed to remove file from database: {}", e);
             }
+        }
+        Err(_) => warn!("Failed to connect to database for cleanup"),
+    }
 
+    match outcome {
+        Ok(()) => {
+            info!("Deleted file: {}", file_path.display());
+            if let Some(video) = &live_photo_video {
+                info!("Deleted Live Photo video: {}", video.display());
+            }
+            let message = match (sidecars.len(), deletes_permanently()) {
+                (0, true) => Message::FileDeleted,
+                (0, false) => Message::FileTrashed,
+                (count, true) => Message::FileAndSidecarsDeleted(count),
+                (count, false) => Message::FileAndSidecarsTrashed(count),
+            }
+            .text(lang);
+            if !sidecars.is_empty() {
+                info!("Deleted {} sidecar files", sidecars.len());
+            }
             Json(DeleteFileResponse {
                 success: true,
-                message: "File deleted successfully".to_string(),
+                message,
+                deleted_sidecars: sidecars.iter().map(|p| p.display().to_string()).collect(),
+                deleted_live_photo_video: live_photo_video.map(|video| video.display().to_string()),
             })
         }
-        Err(e) => {
-            error!("Failed to delete file {}: {}", file_path.display(), e);
+        Err(failure) => {
+            error!("Failed to delete file {}: {}", file_path.display(), failure);
             Json(DeleteFileResponse {
                 success: false,
-                message: format!("Failed to delete file: {e}"),
+                message: Message::DeleteFailed(&failure.to_string()).text(lang),
+                deleted_sidecars: Vec::new(),
+                deleted_live_photo_video: None,
+            })
+        }
+    }
+}
+
+/// Record a manual group adjustment, it's applied on top of every later scan and match result
+fn record_group_override(
+    state: &AppState,
+    group_override: GroupOverride,
+) -> Json<GroupOverrideResponse> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+
+    let result = HashCache::open(&effective_config)
+        .and_then(|cache| cache.add_group_override(&group_override));
+
+    match result {
+        Ok(()) => {
+            info!("Recorded group override: {:?}", group_override);
+            Json(GroupOverrideResponse {
+                success: true,
+                message: Message::OverrideSaved.text(state.lang()),
+            })
+        }
+        Err(e) => {
+            error!("Failed to record group override: {}", e);
+            Json(GroupOverrideResponse {
+                success: false,
+                message: Message::OverrideFailed(&e.to_string()).text(state.lang()),
+            })
+        }
+    }
+}
+
+async fn handle_merge_groups(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<MergeGroupsRequest>,
+) -> Json<GroupOverrideResponse> {
+    let (a, b) = (PathBuf::from(&request.a), PathBuf::from(&request.b));
+    if !is_absolute_path(&a) || !is_absolute_path(&b) {
+        return Json(GroupOverrideResponse {
+            success: false,
+            message: Message::PathsNotAbsolute.text(state.lang()),
+        });
+    }
+    if a == b {
+        return Json(GroupOverrideResponse {
+            success: false,
+            message: Message::CannotMergeWithItself.text(state.lang()),
+        });
+    }
+    let roots = state.roots();
+    for path in [&a, &b] {
+        if let Err(message) = check_within_roots(path, &roots, state.lang()) {
+            warn!("Refusing to merge groups: {}", message);
+            return Json(GroupOverrideResponse {
+                success: false,
+                message,
+            });
+        }
+    }
+
+    record_group_override(&state, GroupOverride::Merge { a, b })
+}
+
+async fn handle_split_group(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SplitGroupRequest>,
+) -> Json<GroupOverrideResponse> {
+    let path = PathBuf::from(&request.path);
+    if !is_absolute_path(&path) {
+        return Json(GroupOverrideResponse {
+            success: false,
+            message: Message::PathNotAbsolute.text(state.lang()),
+        });
+    }
+    if let Err(message) = check_within_roots(&path, &state.roots(), state.lang()) {
+        warn!("Refusing to split group: {}", message);
+        return Json(GroupOverrideResponse {
+            success: false,
+            message,
+        });
+    }
+
+    record_group_override(&state, GroupOverride::Split { path })
+}
+
+async fn handle_pin_group(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<PinGroupRequest>,
+) -> Json<GroupOverrideResponse> {
+    let pinned = request.pinned;
+    let message = if pinned {
+        Message::GroupPinned
+    } else {
+        Message::GroupUnpinned
+    };
+    annotate_group(&state, &request.paths, message, |cache, paths| {
+        cache.set_group_pinned(paths, pinned)
+    })
+}
+
+async fn handle_group_note(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<GroupNoteRequest>,
+) -> Json<GroupOverrideResponse> {
+    let note = request
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+    let message = if note.is_some() {
+        Message::NoteSaved
+    } else {
+        Message::NoteRemoved
+    };
+    annotate_group(&state, &request.paths, message, |cache, paths| {
+        cache.set_group_note(paths, note)
+    })
+}
+
+/// Store a pin or note on every file of a group
+fn annotate_group(
+    state: &AppState,
+    paths: &[String],
+    saved: Message<'_>,
+    update: impl FnOnce(&HashCache, &[PathBuf]) -> Result<()>,
+) -> Json<GroupOverrideResponse> {
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    if paths.is_empty() || !paths.iter().all(|path| is_absolute_path(path)) {
+        return Json(GroupOverrideResponse {
+            success: false,
+            message: Message::PathsNotAbsolute.text(state.lang()),
+        });
+    }
+
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    match HashCache::open(&effective_config).and_then(|cache| update(&cache, &paths)) {
+        Ok(()) => Json(GroupOverrideResponse {
+            success: true,
+            message: saved.text(state.lang()),
+        }),
+        Err(e) => {
+            error!("Failed to annotate group: {}", e);
+            Json(GroupOverrideResponse {
+                success: false,
+                message: Message::AnnotationFailed(&e.to_string()).text(state.lang()),
             })
         }
     }
 }
+
+async fn handle_list_overrides(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<GroupOverridesResponse>, StatusCode> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+    let cache =
+        HashCache::open(&effective_config).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let overrides = cache
+        .get_group_overrides()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(GroupOverridesResponse {
+        success: true,
+        overrides,
+    }))
+}
+
+async fn handle_clear_overrides(State(state): State<Arc<AppState>>) -> Json<GroupOverrideResponse> {
+    let effective_config =
+        state
+            .config()
+            .with_overrides(state.grid_size_override, state.threshold_override, None);
+
+    match HashCache::open(&effective_config).and_then(|cache| cache.clear_group_overrides()) {
+        Ok(removed) => Json(GroupOverrideResponse {
+            success: true,
+            message: Message::OverridesCleared(removed).text(state.lang()),
+        }),
+        Err(e) => Json(GroupOverrideResponse {
+            success: false,
+            message: Message::OverridesClearFailed(&e.to_string()).text(state.lang()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn timeouts_become_json_errors() {
+        let response = handle_timeout_error(Box::new(Elapsed::new())).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body should be readable");
+        let json: serde_json::Value = serde_json::from_slice(&body).expect("body should be JSON");
+        assert_eq!(json["success"], false);
+    }
+
+    const PNG_HEADER: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+    #[test]
+    fn content_type_prefers_file_contents() {
+        assert_eq!(
+            image_content_type(PNG_HEADER, "/photos/mislabelled.jpg", None).as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            image_content_type(PNG_HEADER, "/photos/no_extension", None).as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            image_content_type(b"\0\0\0\x1cftypavif\0\0\0\0", "/photos/IMG_1.avif", None)
+                .as_deref(),
+            Some("image/avif")
+        );
+        assert_eq!(
+            image_content_type(&[0xFF, 0x0A, 0xFA, 0x1F], "/photos/export.jxl", None).as_deref(),
+            Some("image/jxl")
+        );
+    }
+
+    #[test]
+    fn content_type_falls_back_to_extension() {
+        let configured = BTreeMap::from([
+            ("heic".to_string(), "image/heic".to_string()),
+            ("txt".to_string(), "text/plain".to_string()),
+            ("svg".to_string(), "image/svg+xml".to_string()),
+        ]);
+        let unknown = b"....ftypheic";
+
+        assert_eq!(
+            image_content_type(unknown, "/photos/IMG_1.HEIC", Some(&configured)).as_deref(),
+            Some("image/heic")
+        );
+        // Contents that don't match a recognised extension aren't trusted
+        assert_eq!(
+            image_content_type(unknown, "/photos/IMG_1.png", Some(&configured)),
+            None
+        );
+        assert_eq!(
+            image_content_type(unknown, "/photos/IMG_1.heic", None),
+            None
+        );
+        assert_eq!(
+            image_content_type(b"hello", "/notes.txt", Some(&configured)),
+            None
+        );
+        assert_eq!(
+            image_content_type(b"<svg>", "/logo.svg", Some(&configured)),
+            None
+        );
+    }
+
+    #[test]
+    fn paths_must_resolve_inside_a_root() {
+        let temp_dir = tempfile::TempDir::new().expect("temp dir");
+        let root = temp_dir.path().join("photos");
+        let outside = temp_dir.path().join("private");
+        std::fs::create_dir_all(root.join("2024")).expect("create root");
+        std::fs::create_dir_all(&outside).expect("create outside");
+        let roots = vec![root.clone()];
+
+        assert!(check_within_roots(&root.join("2024"), &roots, Lang::En).is_ok());
+        let message = check_within_roots(&outside, &[], Lang::En).expect_err("no roots");
+        assert!(message.contains("server_roots"), "{message}");
+
+        let message = check_within_roots(&outside, &roots, Lang::En).expect_err("outside the root");
+        assert!(message.contains("server_roots"), "{message}");
+        // Lexically inside the root, but resolves to a sibling
+        assert!(check_within_roots(&root.join("../private"), &roots, Lang::En).is_err());
+        assert!(check_within_roots(&root.join("missing"), &roots, Lang::En).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::env;
+use std::path::Path;
+
+use crate::cache::Config;
+
+/// Prefix of the environment variables that override stored settings, e.g.
+/// `VIBE_IMAGE_COMPARATOR_THRESHOLD`
+pub const ENV_PREFIX: &str = "VIBE_IMAGE_COMPARATOR_";
+
+/// Options that can be changed while running and are stored in the database.
+///
+/// They're layered over the config file with this precedence, highest first:
+/// command line, environment variables, database settings, config file, defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore_paths: Option<Vec<String>>,
+    /// Port the web server listens on, used from its next start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+/// Name of a stored setting, as given to `config set` and `config unset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SettingKey {
+    Threshold,
+    IgnorePaths,
+    Port,
+}
+
+impl SettingKey {
+    fn env_var(self) -> String {
+        let name = match self {
+            SettingKey::Threshold => "THRESHOLD",
+            SettingKey::IgnorePaths => "IGNORE_PATHS",
+            SettingKey::Port => "PORT",
+        };
+        format!("{ENV_PREFIX}{name}")
+    }
+}
+
+impl Settings {
+    /// Set one option from text. Ignore paths are separated like `PATH`, by `:` (`;` on Windows)
+    pub fn set(&mut self, key: SettingKey, value: &str) -> Result<()> {
+        match key {
+            SettingKey::Threshold => {
+                self.threshold = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("Invalid threshold '{value}'"))?,
+                );
+            }
+            SettingKey::IgnorePaths => {
+                self.ignore_paths = Some(
+                    env::split_paths(value)
+                        .map(|path| path.display().to_string())
+                        .filter(|path| !path.is_empty())
+                        .collect(),
+                );
+            }
+            SettingKey::Port => {
+                self.port = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("Invalid port '{value}'"))?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget one option, so the next layer down applies again
+    pub fn unset(&mut self, key: SettingKey) {
+        match key {
+            SettingKey::Threshold => self.threshold = None,
+            SettingKey::IgnorePaths => self.ignore_paths = None,
+            SettingKey::Port => self.port = None,
+        }
+    }
+
+    /// Settings given as `VIBE_IMAGE_COMPARATOR_*` environment variables
+    pub fn from_env() -> Result<Self> {
+        let mut settings = Settings::default();
+        for key in SettingKey::value_variants() {
+            let name = key.env_var();
+            if let Ok(value) = env::var(&name) {
+                settings
+                    .set(*key, &value)
+                    .with_context(|| format!("Invalid value in {name}"))?;
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Put the options that are set over `config`'s values
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(threshold) = self.threshold {
+            config.threshold = Some(threshold);
+        }
+        if let Some(ignore_paths) = &self.ignore_paths {
+            config.ignore_paths = Some(ignore_paths.clone());
+        }
+        if let Some(port) = self.port {
+            config.port = Some(port);
+        }
+    }
+}
+
+/// The config file's values with database settings and then environment variables on top.
+/// Command line options are applied afterwards, by `Config::with_overrides`
+pub fn layered_config(config: &Config, stored: &Settings, env_settings: &Settings) -> Config {
+    let mut config = config.clone();
+    stored.apply(&mut config);
+    env_settings.apply(&mut config);
+    config
+}
+
+/// Read the settings table, one JSON value per option
+pub fn read_settings(conn: &Connection) -> Result<Settings> {
+    let mut stmt = conn.prepare("SELECT key, value FROM settings")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut values = Map::new();
+    for row in rows {
+        let (key, value) = row?;
+        let value: Value = serde_json::from_str(&value)
+            .with_context(|| format!("Stored setting {key} is not valid JSON"))?;
+        values.insert(key, value);
+    }
+    // Settings written by newer versions are skipped rather than failing the load
+    serde_json::from_value(Value::Object(values)).context("Stored settings are invalid")
+}
+
+/// Replace the settings table with the options that are set
+pub fn write_settings(conn: &Connection, settings: &Settings) -> Result<()> {
+    let Value::Object(values) = serde_json::to_value(settings)? else {
+        return Err(anyhow!("Settings should serialize to an object"));
+    };
+    conn.execute("DELETE FROM settings", [])?;
+    for (key, value) in values {
+        conn.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)",
+            params![key, value.to_string()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Settings stored in the database at `db_path`, read without opening it for writing so that
+/// loading the config never creates or migrates a database. Empty when there's no database yet
+/// or it predates the settings table
+pub fn load_stored_settings(db_path: &Path) -> Result<Settings> {
+    if !db_path.exists() {
+        return Ok(Settings::default());
+    }
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Could not open {}", db_path.display()))?;
+    let has_table = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'settings'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    if !has_table {
+        return Ok(Settings::default());
+    }
+    read_settings(&conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::HashCache;
+    use tempfile::TempDir;
+
+    #[test]
+    fn later_layers_win() {
+        let file = Config {
+            threshold: Some(10),
+            ignore_paths: Some(vec!["/file".to_string()]),
+            port: Some(8000),
+            ..Config::default()
+        };
+        let stored = Settings {
+            threshold: Some(12),
+            ignore_paths: Some(vec!["/stored".to_string()]),
+            ..Settings::default()
+        };
+        let mut env_settings = Settings::default();
+        env_settings
+            .set(SettingKey::Threshold, "14")
+            .expect("threshold should parse");
+
+        let config = layered_config(&file, &stored, &env_settings);
+        assert_eq!(config.threshold, Some(14));
+        assert_eq!(config.ignore_paths, Some(vec!["/stored".to_string()]));
+        assert_eq!(config.port, Some(8000));
+
+        // The command line still beats every layer
+        let resolved = config.with_overrides(None, Some(3), None);
+        assert_eq!(resolved.threshold, 3);
+        assert!(env_settings.set(SettingKey::Port, "http").is_err());
+    }
+
+    #[test]
+    fn settings_round_trip_through_the_database() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let db_path = temp_dir.path().join("hashes.db");
+        assert_eq!(
+            load_stored_settings(&db_path).expect("missing database"),
+            Settings::default()
+        );
+        assert!(!db_path.exists(), "loading settings shouldn't create it");
+
+        let cache = HashCache::new(Some(&db_path.display().to_string())).expect("cache");
+        let mut settings = Settings::default();
+        settings
+            .set(SettingKey::IgnorePaths, "/a/b")
+            .expect("ignore paths should parse");
+        settings.port = Some(9000);
+        cache.store_settings(&settings).expect("store settings");
+        assert_eq!(cache.get_settings().expect("get settings"), settings);
+
+        settings.unset(SettingKey::Port);
+        cache.store_settings(&settings).expect("store settings");
+        assert_eq!(
+            load_stored_settings(&db_path).expect("stored settings"),
+            Settings {
+                ignore_paths: Some(vec!["/a/b".to_string()]),
+                ..Settings::default()
+            }
+        );
+    }
+}
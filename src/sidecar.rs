@@ -0,0 +1,248 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::journal::{apply_file_operations, FileOperation};
+use crate::livephoto::{live_photo_video, STILL_EXTENSIONS};
+use crate::paths::extended_length_path;
+use crate::strategy::image_extensions;
+
+/// Extensions added to the image's stem: `IMG_0001.xmp` next to `IMG_0001.jpg`
+const STEM_SIDECAR_EXTENSIONS: [&str; 4] = ["xmp", "XMP", "json", "JSON"];
+/// Suffixes added to the full file name: `IMG_0001.jpg.xmp` (darktable) and `IMG_0001.jpg.json`
+/// or `IMG_0001.jpg.supplemental-metadata.json` (Google Takeout)
+const NAME_SIDECAR_SUFFIXES: [&str; 5] = [
+    ".xmp",
+    ".XMP",
+    ".json",
+    ".JSON",
+    ".supplemental-metadata.json",
+];
+
+/// What happens to an image's sidecar metadata files when the image is deleted or moved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SidecarMode {
+    /// Leave sidecars where they are
+    #[default]
+    Ignore,
+    /// Delete or move sidecars together with their image
+    Follow,
+}
+
+impl std::fmt::Display for SidecarMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SidecarMode::Ignore => "ignore",
+            SidecarMode::Follow => "follow",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Sidecar file names that can belong to an image, whether or not they exist. Names made from
+/// the stem are left out unless `with_stem`
+fn sidecar_names(path: &Path, with_stem: bool) -> Vec<OsString> {
+    let mut names = Vec::new();
+    if let Some(stem) = path.file_stem().filter(|_| with_stem) {
+        for extension in STEM_SIDECAR_EXTENSIONS {
+            let mut name = stem.to_os_string();
+            name.push(".");
+            name.push(extension);
+            names.push(name);
+        }
+    }
+    if let Some(file_name) = path.file_name() {
+        for suffix in NAME_SIDECAR_SUFFIXES {
+            let mut name = file_name.to_os_string();
+            name.push(suffix);
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Whether another image or Live Photo still in the folder has the same stem, e.g.
+/// `IMG_0001.jpg` next to `IMG_0001.png`. `IMG_0001.xmp` or `IMG_0001.MOV` could then belong to
+/// either, so it isn't carried along with one of them. A folder that can't be listed counts as
+/// shared
+pub fn shares_stem(path: &Path) -> bool {
+    let (Some(parent), Some(stem), Some(name)) =
+        (path.parent(), path.file_stem(), path.file_name())
+    else {
+        return false;
+    };
+    let Ok(entries) = fs::read_dir(extended_length_path(parent)) else {
+        return true;
+    };
+    let extensions = image_extensions();
+    entries.filter_map(Result::ok).any(|entry| {
+        let other = PathBuf::from(entry.file_name());
+        other.as_os_str() != name
+            && other.file_stem() == Some(stem)
+            && other.extension().is_some_and(|extension| {
+                let extension = extension.to_string_lossy().to_lowercase();
+                extensions.contains(&extension) || STILL_EXTENSIONS.contains(&extension.as_str())
+            })
+    })
+}
+
+/// Existing sidecar files of an image, in a stable order. Sidecars named after the stem are only
+/// found when no other image shares it
+pub fn find_sidecars(path: &Path) -> Vec<PathBuf> {
+    let mut sidecars: Vec<PathBuf> = sidecar_names(path, !shares_stem(path))
+        .into_iter()
+        .map(|name| path.with_file_name(name))
+        .filter(|sidecar| sidecar != path && extended_length_path(sidecar).is_file())
+        .collect();
+    // Case-insensitive filesystems report XMP and xmp as the same existing file
+    sidecars.sort_by_key(|sidecar| sidecar.to_string_lossy().to_lowercase());
+    sidecars.dedup_by_key(|sidecar| sidecar.to_string_lossy().to_lowercase());
+    sidecars
+}
+
+/// Sidecars that an action on the image carries along under `mode`
+pub fn affected_sidecars(path: &Path, mode: SidecarMode) -> Vec<PathBuf> {
+    match mode {
+        SidecarMode::Ignore => Vec::new(),
+        SidecarMode::Follow => find_sidecars(path),
+    }
+}
+
+/// The `paths` not yet in `seen`, each once, adding them to it. A file two images both carry
+/// along, or that is one of the images, only gets one operation
+pub fn unseen_paths(
+    paths: impl IntoIterator<Item = PathBuf>,
+    seen: &mut BTreeSet<PathBuf>,
+) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+/// Where a sidecar goes when its image is renamed from `from` to `to`, keeping the naming
+/// convention it used
+pub fn renamed_sidecar(sidecar: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    let sidecar_name = sidecar.file_name()?.to_string_lossy().into_owned();
+    let from_name = from.file_name()?.to_string_lossy().into_owned();
+    let to_name = to.file_name()?.to_string_lossy().into_owned();
+
+    let new_name = if let Some(suffix) = sidecar_name.strip_prefix(from_name.as_str()) {
+        format!("{to_name}{suffix}")
+    } else {
+        let from_stem = from.file_stem()?.to_string_lossy().into_owned();
+        let to_stem = to.file_stem()?.to_string_lossy().into_owned();
+        let suffix = sidecar_name.strip_prefix(from_stem.as_str())?;
+        format!("{to_stem}{suffix}")
+    };
+    Some(to.with_file_name(new_name))
+}
+
+/// Move an image, its Live Photo video and, under `mode`, its sidecars, all or none of them.
+/// Returns every (from, to) pair moved, the image first
+pub fn move_with_sidecars(
+    from: &Path,
+    to: &Path,
+    mode: SidecarMode,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut moved = vec![(from.to_path_buf(), to.to_path_buf())];
+    let mut seen = BTreeSet::from([from.to_path_buf()]);
+    // Paired by modification time, so it has to be found while the still is in place
+    for video in unseen_paths(live_photo_video(from), &mut seen) {
+        let extension = video.extension().unwrap_or_default().to_os_string();
+        let destination = to.with_extension(extension);
+        moved.push((video, destination));
+    }
+    for sidecar in unseen_paths(affected_sidecars(from, mode), &mut seen) {
+        if let Some(destination) = renamed_sidecar(&sidecar, from, to) {
+            moved.push((sidecar, destination));
+        }
+    }
+
+    let operations: Vec<FileOperation> = moved
+        .iter()
+        .map(|(from, to)| FileOperation::Move {
+            from: from.clone(),
+            to: to.clone(),
+        })
+        .collect();
+    apply_file_operations(&operations)?;
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_xmp_and_takeout_sidecars() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let image = temp_dir.path().join("IMG_0001.jpg");
+        for name in [
+            "IMG_0001.jpg",
+            "IMG_0001.xmp",
+            "IMG_0001.jpg.supplemental-metadata.json",
+            "IMG_0002.xmp",
+        ] {
+            fs::write(temp_dir.path().join(name), b"").expect("write");
+        }
+
+        assert_eq!(
+            find_sidecars(&image),
+            vec![
+                temp_dir
+                    .path()
+                    .join("IMG_0001.jpg.supplemental-metadata.json"),
+                temp_dir.path().join("IMG_0001.xmp"),
+            ]
+        );
+        assert!(affected_sidecars(&image, SidecarMode::Ignore).is_empty());
+    }
+
+    #[test]
+    fn stem_sidecars_stay_with_an_image_sharing_the_stem() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        for name in [
+            "IMG_0001.jpg",
+            "IMG_0001.png",
+            "IMG_0001.xmp",
+            "IMG_0001.json",
+            "IMG_0001.png.xmp",
+        ] {
+            fs::write(temp_dir.path().join(name), b"").expect("write");
+        }
+
+        // Deleting the PNG while the JPEG is kept only takes the sidecar named after the PNG
+        let deleted = temp_dir.path().join("IMG_0001.png");
+        assert_eq!(
+            affected_sidecars(&deleted, SidecarMode::Follow),
+            vec![temp_dir.path().join("IMG_0001.png.xmp")]
+        );
+        assert!(find_sidecars(&temp_dir.path().join("IMG_0001.jpg")).is_empty());
+    }
+
+    #[test]
+    fn moved_sidecars_keep_their_naming_convention() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let from = temp_dir.path().join("IMG_0001.jpg");
+        let to = temp_dir.path().join("kept.jpg");
+        for name in ["IMG_0001.jpg", "IMG_0001.xmp", "IMG_0001.jpg.json"] {
+            fs::write(temp_dir.path().join(name), b"").expect("write");
+        }
+
+        let moved = move_with_sidecars(&from, &to, SidecarMode::Follow).expect("move");
+
+        assert_eq!(moved.len(), 3);
+        for name in ["kept.jpg", "kept.xmp", "kept.jpg.json"] {
+            assert!(temp_dir.path().join(name).is_file(), "{name} should exist");
+        }
+        assert!(!from.exists());
+    }
+}
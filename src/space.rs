@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::estimate::format_size;
+
+/// Left free on top of what an operation writes, so the database and logs still fit
+pub const RESERVED_SPACE: u64 = 100 * 1000 * 1000;
+
+/// Writing would leave a destination with less than `RESERVED_SPACE` free
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutOfSpace {
+    pub destination: PathBuf,
+    pub needed: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for OutOfSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Not enough free space at {}: {} needed, {} available ({} kept free)",
+            self.destination.display(),
+            format_size(self.needed),
+            format_size(self.available),
+            format_size(RESERVED_SPACE)
+        )
+    }
+}
+
+impl std::error::Error for OutOfSpace {}
+
+/// Check that every destination's filesystem has room for the bytes planned for it, added up
+/// per filesystem. Destinations whose free space can't be asked for are let through
+pub fn check_space(writes: &[(PathBuf, u64)]) -> Result<()> {
+    let mut by_filesystem: BTreeMap<u64, (&Path, u64)> = BTreeMap::new();
+    for (destination, bytes) in writes {
+        let Some(id) = filesystem_id(destination) else {
+            continue;
+        };
+        by_filesystem.entry(id).or_insert((destination, 0)).1 += bytes;
+    }
+    for (destination, needed) in by_filesystem.into_values() {
+        ensure_fits(destination, needed)?;
+    }
+    Ok(())
+}
+
+/// Fail with `OutOfSpace` unless `bytes` fit at `destination` right now
+pub fn ensure_fits(destination: &Path, bytes: u64) -> Result<()> {
+    let Some(available) = available_space(destination) else {
+        debug!(
+            "Free space at {} is unknown, not checked",
+            destination.display()
+        );
+        return Ok(());
+    };
+    if available < bytes.saturating_add(RESERVED_SPACE) {
+        return Err(OutOfSpace {
+            destination: destination.to_path_buf(),
+            needed: bytes,
+            available,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The closest existing folder, where a destination that doesn't exist yet would be created
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .find(|ancestor| fs::metadata(ancestor).is_ok())
+}
+
+#[cfg(unix)]
+fn filesystem_id(path: &Path) -> Option<u64> {
+    fs::metadata(existing_ancestor(path)?).ok().map(|m| m.dev())
+}
+
+/// Free space isn't asked for elsewhere either, see `available_space`
+#[cfg(not(unix))]
+fn filesystem_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Bytes this user may still write on the filesystem holding `path`
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    let folder = CString::new(existing_ancestor(path)?.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs is plain old data, and statvfs() only writes into the struct it's given
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(folder.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn writes_are_checked_against_free_space() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let missing = temp_dir.path().join("not/created/yet");
+
+        check_space(&[(missing.clone(), 1), (temp_dir.path().to_path_buf(), 1)])
+            .expect("a few bytes fit");
+        if cfg!(unix) {
+            let error = check_space(&[(missing.clone(), u64::MAX / 2)])
+                .expect_err("nothing has that much space");
+            let out_of_space = error.downcast_ref::<OutOfSpace>().expect("OutOfSpace");
+            assert_eq!(out_of_space.destination, missing);
+            assert_eq!(out_of_space.needed, u64::MAX / 2);
+        }
+    }
+}
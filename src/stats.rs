@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::cache::HashCache;
+use crate::messages::{Lang, Message};
+use crate::resolver::{resolve_candidates, FileCandidate, KeepStrategy};
+
+/// Duplicates and reclaimable space for one file format
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FormatStats {
+    pub format: String,
+    /// Files of this format in a duplicate group
+    pub duplicate_files: usize,
+    /// Files of this format that resolving the groups would delete
+    pub redundant_files: usize,
+    pub reclaimable_bytes: u64,
+}
+
+/// Totals over all duplicate groups, with a breakdown by file format
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DuplicateStats {
+    pub groups: usize,
+    pub duplicate_files: usize,
+    pub redundant_files: usize,
+    pub reclaimable_bytes: u64,
+    /// Hard links to a kept file, neither redundant nor reclaimable
+    pub already_linked: usize,
+    /// Sorted by reclaimable bytes, largest first
+    pub by_format: Vec<FormatStats>,
+}
+
+/// Format name for a file, from its extension, with aliases like jpg/jpeg merged
+pub fn format_name(path: &Path) -> String {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("jpg") | Some("jpeg") => "jpeg".to_string(),
+        Some("tif") | Some("tiff") => "tiff".to_string(),
+        Some(extension) => extension.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Statistics for duplicate groups, counting what `strategy` would delete as reclaimable.
+/// Missing files are left out.
+pub fn duplicate_stats(
+    duplicates: &[Vec<PathBuf>],
+    strategy: KeepStrategy,
+    cache: &HashCache,
+) -> DuplicateStats {
+    let groups = duplicates
+        .iter()
+        .map(|group| {
+            group
+                .iter()
+                .filter_map(|path| FileCandidate::from_path(path, cache))
+                .collect()
+        })
+        .collect();
+    stats_from_candidates(groups, strategy)
+}
+
+/// Statistics for groups whose file details were already gathered
+pub fn stats_from_candidates(
+    groups: Vec<Vec<FileCandidate>>,
+    strategy: KeepStrategy,
+) -> DuplicateStats {
+    let mut stats = DuplicateStats::default();
+    let mut by_format: BTreeMap<String, FormatStats> = BTreeMap::new();
+
+    for candidates in groups {
+        let sizes: BTreeMap<PathBuf, (u64, Option<(u64, u64)>)> = candidates
+            .iter()
+            .map(|candidate| {
+                let details = (candidate.size, candidate.file_id);
+                (candidate.path.clone(), details)
+            })
+            .collect();
+        let Some(resolution) = resolve_candidates(candidates, strategy) else {
+            continue;
+        };
+
+        stats.groups += 1;
+        for path in sizes.keys() {
+            by_format
+                .entry(format_name(path))
+                .or_default()
+                .duplicate_files += 1;
+        }
+        stats.duplicate_files += sizes.len();
+        // Deleted files linked to each other free their space once
+        let mut deleted_ids = BTreeSet::new();
+        for path in &resolution.delete {
+            let format = by_format.entry(format_name(path)).or_default();
+            format.redundant_files += 1;
+            let (size, id) = sizes.get(path).copied().unwrap_or_default();
+            if id.is_none_or(|id| deleted_ids.insert(id)) {
+                format.reclaimable_bytes += size;
+            }
+        }
+        stats.redundant_files += resolution.delete.len();
+        stats.already_linked += resolution.already_linked.len();
+        stats.reclaimable_bytes += resolution.bytes_reclaimed;
+    }
+
+    stats.by_format = by_format
+        .into_iter()
+        .map(|(format, format_stats)| FormatStats {
+            format,
+            ..format_stats
+        })
+        .collect();
+    stats.by_format.sort_by(|a, b| {
+        b.reclaimable_bytes
+            .cmp(&a.reclaimable_bytes)
+            .then_with(|| a.format.cmp(&b.format))
+    });
+    stats
+}
+
+impl DuplicateStats {
+    /// Log the per-format summary shown after the duplicate groups
+    pub fn log_summary(&self, strategy: KeepStrategy, lang: Lang) {
+        let summary = Message::DuplicateSummary {
+            duplicate_files: self.duplicate_files,
+            groups: self.groups,
+            redundant_files: self.redundant_files,
+            reclaimable_bytes: self.reclaimable_bytes,
+            strategy,
+        };
+        info!("{}", summary.text(lang));
+        if self.already_linked > 0 {
+            info!(
+                "{}",
+                Message::AlreadyLinkedFiles(self.already_linked).text(lang)
+            );
+        }
+        for format in &self.by_format {
+            let summary = Message::FormatSummary {
+                format: &format.format,
+                duplicate_files: format.duplicate_files,
+                redundant_files: format.redundant_files,
+                reclaimable_bytes: format.reclaimable_bytes,
+            };
+            info!("  {}", summary.text(lang));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(path: &str, size: u64) -> FileCandidate {
+        FileCandidate {
+            path: PathBuf::from(path),
+            size,
+            modified: None,
+            dimensions: None,
+            keep_tagged: false,
+            file_id: None,
+        }
+    }
+
+    #[test]
+    fn breaks_down_reclaimable_bytes_by_format() {
+        let groups = vec![
+            vec![
+                candidate("/shots/a.png", 900),
+                candidate("/shots/a copy.PNG", 800),
+                candidate("/photos/a.jpg", 300),
+            ],
+            vec![
+                candidate("/photos/b.jpeg", 500),
+                candidate("/photos/b.jpg", 400),
+            ],
+            // Only one file left, nothing to resolve
+            vec![candidate("/photos/c.webp", 100)],
+        ];
+
+        let stats = stats_from_candidates(groups, KeepStrategy::KeepLargest);
+
+        assert_eq!(stats.groups, 2);
+        assert_eq!(stats.duplicate_files, 5);
+        assert_eq!(stats.redundant_files, 3);
+        assert_eq!(stats.reclaimable_bytes, 1500);
+        assert_eq!(
+            stats.by_format,
+            vec![
+                FormatStats {
+                    format: "png".to_string(),
+                    duplicate_files: 2,
+                    redundant_files: 1,
+                    reclaimable_bytes: 800,
+                },
+                FormatStats {
+                    format: "jpeg".to_string(),
+                    duplicate_files: 3,
+                    redundant_files: 2,
+                    reclaimable_bytes: 700,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::cache::HashCache;
+use crate::hasher::{calculate_file_sha256, get_file_metadata};
+use crate::paths::{extended_length_path, same_file};
+use crate::space::{check_space, ensure_fits, OutOfSpace};
+
+/// How an original points at its copy in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// Another name for the store file, used when both are on the same filesystem
+    Hard,
+    /// A symbolic link to the store file, used across filesystems
+    Symbolic,
+}
+
+impl LinkKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkKind::Hard => "hard",
+            LinkKind::Symbolic => "symbolic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hard" => Some(LinkKind::Hard),
+            "symbolic" => Some(LinkKind::Symbolic),
+            _ => None,
+        }
+    }
+}
+
+/// An original file that was replaced by a link into the store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreLink {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub store_path: PathBuf,
+    pub kind: LinkKind,
+    /// The original's modification time in seconds since the Unix epoch, put back on restore
+    pub modified: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+pub struct StoreReport {
+    /// Files replaced by a link this run
+    pub linked: usize,
+    /// Files that were linked by an earlier run
+    pub already_linked: usize,
+    /// Bytes no longer stored twice
+    pub bytes_saved: u64,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    /// Links replaced by a copy of the store file
+    pub restored: usize,
+    /// Entries forgotten because the file was no longer linked, it's left as it is
+    pub detached: usize,
+    /// Store files deleted once nothing linked to them
+    pub removed_from_store: usize,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Where contents with this sha256 live in the store: `<store>/<prefix>/<sha256>.<ext>`, with
+/// the original's extension so the store can be browsed
+pub fn store_path(store: &Path, sha256: &str, original: &Path) -> PathBuf {
+    let prefix = sha256.get(..2).unwrap_or("00");
+    let name = match original.extension() {
+        Some(ext) => format!("{sha256}.{}", ext.to_string_lossy().to_lowercase()),
+        None => sha256.to_string(),
+    };
+    store.join(prefix).join(name)
+}
+
+/// Replace each image with a link to a single copy of its contents in `store`, recording every
+/// link in the database so `restore_from_store` can undo it. The first file with some contents
+/// becomes the store's copy; identical files after it only cost a link. A copy that wouldn't
+/// fit in the store stops the run before it's written, or with `skip_when_full` only skips its
+/// file
+pub fn link_into_store(
+    images: &[PathBuf],
+    store: &Path,
+    cache: &HashCache,
+    skip_when_full: bool,
+) -> Result<StoreReport> {
+    fs::create_dir_all(extended_length_path(store))
+        .with_context(|| format!("Could not create store {}", store.display()))?;
+    // Symbolic links need an absolute target, and files already in the store are skipped
+    let store = fs::canonicalize(extended_length_path(store))?;
+
+    let mut report = StoreReport::default();
+    for image in images {
+        // Recorded paths must stay valid from any working directory
+        let image = std::path::absolute(image)?;
+        if image.starts_with(&store) {
+            continue;
+        }
+        match link_file(&image, &store, cache) {
+            Ok(Linked::New { bytes_saved }) => {
+                report.linked += 1;
+                report.bytes_saved += bytes_saved;
+            }
+            Ok(Linked::Already) => report.already_linked += 1,
+            Err(e) if !skip_when_full && e.is::<OutOfSpace>() => return Err(e),
+            Err(e) => report.failed.push((image, format!("{e:#}"))),
+        }
+    }
+    Ok(report)
+}
+
+enum Linked {
+    New { bytes_saved: u64 },
+    Already,
+}
+
+fn link_file(image: &Path, store: &Path, cache: &HashCache) -> Result<Linked> {
+    if let Some(link) = cache.get_store_link(image)? {
+        if is_linked(&link) {
+            return Ok(Linked::Already);
+        }
+    }
+
+    if fs::symlink_metadata(extended_length_path(image))?
+        .file_type()
+        .is_symlink()
+    {
+        bail!("Symbolic links are left alone, link the file they point to instead");
+    }
+
+    let metadata = get_file_metadata(image)?;
+    let target = store_path(store, &metadata.sha256, image);
+    let mut link = StoreLink {
+        path: image.to_path_buf(),
+        sha256: metadata.sha256.clone(),
+        store_path: target.clone(),
+        kind: LinkKind::Hard,
+        modified: metadata.modified,
+    };
+
+    if !extended_length_path(&target).exists() {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(extended_length_path(parent))?;
+        }
+        // The original becomes the store's copy without moving any data
+        if fs::hard_link(extended_length_path(image), extended_length_path(&target)).is_ok() {
+            cache.add_store_link(&link)?;
+            return Ok(Linked::New { bytes_saved: 0 });
+        }
+        // Only needed across filesystems, where the copy takes up space in the store
+        ensure_fits(&target, metadata.size)?;
+        copy_through_partial(image, &target)?;
+        link.kind = replace_with_link(image, &target)?;
+        cache.add_store_link(&link)?;
+        return Ok(Linked::New { bytes_saved: 0 });
+    }
+
+    if same_file(image, &target) {
+        cache.add_store_link(&link)?;
+        return Ok(Linked::Already);
+    }
+    // Writing to any hard link changes the store's copy, so check it before trusting the name
+    let stored_size = fs::metadata(extended_length_path(&target))?.len();
+    if stored_size != metadata.size || calculate_file_sha256(&target)? != metadata.sha256 {
+        bail!(
+            "{} no longer matches its name, it was changed through another link",
+            target.display()
+        );
+    }
+    link.kind = replace_with_link(image, &target)?;
+    cache.add_store_link(&link)?;
+    debug!("Linked {} to {}", image.display(), target.display());
+    Ok(Linked::New {
+        bytes_saved: metadata.size,
+    })
+}
+
+/// Put every recorded link back as a standalone file with its original modification time, and
+/// delete store files nothing links to anymore. With `under`, only links below those folders.
+/// Every restored file is a new copy, so nothing is restored unless all of them fit, or with
+/// `skip_when_full` files are restored as long as they fit and the rest are skipped
+pub fn restore_from_store(
+    cache: &HashCache,
+    under: &[PathBuf],
+    skip_when_full: bool,
+) -> Result<RestoreReport> {
+    let mut report = RestoreReport::default();
+    let mut released = BTreeSet::new();
+
+    let links: Vec<StoreLink> = cache
+        .get_store_links()?
+        .into_iter()
+        .filter(|link| under.is_empty() || under.iter().any(|folder| link.path.starts_with(folder)))
+        .collect();
+    let copies: Vec<(PathBuf, u64)> = links
+        .iter()
+        .filter(|link| is_linked(link))
+        .map(|link| (link.path.clone(), stored_size(link)))
+        .collect();
+    if !skip_when_full {
+        check_space(&copies)?;
+    }
+
+    for link in links {
+        // A missing file may be on an unmounted drive, keep its store copy for later
+        if fs::symlink_metadata(extended_length_path(&link.path)).is_err() {
+            report.failed.push((
+                link.path,
+                "File is missing, is its drive mounted?".to_string(),
+            ));
+            continue;
+        }
+        if !is_linked(&link) {
+            warn!(
+                "{} was replaced or removed since it was linked, leaving it as it is",
+                link.path.display()
+            );
+            cache.remove_store_link(&link.path)?;
+            report.detached += 1;
+            released.insert(link.store_path);
+            continue;
+        }
+        if let Err(e) = ensure_fits(&link.path, stored_size(&link)) {
+            if !skip_when_full {
+                return Err(e);
+            }
+            report.failed.push((link.path, format!("{e:#}")));
+            continue;
+        }
+        match restore_file(&link) {
+            Ok(()) => {
+                cache.remove_store_link(&link.path)?;
+                report.restored += 1;
+                released.insert(link.store_path);
+            }
+            Err(e) => report.failed.push((link.path, format!("{e:#}"))),
+        }
+    }
+
+    for store_file in released {
+        if cache.count_store_links_to(&store_file)? > 0 {
+            continue;
+        }
+        match fs::remove_file(extended_length_path(&store_file)) {
+            Ok(()) => {
+                report.removed_from_store += 1;
+                // Only succeeds once the prefix folder is empty
+                if let Some(parent) = store_file.parent() {
+                    let _ = fs::remove_dir(extended_length_path(parent));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Could not remove {}: {}", store_file.display(), e),
+        }
+    }
+    Ok(report)
+}
+
+fn restore_file(link: &StoreLink) -> Result<()> {
+    let partial = sibling(&link.path, "restore");
+    fs::copy(
+        extended_length_path(&link.store_path),
+        extended_length_path(&partial),
+    )
+    .with_context(|| format!("Could not copy {}", link.store_path.display()))?;
+    if let Some(modified) = link.modified.and_then(|secs| u64::try_from(secs).ok()) {
+        File::options()
+            .write(true)
+            .open(extended_length_path(&partial))?
+            .set_modified(UNIX_EPOCH + Duration::from_secs(modified))?;
+    }
+    rename_over(&partial, &link.path)
+}
+
+fn stored_size(link: &StoreLink) -> u64 {
+    fs::metadata(extended_length_path(&link.store_path)).map_or(0, |metadata| metadata.len())
+}
+
+/// Whether the recorded file still is a link to its store copy
+fn is_linked(link: &StoreLink) -> bool {
+    match link.kind {
+        LinkKind::Hard => same_file(&link.path, &link.store_path),
+        LinkKind::Symbolic => fs::read_link(extended_length_path(&link.path))
+            .is_ok_and(|target| target == link.store_path),
+    }
+}
+
+/// Swap `original` for a link to `target`, hard if the filesystem allows it. The link is made
+/// next to the original first, so the original is only replaced once the link exists
+fn replace_with_link(original: &Path, target: &Path) -> Result<LinkKind> {
+    let partial = sibling(original, "link");
+    let kind =
+        if fs::hard_link(extended_length_path(target), extended_length_path(&partial)).is_ok() {
+            LinkKind::Hard
+        } else {
+            symlink(target, &extended_length_path(&partial))
+                .with_context(|| format!("Could not link {}", original.display()))?;
+            LinkKind::Symbolic
+        };
+    rename_over(&partial, original)?;
+    Ok(kind)
+}
+
+fn copy_through_partial(source: &Path, destination: &Path) -> Result<()> {
+    let partial = destination.with_extension("partial");
+    fs::copy(extended_length_path(source), extended_length_path(&partial))
+        .with_context(|| format!("Could not copy {} into the store", source.display()))?;
+    rename_over(&partial, destination)
+}
+
+pub(crate) fn rename_over(partial: &Path, destination: &Path) -> Result<()> {
+    fs::rename(
+        extended_length_path(partial),
+        extended_length_path(destination),
+    )
+    .or_else(|e| {
+        let _ = fs::remove_file(extended_length_path(partial));
+        Err(e).with_context(|| format!("Could not replace {}", destination.display()))
+    })
+}
+
+/// A hidden temporary name in the same folder, so renaming it over `path` is atomic
+pub(crate) fn sibling(path: &Path, purpose: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.{purpose}.partial"))
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links aren't supported on this platform",
+    ))
+}
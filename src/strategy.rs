@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Extensions scanned when no strategy adds or skips any
+pub const DEFAULT_IMAGE_EXTENSIONS: [&str; 8] =
+    ["jpg", "jpeg", "png", "gif", "bmp", "tiff", "tif", "webp"];
+
+/// Formats the web server recognises but the `image` build can't decode (AVIF needs dav1d,
+/// JPEG XL a decoder such as jxl-oxide). They're never scanned, even when a strategy names them,
+/// since every file would only end up as a decode error
+pub const UNDECODABLE_EXTENSIONS: [&str; 2] = ["avif", "jxl"];
+
+/// Camera RAW formats. Built with the `raw` feature they're scanned by default and hashed by
+/// their embedded preview unless a strategy says otherwise; without it only the ones a strategy
+/// names are scanned
+pub const RAW_EXTENSIONS: [&str; 10] = [
+    "arw", "cr2", "dng", "nef", "nrw", "orf", "pef", "raf", "rw2", "srw",
+];
+
+/// Set from the config file's `hash_strategies` at startup
+static STRATEGIES: OnceLock<BTreeMap<String, HashStrategy>> = OnceLock::new();
+
+/// How files with an extension are turned into the image that's hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashStrategy {
+    /// Decode the file as it is, using the first frame of animations
+    #[default]
+    Default,
+    /// Blend the frames of an animated GIF, WebP or PNG, so animations that share most of their
+    /// frames match even when they start differently. Costs a decode of every frame
+    AllFrames,
+    /// Use the largest JPEG preview embedded in the file instead of decoding it, for camera
+    /// RAW formats such as CR2, NEF, ARW and DNG
+    EmbeddedPreview,
+    /// Leave these files out of scans
+    Skip,
+}
+
+impl std::fmt::Display for HashStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HashStrategy::Default => "default",
+            HashStrategy::AllFrames => "all-frames",
+            HashStrategy::EmbeddedPreview => "embedded-preview",
+            HashStrategy::Skip => "skip",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Use these per-extension strategies for the rest of the process. Hashes are cached per file,
+/// so files hashed before a strategy changed keep their old hash until they're rehashed
+pub fn set_hash_strategies(strategies: &BTreeMap<String, HashStrategy>) {
+    let normalized = strategies
+        .iter()
+        .map(|(extension, strategy)| (normalize_extension(extension), *strategy))
+        .collect();
+    if STRATEGIES.set(normalized).is_err() {
+        warn!("Hash strategies were already set");
+    }
+}
+
+/// The strategy configured for a file's extension
+pub fn hash_strategy(path: &Path) -> HashStrategy {
+    strategy_for(path, STRATEGIES.get().unwrap_or(&BTreeMap::new()))
+}
+
+/// Extensions that scans pick up: the defaults plus configured ones, minus skipped ones
+pub fn image_extensions() -> Vec<String> {
+    extensions_with(STRATEGIES.get().unwrap_or(&BTreeMap::new()))
+}
+
+fn strategy_for(path: &Path, strategies: &BTreeMap<String, HashStrategy>) -> HashStrategy {
+    let Some(extension) = path
+        .extension()
+        .map(|extension| normalize_extension(&extension.to_string_lossy()))
+    else {
+        return HashStrategy::Default;
+    };
+    match strategies.get(&extension) {
+        Some(strategy) => *strategy,
+        None if cfg!(feature = "raw-previews") && RAW_EXTENSIONS.contains(&extension.as_str()) => {
+            HashStrategy::EmbeddedPreview
+        }
+        None => HashStrategy::Default,
+    }
+}
+
+fn extensions_with(strategies: &BTreeMap<String, HashStrategy>) -> Vec<String> {
+    let raw_extensions: &[&str] = if cfg!(feature = "raw-previews") {
+        &RAW_EXTENSIONS
+    } else {
+        &[]
+    };
+    let mut extensions: Vec<String> = DEFAULT_IMAGE_EXTENSIONS
+        .iter()
+        .chain(raw_extensions)
+        .map(|extension| extension.to_string())
+        .chain(strategies.keys().cloned())
+        .filter(|extension| strategies.get(extension) != Some(&HashStrategy::Skip))
+        .filter(|extension| !UNDECODABLE_EXTENSIONS.contains(&extension.as_str()))
+        .collect();
+    extensions.sort();
+    extensions.dedup();
+    extensions
+}
+
+/// `.CR2` and `cr2` name the same extension
+fn normalize_extension(extension: &str) -> String {
+    extension.trim_start_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategies_add_and_skip_extensions() {
+        let strategies: BTreeMap<String, HashStrategy> = serde_json::from_str(
+            r#"{"gif": "all-frames", "cr2": "embedded-preview", "webp": "skip", "nef": "skip"}"#,
+        )
+        .expect("strategies should parse");
+        let strategies: BTreeMap<String, HashStrategy> = strategies
+            .into_iter()
+            .map(|(extension, strategy)| (normalize_extension(&extension), strategy))
+            .collect();
+
+        assert_eq!(
+            strategy_for(Path::new("/a/IMG_1.CR2"), &strategies),
+            HashStrategy::EmbeddedPreview
+        );
+        assert_eq!(
+            strategy_for(Path::new("/a/b.png"), &strategies),
+            HashStrategy::Default
+        );
+        let raw_default = if cfg!(feature = "raw-previews") {
+            HashStrategy::EmbeddedPreview
+        } else {
+            HashStrategy::Default
+        };
+        assert_eq!(
+            strategy_for(Path::new("/a/DSC_1.ARW"), &strategies),
+            raw_default,
+            "RAW files use their preview unless configured otherwise, with the raw feature"
+        );
+
+        let extensions = extensions_with(&strategies);
+        assert!(extensions.contains(&"cr2".to_string()));
+        assert!(extensions.contains(&"gif".to_string()));
+        assert!(!extensions.contains(&"webp".to_string()));
+        assert_eq!(
+            extensions.contains(&"dng".to_string()),
+            cfg!(feature = "raw-previews"),
+            "unconfigured RAW formats are scanned only with the raw feature"
+        );
+        assert!(!extensions.contains(&"nef".to_string()));
+    }
+
+    #[test]
+    fn undecodable_formats_are_never_scanned() {
+        assert!(!extensions_with(&BTreeMap::new()).contains(&"avif".to_string()));
+        let strategies = BTreeMap::from([
+            ("avif".to_string(), HashStrategy::Default),
+            ("jxl".to_string(), HashStrategy::Default),
+        ]);
+        let extensions = extensions_with(&strategies);
+        assert!(!extensions.contains(&"avif".to_string()));
+        assert!(!extensions.contains(&"jxl".to_string()));
+    }
+}
@@ -0,0 +1,67 @@
+//! Deterministic images and hashes, generated rather than read from disk. Used by the self-test
+//! and by the test fixtures in `test_support`
+
+use image::{DynamicImage, Rgb, RgbImage};
+use imghash::ImageHash;
+use std::path::PathBuf;
+
+/// A smooth pattern of light and dark blobs. Different seeds give images that don't match
+/// each other at any rotation, unlike solid colours or gradients
+pub fn pattern(width: u32, height: u32, seed: u64) -> DynamicImage {
+    let mut rng = XorShift::new(seed);
+    let waves: Vec<(f32, f32, f32)> = (0..4)
+        .map(|_| {
+            (
+                1.0 + rng.next_unit() * 4.0,
+                1.0 + rng.next_unit() * 4.0,
+                rng.next_unit() * std::f32::consts::TAU,
+            )
+        })
+        .collect();
+
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let u = x as f32 / width as f32 * std::f32::consts::TAU;
+        let v = y as f32 / height as f32 * std::f32::consts::TAU;
+        let value: f32 = waves
+            .iter()
+            .map(|(fx, fy, phase)| (u * fx + phase).sin() * (v * fy + phase).cos())
+            .sum::<f32>()
+            / waves.len() as f32;
+        let level = (127.5 + value * 127.5).clamp(0.0, 255.0) as u8;
+        Rgb([level, level / 2 + 64, 255 - level])
+    }))
+}
+
+/// Small deterministic generator, so fixtures are identical on every run
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Next value in `0.0..1.0`
+    pub(crate) fn next_unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Unrelated 64 bit hashes, for measuring grouping without hashing images first
+pub fn random_hashes(count: usize, seed: u64) -> Vec<(PathBuf, ImageHash)> {
+    let mut rng = XorShift::new(seed);
+    (0..count)
+        .map(|number| {
+            let hash = ImageHash::decode(&format!("{:016x}", rng.next_u64()), 8, 8)
+                .expect("64 bit hashes should decode");
+            (PathBuf::from(format!("/synthetic/{number}.png")), hash)
+        })
+        .collect()
+}
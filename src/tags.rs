@@ -0,0 +1,218 @@
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+use std::ffi::CString;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Files carrying this tag are kept by every keep rule and never deleted
+pub const KEEP_TAG: &str = "Keep";
+
+/// Set to anything but 0, false or no to mark a file as kept, e.g.
+/// `setfattr -n user.keep -v 1 photo.jpg`
+const KEEP_ATTRIBUTE: &str = "user.keep";
+/// Comma separated tags, as set by KDE's Dolphin and other freedesktop file managers
+const XDG_TAGS_ATTRIBUTE: &str = "user.xdg.tags";
+/// Finder tags, a binary plist array of "name\ncolour" strings
+const FINDER_TAGS_ATTRIBUTE: &str = "com.apple.metadata:_kMDItemUserTags";
+
+/// Tags set on a file through extended attributes. `user.keep` shows up as the Keep tag.
+/// Files on filesystems without extended attributes have none
+pub fn file_tags(path: &Path) -> Vec<String> {
+    let mut tags = Vec::new();
+    if read_attribute(path, KEEP_ATTRIBUTE).is_some_and(|value| keep_value(&value)) {
+        tags.push(KEEP_TAG.to_string());
+    }
+    if let Some(value) = read_attribute(path, XDG_TAGS_ATTRIBUTE) {
+        tags.extend(
+            String::from_utf8_lossy(&value)
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string),
+        );
+    }
+    if let Some(value) = read_attribute(path, FINDER_TAGS_ATTRIBUTE) {
+        tags.extend(finder_tags(&value).unwrap_or_default());
+    }
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// Whether any of the tags is Keep, ignoring case
+pub fn is_keep_tagged(tags: &[String]) -> bool {
+    tags.iter().any(|tag| tag.eq_ignore_ascii_case(KEEP_TAG))
+}
+
+fn keep_value(value: &[u8]) -> bool {
+    let value = String::from_utf8_lossy(value);
+    !matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "0" | "false" | "no"
+    )
+}
+
+/// Tag names from a Finder tags plist, without the colour Finder appends after a newline
+fn finder_tags(plist: &[u8]) -> Option<Vec<String>> {
+    // The trailer is the last 32 bytes: 6 unused, offset size, reference size, object count,
+    // top object and where the offset table starts
+    let trailer = plist
+        .strip_prefix(b"bplist00")?
+        .get(plist.len().checked_sub(40)?..)?;
+    let offset_size = usize::from(trailer[6]);
+    let reference_size = usize::from(trailer[7]);
+    let objects = usize::try_from(be_uint(&trailer[8..16])?).ok()?;
+    let top = usize::try_from(be_uint(&trailer[16..24])?).ok()?;
+    let table = usize::try_from(be_uint(&trailer[24..32])?).ok()?;
+
+    let object_at = |index: usize| -> Option<usize> {
+        if index >= objects {
+            return None;
+        }
+        let start = table.checked_add(index.checked_mul(offset_size)?)?;
+        usize::try_from(be_uint(plist.get(start..start.checked_add(offset_size)?)?)?).ok()
+    };
+
+    let array = object_at(top)?;
+    let (marker, (count, mut position)) = (*plist.get(array)?, length(plist, array)?);
+    if marker >> 4 != 0xA {
+        return None;
+    }
+    let mut tags = Vec::with_capacity(count);
+    for _ in 0..count {
+        let reference = be_uint(plist.get(position..position + reference_size)?)?;
+        position += reference_size;
+        let object = object_at(usize::try_from(reference).ok()?)?;
+        let (length, start) = length(plist, object)?;
+        let text = match plist.get(object)? >> 4 {
+            // ASCII
+            0x5 => {
+                String::from_utf8_lossy(plist.get(start..start.checked_add(length)?)?).into_owned()
+            }
+            // UTF-16, big endian
+            0x6 => {
+                let units: Vec<u16> = plist
+                    .get(start..start.checked_add(length.checked_mul(2)?)?)?
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => continue,
+        };
+        let name = text.split('\n').next().unwrap_or_default();
+        if !name.is_empty() {
+            tags.push(name.to_string());
+        }
+    }
+    Some(tags)
+}
+
+/// The length in an object's marker and where its contents start. Lengths of 15 and more
+/// follow the marker as an integer object
+fn length(plist: &[u8], object: usize) -> Option<(usize, usize)> {
+    let short = plist.get(object)? & 0x0F;
+    if short != 0x0F {
+        return Some((usize::from(short), object + 1));
+    }
+    let marker = plist.get(object + 1)?;
+    if marker >> 4 != 0x1 {
+        return None;
+    }
+    let size = 1usize.checked_shl(u32::from(marker & 0x0F))?;
+    let start = object + 2;
+    let length = be_uint(plist.get(start..start.checked_add(size)?)?)?;
+    Some((usize::try_from(length).ok()?, start + size))
+}
+
+fn be_uint(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | u64::from(byte)),
+    )
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn read_attribute(path: &Path, name: &str) -> Option<Vec<u8>> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let name = CString::new(name).ok()?;
+    // The attribute can change between asking for its size and reading it, a failed read is
+    // taken as no attribute
+    let size = usize::try_from(getxattr(&path, &name, &mut [])).ok()?;
+    let mut value = vec![0u8; size];
+    let read = usize::try_from(getxattr(&path, &name, &mut value)).ok()?;
+    value.truncate(read);
+    Some(value)
+}
+
+/// Asks for the attribute's size when `value` is empty
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn getxattr(path: &std::ffi::CStr, name: &std::ffi::CStr, value: &mut [u8]) -> isize {
+    let buffer = if value.is_empty() {
+        std::ptr::null_mut()
+    } else {
+        value.as_mut_ptr().cast()
+    };
+    // SAFETY: both strings are NUL terminated, and getxattr() writes at most `value.len()` bytes
+    #[cfg(target_os = "macos")]
+    let read = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), buffer, value.len(), 0, 0) };
+    #[cfg(not(target_os = "macos"))]
+    let read = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), buffer, value.len()) };
+    read
+}
+
+/// Extended attributes are only read on Linux and macOS
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn read_attribute(_path: &Path, _name: &str) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Finder tags plist as macOS writes it
+    fn finder_plist(tags: &[&str]) -> Vec<u8> {
+        let mut plist = b"bplist00".to_vec();
+        let mut offsets = vec![plist.len()];
+        plist.push(0xA0 | tags.len() as u8);
+        plist.extend((1..=tags.len()).map(|reference| reference as u8));
+        for tag in tags {
+            offsets.push(plist.len());
+            plist.push(0x50 | tag.len() as u8);
+            plist.extend(tag.as_bytes());
+        }
+        let table = plist.len();
+        plist.extend(offsets.iter().map(|&offset| offset as u8));
+        plist.extend([0; 6]);
+        plist.extend([1, 1]);
+        plist.extend((offsets.len() as u64).to_be_bytes());
+        plist.extend(0u64.to_be_bytes());
+        plist.extend((table as u64).to_be_bytes());
+        plist
+    }
+
+    /// One tag is coloured and one isn't
+    #[test]
+    fn finder_tags_are_read_without_colours() {
+        let plist = finder_plist(&["Keep\n6", "Holiday"]);
+        assert_eq!(
+            finder_tags(&plist),
+            Some(vec!["Keep".to_string(), "Holiday".to_string()])
+        );
+        assert_eq!(finder_tags(b"bplist00"), None);
+        assert_eq!(finder_tags(&plist[..plist.len() - 1]), None);
+    }
+
+    #[test]
+    fn keep_tag_and_attribute_values() {
+        assert!(is_keep_tagged(&["holiday".to_string(), "keep".to_string()]));
+        assert!(!is_keep_tagged(&["Keeper".to_string()]));
+        assert!(keep_value(b"1") && keep_value(b"") && keep_value(b"yes"));
+        assert!(!keep_value(b"0") && !keep_value(b"false\n") && !keep_value(b"No"));
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::cache::{HashCache, PhotoMetadata};
+use crate::paths::extended_length_path;
+use crate::sidecar::find_sidecars;
+
+/// Takeout truncates sidecar names to this many characters before adding `.json`
+const TAKEOUT_NAME_LIMIT: usize = 46;
+
+/// The parts of a Takeout JSON sidecar that are imported
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TakeoutSidecar {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    photo_taken_time: Option<TakeoutTime>,
+}
+
+#[derive(Deserialize)]
+struct TakeoutTime {
+    /// Seconds since the Unix epoch, as a string
+    timestamp: String,
+}
+
+/// What an import run found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TakeoutImport {
+    pub imported: usize,
+    pub without_sidecar: usize,
+    pub invalid: usize,
+}
+
+/// Read the capture time and description from a Takeout JSON sidecar
+pub fn parse_takeout_sidecar(json: &str) -> Result<PhotoMetadata> {
+    let sidecar: TakeoutSidecar = serde_json::from_str(json)?;
+    Ok(PhotoMetadata {
+        taken_at: sidecar
+            .photo_taken_time
+            .and_then(|time| time.timestamp.parse().ok()),
+        description: sidecar
+            .description
+            .map(|description| description.trim().to_string())
+            .filter(|description| !description.is_empty()),
+    })
+}
+
+/// The Takeout JSON sidecar of an image, covering the names Takeout uses: `IMG_1.jpg.json`,
+/// `IMG_1.jpg.supplemental-metadata.json`, `IMG_1.jpg(1).json` for `IMG_1(1).jpg`, and names
+/// cut off at 46 characters
+pub fn find_takeout_sidecar(path: &Path) -> Option<PathBuf> {
+    let is_json = |sidecar: &PathBuf| {
+        sidecar
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+    };
+    if let Some(sidecar) = find_sidecars(path).into_iter().find(is_json) {
+        return Some(sidecar);
+    }
+
+    let file_name = path.file_name()?.to_str()?;
+    let mut candidates = Vec::new();
+
+    // Duplicate names get their counter after the extension: IMG_1(1).jpg -> IMG_1.jpg(1).json
+    let stem = path.file_stem()?.to_str()?;
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if let (Some(counter_start), Some(extension)) = (stem.rfind('('), extension) {
+        if stem.ends_with(')') {
+            let (base, counter) = stem.split_at(counter_start);
+            candidates.push(format!("{base}.{extension}{counter}.json"));
+        }
+    }
+
+    if file_name.chars().count() > TAKEOUT_NAME_LIMIT {
+        let truncated: String = file_name.chars().take(TAKEOUT_NAME_LIMIT).collect();
+        candidates.push(format!("{truncated}.json"));
+    }
+
+    candidates
+        .into_iter()
+        .map(|name| path.with_file_name(name))
+        .find(|sidecar| extended_length_path(sidecar).is_file())
+}
+
+/// Store the capture time and description from each image's Takeout sidecar in the cache
+pub fn import_takeout_metadata(images: &[PathBuf], cache: &HashCache) -> Result<TakeoutImport> {
+    let mut result = TakeoutImport::default();
+
+    for image in images {
+        let Some(sidecar) = find_takeout_sidecar(image) else {
+            debug!("No Takeout sidecar for {}", image.display());
+            result.without_sidecar += 1;
+            continue;
+        };
+
+        let metadata = fs::read_to_string(extended_length_path(&sidecar))
+            .with_context(|| format!("Failed to read {}", sidecar.display()))
+            .and_then(|json| parse_takeout_sidecar(&json));
+        match metadata {
+            Ok(metadata) => {
+                cache.store_photo_metadata(image, &metadata)?;
+                result.imported += 1;
+            }
+            Err(e) => {
+                warn!("Skipping Takeout sidecar {}: {}", sidecar.display(), e);
+                result.invalid += 1;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn parses_takeout_sidecar() {
+        let json = r#"{
+            "title": "IMG_0001.jpg",
+            "description": "  Beach day ",
+            "imageViews": "3",
+            "creationTime": {"timestamp": "1600000000", "formatted": "13 Sep 2020"},
+            "photoTakenTime": {"timestamp": "1500000000", "formatted": "14 Jul 2017"}
+        }"#;
+        assert_eq!(
+            parse_takeout_sidecar(json).expect("sidecar should parse"),
+            PhotoMetadata {
+                taken_at: Some(1_500_000_000),
+                description: Some("Beach day".to_string()),
+            }
+        );
+
+        let empty = parse_takeout_sidecar(r#"{"title": "x.jpg", "description": ""}"#)
+            .expect("sidecar should parse");
+        assert_eq!(empty, PhotoMetadata::default());
+    }
+
+    #[test]
+    fn finds_renamed_takeout_sidecars() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let dir = temp_dir.path();
+        let long_name = format!("{}.jpg", "a".repeat(50));
+        for name in [
+            "IMG_0001(1).jpg",
+            "IMG_0001.jpg(1).json",
+            long_name.as_str(),
+            &format!("{}.json", "a".repeat(46)),
+        ] {
+            fs::write(dir.join(name), b"{}").expect("write");
+        }
+
+        assert_eq!(
+            find_takeout_sidecar(&dir.join("IMG_0001(1).jpg")),
+            Some(dir.join("IMG_0001.jpg(1).json"))
+        );
+        assert_eq!(
+            find_takeout_sidecar(&dir.join(&long_name)),
+            Some(dir.join(format!("{}.json", "a".repeat(46))))
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Fixture images generated at test time, so tests don't need binary fixtures committed.
+//!
+//! Compiled for this crate's own tests, and for other crates with the `test-support` feature.
+
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+use imghash::ImageHash;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+pub use crate::synthetic::pattern;
+use crate::synthetic::XorShift;
+
+/// Side length of generated fixtures, large enough that hashing isn't dominated by resampling
+pub const FIXTURE_SIZE: u32 = 256;
+
+/// A single flat colour. Every solid image hashes the same, whatever its colour
+pub fn solid(width: u32, height: u32, color: [u8; 3]) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb(color)))
+}
+
+/// A left-to-right blend between two colours
+pub fn gradient(width: u32, height: u32, from: [u8; 3], to: [u8; 3]) -> DynamicImage {
+    let span = width.saturating_sub(1).max(1) as f32;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, _| {
+        let t = x as f32 / span;
+        Rgb(std::array::from_fn(|channel| {
+            (from[channel] as f32 + (to[channel] as f32 - from[channel] as f32) * t).round() as u8
+        }))
+    }))
+}
+
+/// Ways a fixture can be altered while staying a duplicate of the original
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Rotated90,
+    Rotated180,
+    Rotated270,
+    /// Scaled to the given percentage of the original size
+    Resized(u32),
+    /// Every channel shifted by a random amount up to the given value
+    Noised(u8),
+}
+
+impl Variant {
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        match *self {
+            Variant::Rotated90 => img.rotate90(),
+            Variant::Rotated180 => img.rotate180(),
+            Variant::Rotated270 => img.rotate270(),
+            Variant::Resized(percent) => img.resize_exact(
+                (img.width() * percent / 100).max(1),
+                (img.height() * percent / 100).max(1),
+                FilterType::Triangle,
+            ),
+            Variant::Noised(amount) => add_noise(img, amount, u64::from(amount)),
+        }
+    }
+
+    /// Short name used in generated file names
+    pub fn name(&self) -> String {
+        match self {
+            Variant::Rotated90 => "rotated90".to_string(),
+            Variant::Rotated180 => "rotated180".to_string(),
+            Variant::Rotated270 => "rotated270".to_string(),
+            Variant::Resized(percent) => format!("resized{percent}"),
+            Variant::Noised(amount) => format!("noised{amount}"),
+        }
+    }
+}
+
+/// Shift every channel by a deterministic pseudo-random amount in `-amount..=amount`
+pub fn add_noise(img: &DynamicImage, amount: u8, seed: u64) -> DynamicImage {
+    let mut rng = XorShift::new(seed);
+    let mut noisy = img.to_rgb8();
+    for pixel in noisy.pixels_mut() {
+        for channel in pixel.0.iter_mut() {
+            let shift = (rng.next_unit() * 2.0 - 1.0) * f32::from(amount);
+            *channel = (f32::from(*channel) + shift).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    DynamicImage::ImageRgb8(noisy)
+}
+
+/// A temporary directory that fixture images are written into, removed when dropped
+pub struct FixtureDir {
+    dir: TempDir,
+}
+
+impl FixtureDir {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            dir: TempDir::new().context("Failed to create fixture directory")?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Save an image under a relative path, creating subdirectories. The format follows the
+    /// extension
+    pub fn write(&self, name: impl AsRef<Path>, img: &DynamicImage) -> Result<PathBuf> {
+        let path = self.dir.path().join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // JPEG has no alpha channel, so always save plain RGB
+        DynamicImage::ImageRgb8(img.to_rgb8())
+            .save(&path)
+            .with_context(|| format!("Failed to write fixture {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Save `{stem}.png` and a `{stem}_{variant}.png` copy for each variant, returning the
+    /// original's path first
+    pub fn write_with_variants(
+        &self,
+        stem: &str,
+        img: &DynamicImage,
+        variants: &[Variant],
+    ) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![self.write(format!("{stem}.png"), img)?];
+        for variant in variants {
+            paths.push(self.write(
+                format!("{stem}_{}.png", variant.name()),
+                &variant.apply(img),
+            )?);
+        }
+        Ok(paths)
+    }
+}
+
+/// Perceptual hashes in clusters, for grouping tests that don't need images. Each cluster is a
+/// random hash and copies of it with up to `max_flipped` bits changed. Paths are spread over
+/// folders that sort differently by bytes and by path component (`a-b/` and `a/b/`)
+pub fn clustered_hashes(
+    clusters: usize,
+    per_cluster: usize,
+    max_flipped: u32,
+    seed: u64,
+) -> Vec<(PathBuf, ImageHash)> {
+    const FOLDERS: [&str; 4] = ["a", "a-b", "a/b", "b"];
+    let mut rng = XorShift::new(seed);
+    let mut hashes = Vec::new();
+    for cluster in 0..clusters {
+        let base = rng.next_u64();
+        for member in 0..per_cluster {
+            let mut bits = base;
+            for _ in 0..rng.next_u64() % (u64::from(max_flipped) + 1) {
+                bits ^= 1 << (rng.next_u64() % 64);
+            }
+            let folder = FOLDERS[(rng.next_u64() % FOLDERS.len() as u64) as usize];
+            let path = PathBuf::from(format!("/photos/{folder}/{cluster}_{member}.jpg"));
+            let hash = ImageHash::decode(&format!("{bits:016x}"), 8, 8)
+                .expect("64 bit hashes should decode");
+            hashes.push((path, hash));
+        }
+    }
+    hashes
+}
+
+/// The items in a deterministic pseudo-random order
+pub fn shuffled<T: Clone>(items: &[T], seed: u64) -> Vec<T> {
+    let mut rng = XorShift::new(seed);
+    let mut items = items.to_vec();
+    // Fisher-Yates
+    for i in (1..items.len()).rev() {
+        items.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+    }
+    items
+}
@@ -0,0 +1,1041 @@
+use crate::cache::{Config, HashCache};
+use crate::hasher::{find_duplicates, generate_hashes_with_cache, get_duplicates_from_cache};
+use crate::ingest::{download_images, record_downloads, DownloadLimits, IngestedImage};
+use crate::scanner::scan_for_images;
+use crate::server::{router, router_with_scan};
+use crate::settings::load_stored_settings;
+use crate::test_support::{gradient, pattern, solid, FixtureDir, Variant, FIXTURE_SIZE};
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Method, Request, StatusCode};
+use axum::response::Redirect;
+use axum::routing::get;
+use serde_json::{json, Value};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// Out of the 64 bits an 8x8 grid gives, the defaults `scan` uses
+const THRESHOLD: u32 = 15;
+const GRID_SIZE: u8 = 8;
+
+const ALL_VARIANTS: [Variant; 5] = [
+    Variant::Rotated90,
+    Variant::Rotated180,
+    Variant::Rotated270,
+    Variant::Resized(50),
+    Variant::Noised(8),
+];
+
+/// A pattern with every kind of altered copy, plus unrelated images that shouldn't match it
+fn library(fixtures: &FixtureDir) -> Vec<PathBuf> {
+    let original = pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1);
+    let copies = fixtures
+        .write_with_variants("photo", &original, &ALL_VARIANTS)
+        .expect("Failed to write fixture copies");
+
+    for seed in [2, 3] {
+        fixtures
+            .write(
+                format!("others/pattern{seed}.png"),
+                &pattern(FIXTURE_SIZE, FIXTURE_SIZE, seed),
+            )
+            .expect("Failed to write fixture");
+    }
+    fixtures
+        .write(
+            "others/gradient.jpg",
+            &gradient(FIXTURE_SIZE, FIXTURE_SIZE / 2, [0, 0, 0], [255, 255, 255]),
+        )
+        .expect("Failed to write fixture");
+
+    copies
+}
+
+fn sorted(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_altered_copies_group_with_their_original() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+
+    let images = scan_for_images(&[fixtures.path().to_path_buf()], false, false, false, &[])
+        .expect("Failed to scan for images");
+    assert_eq!(images.len(), copies.len() + 3);
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let hashes = generate_hashes_with_cache(&images, GRID_SIZE, &cache, false)
+        .expect("Failed to generate hashes");
+    let duplicates = find_duplicates(&hashes, THRESHOLD);
+
+    assert_eq!(duplicates.len(), 1, "Only the copies should be grouped");
+    assert_eq!(sorted(duplicates[0].clone()), sorted(copies));
+}
+
+#[test]
+fn test_solid_colour_matches_across_formats() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    for (name, color) in [("red.png", [255, 0, 0]), ("red.jpg", [255, 0, 0])] {
+        fixtures
+            .write(name, &solid(FIXTURE_SIZE, FIXTURE_SIZE, color))
+            .expect("Failed to write fixture");
+    }
+
+    let images = scan_for_images(&[fixtures.path().to_path_buf()], false, false, false, &[])
+        .expect("Failed to scan for images");
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let hashes = generate_hashes_with_cache(&images, GRID_SIZE, &cache, false)
+        .expect("Failed to generate hashes");
+
+    // The same flat colour as PNG and JPEG decodes to the same grey, so the hashes are equal
+    assert_eq!(find_duplicates(&hashes, 0).len(), 1);
+}
+
+#[test]
+fn test_groups_from_reopened_cache_match_fresh_scan() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    library(&fixtures);
+    let db_path = fixtures.path().join("hashes.db");
+    let db_path = db_path.to_str().expect("temp path should be valid UTF-8");
+
+    let images = scan_for_images(&[fixtures.path().to_path_buf()], false, false, false, &[])
+        .expect("Failed to scan for images");
+    let scanned = {
+        let cache = HashCache::new(Some(db_path)).expect("Failed to open cache");
+        let hashes = generate_hashes_with_cache(&images, GRID_SIZE, &cache, false)
+            .expect("Failed to generate hashes");
+        find_duplicates(&hashes, THRESHOLD)
+    };
+
+    let cache = HashCache::new(Some(db_path)).expect("Failed to reopen cache");
+    assert_eq!(
+        cache
+            .get_all_cached_hashes()
+            .expect("Failed to read cached hashes")
+            .len(),
+        images.len()
+    );
+    let cached = get_duplicates_from_cache(&cache, THRESHOLD, GRID_SIZE, None, None)
+        .expect("Failed to group cached hashes");
+
+    let normalise = |groups: Vec<Vec<PathBuf>>| {
+        let mut groups: Vec<Vec<PathBuf>> = groups.into_iter().map(sorted).collect();
+        groups.sort();
+        groups
+    };
+    assert_eq!(normalise(cached), normalise(scanned));
+}
+
+async fn request_json(app: axum::Router, method: Method, uri: &str, body: Option<Value>) -> Value {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .expect("Failed to build request");
+
+    let response = app.oneshot(request).await.expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::OK, "{uri} should succeed");
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Failed to read response body");
+    serde_json::from_slice(&bytes).expect("Response should be JSON")
+}
+
+#[tokio::test]
+async fn test_api_scan_then_matches() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+    let db_path = fixtures.path().join("api.db");
+    let config = Config {
+        database_path: Some(db_path.display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+
+    let scan = request_json(
+        app.clone(),
+        Method::POST,
+        "/api/scan",
+        Some(json!({ "paths": [fixtures.path()], "include_hashes": true })),
+    )
+    .await;
+    assert_eq!(scan["success"], true);
+    assert_eq!(scan["duplicate_count"], 1);
+    assert_eq!(scan["skipped_count"], 0);
+    let group = scan["duplicates"][0]
+        .as_array()
+        .expect("group should be a list");
+    assert_eq!(group.len(), copies.len());
+    assert!(group
+        .iter()
+        .all(|file| file["exists"] == true && file["sha256"].is_string()));
+
+    // Matches are computed from what the scan cached
+    let matches = request_json(app.clone(), Method::GET, "/api/matches", None).await;
+    assert_eq!(matches["threshold"], THRESHOLD);
+    let mut paths: Vec<PathBuf> = matches["duplicates"][0]
+        .as_array()
+        .expect("group should be a list")
+        .iter()
+        .map(|file| PathBuf::from(file["path"].as_str().expect("path should be a string")))
+        .collect();
+    paths.sort();
+    assert_eq!(paths, sorted(copies.clone()));
+    assert_eq!(matches["confidence"].as_array().map(Vec::len), Some(1));
+
+    // The copies are altered, so none of them is an exact duplicate
+    let exact = request_json(
+        app.clone(),
+        Method::GET,
+        "/api/matches?min_confidence=exact",
+        None,
+    )
+    .await;
+    assert_eq!(exact["duplicates"], json!([]));
+
+    // Every copy is a PNG, so all reclaimable space is attributed to PNG
+    let stats = request_json(app, Method::GET, "/api/stats", None).await;
+    assert_eq!(stats["groups"], 1);
+    assert_eq!(stats["redundant_files"], copies.len() - 1);
+    assert_eq!(stats["by_format"][0]["format"], "png");
+    assert_eq!(stats["by_format"][0]["duplicate_files"], copies.len());
+}
+
+#[tokio::test]
+async fn test_background_scan_is_followed_through_the_api() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        ..Config::default()
+    };
+    let app = router_with_scan(
+        config,
+        Some(THRESHOLD),
+        Some(GRID_SIZE),
+        vec![fixtures.path().to_path_buf()],
+    );
+
+    // The server answers while the scan is still going
+    let mut status = request_json(app.clone(), Method::GET, "/api/scan/status", None).await;
+    assert_ne!(status["state"], "idle");
+    assert_eq!(status["paths"], json!([fixtures.path()]));
+    for _ in 0..600 {
+        if status["state"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        status = request_json(app.clone(), Method::GET, "/api/scan/status", None).await;
+    }
+    assert_eq!(status["state"], "finished", "{status}");
+    assert_eq!(status["duplicate_count"], 1);
+    assert!(status["finished_at"].is_i64());
+
+    let matches = request_json(app.clone(), Method::GET, "/api/matches", None).await;
+    assert_eq!(
+        matches["duplicates"][0].as_array().map(Vec::len),
+        Some(copies.len())
+    );
+
+    // The scan is kept in the job history, with what it logged
+    let jobs = request_json(app.clone(), Method::GET, "/api/jobs?history=1", None).await;
+    assert_eq!(jobs["scheduled"], json!([]));
+    let run = &jobs["history"][0];
+    assert_eq!(run["kind"], "scan");
+    assert_eq!(run["trigger"], "startup");
+    assert_eq!(run["state"], "finished");
+    assert_eq!(run["message"], status["message"]);
+    let jobs = request_json(app.clone(), Method::GET, "/api/jobs", None).await;
+    assert!(jobs.get("history").is_none());
+
+    // Without --scan there's no job to report
+    let config = Config {
+        database_path: Some(fixtures.path().join("idle.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let idle = router(config, None, None);
+    let status = request_json(idle, Method::GET, "/api/scan/status", None).await;
+    assert_eq!(status["state"], "idle");
+}
+
+#[tokio::test]
+async fn test_api_scan_refuses_paths_outside_server_roots() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    library(&fixtures);
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().join("others").display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/scan")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "paths": [fixtures.path()] }).to_string(),
+        ))
+        .expect("Failed to build request");
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Failed to read response body");
+    let error: Value = serde_json::from_slice(&bytes).expect("Response should be JSON");
+    assert_eq!(error["success"], false);
+    assert!(error["message"]
+        .as_str()
+        .is_some_and(|message| message.contains("outside")));
+
+    // Only files inside a root can be looked up, though ones that are gone are reported missing
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/check-files")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "paths": [fixtures.path().join("api.db")] }).to_string(),
+        ))
+        .expect("Failed to build request");
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let checked = request_json(
+        app.clone(),
+        Method::POST,
+        "/api/check-files",
+        Some(json!({ "paths": [fixtures.path().join("others/gone.png")] })),
+    )
+    .await;
+    assert_eq!(checked["files"][0]["exists"], false);
+
+    let scan = request_json(
+        app,
+        Method::POST,
+        "/api/scan",
+        Some(json!({ "paths": [fixtures.path().join("others")] })),
+    )
+    .await;
+    assert_eq!(scan["success"], true);
+}
+
+fn group_paths(matches: &Value, index: usize) -> Vec<String> {
+    matches["duplicates"][index]
+        .as_array()
+        .expect("group should be a list")
+        .iter()
+        .map(|file| {
+            file["path"]
+                .as_str()
+                .expect("path should be a string")
+                .to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_api_pinned_groups_and_notes_are_kept() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    library(&fixtures);
+    fixtures
+        .write_with_variants(
+            "second",
+            &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 4),
+            &ALL_VARIANTS,
+        )
+        .expect("Failed to write fixture copies");
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config.clone(), Some(THRESHOLD), Some(GRID_SIZE));
+    let scan = request_json(
+        app.clone(),
+        Method::POST,
+        "/api/scan",
+        Some(json!({ "paths": [fixtures.path()] })),
+    )
+    .await;
+    assert_eq!(scan["duplicate_count"], 2);
+
+    let matches = request_json(app.clone(), Method::GET, "/api/matches", None).await;
+    assert_eq!(
+        matches["annotations"],
+        json!([{ "pinned": false }, { "pinned": false }])
+    );
+    let (first, second) = (group_paths(&matches, 0), group_paths(&matches, 1));
+
+    let pinned = request_json(
+        app.clone(),
+        Method::POST,
+        "/api/groups/pin",
+        Some(json!({ "paths": second, "pinned": true })),
+    )
+    .await;
+    assert_eq!(pinned["success"], true);
+    let noted = request_json(
+        app.clone(),
+        Method::PUT,
+        "/api/groups/note",
+        Some(json!({ "paths": second, "note": " waiting for mum to confirm " })),
+    )
+    .await;
+    assert_eq!(noted["success"], true);
+
+    // Pins and notes are in the database, so a restarted server still has them
+    let restarted = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+    let matches = request_json(restarted.clone(), Method::GET, "/api/matches?count=1", None).await;
+    assert_eq!(group_paths(&matches, 0), second);
+    assert_eq!(
+        matches["annotations"],
+        json!([{ "pinned": true, "note": "waiting for mum to confirm" }])
+    );
+
+    request_json(
+        restarted.clone(),
+        Method::POST,
+        "/api/groups/pin",
+        Some(json!({ "paths": second, "pinned": false })),
+    )
+    .await;
+    let matches = request_json(restarted.clone(), Method::GET, "/api/matches", None).await;
+    assert_eq!(group_paths(&matches, 0), first);
+    assert_eq!(
+        matches["annotations"][1]["note"],
+        "waiting for mum to confirm"
+    );
+
+    request_json(
+        restarted.clone(),
+        Method::PUT,
+        "/api/groups/note",
+        Some(json!({ "paths": second, "note": "" })),
+    )
+    .await;
+    let matches = request_json(restarted, Method::GET, "/api/matches", None).await;
+    assert_eq!(
+        matches["annotations"],
+        json!([{ "pinned": false }, { "pinned": false }])
+    );
+}
+
+async fn request_feed(app: axum::Router) -> String {
+    let request = Request::builder()
+        .uri("/feed.xml")
+        .header(header::HOST, "photos.local:8080")
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app.oneshot(request).await.expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/atom+xml; charset=utf-8"
+    );
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Failed to read response body");
+    String::from_utf8(bytes.to_vec()).expect("Feed should be UTF-8")
+}
+
+#[tokio::test]
+async fn test_feed_lists_discovered_groups() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+    let scan = json!({ "paths": [fixtures.path()] });
+    request_json(app.clone(), Method::POST, "/api/scan", Some(scan.clone())).await;
+
+    let feed = request_feed(app.clone()).await;
+    assert_eq!(feed.matches("<entry>").count(), 1);
+    assert!(feed.contains(&format!("<title>{} duplicate files, ", copies.len())));
+    assert!(feed.contains("<link href=\"http://photos.local:8080/\"/>"));
+    let first_id = feed
+        .lines()
+        .find(|line| line.contains("urn:vibe-image-comparator:group:"))
+        .expect("Entry should have an id")
+        .to_string();
+
+    // A new group is added, the unchanged one keeps its entry
+    fixtures
+        .write_with_variants(
+            "second",
+            &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 4),
+            &ALL_VARIANTS,
+        )
+        .expect("Failed to write fixture copies");
+    request_json(app.clone(), Method::POST, "/api/scan", Some(scan)).await;
+    let feed = request_feed(app).await;
+    assert_eq!(feed.matches("<entry>").count(), 2);
+    assert_eq!(feed.matches(first_id.as_str()).count(), 1);
+}
+
+#[tokio::test]
+async fn test_api_scan_reports_file_warnings() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    // The copies and the three unrelated images
+    let images = library(&fixtures).len() + 3;
+    // A PNG signature passes validation, but nothing after it decodes
+    let truncated = fixtures.path().join("truncated.png");
+    let mut contents = b"\x89PNG\r\n\x1a\n".to_vec();
+    contents.extend_from_slice(b"not image data");
+    std::fs::write(&truncated, contents).expect("Failed to write truncated file");
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+
+    let request = json!({ "paths": [fixtures.path()] });
+    let scan = request_json(
+        app.clone(),
+        Method::POST,
+        "/api/scan",
+        Some(request.clone()),
+    )
+    .await;
+    assert_eq!(scan["skipped"]["decode_error"], json!([truncated]));
+    assert_eq!(scan["skipped_by_reason"], json!({ "decode_error": 1 }));
+    assert_eq!(scan["warnings"]["decode"]["count"], 1);
+    assert_eq!(
+        scan["warnings"]["decode"]["examples"][0]["path"],
+        json!(truncated)
+    );
+    assert_eq!(scan["scanned_count"], images + 1);
+    assert_eq!(scan["hashed_count"], images);
+    assert_eq!(scan["cache_hits"], 0);
+    assert_eq!(scan["cache_misses"], images);
+
+    let rescan = request_json(app, Method::POST, "/api/scan", Some(request)).await;
+    assert_eq!(rescan["hashed_count"], images);
+    assert_eq!(rescan["cache_hits"], images);
+    assert_eq!(rescan["cache_misses"], 0);
+}
+
+#[tokio::test]
+async fn test_api_shared_hashes_list_identical_copies() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+    let resaved = fixtures.path().join("photo_again.png");
+    std::fs::copy(&copies[0], &resaved).expect("Failed to copy fixture");
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+    let scan = json!({ "paths": [fixtures.path()] });
+    request_json(app.clone(), Method::POST, "/api/scan", Some(scan)).await;
+
+    let shared = request_json(app.clone(), Method::GET, "/api/shared-hashes", None).await;
+    let total = shared["total"].as_u64().expect("total should be a number");
+    assert!(total >= 1);
+    let listed: Vec<&Value> = shared["hashes"]
+        .as_array()
+        .expect("hashes should be a list")
+        .iter()
+        .flat_map(|hash| hash["paths"].as_array().expect("paths should be a list"))
+        .collect();
+    assert!(listed.contains(&&json!(copies[0])));
+    assert!(listed.contains(&&json!(resaved)));
+
+    let paged = request_json(app, Method::GET, "/api/shared-hashes?offset=1", None).await;
+    assert_eq!(paged["total"], total);
+    assert_eq!(
+        paged["hashes"].as_array().map(Vec::len),
+        Some(total as usize - 1)
+    );
+}
+
+#[tokio::test]
+async fn test_api_recompute_regroups_from_stored_distances() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    library(&fixtures);
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+    let scan = json!({ "paths": [fixtures.path()] });
+    request_json(app.clone(), Method::POST, "/api/scan", Some(scan)).await;
+
+    // Nothing has grouped from the cached hashes yet, so no distances are stored
+    let request = Request::builder()
+        .uri("/api/matches/recompute?threshold=3")
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // Grouping at an uncached threshold compares the hashes once and stores the distances
+    let computed = request_json(app.clone(), Method::GET, "/api/matches?threshold=3", None).await;
+    let regrouped = request_json(
+        app.clone(),
+        Method::GET,
+        "/api/matches/recompute?threshold=3",
+        None,
+    )
+    .await;
+    assert_eq!(regrouped["duplicates"], computed["duplicates"]);
+
+    let matches = request_json(app.clone(), Method::GET, "/api/matches", None).await;
+    let uri = format!("/api/matches/recompute?threshold={THRESHOLD}");
+    let regrouped = request_json(app, Method::GET, &uri, None).await;
+    assert_eq!(regrouped["threshold"], THRESHOLD);
+    assert_eq!(regrouped["duplicates"], matches["duplicates"]);
+    assert!(!matches["duplicates"].as_array().expect("list").is_empty());
+}
+
+#[tokio::test]
+async fn test_api_rehash_updates_the_files_groups() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+    let scan = json!({ "paths": [fixtures.path()] });
+    request_json(app.clone(), Method::POST, "/api/scan", Some(scan)).await;
+
+    // Edited elsewhere into a different picture, under the same name
+    let edited = copies[0].clone();
+    pattern(FIXTURE_SIZE, FIXTURE_SIZE, 9)
+        .save(&edited)
+        .expect("Failed to overwrite fixture");
+    let rehash = request_json(
+        app.clone(),
+        Method::POST,
+        "/api/rehash",
+        Some(json!({ "paths": [edited] })),
+    )
+    .await;
+    assert_eq!(rehash["rehashed"], json!([edited]));
+    let group: Vec<PathBuf> = rehash["duplicates"][0]
+        .as_array()
+        .expect("The group it left should be listed")
+        .iter()
+        .map(|file| PathBuf::from(file["path"].as_str().expect("path should be a string")))
+        .collect();
+    assert_eq!(sorted(group), sorted(copies[1..].to_vec()));
+
+    let matches = request_json(app, Method::GET, "/api/matches", None).await;
+    assert_eq!(
+        matches["duplicates"][0].as_array().map(Vec::len),
+        Some(copies.len() - 1)
+    );
+}
+
+#[tokio::test]
+async fn test_read_only_server_only_browses() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let scan = json!({ "paths": [fixtures.path()] });
+    let app = router(config.clone(), Some(THRESHOLD), Some(GRID_SIZE));
+    request_json(app, Method::POST, "/api/scan", Some(scan.clone())).await;
+
+    let read_only = router(
+        Config {
+            read_only: Some(true),
+            ..config
+        },
+        Some(THRESHOLD),
+        Some(GRID_SIZE),
+    );
+    let served_config = request_json(read_only.clone(), Method::GET, "/api/config", None).await;
+    assert_eq!(served_config["read_only"], true);
+    assert_eq!(
+        served_config["hashing"]["tool_version"],
+        env!("CARGO_PKG_VERSION")
+    );
+    let cached_hashes = &served_config["cached"]["hashes"];
+    assert_eq!(cached_hashes.as_array().map(Vec::len), Some(1));
+    assert_eq!(cached_hashes[0]["tool_version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(cached_hashes[0]["hash_size"], GRID_SIZE);
+    assert_eq!(
+        served_config["cached"]["group_sets"][0]["tool_version"],
+        env!("CARGO_PKG_VERSION")
+    );
+    let matches = request_json(read_only.clone(), Method::GET, "/api/matches", None).await;
+    assert_eq!(matches["duplicates"].as_array().map(Vec::len), Some(1));
+
+    let deleted = json!({ "path": copies[0] });
+    for (method, uri, body) in [
+        (Method::POST, "/api/scan", Some(scan)),
+        (Method::POST, "/api/delete-file", Some(deleted)),
+        (Method::GET, "/api/resolve-preview", None),
+        (Method::PUT, "/api/settings", Some(json!({}))),
+        (Method::DELETE, "/api/groups/overrides", None),
+    ] {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .expect("Failed to build request");
+        let response = read_only
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("Request should complete");
+        assert!(
+            matches!(
+                response.status(),
+                StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+            ),
+            "{uri} should not be served"
+        );
+    }
+    assert!(copies[0].exists());
+}
+
+/// Set `user.keep` on a file, false where the filesystem has no user attributes
+#[cfg(target_os = "linux")]
+fn set_keep_attribute(path: &std::path::Path) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).expect("path without NUL");
+    let name = CString::new("user.keep").expect("name without NUL");
+    // SAFETY: both strings are NUL terminated and the value is read for its length only
+    unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), b"1".as_ptr().cast(), 1, 0) == 0 }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_keep_tagged_files_are_kept_and_not_deleted() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let copies = library(&fixtures);
+    // The half size copy, the smallest file
+    let tagged = copies[4].clone();
+    if !set_keep_attribute(&tagged) {
+        eprintln!("Skipping, the temporary folder has no user extended attributes");
+        return;
+    }
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+    let scan = json!({ "paths": [fixtures.path()] });
+    request_json(app.clone(), Method::POST, "/api/scan", Some(scan)).await;
+
+    let matches = request_json(app.clone(), Method::GET, "/api/matches", None).await;
+    let tags: Vec<&Value> = matches["duplicates"][0]
+        .as_array()
+        .expect("The copies should group")
+        .iter()
+        .filter(|file| file["path"] == json!(tagged))
+        .map(|file| &file["tags"])
+        .collect();
+    assert_eq!(tags, [&json!(["Keep"])]);
+
+    let preview = request_json(
+        app.clone(),
+        Method::GET,
+        "/api/resolve-preview?strategy=keep-largest",
+        None,
+    )
+    .await;
+    assert_eq!(preview["groups"][0]["keep"], json!(tagged));
+
+    let deleted = request_json(
+        app,
+        Method::POST,
+        "/api/delete-file",
+        Some(json!({ "path": tagged })),
+    )
+    .await;
+    assert_eq!(deleted["success"], false);
+    assert!(tagged.exists());
+}
+
+#[tokio::test]
+async fn test_api_ignore_paths_are_stored_and_used_by_later_scans() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    library(&fixtures);
+    let db_path = fixtures.path().join("api.db");
+    let config = Config {
+        database_path: Some(db_path.display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config.clone(), Some(THRESHOLD), Some(GRID_SIZE));
+
+    // Every altered copy is named photo_<variant>.png, leaving only the original
+    let ignored = fixtures.path().join("photo_").display().to_string();
+    let updated = request_json(
+        app.clone(),
+        Method::PUT,
+        "/api/config/ignore-paths",
+        Some(json!({ "ignore_paths": [format!(" {ignored} "), "", ignored] })),
+    )
+    .await;
+    assert_eq!(updated["ignore_paths"], json!([ignored]));
+
+    let listed = request_json(app.clone(), Method::GET, "/api/config/ignore-paths", None).await;
+    assert_eq!(listed, updated);
+    let stored = load_stored_settings(&db_path).expect("settings should be stored");
+    assert_eq!(stored.ignore_paths, Some(vec![ignored.clone()]));
+
+    let scan = request_json(
+        app,
+        Method::POST,
+        "/api/scan",
+        Some(json!({ "paths": [fixtures.path()] })),
+    )
+    .await;
+    assert_eq!(scan["success"], true);
+    assert_eq!(scan["duplicate_count"], 0);
+
+    // Stored settings outlive the server
+    let restarted = router(config, Some(THRESHOLD), Some(GRID_SIZE));
+    let settings = request_json(restarted.clone(), Method::GET, "/api/settings", None).await;
+    assert_eq!(settings, json!({ "ignore_paths": [ignored] }));
+
+    let schema = request_json(restarted, Method::GET, "/api/config/schema", None).await;
+    let option = |name: &str| {
+        schema
+            .as_array()
+            .and_then(|options| options.iter().find(|option| option["name"] == name))
+            .cloned()
+            .unwrap_or_else(|| panic!("{name} should be described"))
+    };
+    assert_eq!(option("ignore_paths")["source"], "database");
+    assert_eq!(option("ignore_paths")["value"], json!([ignored]));
+    assert_eq!(option("threshold")["source"], "cli");
+    assert_eq!(option("max_group_size")["source"], "default");
+    assert_eq!(option("port")["type"], "integer");
+}
+
+#[tokio::test]
+async fn test_api_serves_images_by_content_and_refuses_other_files() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let png = fixtures
+        .write("photo.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let mislabelled = fixtures.path().join("photo.jpg");
+    std::fs::copy(&png, &mislabelled).expect("Failed to copy fixture");
+    let text = fixtures.path().join("notes.png");
+    std::fs::write(&text, "not an image").expect("Failed to write text file");
+
+    // Served files are logged, so keep the database out of the user's cache directory
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, None, None);
+    let get = |path: &std::path::Path| {
+        let uri = format!(
+            "/api/image/{}",
+            urlencoding::encode(&path.display().to_string())
+        );
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+
+    let response = get(&mislabelled).await.expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+    let response = get(&text).await.expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn test_api_downscales_images_on_request() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let png = fixtures
+        .write("photo.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE / 2, 1))
+        .expect("Failed to write fixture");
+    let db_path = fixtures.path().join("api.db");
+    let config = Config {
+        database_path: Some(db_path.display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, None, None);
+    let encoded = urlencoding::encode(&png.display().to_string()).into_owned();
+    let get = |query: &str| {
+        let uri = format!("/api/image/{encoded}{query}");
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+
+    let response = get("?max=64").await.expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Failed to read response body");
+    let preview = image::load_from_memory(&body).expect("Preview should decode");
+    assert_eq!((preview.width(), preview.height()), (64, 32));
+    assert!(std::fs::read_dir(db_path.with_file_name("thumbnails"))
+        .expect("Previews are stored with the thumbnails")
+        .next()
+        .is_some());
+
+    // Without the parameter, or when the image fits, the original is sent
+    for query in ["", "?max=8192"] {
+        let response = get(query).await.expect("Request should complete");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+    }
+    let response = get("?max=1").await.expect("Request should complete");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_api_usage_counts_served_images_and_thumbnails() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let png = fixtures
+        .write("photo.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let config = Config {
+        database_path: Some(fixtures.path().join("api.db").display().to_string()),
+        server_roots: Some(vec![fixtures.path().display().to_string()]),
+        ..Config::default()
+    };
+    let app = router(config, None, None);
+    let encoded = urlencoding::encode(&png.display().to_string()).into_owned();
+
+    let mut sent = 0;
+    for uri in [
+        format!("/api/image/{encoded}"),
+        format!("/api/thumbnail/{encoded}"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .expect("Request should complete");
+        assert_eq!(response.status(), StatusCode::OK);
+        sent += to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body")
+            .len();
+    }
+
+    let usage = request_json(app, Method::GET, "/api/usage", None).await;
+    assert_eq!(usage["session"]["requests"], 2);
+    assert_eq!(usage["session"]["files"], 1);
+    assert_eq!(usage["session"]["bytes"], sent);
+    assert_eq!(usage["recent"][0]["kind"], "thumbnail");
+    assert_eq!(usage["recent"][1]["kind"], "image");
+    assert_eq!(
+        usage["session"]["image_bytes"],
+        std::fs::metadata(&png).expect("metadata").len()
+    );
+}
+
+#[tokio::test]
+async fn test_ingested_urls_are_matched_against_local_files() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let original = pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1);
+    let local = fixtures
+        .write("library/photo.png", &original)
+        .expect("Failed to write fixture");
+    let copy = fixtures
+        .write("served/copy.png", &Variant::Noised(8).apply(&original))
+        .expect("Failed to write fixture");
+    let other = fixtures
+        .write("served/other.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 2))
+        .expect("Failed to write fixture");
+
+    let db_path = fixtures.path().join("hashes.db");
+    let cache = HashCache::new(Some(&db_path.display().to_string())).expect("Failed to open cache");
+    generate_hashes_with_cache(std::slice::from_ref(&local), GRID_SIZE, &cache, false)
+        .expect("Failed to hash local library");
+
+    let copy_bytes = std::fs::read(&copy).expect("Failed to read fixture");
+    let other_bytes = std::fs::read(&other).expect("Failed to read fixture");
+    let feed = axum::Router::new()
+        .route("/copy.png", get(move || async move { copy_bytes }))
+        .route("/other.png", get(move || async move { other_bytes }))
+        .route(
+            "/moved",
+            get(|| async { Redirect::temporary("/other.png") }),
+        )
+        .route("/notes.txt", get(|| async { "not an image" }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind feed server");
+    let base = format!("http://{}", listener.local_addr().expect("Local address"));
+    tokio::spawn(async move { axum::serve(listener, feed).await });
+
+    let urls: Vec<String> = [
+        "/copy.png",
+        "/other.png",
+        "/moved",
+        "/notes.txt",
+        "/missing",
+    ]
+    .iter()
+    .map(|path| format!("{base}{path}"))
+    .collect();
+    let limits = DownloadLimits {
+        concurrency: 2,
+        requests_per_second: Some(50.0),
+    };
+    let dir = cache.download_dir().expect("Database is on disk");
+    let downloads = download_images(&urls, &dir, limits).await;
+    let report =
+        record_downloads(downloads, &dir, &cache, THRESHOLD, GRID_SIZE).expect("Failed to ingest");
+
+    let duplicates: Vec<&str> = report
+        .images
+        .iter()
+        .filter(|image| !image.local_matches.is_empty())
+        .map(|image| image.url.as_str())
+        .collect();
+    assert_eq!(duplicates, vec![urls[0].as_str()]);
+    assert_eq!(report.images[0].local_matches[0].0, local);
+
+    // The redirect leads to the same image, which is stored once under both URLs
+    let new: Vec<&IngestedImage> = report.new_images().collect();
+    assert_eq!(new.len(), 2);
+    assert_eq!(new[0].path, new[1].path);
+    assert!(new[0].path.starts_with(&dir));
+    assert_eq!(
+        cache.get_source_urls(&new[0].path).expect("Source URLs"),
+        vec![urls[2].clone(), urls[1].clone()]
+    );
+
+    let failed: Vec<&str> = report.failed.iter().map(|(url, _)| url.as_str()).collect();
+    assert_eq!(failed, vec![urls[3].as_str(), urls[4].as_str()]);
+}
@@ -1,8 +1,28 @@
-use crate::cache::HashCache;
-use crate::hasher::{find_duplicates, generate_hashes_with_cache};
-use crate::scanner::scan_for_images;
+use crate::action::{apply_hardlinks, plan_hardlinks};
+use crate::algorithm::HashAlgorithm;
+use crate::cache::{Config, CrossCacheMatch, FileMetadata, GroupingKey, HashCache, HashProvenance};
+use crate::hasher::{
+    find_duplicates, find_duplicates_for_thresholds, generate_hashes_with_cache,
+    generate_hashes_with_report, get_duplicates_from_cache, refresh_duplicate_groups,
+    regroup_from_stored_distances, split_groups_by_dimensions, STORED_DISTANCE_LIMIT,
+};
+use crate::hashlist::{match_hash_lists, HashList, HashListEntry};
+use crate::hotfolder::{ingest_folder, DuplicateAction};
+use crate::overrides::GroupOverride;
+#[cfg(unix)]
+use crate::paths::file_id;
+use crate::paths::is_case_insensitive;
+use crate::report::SkipReason;
+use crate::resolver::{apply_resolution, resolve_group, KeepStrategy};
+use crate::scanner::{scan_for_images, scan_for_images_with_report, sort_images, HashOrder};
+use crate::sidecar::{move_with_sidecars, SidecarMode};
+use crate::store::{link_into_store, restore_from_store};
+use crate::test_support::{pattern, shuffled, FixtureDir, FIXTURE_SIZE};
+use crate::usage::ServeKind;
+use crate::watch::apply_changes;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 use tempfile::TempDir;
 
 #[test]
@@ -174,7 +194,7 @@ fn test_broken_symlink_handling() {
 
     // Test cleanup doesn't fail when files are missing
     let deleted = cache
-        .cleanup_missing_files_and_hashes()
+        .cleanup_missing_files_and_hashes(None)
         .expect("Cleanup should not fail");
     assert_eq!(
         deleted,
@@ -298,3 +318,1392 @@ fn test_cache_optimization_skips_file_processing() {
     // The optimization should avoid file processing entirely on the second run
     // This is evidenced by the cache stats showing all hits, no misses
 }
+
+#[test]
+fn test_two_caches_share_one_database() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let db_path = temp_dir.path().join("shared.db");
+    let db_path = db_path.to_str().expect("temp path should be valid UTF-8");
+
+    // Both connections run migrations against the same file, as two server processes would
+    let first = HashCache::new(Some(db_path)).expect("first cache should open");
+    let second = HashCache::new(Some(db_path)).expect("second cache should open");
+
+    let test_dir = Path::new("test_images/all_same");
+    let paths = vec![test_dir.to_path_buf()];
+    let images =
+        scan_for_images(&paths, false, false, false, &[]).expect("Failed to scan for images");
+    let (first_half, second_half) = images.split_at(1);
+
+    generate_hashes_with_cache(first_half, 64, &first, false)
+        .expect("first cache should store hashes");
+    generate_hashes_with_cache(second_half, 64, &second, false)
+        .expect("second cache should store hashes");
+
+    // Each connection sees what the other one wrote
+    assert_eq!(
+        first
+            .get_all_cached_hashes()
+            .expect("should read hashes")
+            .len(),
+        images.len()
+    );
+    assert_eq!(
+        second
+            .get_all_cached_hashes()
+            .expect("should read hashes")
+            .len(),
+        images.len()
+    );
+    assert!(
+        Path::new(&format!("{db_path}-wal")).exists(),
+        "database should use write-ahead logging"
+    );
+}
+
+#[test]
+fn test_exported_hash_lists_match_without_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let test_dir = Path::new("test_images/all_same");
+    let paths = vec![test_dir.to_path_buf()];
+    let images =
+        scan_for_images(&paths, false, false, false, &[]).expect("Failed to scan for images");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    generate_hashes_with_cache(&images, 64, &cache, false).expect("Failed to generate hashes");
+
+    let exported = HashList::from_cache(&cache).expect("Failed to export hashes");
+    assert_eq!(exported.hashes.len(), images.len());
+    assert!(exported.hashes.iter().all(|entry| entry.sha256.is_some()));
+
+    // Split the export into two lists for libraries that aren't mounted here
+    let (first, second) = exported.hashes.split_at(1);
+    let relocate = |entries: &[HashListEntry], root: &str| HashList {
+        version: exported.version,
+        hashes: entries
+            .iter()
+            .map(|entry| HashListEntry {
+                path: Path::new(root).join(entry.path.file_name().expect("file name")),
+                ..entry.clone()
+            })
+            .collect(),
+    };
+    let list_a = temp_dir.path().join("a.json");
+    let list_b = temp_dir.path().join("b.json");
+    relocate(first, "/missing/library-a")
+        .save(&list_a)
+        .expect("Failed to save first list");
+    relocate(second, "/missing/library-b")
+        .save(&list_b)
+        .expect("Failed to save second list");
+
+    let lists = vec![
+        HashList::load(&list_a).expect("Failed to load first list"),
+        HashList::load(&list_b).expect("Failed to load second list"),
+    ];
+    let duplicates = match_hash_lists(&lists, 15);
+
+    assert_eq!(
+        duplicates.len(),
+        1,
+        "Should find one group across both lists"
+    );
+    assert_eq!(duplicates[0].len(), images.len());
+    assert!(duplicates[0]
+        .iter()
+        .any(|path| path.starts_with("/missing/library-a")));
+    assert!(duplicates[0]
+        .iter()
+        .any(|path| path.starts_with("/missing/library-b")));
+}
+
+#[test]
+fn test_group_overrides_survive_recomputation() {
+    let test_dir = Path::new("test_images/all_same");
+    let paths = vec![test_dir.to_path_buf()];
+    let images =
+        scan_for_images(&paths, false, false, false, &[]).expect("Failed to scan for images");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    generate_hashes_with_cache(&images, 64, &cache, false).expect("Failed to generate hashes");
+
+    let duplicates = get_duplicates_from_cache(&cache, 15, 64, None, None).expect("groups");
+    assert_eq!(duplicates.len(), 1);
+    let split_out = duplicates[0][0].clone();
+
+    cache
+        .add_group_override(&GroupOverride::Split {
+            path: split_out.clone(),
+        })
+        .expect("Failed to store split");
+
+    // Recompute from scratch, the override is still applied on top
+    cache
+        .clear_duplicate_groups_cache()
+        .expect("Failed to clear cached groups");
+    let duplicates = get_duplicates_from_cache(&cache, 15, 64, None, None).expect("groups");
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0].len(), images.len() - 1);
+    assert!(!duplicates[0].contains(&split_out));
+
+    // Merging it back with a group member restores the full group
+    cache
+        .add_group_override(&GroupOverride::Merge {
+            a: duplicates[0][0].clone(),
+            b: split_out.clone(),
+        })
+        .expect("Failed to store merge");
+    let duplicates = get_duplicates_from_cache(&cache, 15, 64, None, None).expect("groups");
+    assert_eq!(duplicates[0].len(), images.len());
+}
+
+#[test]
+fn test_refresh_rechecks_only_grouped_files() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let original = fixtures
+        .write("a.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let edited = fixtures.path().join("b.png");
+    let deleted = fixtures.path().join("c.png");
+    fs::copy(&original, &edited).expect("Failed to copy fixture");
+    fs::copy(&original, &deleted).expect("Failed to copy fixture");
+    let untouched = fixtures
+        .write("d.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 3))
+        .expect("Failed to write fixture");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let images = vec![original.clone(), edited.clone(), deleted.clone(), untouched];
+    let hashes = generate_hashes_with_cache(&images, 16, &cache, false).expect("hashes");
+    let duplicates = find_duplicates(&hashes, 0);
+    assert_eq!(duplicates.len(), 1);
+    cache
+        .store_duplicate_groups(GroupingKey::new(0, 16), &duplicates)
+        .expect("Failed to cache groups");
+
+    fs::remove_file(&deleted).expect("Failed to delete fixture");
+    fixtures
+        .write("b.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 2))
+        .expect("Failed to overwrite fixture");
+
+    let (duplicates, report) = refresh_duplicate_groups(&cache, 0, 16).expect("refresh");
+    assert_eq!(report.checked, 3, "only the grouped files are checked");
+    assert_eq!(report.missing, vec![deleted.clone()]);
+    assert_eq!(report.changed, vec![edited]);
+    assert!(duplicates.is_empty(), "the original has no copies left");
+    assert!(cache
+        .get_cached_hash_details(&deleted)
+        .expect("lookup")
+        .is_none());
+}
+
+#[test]
+fn test_threshold_sweep_matches_single_threshold_runs() {
+    let paths = vec![
+        Path::new("test_images/all_same").to_path_buf(),
+        Path::new("test_images/rotated").to_path_buf(),
+    ];
+    let images =
+        scan_for_images(&paths, false, false, false, &[]).expect("Failed to scan for images");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let hashes =
+        generate_hashes_with_cache(&images, 16, &cache, false).expect("Failed to generate hashes");
+
+    let thresholds = [0, 5, 15, 20];
+    let sweep = find_duplicates_for_thresholds(&hashes, &thresholds);
+
+    assert_eq!(sweep.len(), thresholds.len());
+    for (threshold, groups) in sweep {
+        assert_eq!(
+            groups,
+            find_duplicates(&hashes, threshold),
+            "Sweep result for threshold {threshold} should match a single pass"
+        );
+    }
+}
+
+#[test]
+fn test_stored_distances_regroup_like_the_hashes() {
+    let paths = vec![
+        Path::new("test_images/all_same").to_path_buf(),
+        Path::new("test_images/rotated").to_path_buf(),
+    ];
+    let images =
+        scan_for_images(&paths, false, false, false, &[]).expect("Failed to scan for images");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let hashes =
+        generate_hashes_with_cache(&images, 16, &cache, false).expect("Failed to generate hashes");
+    assert!(
+        regroup_from_stored_distances(&cache, GroupingKey::new(5, 16))
+            .expect("regroup")
+            .is_none()
+    );
+
+    // Computing the groups once stores the distances for the other thresholds
+    get_duplicates_from_cache(&cache, 5, 16, None, None).expect("groups");
+    for threshold in [0, 10, STORED_DISTANCE_LIMIT] {
+        assert_eq!(
+            regroup_from_stored_distances(&cache, GroupingKey::new(threshold, 16))
+                .expect("regroup"),
+            Some(find_duplicates(&hashes, threshold)),
+            "Regrouping for threshold {threshold} should match the hashes"
+        );
+    }
+    assert!(
+        regroup_from_stored_distances(&cache, GroupingKey::new(STORED_DISTANCE_LIMIT + 1, 16))
+            .expect("regroup")
+            .is_none(),
+        "the stored distances don't go that far"
+    );
+
+    cache
+        .remove_file_entry(&images[0])
+        .expect("Failed to remove file");
+    assert!(
+        regroup_from_stored_distances(&cache, GroupingKey::new(5, 16))
+            .expect("regroup")
+            .is_none(),
+        "the hashes changed"
+    );
+}
+
+#[test]
+fn test_same_dimensions_excludes_resized_copies() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let temp_path = temp_dir.path();
+
+    fs::copy(
+        "test_images/all_same/dallepig.jpg",
+        temp_path.join("original.jpg"),
+    )
+    .expect("Failed to copy test image");
+    let original = image::open("test_images/all_same/dallepig.jpg").expect("Failed to open image");
+    original
+        .save(temp_path.join("reexport.png"))
+        .expect("Failed to save re-exported image");
+    original
+        .resize(
+            original.width() / 2,
+            original.height() / 2,
+            image::imageops::FilterType::Triangle,
+        )
+        .save(temp_path.join("resized.png"))
+        .expect("Failed to save resized image");
+
+    let images = scan_for_images(&[temp_path.to_path_buf()], false, false, false, &[])
+        .expect("Failed to scan for images");
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let hashes =
+        generate_hashes_with_cache(&images, 16, &cache, false).expect("Failed to generate hashes");
+
+    let duplicates = find_duplicates(&hashes, 20);
+    assert_eq!(duplicates.iter().map(|g| g.len()).sum::<usize>(), 3);
+
+    let same_dimensions = split_groups_by_dimensions(duplicates, &cache);
+    assert_eq!(same_dimensions.len(), 1, "Should leave one same-size group");
+    assert_eq!(same_dimensions[0].len(), 2);
+    assert!(same_dimensions[0]
+        .iter()
+        .all(|p| p.file_name().is_some_and(|n| n != "resized.png")));
+}
+
+#[test]
+fn test_scan_report_groups_skipped_files_by_reason() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let temp_path = temp_dir.path();
+
+    fs::copy(
+        "test_images/all_same/dallepig.jpg",
+        temp_path.join("good.jpg"),
+    )
+    .expect("Failed to copy test image");
+    fs::write(
+        temp_path.join("not_really.png"),
+        b"definitely not a png file",
+    )
+    .expect("Failed to write fake image");
+    let ignored_dir = temp_path.join("ignored");
+    fs::create_dir_all(&ignored_dir).expect("Failed to create ignored directory");
+    fs::copy(
+        "test_images/all_same/dallepig.jpg",
+        ignored_dir.join("skip.jpg"),
+    )
+    .expect("Failed to copy test image");
+
+    let ignore_paths = vec![ignored_dir.to_string_lossy().to_string()];
+    let (images, skipped) = scan_for_images_with_report(
+        &[temp_path.to_path_buf()],
+        false,
+        false,
+        false,
+        &ignore_paths,
+        false,
+    )
+    .expect("Failed to scan for images");
+
+    assert_eq!(images.len(), 1, "Only the valid image should be found");
+    assert_eq!(skipped.get(SkipReason::ValidationFailed).len(), 1);
+    assert_eq!(skipped.get(SkipReason::Ignored), &[ignored_dir]);
+    assert_eq!(skipped.total(), 2);
+    assert_eq!(skipped.counts().get(&SkipReason::Ignored), Some(&1));
+    assert_eq!(skipped.capped(0).total(), 0);
+
+    let json = serde_json::to_value(&skipped).expect("Skipped files should serialize");
+    assert!(json.get("validation_failed").is_some());
+}
+
+#[test]
+fn test_sort_images_by_size_descending() {
+    let mut images = vec![
+        Path::new("test_images/all_same/dallepig.png").to_path_buf(),
+        Path::new("test_images/all_same/dallepig.jpg").to_path_buf(),
+        Path::new("test_images/all_same/dallepig.webp").to_path_buf(),
+    ];
+
+    sort_images(&mut images, HashOrder::SizeDesc);
+
+    let extensions: Vec<_> = images
+        .iter()
+        .filter_map(|p| p.extension())
+        .map(|ext| ext.to_string_lossy().to_string())
+        .collect();
+    assert_eq!(extensions, vec!["jpg", "webp", "png"]);
+}
+
+#[test]
+fn test_clean_missing_keeps_files_in_unavailable_folders() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let local = temp_dir.path().join("local");
+    let share = temp_dir.path().join("share");
+    for dir in [&local, &share] {
+        fs::create_dir_all(dir).expect("Failed to create directory");
+        for name in ["a.jpg", "b.jpg"] {
+            fs::copy("test_images/all_same/dallepig.jpg", dir.join(name))
+                .expect("Failed to copy test image");
+        }
+    }
+
+    let images = scan_for_images(&[temp_dir.path().to_path_buf()], false, false, false, &[])
+        .expect("Failed to scan for images");
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    generate_hashes_with_cache(&images, 64, &cache, false).expect("Failed to generate hashes");
+
+    // One file deleted locally, and the whole share goes offline
+    fs::remove_file(local.join("a.jpg")).expect("Failed to delete file");
+    fs::remove_dir_all(&share).expect("Failed to remove share");
+
+    assert_eq!(
+        cache
+            .find_missing_files(None)
+            .expect("Listing should not fail"),
+        vec![local.join("a.jpg")],
+        "a dry run lists what cleanup removes"
+    );
+    let (files_removed, _) = cache
+        .cleanup_missing_files_and_hashes(None)
+        .expect("Cleanup should not fail");
+    assert_eq!(files_removed, 1, "Only the locally deleted file is missing");
+
+    // Scoping to a folder that isn't there is refused
+    assert!(cache
+        .cleanup_missing_files_and_hashes(Some(&share))
+        .is_err());
+
+    // Scoping to a mounted parent treats everything under it as really missing
+    let (files_removed, _) = cache
+        .cleanup_missing_files_and_hashes(Some(temp_dir.path()))
+        .expect("Cleanup should not fail");
+    assert_eq!(files_removed, 2);
+    assert_eq!(
+        cache
+            .get_all_cached_hashes()
+            .expect("should read hashes")
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_backups_are_taken_before_migrations_and_cleanup() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let db_path = temp_dir.path().join("hashes.db");
+    let backup_dir = temp_dir.path().join("backups");
+
+    // A database from before image dimensions were recorded
+    {
+        let conn = rusqlite::Connection::open(&db_path).expect("Failed to create old database");
+        conn.execute_batch(
+            "CREATE TABLE perceptual_hashes (
+                 id INTEGER PRIMARY KEY,
+                 sha256 TEXT UNIQUE NOT NULL,
+                 perceptual_hash TEXT NOT NULL
+             );
+             CREATE TABLE files (
+                 id INTEGER PRIMARY KEY,
+                 path TEXT UNIQUE NOT NULL,
+                 size INTEGER NOT NULL,
+                 perceptual_hash_id INTEGER NOT NULL
+             );",
+        )
+        .expect("Failed to create old schema");
+    }
+    let backups = || {
+        let mut names: Vec<String> = fs::read_dir(&backup_dir)
+            .map(|entries| {
+                entries
+                    .map(|entry| {
+                        entry
+                            .expect("Failed to read backup entry")
+                            .file_name()
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    };
+
+    let db_path = db_path.to_str().expect("temp path should be valid UTF-8");
+    let cache = HashCache::new(Some(db_path)).expect("Failed to open old database");
+    let after_migration = backups();
+    assert_eq!(after_migration.len(), 1);
+    assert!(after_migration[0].ends_with("-migration.db"));
+
+    // Already migrated, so reopening doesn't back up again
+    drop(cache);
+    let cache = HashCache::new(Some(db_path)).expect("Failed to reopen database");
+    assert_eq!(backups(), after_migration);
+
+    cache
+        .cleanup_missing_files_and_hashes(None)
+        .expect("Failed to clean up");
+    assert!(backups()
+        .iter()
+        .any(|name| name.ends_with("-clean-missing.db")));
+}
+
+#[test]
+fn test_store_links_are_recorded_and_restored() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let original = fixtures
+        .write("photos/a.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let copy = fixtures.path().join("photos/b.png");
+    fs::copy(&original, &copy).expect("Failed to copy fixture");
+    let other = fixtures
+        .write("photos/c.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 2))
+        .expect("Failed to write fixture");
+    let copy_modified = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&copy)
+        .and_then(|file| file.set_modified(copy_modified))
+        .expect("Failed to set modification time");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create cache");
+    let store = fixtures.path().join("photos/.store");
+    let images = vec![original.clone(), copy.clone(), other.clone()];
+    let report =
+        link_into_store(&images, &store, &cache, false).expect("Failed to link into store");
+    assert_eq!((report.linked, report.failed.len()), (3, 0));
+    assert_eq!(
+        report.bytes_saved,
+        fs::metadata(&copy).expect("metadata").len()
+    );
+    assert_eq!(cache.get_store_links().expect("Store links").len(), 3);
+    let link = cache
+        .get_store_link(&copy)
+        .expect("Store link")
+        .expect("copy is linked");
+    assert_eq!(
+        Some(link.store_path.clone()),
+        cache
+            .get_store_link(&original)
+            .expect("Store link")
+            .map(|link| link.store_path)
+    );
+
+    // Running again finds everything linked already
+    let again = link_into_store(&images, &store, &cache, false).expect("Failed to link into store");
+    assert_eq!((again.linked, again.already_linked), (0, 3));
+
+    let restored = restore_from_store(&cache, &[], false).expect("Failed to restore");
+    assert_eq!((restored.restored, restored.removed_from_store), (3, 2));
+    assert!(cache.get_store_links().expect("Store links").is_empty());
+    assert!(!link.store_path.exists());
+    assert_eq!(
+        fs::read(&original).expect("read original"),
+        fs::read(&copy).expect("read copy")
+    );
+    assert_eq!(
+        fs::metadata(&copy)
+            .and_then(|m| m.modified())
+            .expect("mtime"),
+        copy_modified
+    );
+
+    // The copy is a separate file again, changing it leaves the original alone
+    fs::write(&copy, b"changed").expect("Failed to change copy");
+    assert_ne!(fs::read(&original).expect("read original"), b"changed");
+}
+
+#[test]
+fn test_live_photo_videos_go_with_their_still() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let image = pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1);
+    let kept = fixtures
+        .write("A_export.jpg", &image)
+        .expect("Failed to write fixture");
+    let still = fixtures
+        .write("IMG_0001.jpg", &image)
+        .expect("Failed to write fixture");
+    let video = fixtures.path().join("IMG_0001.MOV");
+    fs::write(&video, b"motion").expect("Failed to write video");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let group = vec![kept.clone(), still.clone()];
+    let resolution = resolve_group(
+        &group,
+        KeepStrategy::KeepLargest,
+        SidecarMode::Ignore,
+        &cache,
+    )
+    .expect("a group of two needs resolving");
+    assert_eq!(resolution.keep, kept);
+    assert_eq!(resolution.delete, vec![still.clone()]);
+    assert_eq!(resolution.live_photo_videos, vec![video.clone()]);
+    assert_eq!(
+        resolution.bytes_reclaimed,
+        fs::metadata(&still).expect("still exists").len() + 6
+    );
+
+    // Moving the still takes its motion part along, even with sidecars ignored
+    let moved = move_with_sidecars(
+        &still,
+        &fixtures.path().join("kept.jpg"),
+        SidecarMode::Ignore,
+    )
+    .expect("Failed to move");
+    assert_eq!(moved.len(), 2);
+    assert!(fixtures.path().join("kept.MOV").is_file());
+    assert!(!video.exists());
+}
+
+#[test]
+fn test_cross_cache_matches_come_from_attached_database() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let other_path = temp_dir.path().join("nas #1?.db");
+    let local = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let other = HashCache::new(Some(
+        other_path
+            .to_str()
+            .expect("temp path should be valid UTF-8"),
+    ))
+    .expect("other cache should open");
+
+    let store = |cache: &HashCache, path: &str, sha256: &str, perceptual_hash: &str| {
+        cache
+            .store_hash(&FileMetadata {
+                path: Path::new(path).to_path_buf(),
+                size: 1,
+                sha256: sha256.to_string(),
+                perceptual_hash: perceptual_hash.to_string(),
+                width: None,
+                height: None,
+                dominant_color: None,
+                modified: None,
+            })
+            .expect("Failed to store hash");
+    };
+    store(&local, "/home/a.jpg", "aa", "0000000000000000");
+    store(&local, "/home/b.jpg", "bb", "ff00000000000000");
+    store(&local, "/home/c.jpg", "cc", "0f0f0f0f0f0f0f0f");
+    store(&other, "/nas/a.jpg", "aa", "0000000000000000");
+    store(&other, "/nas/b.jpg", "b2", "ff00000000000003");
+    // Within the threshold but with a different prefix, so not a candidate
+    store(&other, "/nas/c.jpg", "c2", "1f0f0f0f0f0f0f0f");
+    // Close to b.jpg, but hashed with another algorithm, so not comparable
+    store(&other, "/nas/d.jpg", "dd", "ff00000000000001");
+    drop(other);
+    rusqlite::Connection::open(&other_path)
+        .expect("Failed to open other cache")
+        .execute(
+            "UPDATE perceptual_hashes SET algorithm = 'difference-rotation-invariant'
+             WHERE sha256 = 'dd'",
+            [],
+        )
+        .expect("Failed to change algorithm");
+
+    let mut matches = Vec::new();
+    local
+        .for_each_cross_cache_match(&other_path, 4, 8, |found| matches.push(found))
+        .expect("Failed to compare caches");
+    assert_eq!(
+        matches,
+        vec![
+            CrossCacheMatch {
+                local: "/home/a.jpg".into(),
+                other: "/nas/a.jpg".into(),
+                distance: None,
+            },
+            CrossCacheMatch {
+                local: "/home/b.jpg".into(),
+                other: "/nas/b.jpg".into(),
+                distance: Some(2),
+            },
+        ]
+    );
+
+    // The other database is detached again, so it can be compared once more
+    local
+        .for_each_cross_cache_match(&other_path, 4, 8, |_| {})
+        .expect("Failed to compare caches again");
+}
+
+#[test]
+fn test_find_similar_looks_up_candidates_by_hash_prefix() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let db_path = temp_dir.path().join("hashes.db");
+
+    // Cached before hash prefixes were stored, so its prefix is backfilled on opening
+    {
+        let conn = rusqlite::Connection::open(&db_path).expect("Failed to create old database");
+        conn.execute_batch(
+            "CREATE TABLE perceptual_hashes (
+                 id INTEGER PRIMARY KEY,
+                 sha256 TEXT UNIQUE NOT NULL,
+                 perceptual_hash TEXT NOT NULL,
+                 width INTEGER,
+                 height INTEGER,
+                 dominant_color TEXT
+             );
+             CREATE TABLE files (
+                 id INTEGER PRIMARY KEY,
+                 path TEXT UNIQUE NOT NULL,
+                 size INTEGER NOT NULL,
+                 perceptual_hash_id INTEGER NOT NULL,
+                 modified INTEGER
+             );
+             INSERT INTO perceptual_hashes (id, sha256, perceptual_hash)
+                 VALUES (1, 'old', '000000000000000f');
+             INSERT INTO files (path, size, perceptual_hash_id) VALUES ('/old.jpg', 1, 1);",
+        )
+        .expect("Failed to create old schema");
+    }
+    let cache = HashCache::new(Some(
+        db_path.to_str().expect("temp path should be valid UTF-8"),
+    ))
+    .expect("Failed to open old database");
+
+    for (path, sha256, perceptual_hash) in [
+        ("/same.jpg", "same", "0000000000000000"),
+        ("/close.jpg", "close", "0300000000000000"),
+        // Within the threshold, but 3 of the first 16 bits differ
+        ("/missed.jpg", "missed", "0700000000000000"),
+        ("/far.jpg", "far", "00000000000000ff"),
+    ] {
+        cache
+            .store_hash(&FileMetadata {
+                path: Path::new(path).to_path_buf(),
+                size: 1,
+                sha256: sha256.to_string(),
+                perceptual_hash: perceptual_hash.to_string(),
+                width: None,
+                height: None,
+                dominant_color: None,
+                modified: None,
+            })
+            .expect("Failed to store hash");
+    }
+
+    assert_eq!(
+        cache
+            .find_similar("0000000000000000", 4)
+            .expect("Failed to look up similar files"),
+        vec![
+            ("/same.jpg".into(), 0),
+            ("/close.jpg".into(), 2),
+            ("/old.jpg".into(), 4),
+        ]
+    );
+    assert!(cache.find_similar("not hex", 4).is_err());
+}
+
+#[test]
+fn test_shared_hashes_list_files_with_identical_hashes() {
+    let cache = HashCache::new_in_memory().expect("Failed to create cache");
+    for (path, sha256, perceptual_hash) in [
+        ("/b/copy.jpg", "same", "0000000000000000"),
+        ("/a/original.jpg", "same", "0000000000000000"),
+        // Different contents, same picture
+        ("/a/resaved.png", "resaved", "0000000000000000"),
+        ("/c/one.jpg", "one", "00000000000000ff"),
+        ("/c/two.jpg", "two", "00000000000000ff"),
+        // One bit away is similar, not identical
+        ("/c/close.jpg", "close", "00000000000000fe"),
+    ] {
+        cache
+            .store_hash(&FileMetadata {
+                path: Path::new(path).to_path_buf(),
+                size: 1,
+                sha256: sha256.to_string(),
+                perceptual_hash: perceptual_hash.to_string(),
+                width: None,
+                height: None,
+                dominant_color: None,
+                modified: None,
+            })
+            .expect("Failed to store hash");
+    }
+
+    let shared = cache
+        .get_shared_hashes()
+        .expect("Failed to list shared hashes");
+    let listed: Vec<(&str, Vec<&Path>)> = shared
+        .iter()
+        .map(|hash| {
+            (
+                hash.perceptual_hash.as_str(),
+                hash.paths.iter().map(PathBuf::as_path).collect(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        listed,
+        vec![
+            (
+                "0000000000000000",
+                vec![
+                    Path::new("/a/original.jpg"),
+                    Path::new("/a/resaved.png"),
+                    Path::new("/b/copy.jpg"),
+                ]
+            ),
+            (
+                "00000000000000ff",
+                vec![Path::new("/c/one.jpg"), Path::new("/c/two.jpg")]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_case_aliases_are_folded_only_on_case_insensitive_volumes() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let photo = temp_dir.path().join("Photo.JPG");
+    fs::write(&photo, b"photo").expect("Failed to write file");
+    let alias = temp_dir.path().join("photo.jpg");
+    let cache = HashCache::new_in_memory().expect("Failed to create cache");
+    for path in [&alias, &photo] {
+        cache
+            .store_hash(&FileMetadata {
+                path: path.clone(),
+                size: 5,
+                sha256: "photo".to_string(),
+                perceptual_hash: "0000000000000000".to_string(),
+                width: None,
+                height: None,
+                dominant_color: None,
+                modified: None,
+            })
+            .expect("Failed to store hash");
+    }
+
+    let folded = cache.fold_case_aliases().expect("Failed to fold aliases");
+    let cached: Vec<PathBuf> = cache
+        .get_all_cached_hashes()
+        .expect("Failed to list hashes")
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    if is_case_insensitive(&photo) {
+        assert_eq!(folded, vec![alias]);
+        assert_eq!(cached, vec![photo]);
+    } else {
+        // Two different files that happen to share a name up to case
+        assert!(folded.is_empty());
+        assert_eq!(cached.len(), 2);
+    }
+}
+
+#[test]
+fn test_cached_groups_are_kept_per_comparison_settings() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let db_path = temp_dir.path().join("hashes.db");
+
+    // Groups cached before their settings were stored can't be trusted, so they're dropped
+    {
+        let conn = rusqlite::Connection::open(&db_path).expect("Failed to create old database");
+        conn.execute_batch(
+            "CREATE TABLE perceptual_hashes (
+                 id INTEGER PRIMARY KEY,
+                 sha256 TEXT UNIQUE NOT NULL,
+                 perceptual_hash TEXT NOT NULL
+             );
+             CREATE TABLE files (
+                 id INTEGER PRIMARY KEY,
+                 path TEXT UNIQUE NOT NULL,
+                 size INTEGER NOT NULL,
+                 perceptual_hash_id INTEGER NOT NULL
+             );
+             CREATE TABLE duplicate_groups (
+                 id INTEGER PRIMARY KEY,
+                 threshold INTEGER NOT NULL,
+                 group_hash TEXT NOT NULL,
+                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+             );
+             INSERT INTO duplicate_groups (threshold, group_hash) VALUES (5, 'old');",
+        )
+        .expect("Failed to create old schema");
+    }
+    let cache = HashCache::new(Some(
+        db_path.to_str().expect("temp path should be valid UTF-8"),
+    ))
+    .expect("Failed to open old database");
+    let grid_16 = GroupingKey {
+        threshold: 5,
+        grid_size: 16,
+        algorithm: HashAlgorithm::Perceptual,
+        max_group_size: Some(100),
+    };
+    assert_eq!(
+        cache
+            .get_cached_duplicate_groups(grid_16, None, None)
+            .expect("Failed to read groups"),
+        None
+    );
+
+    for (path, sha256) in [("/a.jpg", "a"), ("/b.jpg", "b"), ("/c.jpg", "c")] {
+        cache
+            .store_hash(&FileMetadata {
+                path: Path::new(path).to_path_buf(),
+                size: 1,
+                sha256: sha256.to_string(),
+                perceptual_hash: "0000000000000000".to_string(),
+                width: None,
+                height: None,
+                dominant_color: None,
+                modified: None,
+            })
+            .expect("Failed to store hash");
+    }
+    let together = vec![vec![
+        PathBuf::from("/a.jpg"),
+        PathBuf::from("/b.jpg"),
+        PathBuf::from("/c.jpg"),
+    ]];
+    let apart = vec![vec![PathBuf::from("/a.jpg"), PathBuf::from("/b.jpg")]];
+    cache
+        .store_duplicate_groups(grid_16, &together)
+        .expect("Failed to cache groups");
+
+    // Another grid size, algorithm or group size limit has its own groups
+    let grid_32 = GroupingKey {
+        grid_size: 32,
+        ..grid_16
+    };
+    let blockhash = GroupingKey {
+        algorithm: HashAlgorithm::Blockhash,
+        ..grid_16
+    };
+    let capped = GroupingKey {
+        max_group_size: Some(2),
+        ..grid_16
+    };
+    for key in [grid_32, blockhash, capped] {
+        assert_eq!(
+            cache
+                .get_cached_duplicate_groups(key, None, None)
+                .expect("Failed to read groups"),
+            None
+        );
+    }
+    cache
+        .store_duplicate_groups(capped, &apart)
+        .expect("Failed to cache groups");
+    assert_eq!(
+        cache
+            .get_cached_duplicate_groups(capped, None, None)
+            .expect("Failed to read groups"),
+        Some(apart)
+    );
+    assert_eq!(
+        cache
+            .get_cached_duplicate_groups(grid_16, None, None)
+            .expect("Failed to read groups"),
+        Some(together)
+    );
+
+    // Hashes changing invalidates the groups of every setting
+    cache
+        .remove_file_entry(Path::new("/c.jpg"))
+        .expect("Failed to remove file");
+    for key in [grid_16, capped] {
+        assert_eq!(
+            cache
+                .get_cached_duplicate_groups(key, None, None)
+                .expect("Failed to read groups"),
+            None
+        );
+    }
+}
+
+#[test]
+fn test_hashes_record_the_version_that_made_them() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let db_path = temp_dir.path().join("hashes.db");
+
+    // A hash cached before versions were recorded
+    {
+        let conn = rusqlite::Connection::open(&db_path).expect("Failed to create old database");
+        conn.execute_batch(
+            "CREATE TABLE perceptual_hashes (
+                 id INTEGER PRIMARY KEY,
+                 sha256 TEXT UNIQUE NOT NULL,
+                 perceptual_hash TEXT NOT NULL
+             );
+             CREATE TABLE files (
+                 id INTEGER PRIMARY KEY,
+                 path TEXT UNIQUE NOT NULL,
+                 size INTEGER NOT NULL,
+                 perceptual_hash_id INTEGER NOT NULL
+             );
+             INSERT INTO perceptual_hashes (id, sha256, perceptual_hash)
+                 VALUES (1, 'old', '0000000000000000');
+             INSERT INTO files (path, size, perceptual_hash_id) VALUES ('/old.jpg', 1, 1);",
+        )
+        .expect("Failed to create old schema");
+    }
+    let cache = HashCache::new(Some(
+        db_path.to_str().expect("temp path should be valid UTF-8"),
+    ))
+    .expect("Failed to open old database");
+    cache
+        .store_hash(&FileMetadata {
+            path: PathBuf::from("/new.jpg"),
+            size: 1,
+            sha256: "new".to_string(),
+            perceptual_hash: "ffffffffffffffff".to_string(),
+            width: None,
+            height: None,
+            dominant_color: None,
+            modified: None,
+        })
+        .expect("Failed to store hash");
+
+    let provenance = cache.provenance().expect("Failed to read provenance");
+    let old = HashProvenance {
+        tool_version: None,
+        algorithm: None,
+        hash_size: None,
+        invariance: None,
+        hashes: 1,
+    };
+    let new = HashProvenance {
+        hashes: 1,
+        ..HashProvenance::current(8)
+    };
+    assert_eq!(provenance.hashes, vec![old, new.clone()]);
+
+    assert_eq!(
+        cache
+            .forget_hashes_made_by(None)
+            .expect("Failed to forget hashes"),
+        1
+    );
+    assert_eq!(
+        cache
+            .get_cached_hash(Path::new("/old.jpg"), 1, "old", 8)
+            .expect("Failed to read hash"),
+        None
+    );
+    assert!(cache
+        .get_cached_hash(Path::new("/new.jpg"), 1, "new", 8)
+        .expect("Failed to read hash")
+        .is_some());
+    assert_eq!(
+        cache
+            .provenance()
+            .expect("Failed to read provenance")
+            .hashes,
+        vec![new]
+    );
+}
+
+#[test]
+fn test_hashes_of_another_grid_size_are_made_again() {
+    let images = scan_for_images(
+        &[PathBuf::from("test_images/all_same")],
+        false,
+        false,
+        false,
+        &[],
+    )
+    .expect("Failed to scan for images");
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+
+    let (_, report) =
+        generate_hashes_with_report(&images, 8, &cache, false).expect("Failed to hash at 8");
+    assert_eq!(report.cache_misses, images.len());
+    let (hashes, report) =
+        generate_hashes_with_report(&images, 16, &cache, false).expect("Failed to hash at 16");
+    assert_eq!(
+        report.cache_misses,
+        images.len(),
+        "8x8 hashes aren't used for a 16x16 grid"
+    );
+    assert_eq!(find_duplicates(&hashes, 15).len(), 1);
+
+    let cached = cache
+        .get_all_cached_hashes()
+        .expect("Failed to read hashes");
+    assert!(cached.iter().all(|(_, hash)| hash.len() == 64));
+    let (_, report) =
+        generate_hashes_with_report(&images, 16, &cache, false).expect("Failed to hash again");
+    assert_eq!(report.cache_hits, images.len());
+    let provenance = cache.provenance().expect("Failed to read provenance");
+    assert_eq!(provenance.hashes.len(), 1);
+    assert_eq!(provenance.hashes[0].hash_size, Some(16));
+}
+
+#[test]
+fn test_ingest_only_imports_images_the_library_lacks() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let kept = fixtures
+        .write("library/a.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let source = fixtures.path().join("incoming");
+    let library = fixtures.path().join("library");
+    let copy = fixtures
+        .write(
+            "incoming/a copy.png",
+            &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1),
+        )
+        .expect("Failed to write fixture");
+    let new = fixtures
+        .write(
+            "incoming/2024/b.png",
+            &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 3),
+        )
+        .expect("Failed to write fixture");
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    generate_hashes_with_cache(std::slice::from_ref(&kept), 16, &cache, false).expect("hashes");
+    let config = Config::default().with_overrides(Some(16), Some(0), None);
+
+    let report =
+        ingest_folder(&source, &library, DuplicateAction::Move, &config, &cache).expect("ingest");
+    let imported = library.join("2024/b.png");
+    assert_eq!(report.imported, vec![(new.clone(), imported.clone())]);
+    assert!(imported.exists() && !new.exists());
+    assert!(
+        cache
+            .get_cached_hash_details(&imported)
+            .expect("lookup")
+            .is_some(),
+        "imported files keep their hashes"
+    );
+    assert_eq!(report.duplicates.len(), 1);
+    assert_eq!(report.duplicates[0].matches, kept);
+    let set_aside = source.join("duplicates/a copy.png");
+    assert_eq!(report.duplicates[0].moved_to.as_ref(), Some(&set_aside));
+    assert!(set_aside.exists() && !copy.exists());
+
+    // The set aside copy isn't looked at again, a second copy of the import is deleted
+    fs::copy(&imported, &new).expect("Failed to copy fixture");
+    let report =
+        ingest_folder(&source, &library, DuplicateAction::Delete, &config, &cache).expect("ingest");
+    assert!(report.imported.is_empty());
+    assert_eq!(report.duplicates.len(), 1);
+    assert_eq!(report.duplicates[0].matches, imported);
+    assert_eq!(report.duplicates[0].moved_to, None);
+    assert!(!new.exists() && set_aside.exists());
+}
+
+#[test]
+fn test_identical_files_are_replaced_by_hard_links() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let keeper = fixtures
+        .write("a.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let copy = fixtures.path().join("copies/a.png");
+    fs::create_dir_all(fixtures.path().join("copies")).expect("Failed to create folder");
+    fs::copy(&keeper, &copy).expect("Failed to copy fixture");
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let images = vec![keeper.clone(), copy.clone()];
+    generate_hashes_with_cache(&images, 16, &cache, false).expect("hashes");
+    let size = fs::metadata(&keeper).expect("metadata").len();
+
+    let plan = plan_hardlinks(
+        std::slice::from_ref(&images),
+        KeepStrategy::KeepShortestPath,
+        &cache,
+    );
+    assert_eq!(plan.links.len(), 1);
+    assert_eq!(plan.links[0].path, copy);
+    assert_eq!(plan.links[0].keeper, keeper);
+    assert_eq!(plan.bytes_saved, size);
+
+    let report = apply_hardlinks(&plan);
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    assert_eq!(report.linked, vec![copy.clone()]);
+    assert_eq!(report.bytes_saved, size);
+    assert_eq!(
+        fs::read(&copy).expect("read"),
+        fs::read(&keeper).expect("read")
+    );
+    #[cfg(unix)]
+    {
+        assert_eq!(file_id(&copy), file_id(&keeper));
+        let plan = plan_hardlinks(&[images], KeepStrategy::KeepShortestPath, &cache);
+        assert!(plan.links.is_empty());
+        assert_eq!(plan.already_linked, 1);
+    }
+}
+
+#[test]
+fn test_auto_resolve_deletes_all_but_the_keeper() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let newest = fixtures
+        .write("a.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let older = fixtures
+        .write("old/a.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    let sidecar = fixtures.path().join("old/a.xmp");
+    fs::write(&sidecar, "<x:xmpmeta/>").expect("Failed to write sidecar");
+    fs::File::options()
+        .write(true)
+        .open(&older)
+        .and_then(|file| file.set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000)))
+        .expect("Failed to age fixture");
+    let group = vec![newest.clone(), older.clone()];
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    generate_hashes_with_cache(&group, 16, &cache, false).expect("hashes");
+
+    let resolution = resolve_group(
+        &group,
+        KeepStrategy::KeepOldest,
+        SidecarMode::Follow,
+        &cache,
+    )
+    .expect("group should resolve");
+    assert_eq!(resolution.keep, older);
+    assert_eq!(resolution.delete, vec![newest.clone()]);
+    assert!(resolution.sidecars.is_empty());
+
+    let resolution = resolve_group(
+        &group,
+        KeepStrategy::KeepNewest,
+        SidecarMode::Follow,
+        &cache,
+    )
+    .expect("group should resolve");
+    assert_eq!(resolution.sidecars, vec![sidecar.clone()]);
+    apply_resolution(&resolution, &cache).expect("resolve");
+    assert!(newest.exists());
+    assert!(!older.exists() && !sidecar.exists());
+    assert!(!cache.is_path_cached(&older).expect("lookup"));
+    assert!(cache.is_path_cached(&newest).expect("lookup"));
+}
+
+#[test]
+fn test_watched_changes_update_the_cached_groups() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let original = fixtures
+        .write("inbox/a.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    fixtures
+        .write("inbox/b.png", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 2))
+        .expect("Failed to write fixture");
+    let inbox = fixtures.path().join("inbox");
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let config = Config::default().with_overrides(Some(16), Some(0), None);
+
+    let update = apply_changes(
+        std::slice::from_ref(&inbox),
+        std::slice::from_ref(&inbox),
+        &config,
+        &cache,
+    )
+    .expect("catch up");
+    assert_eq!(update.hashed.len(), 2);
+    assert!(update.groups.is_empty());
+
+    let copy = fixtures
+        .write(
+            "inbox/trip/a copy.png",
+            &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1),
+        )
+        .expect("Failed to write fixture");
+    let update = apply_changes(
+        std::slice::from_ref(&copy),
+        std::slice::from_ref(&inbox),
+        &config,
+        &cache,
+    )
+    .expect("new file");
+    assert_eq!(update.hashed, vec![copy.clone()]);
+    assert_eq!(update.groups, vec![vec![original.clone(), copy.clone()]]);
+    assert_eq!(
+        cache
+            .get_cached_duplicate_groups(GroupingKey::new(0, 16), None, None)
+            .expect("groups"),
+        Some(update.groups.clone()),
+        "the stored groups include the new file"
+    );
+    let discoveries = cache.get_group_discoveries(0, 10).expect("discoveries");
+    assert_eq!(discoveries.len(), 1);
+    assert_eq!(discoveries[0].paths, vec![original.clone(), copy.clone()]);
+
+    // A removed folder takes its cached files with it
+    let trip = inbox.join("trip");
+    fs::remove_dir_all(&trip).expect("Failed to remove fixture");
+    let update = apply_changes(&[trip], std::slice::from_ref(&inbox), &config, &cache)
+        .expect("removed folder");
+    assert_eq!(update.removed, vec![copy.clone()]);
+    assert!(!cache.is_path_cached(&copy).expect("lookup"));
+    assert!(get_duplicates_from_cache(&cache, 0, 16, None, None)
+        .expect("groups")
+        .is_empty());
+    assert!(cache
+        .get_group_discoveries(0, 10)
+        .expect("discoveries")
+        .is_empty());
+
+    // Safari downloads into a `.download` folder below the watched one
+    let downloading = fixtures
+        .write(
+            "inbox/b.png.download/b.png",
+            &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 2),
+        )
+        .expect("Failed to write fixture");
+    let update = apply_changes(
+        std::slice::from_ref(&downloading),
+        std::slice::from_ref(&inbox),
+        &config,
+        &cache,
+    )
+    .expect("download");
+    assert!(update.hashed.is_empty());
+    assert!(!cache.is_path_cached(&downloading).expect("lookup"));
+}
+
+#[test]
+fn test_streamed_hashes_keep_the_order_given() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let images: Vec<PathBuf> = (0..24)
+        .map(|seed| {
+            fixtures
+                .write(
+                    format!("{seed}.png"),
+                    &pattern(FIXTURE_SIZE, FIXTURE_SIZE, seed),
+                )
+                .expect("Failed to write fixture")
+        })
+        .collect();
+    let images = shuffled(&images, 7);
+
+    let even: Vec<PathBuf> = images.iter().step_by(2).cloned().collect();
+    let odd: Vec<PathBuf> = images.iter().skip(1).step_by(2).cloned().collect();
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let hashed = generate_hashes_with_cache(&even, 16, &cache, false).expect("hashes");
+    let paths: Vec<PathBuf> = hashed.into_iter().map(|(path, _)| path).collect();
+    assert_eq!(paths, even);
+
+    // Cached and new files alternate, hits come as they're found and misses batch by batch
+    let (hashed, report) = generate_hashes_with_report(&images, 16, &cache, false).expect("hashes");
+    assert_eq!((report.cache_hits, report.cache_misses), (12, 12));
+    let paths: Vec<PathBuf> = hashed.into_iter().map(|(path, _)| path).collect();
+    assert_eq!(paths, [even, odd].concat());
+}
+
+#[cfg(feature = "raw-previews")]
+#[test]
+fn test_raw_files_are_hashed_by_their_preview() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    let jpeg = fixtures
+        .write("IMG_1.jpg", &pattern(FIXTURE_SIZE, FIXTURE_SIZE, 1))
+        .expect("Failed to write fixture");
+    // A TIFF header, some sensor data, then the camera's JPEG preview
+    let mut raw = b"II*\0 sensor data".to_vec();
+    raw.extend(fs::read(&jpeg).expect("Failed to read fixture"));
+    fs::write(fixtures.path().join("IMG_1.CR2"), &raw).expect("Failed to write RAW file");
+    fs::write(fixtures.path().join("IMG_2.nef"), b"not a RAW file at all")
+        .expect("Failed to write fake RAW file");
+
+    let (images, report) = scan_for_images_with_report(
+        &[fixtures.path().to_path_buf()],
+        false,
+        false,
+        false,
+        &[],
+        false,
+    )
+    .expect("Failed to scan");
+    assert_eq!(images.len(), 2, "the JPEG and the CR2");
+    assert_eq!(
+        report.get(SkipReason::ValidationFailed).len(),
+        1,
+        "the NEF without a TIFF header is skipped"
+    );
+
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    let hashes = generate_hashes_with_cache(&images, 8, &cache, false).expect("Failed to hash");
+    assert_eq!(find_duplicates(&hashes, 0).len(), 1);
+}
+
+#[test]
+fn test_avif_and_jpeg_xl_files_are_not_scanned() {
+    let fixtures = FixtureDir::new().expect("Failed to create fixtures");
+    fs::write(
+        fixtures.path().join("IMG_1.avif"),
+        b"\0\0\0\x1cftypavif\0\0\0\0mif1miaf",
+    )
+    .expect("Failed to write AVIF");
+    fs::write(
+        fixtures.path().join("export.jxl"),
+        [0xFF, 0x0A, 0xFA, 0x1F, 0x00],
+    )
+    .expect("Failed to write JPEG XL");
+
+    let (images, skipped) = scan_for_images_with_report(
+        &[fixtures.path().to_path_buf()],
+        false,
+        false,
+        false,
+        &[],
+        false,
+    )
+    .expect("Failed to scan");
+    // There's no decoder for them, so they'd only ever be decode errors
+    assert!(images.is_empty());
+    assert!(skipped.is_empty());
+}
+
+#[test]
+fn test_serve_log_keeps_the_newest_server_runs() {
+    let cache = HashCache::new_in_memory().expect("Failed to create in-memory cache");
+    for session_started in [100, 200, 300] {
+        cache
+            .log_served(
+                session_started,
+                Path::new("/photos/a.jpg"),
+                ServeKind::Image,
+                10,
+            )
+            .expect("Failed to log served file");
+    }
+    assert_eq!(cache.prune_serve_log(2).expect("Failed to prune"), 1);
+    let sessions: Vec<i64> = cache
+        .get_session_usage()
+        .expect("Failed to read usage")
+        .iter()
+        .map(|session| session.started_at)
+        .collect();
+    assert_eq!(sessions, vec![300, 200]);
+}
@@ -1 +1,3 @@
+mod end_to_end_tests;
 mod integration_tests;
+mod property_tests;
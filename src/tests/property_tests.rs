@@ -0,0 +1,108 @@
+//! Grouping properties checked over many generated hash sets, since the order hashes arrive
+//! in depends on thread scheduling and database order
+
+use crate::cache::{GroupingKey, HashCache};
+use crate::hasher::{find_duplicates, find_duplicates_for_thresholds};
+use crate::test_support::{clustered_hashes, shuffled};
+use imghash::ImageHash;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const SEEDS: u64 = 25;
+const SHUFFLES: u64 = 8;
+const THRESHOLDS: [u32; 4] = [0, 4, 10, 20];
+
+/// Hash sets with tight clusters, loose clusters that overlap each other, and no clusters
+fn hash_sets() -> impl Iterator<Item = (u64, Vec<(PathBuf, ImageHash)>)> {
+    (0..SEEDS).map(|seed| {
+        let hashes = match seed % 3 {
+            0 => clustered_hashes(6, 4, 3, seed),
+            1 => clustered_hashes(4, 6, 16, seed),
+            _ => clustered_hashes(20, 1, 0, seed),
+        };
+        (seed, hashes)
+    })
+}
+
+#[test]
+fn test_groups_do_not_depend_on_input_order() {
+    for (seed, hashes) in hash_sets() {
+        for threshold in THRESHOLDS {
+            let expected = find_duplicates(&hashes, threshold);
+            for shuffle in 0..SHUFFLES {
+                let reordered = shuffled(&hashes, seed * SHUFFLES + shuffle);
+                assert_eq!(
+                    find_duplicates(&reordered, threshold),
+                    expected,
+                    "seed {seed}, threshold {threshold}, shuffle {shuffle}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_groups_are_sorted_and_disjoint() {
+    for (seed, hashes) in hash_sets() {
+        let by_path: HashMap<&PathBuf, &ImageHash> =
+            hashes.iter().map(|(path, hash)| (path, hash)).collect();
+        let distance = |a: &PathBuf, b: &PathBuf| {
+            by_path[a]
+                .distance(by_path[b])
+                .expect("hashes have the same size") as u32
+        };
+
+        for threshold in THRESHOLDS {
+            let groups = find_duplicates(&hashes, threshold);
+            let firsts: Vec<&PathBuf> = groups.iter().map(|group| &group[0]).collect();
+            assert!(
+                firsts.windows(2).all(|pair| pair[0] < pair[1]),
+                "seed {seed}: groups should be sorted by first path"
+            );
+
+            let mut seen = Vec::new();
+            for group in &groups {
+                assert!(group.len() > 1);
+                let first = &group[0];
+                let keys: Vec<(u32, &PathBuf)> = group
+                    .iter()
+                    .map(|path| (distance(first, path), path))
+                    .collect();
+                assert!(
+                    keys.windows(2).all(|pair| pair[0] < pair[1]),
+                    "seed {seed}: members should be sorted by distance, then path: {keys:?}"
+                );
+                assert!(keys.iter().all(|(distance, _)| *distance <= threshold));
+                assert!(group.iter().all(|path| path >= first));
+                seen.extend(group.iter().cloned());
+            }
+            let total = seen.len();
+            seen.sort();
+            seen.dedup();
+            assert_eq!(seen.len(), total, "seed {seed}: a file is in two groups");
+        }
+    }
+}
+
+#[test]
+fn test_threshold_sweep_and_cache_keep_the_order() {
+    let cache = HashCache::new_in_memory().expect("Failed to create cache");
+    for (seed, hashes) in hash_sets() {
+        let reordered = shuffled(&hashes, seed);
+        for (threshold, groups) in find_duplicates_for_thresholds(&reordered, &THRESHOLDS) {
+            assert_eq!(groups, find_duplicates(&hashes, threshold), "seed {seed}");
+
+            cache
+                .store_duplicate_groups(GroupingKey::new(threshold, 16), &groups)
+                .expect("Failed to store groups");
+            if !groups.is_empty() {
+                assert_eq!(
+                    cache
+                        .get_cached_duplicate_groups(GroupingKey::new(threshold, 16), None, None)
+                        .expect("Failed to read groups"),
+                    Some(groups)
+                );
+            }
+        }
+    }
+}
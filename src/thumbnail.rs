@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::fs;
+use std::io::{Cursor, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::extract::open_image;
+
+/// Longest edge of a thumbnail, in pixels
+pub const THUMBNAIL_SIZE: u32 = 256;
+/// Longest edges downscaled copies can be asked for with, in pixels
+pub const PREVIEW_SIZES: RangeInclusive<u32> = 64..=8192;
+
+/// Folder next to the database that thumbnails are stored in
+pub fn thumbnail_dir(db_path: &Path) -> PathBuf {
+    db_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("thumbnails")
+}
+
+/// Where the thumbnail for contents with this sha256 lives. Thumbnails are shared between
+/// identical files, and spread over subfolders so no single folder gets huge
+pub fn thumbnail_path(dir: &Path, sha256: &str) -> PathBuf {
+    let prefix = sha256.get(..2).unwrap_or("00");
+    dir.join(prefix).join(format!("{sha256}.jpg"))
+}
+
+/// Where the copy downscaled to `max` pixels of contents with this sha256 lives, next to its
+/// thumbnail
+pub fn preview_path(dir: &Path, sha256: &str, max: u32) -> PathBuf {
+    let prefix = sha256.get(..2).unwrap_or("00");
+    dir.join(prefix).join(format!("{sha256}-{max}.jpg"))
+}
+
+/// Shrink an image to fit within `THUMBNAIL_SIZE` and encode it as JPEG
+pub fn render_thumbnail(img: &DynamicImage) -> Result<Vec<u8>> {
+    encode_jpeg(img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+}
+
+/// Shrink an image to fit within `max` pixels and encode it as JPEG. Filtered more carefully
+/// than thumbnails, as these are looked at closely
+pub fn render_resized(img: &DynamicImage, max: u32) -> Result<Vec<u8>> {
+    encode_jpeg(img.resize(max, max, FilterType::Triangle))
+}
+
+fn encode_jpeg(img: DynamicImage) -> Result<Vec<u8>> {
+    let mut buffer = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(img.to_rgb8()).write_to(&mut buffer, ImageFormat::Jpeg)?;
+    Ok(buffer.into_inner())
+}
+
+/// Store the thumbnail for an already decoded image, unless it's there already
+pub fn save_thumbnail(dir: &Path, sha256: &str, img: &DynamicImage) -> Result<()> {
+    let path = thumbnail_path(dir, sha256);
+    if path.exists() {
+        return Ok(());
+    }
+    write_thumbnail(&path, &render_thumbnail(img)?)
+}
+
+/// The stored thumbnail for a file, decoding the image and storing it first if needed
+pub fn load_or_create_thumbnail(dir: &Path, image: &Path, sha256: &str) -> Result<Vec<u8>> {
+    let path = thumbnail_path(dir, sha256);
+    if let Ok(data) = fs::read(&path) {
+        return Ok(data);
+    }
+    let data = render_thumbnail(&open_image(image)?)?;
+    write_thumbnail(&path, &data)?;
+    Ok(data)
+}
+
+/// A JPEG of an image downscaled to fit within `max` pixels, None when it fits already. With a
+/// `dir` the copy is stored there and read back on later requests
+pub fn load_or_create_preview(
+    dir: Option<&Path>,
+    image: &Path,
+    sha256: &str,
+    max: u32,
+) -> Result<Option<Vec<u8>>> {
+    let path = dir.map(|dir| preview_path(dir, sha256, max));
+    if let Some(data) = path.as_deref().and_then(|path| fs::read(path).ok()) {
+        return Ok(Some(data));
+    }
+    let img = open_image(image)?;
+    if img.width() <= max && img.height() <= max {
+        return Ok(None);
+    }
+    let data = render_resized(&img, max)?;
+    if let Some(path) = &path {
+        write_thumbnail(path, &data)?;
+    }
+    Ok(Some(data))
+}
+
+/// Write through a temporary file so a reader never sees a half written thumbnail
+fn write_thumbnail(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create thumbnail folder {}", parent.display()))?;
+    }
+    // A uniquely named file per writer, so two requests for the same thumbnail can't interleave
+    let mut partial = tempfile::NamedTempFile::new_in(path.parent().unwrap_or(Path::new(".")))
+        .with_context(|| format!("Could not write thumbnail {}", path.display()))?;
+    partial
+        .write_all(data)
+        .with_context(|| format!("Could not write thumbnail {}", partial.path().display()))?;
+    partial
+        .persist(path)
+        .with_context(|| format!("Could not write thumbnail {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn thumbnails_fit_the_size_and_are_reused() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(1024, 512, Rgb([10, 200, 30])));
+        let sha256 = "ab".repeat(32);
+
+        save_thumbnail(temp_dir.path(), &sha256, &img).expect("save thumbnail");
+        let stored = thumbnail_path(temp_dir.path(), &sha256);
+        assert!(stored.starts_with(temp_dir.path().join("ab")));
+
+        let thumbnail = image::load_from_memory(&fs::read(&stored).expect("read thumbnail"))
+            .expect("thumbnail should decode");
+        assert_eq!(
+            (thumbnail.width(), thumbnail.height()),
+            (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2)
+        );
+
+        // Served from the store without touching the (missing) original
+        let served = load_or_create_thumbnail(temp_dir.path(), Path::new("/gone.png"), &sha256)
+            .expect("stored thumbnail");
+        assert_eq!(served, fs::read(&stored).expect("read thumbnail"));
+    }
+
+    #[test]
+    fn previews_are_only_made_for_larger_images() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let original = temp_dir.path().join("wide.png");
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(1024, 512, Rgb([10, 200, 30])))
+            .save(&original)
+            .expect("save image");
+        let sha256 = "cd".repeat(32);
+
+        let fits = load_or_create_preview(Some(temp_dir.path()), &original, &sha256, 1024)
+            .expect("preview");
+        assert!(fits.is_none());
+
+        let preview = load_or_create_preview(Some(temp_dir.path()), &original, &sha256, 300)
+            .expect("preview")
+            .expect("the image is larger than 300 pixels");
+        let decoded = image::load_from_memory(&preview).expect("preview should decode");
+        assert_eq!((decoded.width(), decoded.height()), (300, 150));
+
+        fs::remove_file(&original).expect("remove original");
+        let stored = load_or_create_preview(Some(temp_dir.path()), &original, &sha256, 300)
+            .expect("stored preview");
+        assert_eq!(stored, Some(preview));
+    }
+}
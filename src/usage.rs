@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Web server runs whose served files are kept in the database when `serve_log_sessions` isn't
+/// set
+pub const DEFAULT_SERVE_LOG_SESSIONS: usize = 10;
+
+/// What the web server sent for a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServeKind {
+    /// The full image, as read from disk
+    Image,
+    /// A small JPEG from the thumbnail store
+    Thumbnail,
+}
+
+impl ServeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServeKind::Image => "image",
+            ServeKind::Thumbnail => "thumbnail",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "image" => Some(ServeKind::Image),
+            "thumbnail" => Some(ServeKind::Thumbnail),
+            _ => None,
+        }
+    }
+}
+
+/// Totals for one run of the web server, identified by when it started
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionUsage {
+    /// Server start time in seconds since the Unix epoch
+    pub started_at: i64,
+    pub requests: u64,
+    /// Different files served, counting images and thumbnails of one file once
+    pub files: u64,
+    pub bytes: u64,
+    pub image_bytes: u64,
+    pub thumbnail_bytes: u64,
+}
+
+/// One logged response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServedFile {
+    pub path: PathBuf,
+    pub kind: ServeKind,
+    pub bytes: u64,
+    /// Seconds since the Unix epoch
+    pub served_at: i64,
+}
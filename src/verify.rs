@@ -0,0 +1,185 @@
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use tracing::{info, instrument, warn};
+
+use crate::cache::{CachedContent, HashCache};
+use crate::hasher::calculate_file_sha256;
+use crate::paths::extended_length_path;
+
+/// What reading a cached file back turned up
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContentProblem {
+    /// Gone from a folder that's still there
+    Missing,
+    /// There, but reading it failed
+    Unreadable,
+    /// Different contents and a different size or modification time, it was edited or replaced
+    Modified,
+    /// Different contents with the same size and modification time, nothing should have
+    /// changed it: bit rot or a silent write
+    Corrupted,
+}
+
+enum Outcome {
+    /// Its folder isn't there, so it can't be told apart from a deleted file
+    Unavailable,
+    Intact,
+    Problem(ContentProblem),
+}
+
+/// Outcome of checking cached files against their contents
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub checked: usize,
+    /// Files in folders that aren't there, e.g. on an unmounted drive. They stay due for a check
+    pub unavailable: usize,
+    pub problems: Vec<(PathBuf, ContentProblem)>,
+}
+
+/// Parse `--sample`, a percentage such as `10%` or `2.5`
+pub fn parse_percentage(text: &str) -> Result<f64> {
+    let number = text.trim().trim_end_matches('%');
+    let percentage: f64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid percentage '{text}', expected e.g. 10%"))?;
+    if !(percentage > 0.0 && percentage <= 100.0) {
+        bail!("Percentage '{text}' must be above 0 and at most 100");
+    }
+    Ok(percentage)
+}
+
+/// Read cached files back and compare their sha256 with the cached one. With `sample`, only
+/// that percentage of the files is read, picking those checked longest ago, so repeated runs
+/// work through the whole cache like a scrub
+#[instrument(level = "debug", skip(cache))]
+pub fn verify_content(cache: &HashCache, sample: Option<f64>) -> Result<VerifyReport> {
+    let total = cache.cached_file_count()?;
+    let limit = match sample {
+        Some(percentage) => ((total as f64 * percentage / 100.0).ceil() as usize).min(total),
+        None => total,
+    };
+    let files = cache.files_to_verify(limit)?;
+    info!(
+        "Verifying the contents of {} of {total} cached files...",
+        files.len()
+    );
+
+    let outcomes: Vec<(PathBuf, Outcome)> = files
+        .par_iter()
+        .map(|file| (file.path.clone(), check_file(file)))
+        .collect();
+
+    let mut report = VerifyReport::default();
+    let mut checked = Vec::new();
+    for (path, outcome) in outcomes {
+        match outcome {
+            Outcome::Unavailable => report.unavailable += 1,
+            Outcome::Intact => checked.push(path),
+            Outcome::Problem(problem) => {
+                report.problems.push((path.clone(), problem));
+                checked.push(path);
+            }
+        }
+    }
+    report.checked = checked.len();
+    cache.record_content_checks(&checked)?;
+    Ok(report)
+}
+
+fn check_file(file: &CachedContent) -> Outcome {
+    let fs_path = extended_length_path(&file.path);
+    let metadata = match fs::metadata(&fs_path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            let folder_exists = file
+                .path
+                .parent()
+                .is_some_and(|parent| extended_length_path(parent).is_dir());
+            return if folder_exists {
+                Outcome::Problem(ContentProblem::Missing)
+            } else {
+                Outcome::Unavailable
+            };
+        }
+    };
+    let sha256 = match calculate_file_sha256(&file.path) {
+        Ok(sha256) => sha256,
+        Err(e) => {
+            warn!("Could not read {}: {}", file.path.display(), e);
+            return Outcome::Problem(ContentProblem::Unreadable);
+        }
+    };
+    if sha256 == file.sha256 {
+        return Outcome::Intact;
+    }
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64);
+    if metadata.len() != file.size || modified != file.modified {
+        Outcome::Problem(ContentProblem::Modified)
+    } else {
+        Outcome::Problem(ContentProblem::Corrupted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::FileMetadata;
+    use crate::hasher::get_file_metadata;
+    use tempfile::TempDir;
+
+    #[test]
+    fn changed_contents_are_reported() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let cache = HashCache::new_in_memory().expect("cache");
+        let cache_file = |name: &str, sha256: Option<&str>, modified: Option<i64>| {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, name).expect("write file");
+            let metadata = get_file_metadata(&path).expect("metadata");
+            cache
+                .store_hash(&FileMetadata {
+                    path: path.clone(),
+                    size: metadata.size,
+                    sha256: sha256.map_or(metadata.sha256, str::to_string),
+                    perceptual_hash: "0000000000000000".to_string(),
+                    width: None,
+                    height: None,
+                    dominant_color: None,
+                    modified: modified.or(metadata.modified),
+                })
+                .expect("store");
+            path
+        };
+        cache_file("a.jpg", None, None);
+        let edited = cache_file("b.jpg", Some("00"), Some(0));
+        let rotten = cache_file("c.jpg", Some("11"), None);
+        let gone = cache_file("d.jpg", None, None);
+        fs::remove_file(&gone).expect("remove");
+
+        let first = verify_content(&cache, Some(50.0)).expect("verify");
+        let second = verify_content(&cache, Some(50.0)).expect("verify");
+        assert_eq!(first.checked + second.checked, 4);
+        let mut problems = [first.problems, second.problems].concat();
+        problems.sort();
+        assert_eq!(
+            problems,
+            vec![
+                (edited, ContentProblem::Modified),
+                (rotten, ContentProblem::Corrupted),
+                (gone, ContentProblem::Missing),
+            ]
+        );
+
+        assert_eq!(parse_percentage("10%").expect("valid"), 10.0);
+        assert!(parse_percentage("0").is_err());
+        assert!(parse_percentage("150%").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, Frame};
+use imghash::ImageHash;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{debug_span, info, warn};
+use wait_timeout::ChildExt;
+
+use crate::algorithm::{hash_algorithm, GridHasher};
+use crate::background::throttle_io;
+use crate::cache::{hash_side, HashCache};
+use crate::extract::average_frames;
+use crate::hasher::generate_rotation_invariant_hash_safe;
+use crate::paths::extended_length_path;
+
+/// Video formats scans pick up with `--videos`
+pub const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "m4v", "mov", "mkv"];
+/// Frames sampled from each video, spread evenly over its length
+pub const SAMPLED_FRAMES: usize = 8;
+/// How long ffprobe or ffmpeg may take on one video or frame before it's killed, so a damaged
+/// file or a stalled network share can't hold up the scan
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Set from `scan --videos`
+static INCLUDE_VIDEOS: OnceLock<bool> = OnceLock::new();
+
+/// Pick up videos in scans for the rest of the process
+pub fn set_include_videos(include: bool) {
+    if INCLUDE_VIDEOS.set(include).is_err() {
+        warn!("Video scanning was already set");
+    }
+}
+
+pub fn videos_included() -> bool {
+    INCLUDE_VIDEOS.get().copied().unwrap_or(false)
+}
+
+/// Check whether a file is a video, whose sampled frames are hashed instead of the file itself
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// The signature image of a video: `SAMPLED_FRAMES` frames blended into one, the way the
+/// `all-frames` strategy blends animations. Re-encodes, resizes and container changes of a clip
+/// blend to nearly the same image, so they hash close together and are found as candidates like
+/// any image. `confirm_with_frame_hashes` then checks them frame by frame. Frames are decoded by
+/// the `ffprobe` and `ffmpeg` commands, which have to be on the `PATH`
+pub fn video_signature(path: &Path) -> Result<DynamicImage> {
+    let frames = sample_frames(path)?
+        .into_iter()
+        .map(|frame| Frame::new(frame.to_rgba8()))
+        .collect::<Vec<_>>();
+    Ok(average_frames(&frames))
+}
+
+/// `SAMPLED_FRAMES` frames spread over the video
+fn sample_frames(path: &Path) -> Result<Vec<DynamicImage>> {
+    let duration = duration(path)?;
+    sample_times(duration, SAMPLED_FRAMES)
+        .into_iter()
+        .map(|seconds| frame_at(path, seconds))
+        .collect()
+}
+
+/// The hashes of a video's sampled frames, in order. Two clips are only as close as their
+/// frames are, so this is what confirms a match of their blended signatures
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHashes {
+    /// Side of the frames' hash grid
+    grid: u8,
+    frames: Vec<ImageHash>,
+}
+
+impl FrameHashes {
+    /// Frame hashes as they're cached, comma separated
+    pub fn encode(&self) -> Result<String> {
+        Ok(self
+            .frames
+            .iter()
+            .map(ImageHash::encode)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(","))
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let grid = hash_side(encoded.split(',').next().unwrap_or_default());
+        let frames = encoded
+            .split(',')
+            .map(|frame| Ok(ImageHash::decode(frame, grid, grid)?))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { grid, frames })
+    }
+
+    /// Differing bits summed over the frames, None when the videos weren't sampled alike
+    pub fn distance(&self, other: &FrameHashes) -> Option<u32> {
+        if self.grid != other.grid || self.frames.len() != other.frames.len() {
+            return None;
+        }
+        self.frames
+            .iter()
+            .zip(&other.frames)
+            .map(|(a, b)| a.distance(b).ok().map(|distance| distance as u32))
+            .sum()
+    }
+}
+
+/// Check groups of videos frame by frame. A group whose files are all videos is regrouped by
+/// their frame hashes, with the threshold applying to each frame on average, so clips whose
+/// blended frames only happen to look alike drop out. Frame hashes are computed for grouped
+/// videos only and cached by contents. Groups with images, or with a video whose frames can't
+/// be decoded, are kept as they are
+pub fn confirm_with_frame_hashes(
+    groups: Vec<Vec<PathBuf>>,
+    threshold: u32,
+    grid_size: u8,
+    cache: &HashCache,
+) -> Result<Vec<Vec<PathBuf>>> {
+    let paths: Vec<PathBuf> = groups
+        .iter()
+        .filter(|group| group.iter().all(|path| is_video(path)))
+        .flatten()
+        .cloned()
+        .collect();
+    if paths.is_empty() {
+        return Ok(groups);
+    }
+    let frame_hashes = frame_hashes(&paths, grid_size, cache)?;
+    let frame_threshold = threshold * SAMPLED_FRAMES as u32;
+
+    let mut confirmed = Vec::new();
+    let mut rejected = 0;
+    for group in groups {
+        let hashes: Vec<&FrameHashes> = group
+            .iter()
+            .filter_map(|path| frame_hashes.get(path))
+            .collect();
+        if hashes.len() < group.len() {
+            confirmed.push(group);
+            continue;
+        }
+        let regrouped = group_by_distance(&group, &hashes, frame_threshold);
+        rejected += group.len() - regrouped.iter().map(Vec::len).sum::<usize>();
+        confirmed.extend(regrouped);
+    }
+    if rejected > 0 {
+        info!("{rejected} videos looked alike blended but not frame by frame");
+    }
+    confirmed.sort();
+    Ok(confirmed)
+}
+
+/// Connected groups of two or more videos within `threshold` of each other
+fn group_by_distance(
+    paths: &[PathBuf],
+    hashes: &[&FrameHashes],
+    threshold: u32,
+) -> Vec<Vec<PathBuf>> {
+    let mut groups: Vec<usize> = (0..paths.len()).collect();
+    for i in 0..paths.len() {
+        for j in i + 1..paths.len() {
+            if hashes[i]
+                .distance(hashes[j])
+                .is_some_and(|d| d <= threshold)
+            {
+                let (from, to) = (groups[j], groups[i]);
+                for group in groups.iter_mut().filter(|group| **group == from) {
+                    *group = to;
+                }
+            }
+        }
+    }
+    let mut by_group: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+    for (path, group) in paths.iter().zip(groups) {
+        by_group.entry(group).or_default().push(path.clone());
+    }
+    by_group
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+/// Frame hashes for these videos on the scan's grid, from the cache or computed and cached.
+/// Videos that aren't in the cache or can't be decoded are left out
+fn frame_hashes(
+    paths: &[PathBuf],
+    grid_size: u8,
+    cache: &HashCache,
+) -> Result<BTreeMap<PathBuf, FrameHashes>> {
+    let _span = debug_span!("frame_hashes", videos = paths.len()).entered();
+    let mut hashes = BTreeMap::new();
+    let mut missing = Vec::new();
+    for path in paths {
+        let Some((_, sha256)) = cache.get_cached_hash_details(path)? else {
+            continue;
+        };
+        let cached = cache
+            .get_video_signature(&sha256)?
+            .and_then(|encoded| FrameHashes::decode(&encoded).ok())
+            .filter(|frames| frames.grid == grid_size);
+        match cached {
+            Some(frames) => {
+                hashes.insert(path.clone(), frames);
+            }
+            None => missing.push((path, sha256)),
+        }
+    }
+
+    if !missing.is_empty() {
+        info!("Hashing the frames of {} videos...", missing.len());
+    }
+    let hasher = hash_algorithm().hasher(grid_size)?;
+    let computed: Vec<_> = missing
+        .par_iter()
+        .filter_map(
+            |(path, sha256)| match hash_frames(&hasher, grid_size, path) {
+                Ok(frames) => Some(((*path).clone(), sha256, frames)),
+                Err(e) => {
+                    warn!("Could not hash the frames of {}: {:#}", path.display(), e);
+                    None
+                }
+            },
+        )
+        .collect();
+    for (path, sha256, frames) in computed {
+        let stored = frames
+            .encode()
+            .and_then(|encoded| cache.store_video_signature(sha256, &encoded));
+        if let Err(e) = stored {
+            warn!("Could not cache frame hashes for {}: {}", path.display(), e);
+        }
+        hashes.insert(path, frames);
+    }
+    Ok(hashes)
+}
+
+fn hash_frames(hasher: &GridHasher, grid: u8, path: &Path) -> Result<FrameHashes> {
+    if let Ok(metadata) = fs::metadata(extended_length_path(path)) {
+        throttle_io(metadata.len());
+    }
+    let frames = sample_frames(path)?
+        .iter()
+        .map(|frame| generate_rotation_invariant_hash_safe(hasher, frame))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(FrameHashes { grid, frames })
+}
+
+/// Evenly spaced times in the middle of `count` equal stretches of the video, avoiding the
+/// black frames at either end
+fn sample_times(duration: f64, count: usize) -> Vec<f64> {
+    (0..count)
+        .map(|i| duration * (i as f64 + 0.5) / count as f64)
+        .collect()
+}
+
+/// Length of a video in seconds
+fn duration(path: &Path) -> Result<f64> {
+    let output = run(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-show_entries", "format=duration"])
+            .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+            .arg(path),
+        COMMAND_TIMEOUT,
+    )?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.trim()
+        .parse()
+        .with_context(|| format!("ffprobe reported no duration: {}", text.trim()))
+}
+
+/// Decode the frame shown `seconds` into a video
+fn frame_at(path: &Path, seconds: f64) -> Result<DynamicImage> {
+    let output = run(
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-ss", &format!("{seconds:.3}"), "-i"])
+            .arg(path)
+            .args(["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-"]),
+        COMMAND_TIMEOUT,
+    )?;
+    if !output.status.success() || output.stdout.is_empty() {
+        bail!(
+            "ffmpeg could not decode a frame at {seconds:.1}s: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(image::load_from_memory(&output.stdout)?)
+}
+
+/// Run a command to completion like `Command::output`, but kill it after `timeout`
+fn run(command: &mut Command, timeout: Duration) -> Result<Output> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not run {program}"))?;
+    // Drain the pipes while waiting, a child blocked on a full pipe would never exit
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    let Some(status) = child.wait_timeout(timeout)? else {
+        child.kill()?;
+        child.wait()?;
+        bail!("{program} was killed after {timeout:?} without finishing");
+    };
+    let collect = |reader: Option<JoinHandle<Vec<u8>>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+    };
+    Ok(Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    })
+}
+
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_are_spread_over_the_video() {
+        assert_eq!(sample_times(8.0, 4), vec![1.0, 3.0, 5.0, 7.0]);
+        assert_eq!(sample_times(0.0, 2), vec![0.0, 0.0]);
+        assert!(is_video(Path::new("/clips/IMG_1.MOV")));
+        assert!(!is_video(Path::new("/clips/IMG_1.jpg")));
+    }
+
+    fn frames(encoded: &[&str]) -> FrameHashes {
+        FrameHashes::decode(&encoded.join(",")).expect("frame hashes should decode")
+    }
+
+    #[test]
+    fn videos_are_regrouped_by_their_frames() {
+        let clip = frames(&["0000000000000000", "ffffffffffffffff"]);
+        let reencoded = frames(&["0000000000000001", "ffffffffffffffff"]);
+        // Blends to the same image as the clip, but shows something else
+        let other = frames(&["ffffffffffffffff", "0000000000000000"]);
+        assert_eq!(clip.distance(&reencoded), Some(1));
+        assert_eq!(clip.distance(&other), Some(128));
+        assert_eq!(clip.grid, 8);
+        assert_eq!(
+            FrameHashes::decode(&clip.encode().expect("encode")).expect("decode"),
+            clip
+        );
+
+        let paths = ["a.mp4", "b.mov", "c.mkv"].map(PathBuf::from);
+        assert_eq!(
+            group_by_distance(&paths, &[&clip, &other, &reencoded], 4),
+            vec![vec![paths[0].clone(), paths[2].clone()]]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hung_commands_are_killed() {
+        let output = run(
+            Command::new("sh").args(["-c", "echo frame; echo oops >&2"]),
+            COMMAND_TIMEOUT,
+        )
+        .expect("sh should run");
+        assert_eq!(output.stdout, b"frame\n");
+        assert_eq!(output.stderr, b"oops\n");
+
+        let started = std::time::Instant::now();
+        let hung = run(Command::new("sleep").arg("30"), Duration::from_millis(200));
+        assert!(hung.is_err());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,212 @@
+use image::{Rgb, RgbImage};
+use imghash::ImageHash;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::cache::HashCache;
+use crate::diff::heat_color;
+use crate::hasher::decode_hashes;
+
+/// Side of one cell in a rendered grid image, in pixels
+const CELL_SIZE: u32 = 24;
+
+/// Hamming distances between every two files of a group, to tell one tight cluster from two
+/// loosely joined ones. Members are ordered so that close files sit next to each other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DistanceGrid {
+    pub paths: Vec<PathBuf>,
+    /// None where a hash isn't cached or two hashes can't be compared
+    pub distances: Vec<Vec<Option<u32>>>,
+}
+
+impl DistanceGrid {
+    /// The grid of a group from its cached hashes
+    pub fn from_cache(group: &[PathBuf], cache: &HashCache) -> Self {
+        let hashes = group
+            .iter()
+            .map(|path| {
+                let (perceptual_hash, _) = cache.get_cached_hash_details(path).ok()??;
+                decode_hashes(vec![(path.clone(), perceptual_hash)])
+                    .pop()
+                    .map(|(_, hash)| hash)
+            })
+            .collect::<Vec<_>>();
+        Self::from_hashes(group, &hashes)
+    }
+
+    pub fn from_hashes(paths: &[PathBuf], hashes: &[Option<ImageHash>]) -> Self {
+        let distance = |a: usize, b: usize| -> Option<u32> {
+            let distance = hashes[a].as_ref()?.distance(hashes[b].as_ref()?).ok()?;
+            Some(distance as u32)
+        };
+        let order = nearest_neighbour_order(paths.len(), distance);
+        Self {
+            paths: order.iter().map(|&i| paths[i].clone()).collect(),
+            distances: order
+                .iter()
+                .map(|&a| order.iter().map(|&b| distance(a, b)).collect())
+                .collect(),
+        }
+    }
+
+    /// The largest distance between two members, None when a pair can't be compared
+    pub fn widest(&self) -> Option<u32> {
+        self.pairs()
+            .try_fold(0, |widest, distance| Some(widest.max(distance?)))
+    }
+
+    /// How many pairs of members are within `threshold` of each other, and how many pairs
+    /// there are
+    pub fn pairs_within(&self, threshold: u32) -> (usize, usize) {
+        self.pairs().fold((0, 0), |(within, total), distance| {
+            let close = distance.is_some_and(|distance| distance <= threshold);
+            (within + usize::from(close), total + 1)
+        })
+    }
+
+    fn pairs(&self) -> impl Iterator<Item = Option<u32>> + '_ {
+        self.distances
+            .iter()
+            .enumerate()
+            .flat_map(|(i, row)| row[i + 1..].iter().copied())
+    }
+
+    /// The grid as text, numbered rows and columns followed by the numbered paths. With
+    /// `colour`, cells are shaded with ANSI colours from cold (identical) to hot (twice
+    /// `threshold` and more)
+    pub fn render_text(&self, threshold: u32, colour: bool) -> String {
+        let width = self.paths.len().to_string().len().max(2) + 1;
+        let mut out = format!("{:>width$}", "");
+        for column in 1..=self.paths.len() {
+            let _ = write!(out, "{column:>width$}");
+        }
+        out.push('\n');
+        for (row, distances) in self.distances.iter().enumerate() {
+            let _ = write!(out, "{:>width$}", row + 1);
+            for (column, distance) in distances.iter().enumerate() {
+                let cell = match distance {
+                    _ if row == column => "·".to_string(),
+                    Some(distance) => distance.to_string(),
+                    None => "?".to_string(),
+                };
+                match distance.filter(|_| colour && row != column) {
+                    Some(distance) => {
+                        let Rgb([r, g, b]) = cell_colour(distance, threshold);
+                        // Dark text on the bright end of the scale
+                        let text = if u16::from(r) + u16::from(g) > 300 {
+                            30
+                        } else {
+                            97
+                        };
+                        let _ = write!(
+                            out,
+                            "\x1b[48;2;{r};{g};{b}m\x1b[{text}m{cell:>width$}\x1b[0m"
+                        );
+                    }
+                    None => {
+                        let _ = write!(out, "{cell:>width$}");
+                    }
+                }
+            }
+            out.push('\n');
+        }
+        out.push('\n');
+        for (number, path) in self.paths.iter().enumerate() {
+            let _ = writeln!(out, "{:>width$}  {}", number + 1, path.display());
+        }
+        out
+    }
+
+    /// The grid as an image, one shaded square per pair. Pairs that can't be compared are grey
+    pub fn render_image(&self, threshold: u32) -> RgbImage {
+        let side = self.paths.len() as u32 * CELL_SIZE;
+        RgbImage::from_fn(side.max(1), side.max(1), |x, y| {
+            let (row, column) = ((y / CELL_SIZE) as usize, (x / CELL_SIZE) as usize);
+            // A one pixel gap between cells
+            if x % CELL_SIZE == CELL_SIZE - 1 || y % CELL_SIZE == CELL_SIZE - 1 {
+                return Rgb([255, 255, 255]);
+            }
+            match self
+                .distances
+                .get(row)
+                .and_then(|distances| distances.get(column))
+            {
+                Some(Some(distance)) => cell_colour(*distance, threshold),
+                _ => Rgb([128, 128, 128]),
+            }
+        })
+    }
+}
+
+/// Shade a distance on the diff heat scale, reaching its hottest at twice the threshold: the
+/// furthest two files of one group can be when both are within it of the first file
+fn cell_colour(distance: u32, threshold: u32) -> Rgb<u8> {
+    let hottest = u64::from(threshold.max(1)) * 2;
+    let heat = (u64::from(distance) * 255 / hottest).min(255);
+    heat_color(heat as u8)
+}
+
+/// Start with the first member and keep following the closest one not placed yet, so clusters
+/// end up as blocks along the diagonal
+fn nearest_neighbour_order(
+    len: usize,
+    distance: impl Fn(usize, usize) -> Option<u32>,
+) -> Vec<usize> {
+    let mut order = Vec::with_capacity(len);
+    let mut placed = vec![false; len];
+    let mut current = 0;
+    while order.len() < len {
+        order.push(current);
+        placed[current] = true;
+        let next = (0..len)
+            .filter(|&i| !placed[i])
+            .min_by_key(|&i| (distance(current, i).unwrap_or(u32::MAX), i));
+        match next {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clusters_are_placed_next_to_each_other() {
+        // Two pairs of close files, a and c, b and d
+        let paths: Vec<PathBuf> = ["a", "b", "c", "d"].iter().map(PathBuf::from).collect();
+        let matrix = [
+            [0, 20, 1, 21],
+            [20, 0, 19, 2],
+            [1, 19, 0, 20],
+            [21, 2, 20, 0],
+        ];
+        let order = nearest_neighbour_order(4, |a, b| Some(matrix[a][b]));
+        assert_eq!(order, vec![0, 2, 1, 3]);
+
+        let grid = DistanceGrid {
+            paths: order.iter().map(|&i| paths[i].clone()).collect(),
+            distances: order
+                .iter()
+                .map(|&a| order.iter().map(|&b| Some(matrix[a][b])).collect())
+                .collect(),
+        };
+        assert_eq!(grid.widest(), Some(21));
+        assert_eq!(grid.pairs_within(10), (2, 6));
+
+        let text = grid.render_text(10, false);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "     1  2  3  4");
+        assert_eq!(lines[1], "  1  ·  1 20 21");
+        assert_eq!(lines[6], "  1  a");
+        assert_eq!(lines[7], "  2  c");
+        assert!(grid.render_text(10, true).contains("\x1b[48;2;"));
+
+        let image = grid.render_image(10);
+        assert_eq!(image.dimensions(), (4 * CELL_SIZE, 4 * CELL_SIZE));
+        assert_eq!(*image.get_pixel(0, 0), heat_color(0));
+        assert_eq!(*image.get_pixel(3 * CELL_SIZE, 0), heat_color(255));
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::{bail, Context, Result};
+use notify::{recommended_watcher, Event, EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::cache::{GroupingKey, HashCache, ResolvedConfig};
+use crate::deadline::time_is_up;
+use crate::hasher::{generate_hashes_with_report, get_duplicates_from_cache};
+use crate::partial::{is_partial_file, settles_in};
+use crate::paths::extended_length_path;
+use crate::report::{FileWarnings, SkipReason, SkippedFiles};
+use crate::scanner::scan_for_images_with_report;
+
+/// How long the watched folders have to be quiet before changes are handled, unless configured
+/// otherwise. Copying a batch of photos in is one change rather than one per file
+pub const DEFAULT_SETTLE: Duration = Duration::from_secs(2);
+
+/// What handling one batch of changes did to the cache
+#[derive(Debug, Default)]
+pub struct WatchUpdate {
+    /// New or changed images, hashed unless their cached hash was still valid
+    pub hashed: Vec<PathBuf>,
+    /// Cached files that are gone, they're removed from the cache
+    pub removed: Vec<PathBuf>,
+    pub skipped: SkippedFiles,
+    pub warnings: FileWarnings,
+    /// The duplicate groups the hashed images are in now
+    pub groups: Vec<Vec<PathBuf>>,
+    /// Images skipped because their folder's debounce interval hasn't passed, and how long
+    /// until it has. They're handled again then, even if nothing else changes
+    pub settling: Vec<(PathBuf, Duration)>,
+}
+
+/// Watch `paths` until the `--max-duration` deadline, if any: images already there are hashed
+/// first, then each batch of new, changed and removed files is handled with `apply_changes` once
+/// the folders have been quiet for `settle`. Images that were still settling are handled again
+/// once their interval is over. A pass that fails is logged and watching goes on. `on_update`
+/// sees what every pass did
+pub fn watch_paths(
+    paths: &[PathBuf],
+    settle: Duration,
+    config: &ResolvedConfig,
+    cache: &HashCache,
+    mut on_update: impl FnMut(&WatchUpdate),
+) -> Result<()> {
+    let (sender, events) = channel();
+    let mut watcher =
+        recommended_watcher(sender).context("Could not start watching for changes")?;
+    for path in paths {
+        watcher
+            .watch(&extended_length_path(path), RecursiveMode::Recursive)
+            .with_context(|| format!("Could not watch {}", path.display()))?;
+    }
+
+    // Files waiting out their debounce interval, and when it's over
+    let mut settling: BTreeMap<PathBuf, Instant> = BTreeMap::new();
+    let mut handle =
+        |changed: &[PathBuf], settling: &mut BTreeMap<PathBuf, Instant>| match apply_changes(
+            changed, paths, config, cache,
+        ) {
+            Ok(update) => {
+                let now = Instant::now();
+                for (path, remaining) in &update.settling {
+                    settling.insert(path.clone(), now + *remaining);
+                }
+                on_update(&update);
+            }
+            Err(e) => warn!("Could not handle {} changed paths: {e:#}", changed.len()),
+        };
+
+    // Watching starts before the catch-up pass, so nothing arriving during it is missed
+    handle(paths, &mut settling);
+
+    let mut pending = BTreeSet::new();
+    while !time_is_up() {
+        let next_settled = settling
+            .values()
+            .min()
+            .map(|due| due.saturating_duration_since(Instant::now()));
+        match events.recv_timeout(next_settled.map_or(settle, |due| due.min(settle))) {
+            Ok(Ok(event)) => pending.extend(changed_paths(event, paths)),
+            Ok(Err(e)) => warn!("Error watching for changes: {e}"),
+            Err(RecvTimeoutError::Timeout) => {
+                let now = Instant::now();
+                settling.retain(|path, due| {
+                    if *due > now {
+                        return true;
+                    }
+                    pending.insert(path.clone());
+                    false
+                });
+                if pending.is_empty() {
+                    continue;
+                }
+                let changed: Vec<PathBuf> = std::mem::take(&mut pending).into_iter().collect();
+                debug!("Handling {} changed paths", changed.len());
+                handle(&changed, &mut settling);
+            }
+            Err(RecvTimeoutError::Disconnected) => bail!("Stopped receiving file changes"),
+        }
+    }
+    Ok(())
+}
+
+/// The paths an event touched, except reads and files in hidden folders below the watched ones
+fn changed_paths(event: Event, roots: &[PathBuf]) -> Vec<PathBuf> {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return Vec::new();
+    }
+    event
+        .paths
+        .into_iter()
+        .filter(|path| !is_hidden_below(path, roots))
+        .collect()
+}
+
+fn is_hidden_below(path: &Path, roots: &[PathBuf]) -> bool {
+    roots
+        .iter()
+        .filter_map(|root| path.strip_prefix(extended_length_path(root)).ok())
+        .any(|relative| {
+            relative.components().any(|component| {
+                matches!(component, Component::Normal(name) if name.to_string_lossy().starts_with('.'))
+            })
+        })
+}
+
+/// Bring the cache up to date with `changed` files and folders below the watched `roots`:
+/// images in the ones that exist are hashed, cached files at or below the ones that don't are
+/// removed, then the groups of the hashed images are looked up from the cached hashes close to
+/// theirs. Only changed files are read and only their neighbours are compared, so this is cheap
+/// enough to run on every batch of changes
+pub fn apply_changes(
+    changed: &[PathBuf],
+    roots: &[PathBuf],
+    config: &ResolvedConfig,
+    cache: &HashCache,
+) -> Result<WatchUpdate> {
+    let mut update = WatchUpdate::default();
+    let mut present = Vec::new();
+    let mut in_temporary_folders = SkippedFiles::default();
+    for path in changed {
+        if extended_length_path(path).exists() {
+            // Changed files are scanned one by one, so folders between them and the watched one,
+            // such as Safari's `.download` folders, are checked here
+            let root = roots
+                .iter()
+                .find(|root| path.starts_with(root) && path != *root);
+            if root.is_some_and(|root| is_partial_file(path, root, None)) {
+                in_temporary_folders.record(SkipReason::StillWriting, path.clone());
+            } else {
+                present.push(path.clone());
+            }
+            continue;
+        }
+        for gone in cache.get_cached_paths_under(path)? {
+            cache.remove_file_entry(&gone)?;
+            update.removed.push(gone);
+        }
+    }
+
+    let (images, skipped) =
+        scan_for_images_with_report(&present, false, false, false, &config.ignore_paths, false)?;
+    update.settling = skipped
+        .get(SkipReason::StillWriting)
+        .iter()
+        .filter_map(|path| {
+            let modified = fs::metadata(extended_length_path(path))
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            Some((path.clone(), settles_in(path, modified)?))
+        })
+        .collect();
+    update.skipped = skipped;
+    update.skipped.extend(in_temporary_folders);
+    if !images.is_empty() {
+        let (hashes, report) =
+            generate_hashes_with_report(&images, config.grid_size, cache, false)?;
+        update.skipped.extend(report.skipped);
+        update.warnings = report.warnings;
+        update.hashed = hashes.into_iter().map(|(path, _)| path).collect();
+    }
+    if update.hashed.is_empty() && update.removed.is_empty() {
+        return Ok(update);
+    }
+
+    update.groups = groups_of(&update.hashed, config.threshold, cache)?;
+    store_groups(&update, config, cache)?;
+    info!(
+        "Hashed {} images and removed {} missing files",
+        update.hashed.len(),
+        update.removed.len()
+    );
+    Ok(update)
+}
+
+/// The groups `hashed` images are in: each with the cached files within `threshold` of it (see
+/// `HashCache::find_similar`), merged where they share a file. Neighbours of neighbours aren't
+/// looked up, the next scan or `matches` groups the whole cache
+fn groups_of(hashed: &[PathBuf], threshold: u32, cache: &HashCache) -> Result<Vec<Vec<PathBuf>>> {
+    let mut groups: Vec<BTreeSet<PathBuf>> = Vec::new();
+    for path in hashed {
+        let Some((hash, _)) = cache.get_cached_hash_details(path)? else {
+            continue;
+        };
+        let mut group: BTreeSet<PathBuf> = cache
+            .find_similar(&hash, threshold)?
+            .into_iter()
+            .map(|(similar, _)| similar)
+            .collect();
+        group.insert(path.clone());
+        if group.len() < 2 {
+            continue;
+        }
+        merge_group(&mut groups, group);
+    }
+    let mut groups: Vec<Vec<PathBuf>> = groups
+        .into_iter()
+        .map(|group| group.into_iter().collect())
+        .collect();
+    groups.sort();
+    Ok(groups)
+}
+
+/// Add `group` to `groups`, merged with the ones it shares a file with
+fn merge_group(groups: &mut Vec<BTreeSet<PathBuf>>, mut group: BTreeSet<PathBuf>) {
+    let (overlapping, separate): (Vec<_>, Vec<_>) = std::mem::take(groups)
+        .into_iter()
+        .partition(|other| !other.is_disjoint(&group));
+    *groups = separate;
+    group.extend(overlapping.into_iter().flatten());
+    groups.push(group);
+}
+
+/// Update the stored groups, and with them the discoveries `/feed.xml` lists, with the changes:
+/// hashed and removed files leave the groups they were in, then the groups they're in now are
+/// merged in. Without stored groups to update, every group is computed from the cache
+fn store_groups(update: &WatchUpdate, config: &ResolvedConfig, cache: &HashCache) -> Result<()> {
+    let key = GroupingKey::new(config.threshold, config.grid_size);
+    let stored = cache.get_last_duplicate_groups(key)?;
+    if stored.is_empty() {
+        get_duplicates_from_cache(cache, config.threshold, config.grid_size, None, None)?;
+        return Ok(());
+    }
+
+    let changed: BTreeSet<&PathBuf> = update.hashed.iter().chain(&update.removed).collect();
+    let mut groups: Vec<BTreeSet<PathBuf>> = stored
+        .into_iter()
+        .map(|group| {
+            group
+                .into_iter()
+                .filter(|path| !changed.contains(path))
+                .collect()
+        })
+        .collect();
+    for group in &update.groups {
+        merge_group(&mut groups, group.iter().cloned().collect());
+    }
+    let groups: Vec<Vec<PathBuf>> = groups
+        .into_iter()
+        .filter(|group| group.len() >= 2)
+        .map(|group| group.into_iter().collect())
+        .collect();
+    cache.store_duplicate_groups(key, &groups)
+}